@@ -0,0 +1,102 @@
+//! Heap allocations of framing chunks the way the send loop does, against
+//! the copy-per-chunk path it replaced. Run with `cargo bench`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tauri_app_lib::transfer::protocol::{encode_frame, MessageType};
+
+/// Counts every allocation and the bytes asked for
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+const CHUNKS: u64 = 256;
+
+/// A fresh `Vec` for each chunk's data and for each frame
+fn copying(transfer_id: &str, buffer: &[u8]) -> usize {
+    let mut written = 0;
+    for chunk_index in 0..CHUNKS {
+        let msg = MessageType::ChunkData {
+            transfer_id: transfer_id.to_string(),
+            chunk_index,
+            data: buffer.to_vec(),
+            chunk_hash: String::new(),
+        };
+        let mut frame = Vec::new();
+        encode_frame(&msg, &mut frame).unwrap();
+        written += frame.len();
+    }
+    written
+}
+
+/// The chunk buffer moved into each message and taken back, frames encoded
+/// into one reused `Vec`, as `FileSender::send_file` does
+fn reusing(transfer_id: &str, mut buffer: Vec<u8>) -> usize {
+    let mut frame = Vec::with_capacity(CHUNK_SIZE + 256);
+    let mut written = 0;
+    for chunk_index in 0..CHUNKS {
+        let msg = MessageType::ChunkData {
+            transfer_id: transfer_id.to_string(),
+            chunk_index,
+            data: std::mem::take(&mut buffer),
+            chunk_hash: String::new(),
+        };
+        encode_frame(&msg, &mut frame).unwrap();
+        written += frame.len();
+        if let MessageType::ChunkData { data, .. } = msg {
+            buffer = data;
+        }
+    }
+    written
+}
+
+fn measure(name: &str, run: impl FnOnce() -> usize) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let started = Instant::now();
+    let written = std::hint::black_box(run());
+    let elapsed = started.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let bytes = BYTES.load(Ordering::Relaxed) - bytes;
+    println!(
+        "{:<8} {:>6} allocations ({:.2}/chunk), {:>6} MiB allocated, {:>5} MiB framed in {:?}",
+        name,
+        allocations,
+        allocations as f64 / CHUNKS as f64,
+        bytes / (1024 * 1024),
+        written / (1024 * 1024),
+        elapsed
+    );
+}
+
+fn main() {
+    let transfer_id = uuid::Uuid::new_v4().to_string();
+    let buffer = vec![7u8; CHUNK_SIZE];
+    println!("{} chunks of {} bytes", CHUNKS, CHUNK_SIZE);
+    measure("copying", || copying(&transfer_id, &buffer));
+    measure("reusing", || reusing(&transfer_id, buffer.clone()));
+}
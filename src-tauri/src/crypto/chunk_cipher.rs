@@ -0,0 +1,63 @@
+use crate::error::ProxiError;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+
+/// Context string for deriving per-transfer chunk keys
+const CHUNK_KEY_CONTEXT: &str = "ProxiShare chunk encryption v1";
+
+/// ChaCha20-Poly1305 sealing of chunk payloads inside the QUIC stream, so
+/// file contents stay private even if the TLS layer were compromised
+pub struct ChunkCipher {
+    key: LessSafeKey,
+    transfer_id: String,
+}
+
+impl ChunkCipher {
+    /// Key for one offer, derived from the shared passphrase key and the
+    /// receiver's random challenge nonce, so re-offering a transfer never
+    /// reuses a key with the same chunk nonces
+    pub fn derive(passphrase_key_hex: &str, transfer_id: &str, challenge: &[u8]) -> Option<Self> {
+        let passphrase_key = blake3::Hash::from_hex(passphrase_key_hex).ok()?;
+        let mut hasher = blake3::Hasher::new_derive_key(CHUNK_KEY_CONTEXT);
+        hasher.update(passphrase_key.as_bytes());
+        hasher.update(transfer_id.as_bytes());
+        hasher.update(challenge);
+        let key = UnboundKey::new(&CHACHA20_POLY1305, hasher.finalize().as_bytes()).ok()?;
+        Some(Self {
+            key: LessSafeKey::new(key),
+            transfer_id: transfer_id.to_string(),
+        })
+    }
+
+    /// Encrypt `data` in place and append the tag
+    pub fn seal(&self, chunk_index: u64, data: &mut Vec<u8>) -> Result<(), ProxiError> {
+        self.key
+            .seal_in_place_append_tag(
+                chunk_nonce(chunk_index),
+                Aad::from(self.transfer_id.as_bytes()),
+                data,
+            )
+            .map_err(|_| ProxiError::Crypto("Failed to encrypt chunk".into()))
+    }
+
+    /// Check the tag and decrypt `data` in place, leaving only the plaintext
+    pub fn open(&self, chunk_index: u64, data: &mut Vec<u8>) -> Result<(), ProxiError> {
+        let len = self
+            .key
+            .open_in_place(
+                chunk_nonce(chunk_index),
+                Aad::from(self.transfer_id.as_bytes()),
+                data,
+            )
+            .map_err(|_| ProxiError::Crypto(format!("Chunk {} failed to decrypt", chunk_index)))?
+            .len();
+        data.truncate(len);
+        Ok(())
+    }
+}
+
+/// Chunk indices are unique within a transfer, so they make unique nonces
+fn chunk_nonce(chunk_index: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&chunk_index.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
@@ -1,23 +1,87 @@
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
 use std::sync::Arc;
 
+/// File holding the persisted TLS certificate and key
+const CERT_FILE: &str = "tls_cert.bin";
+/// Validity period used when none is configured
+pub const DEFAULT_CERT_VALIDITY_DAYS: u32 = 365;
+
+#[derive(Serialize, Deserialize)]
 pub struct CertificateManager {
     pub cert_der: Vec<u8>,
     pub key_der: Vec<u8>,
+    /// Expiry of the certificate (seconds since the Unix epoch)
+    pub not_after: i64,
 }
 
 impl CertificateManager {
-    pub fn generate_self_signed() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let cert_params = rcgen::CertificateParams::new(vec!["proxishare.local".to_string()]);
+    pub fn generate_self_signed(
+        validity_days: u32,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut cert_params = rcgen::CertificateParams::new(vec!["proxishare.local".to_string()]);
+        cert_params.not_before = now - time::Duration::days(1);
+        cert_params.not_after = now + time::Duration::days(validity_days as i64);
+        let not_after = cert_params.not_after.unix_timestamp();
         let cert = rcgen::Certificate::from_params(cert_params)?;
 
         Ok(Self {
             cert_der: cert.serialize_der()?,
             key_der: cert.serialize_private_key_der(),
+            not_after,
         })
     }
 
+    /// Reuse the certificate stored in the app data dir so its fingerprint stays
+    /// stable across launches. A missing, corrupt or expired one is replaced.
+    pub fn load_or_generate(
+        app_dir: &Path,
+        validity_days: u32,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let cert_path = app_dir.join(CERT_FILE);
+        if let Ok(bytes) = fs::read(&cert_path) {
+            match bincode::deserialize::<Self>(&bytes) {
+                Ok(stored) if !stored.is_expired() => return Ok(stored),
+                Ok(_) => println!("[Security] Stored certificate expired, regenerating"),
+                Err(e) => println!("[Security] Stored certificate is unreadable: {}", e),
+            }
+        }
+
+        Self::regenerate(app_dir, validity_days)
+    }
+
+    /// Create and persist a fresh certificate, replacing any stored one.
+    /// Peers that pinned the old fingerprint will have to re-pin.
+    pub fn regenerate(
+        app_dir: &Path,
+        validity_days: u32,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let manager = Self::generate_self_signed(validity_days)?;
+        crate::crypto::identity::write_private_file(
+            &app_dir.join(CERT_FILE),
+            &bincode::serialize(&manager)?,
+        )?;
+        println!(
+            "[Security] Generated certificate {} valid for {} days",
+            manager.fingerprint(),
+            validity_days
+        );
+        Ok(manager)
+    }
+
+    fn is_expired(&self) -> bool {
+        time::OffsetDateTime::now_utc().unix_timestamp() >= self.not_after
+    }
+
+    /// blake3 fingerprint of the DER certificate (hex), for pinning
+    pub fn fingerprint(&self) -> String {
+        blake3::hash(&self.cert_der).to_hex().to_string()
+    }
+
     pub fn get_server_config(
         &self,
     ) -> Result<rustls::ServerConfig, Box<dyn std::error::Error + Send + Sync>> {
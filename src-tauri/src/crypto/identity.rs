@@ -0,0 +1,170 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs;
+use std::path::Path;
+
+/// File holding the Ed25519 private key (raw 32-byte seed)
+const KEY_FILE: &str = "device_key.bin";
+/// Legacy random UUID id written by older versions
+const LEGACY_ID_FILE: &str = "device_id.txt";
+/// Number of fingerprint bytes used for a key-derived device id (hex encoded)
+const DEVICE_ID_BYTES: usize = 16;
+
+/// Cryptographic identity of this device.
+/// The device id is derived from the public key so peers can verify who they talk to.
+pub struct DeviceIdentity {
+    signing_key: SigningKey,
+    device_id: String,
+}
+
+impl DeviceIdentity {
+    /// Load the keypair from the app data dir, generating one on first run.
+    /// Installs that already have a UUID in `device_id.txt` keep it so existing
+    /// trust relationships survive the upgrade; messages are still signed.
+    pub fn load_or_generate(app_dir: &Path) -> Result<Self, crate::GenericError> {
+        let key_path = app_dir.join(KEY_FILE);
+        let stored = if key_path.exists() {
+            match read_key(&key_path) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    // A bad key can't be recovered; set it aside and start over
+                    let corrupt_path = key_path.with_extension("bin.corrupt");
+                    println!(
+                        "[Identity] WARNING: device key file is unreadable ({}), moving it to {:?} and generating a new keypair",
+                        e, corrupt_path
+                    );
+                    fs::rename(&key_path, &corrupt_path)?;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let signing_key = match stored {
+            Some(key) => key,
+            None => {
+                let key = SigningKey::generate(&mut rand::rngs::OsRng);
+                write_private_file(&key_path, &key.to_bytes())?;
+                println!("[Identity] Generated new device keypair");
+                key
+            }
+        };
+
+        let legacy_id = fs::read_to_string(app_dir.join(LEGACY_ID_FILE))
+            .ok()
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+
+        let device_id = match legacy_id {
+            Some(id) => {
+                println!("[Identity] Using migrated legacy device id {}", id);
+                id
+            }
+            None => fingerprint(&signing_key.verifying_key()),
+        };
+
+        Ok(Self {
+            signing_key,
+            device_id,
+        })
+    }
+
+    /// Replace the keypair (and any legacy id) with a fresh one, e.g. when a
+    /// copied install shares our id. Takes effect the next time the app starts.
+    pub fn regenerate(app_dir: &Path) -> Result<Self, crate::GenericError> {
+        let key = SigningKey::generate(&mut rand::rngs::OsRng);
+        write_private_file(&app_dir.join(KEY_FILE), &key.to_bytes())?;
+        let legacy_path = app_dir.join(LEGACY_ID_FILE);
+        if legacy_path.exists() {
+            fs::remove_file(&legacy_path)?;
+        }
+
+        let device_id = fingerprint(&key.verifying_key());
+        println!(
+            "[Identity] Regenerated device keypair, new id {}",
+            device_id
+        );
+        Ok(Self {
+            signing_key: key,
+            device_id,
+        })
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.signing_key.verifying_key().to_bytes().to_vec()
+    }
+
+    pub fn sign(&self, payload: &[u8]) -> Vec<u8> {
+        self.signing_key.sign(payload).to_bytes().to_vec()
+    }
+}
+
+/// Read a raw 32-byte seed, failing on a file of any other length
+fn read_key(path: &Path) -> Result<SigningKey, crate::GenericError> {
+    let bytes = fs::read(path)?;
+    let seed: [u8; 32] = bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| format!("expected 32 bytes, found {}", bytes.len()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Derive a device id from a public key (truncated blake3 fingerprint)
+pub fn fingerprint(key: &VerifyingKey) -> String {
+    let hash = blake3::hash(key.as_bytes());
+    hash.as_bytes()[..DEVICE_ID_BYTES]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Build the canonical byte payload that gets signed for a message
+pub fn signing_payload(fields: &[&str]) -> Vec<u8> {
+    fields.join("\0").into_bytes()
+}
+
+/// Check that `signature` over `payload` was produced by `public_key`
+pub fn verify_signature(public_key: &[u8], payload: &[u8], signature: &[u8]) -> bool {
+    let Ok(key_bytes) = <[u8; 32]>::try_from(public_key) else {
+        return false;
+    };
+    let Ok(key) = VerifyingKey::from_bytes(&key_bytes) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    key.verify(payload, &signature).is_ok()
+}
+
+/// Whether `device_id` is the fingerprint of `public_key`.
+/// Legacy UUID ids can't be tied to a key this way.
+pub fn id_matches_key(device_id: &str, public_key: &[u8]) -> bool {
+    <[u8; 32]>::try_from(public_key)
+        .ok()
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .map(|key| fingerprint(&key) == device_id)
+        .unwrap_or(false)
+}
+
+pub(crate) fn write_private_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(data)
+    }
+    #[cfg(not(unix))]
+    {
+        fs::write(path, data)
+    }
+}
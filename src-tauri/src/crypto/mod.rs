@@ -1,2 +1,4 @@
+pub mod chunk_cipher;
 pub mod encryption;
+pub mod identity;
 pub mod security;
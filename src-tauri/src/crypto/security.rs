@@ -1,51 +1,476 @@
+use crate::error::ProxiError;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Current on-disk format of the trust store
+pub const TRUST_STORE_VERSION: u32 = 1;
+
+/// What we know about a trusted device. New fields need `#[serde(default)]`
+/// so stores written by older builds still load.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct TrustedDevice {
+    /// Seconds since the Unix epoch; `None` for devices migrated from the
+    /// unversioned format
+    pub trusted_at: Option<i64>,
+    /// Seconds since the Unix epoch after which the device is no longer
+    /// trusted; `None` for permanent trust
+    pub expires_at: Option<i64>,
+}
+
+impl TrustedDevice {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TrustStore {
-    pub trusted_devices: HashSet<String>, // Set of device IDs
+    pub version: u32,
+    pub devices: HashMap<String, TrustedDevice>,
+    /// Devices refused outright, trusted or not. Not exported.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub blocked: HashSet<String>,
+}
+
+/// Every format the trust store file has had
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredTrust {
+    Versioned(TrustStore),
+    /// Before versioning: a bare set of device ids
+    Legacy(HashSet<String>),
+}
+
+impl StoredTrust {
+    /// Upgrade to the current format, returning whether anything changed
+    fn migrate(self) -> (TrustStore, bool) {
+        match self {
+            StoredTrust::Versioned(store) => (store, false),
+            StoredTrust::Legacy(ids) => (
+                TrustStore {
+                    version: TRUST_STORE_VERSION,
+                    devices: ids
+                        .into_iter()
+                        .map(|id| (id, TrustedDevice::default()))
+                        .collect(),
+                    blocked: HashSet::new(),
+                },
+                true,
+            ),
+        }
+    }
+}
+
+/// Result of merging an exported trust store into ours
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TrustImport {
+    /// Devices we didn't trust before
+    pub added: Vec<String>,
+    /// Existing entries replaced by the imported ones (only with `overwrite`)
+    pub overwritten: Vec<String>,
+    /// Existing entries that differ from the imported ones and were kept;
+    /// import again with `overwrite` to replace them
+    pub conflicts: Vec<String>,
+}
+
+/// Device ids are hex fingerprints or legacy UUIDs
+fn is_valid_device_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
 }
 
 pub struct SecurityService {
     store_path: PathBuf,
-    pub trusted_devices: HashSet<String>,
+    pub trusted_devices: HashMap<String, TrustedDevice>,
+    /// Devices that may not pair, transfer or even prompt us
+    pub blocked_devices: HashSet<String>,
 }
 
 impl SecurityService {
     pub fn new(app_dir: PathBuf) -> Self {
         let store_path = app_dir.join("trust_store.json");
-        let trusted_devices = if store_path.exists() {
-            let content = fs::read_to_string(&store_path).unwrap_or_default();
-            serde_json::from_str(&content).unwrap_or_else(|_| HashSet::new())
-        } else {
-            HashSet::new()
-        };
+        let (store, migrated) = Self::load(&store_path);
 
-        Self {
+        let service = Self {
             store_path,
-            trusted_devices,
+            trusted_devices: store.devices,
+            blocked_devices: store.blocked,
+        };
+        if migrated {
+            service.migrate_file();
+        }
+        service
+    }
+
+    /// Load the trust store, falling back to the last good backup if the
+    /// primary file is missing or corrupt (e.g. truncated by a crash).
+    /// Also reports whether the loaded file was in an older format.
+    fn load(store_path: &Path) -> (TrustStore, bool) {
+        if store_path.exists() {
+            match Self::read_store(store_path) {
+                Ok((store, migrated)) => return (store, migrated),
+                Err(e) => println!("[Security] Trust store is unreadable: {}", e),
+            }
+        }
+
+        let backup_path = backup_path(store_path);
+        if backup_path.exists() {
+            match Self::read_store(&backup_path) {
+                Ok((store, migrated)) => {
+                    println!(
+                        "[Security] Recovered {} trusted devices from backup",
+                        store.devices.len()
+                    );
+                    return (store, migrated);
+                }
+                Err(e) => println!("[Security] Trust store backup is unreadable: {}", e),
+            }
         }
+
+        (
+            TrustStore {
+                version: TRUST_STORE_VERSION,
+                devices: HashMap::new(),
+                blocked: HashSet::new(),
+            },
+            false,
+        )
+    }
+
+    fn read_store(path: &Path) -> Result<(TrustStore, bool), Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        let stored: StoredTrust = serde_json::from_str(&content)?;
+        Ok(stored.migrate())
+    }
+
+    /// Rewrite an old-format store in the current format, keeping the
+    /// original next to it in case the upgrade needs undoing
+    fn migrate_file(&self) {
+        if self.store_path.exists() {
+            let pre_migration = self.store_path.with_extension("json.pre-migration");
+            if let Err(e) = fs::copy(&self.store_path, &pre_migration) {
+                println!(
+                    "[Security] Failed to back up trust store before migration: {}",
+                    e
+                );
+                return;
+            }
+        }
+        match self.save() {
+            Ok(()) => println!(
+                "[Security] Migrated trust store to version {}",
+                TRUST_STORE_VERSION
+            ),
+            Err(e) => println!("[Security] Failed to write migrated trust store: {}", e),
+        }
+    }
+
+    pub fn store_path(&self) -> &Path {
+        &self.store_path
     }
 
+    /// Expired temporary trust counts as untrusted even before it's pruned,
+    /// and a blocked device is never trusted
     pub fn is_trusted(&self, device_id: &str) -> bool {
-        self.trusted_devices.contains(device_id)
+        let now = chrono::Utc::now().timestamp();
+        !self.is_blocked(device_id)
+            && self
+                .trusted_devices
+                .get(device_id)
+                .is_some_and(|device| !device.is_expired(now))
     }
 
+    /// Ids of the devices currently trusted
+    pub fn trusted_ids(&self) -> Vec<String> {
+        let now = chrono::Utc::now().timestamp();
+        self.trusted_devices
+            .iter()
+            .filter(|(id, device)| !device.is_expired(now) && !self.is_blocked(id))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    pub fn is_blocked(&self, device_id: &str) -> bool {
+        self.blocked_devices.contains(device_id)
+    }
+
+    /// Refuse a device from now on. Its trust entry is kept, so unblocking
+    /// restores it. Returns whether it wasn't blocked already.
+    pub fn block_device(&mut self, device_id: String) -> Result<bool, Box<dyn std::error::Error>> {
+        if !is_valid_device_id(&device_id) {
+            return Err(format!("Invalid device id: {:?}", device_id).into());
+        }
+        if !self.blocked_devices.insert(device_id) {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Returns whether the device was blocked
+    pub fn unblock_device(&mut self, device_id: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        if !self.blocked_devices.remove(device_id) {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Trust a device permanently, also turning temporary trust permanent
     pub fn add_trusted(&mut self, device_id: String) -> Result<(), Box<dyn std::error::Error>> {
-        self.trusted_devices.insert(device_id);
+        let now = chrono::Utc::now().timestamp();
+        let device = self
+            .trusted_devices
+            .entry(device_id)
+            .or_insert_with(|| TrustedDevice {
+                trusted_at: Some(now),
+                expires_at: None,
+            });
+        if device.is_expired(now) {
+            device.trusted_at = Some(now);
+        }
+        device.expires_at = None;
         self.save()
     }
 
+    /// Trust a device until `duration` from now, e.g. a guest's phone.
+    /// A device that is already trusted permanently stays so. Returns when
+    /// the trust expires, or `None` if it's permanent.
+    pub fn add_temporary_trust(
+        &mut self,
+        device_id: String,
+        duration: std::time::Duration,
+    ) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+        let now = chrono::Utc::now().timestamp();
+        let expires_at = now.saturating_add(i64::try_from(duration.as_secs()).unwrap_or(i64::MAX));
+        let device = self
+            .trusted_devices
+            .entry(device_id)
+            .or_insert_with(|| TrustedDevice {
+                trusted_at: Some(now),
+                expires_at: Some(expires_at),
+            });
+        if device.expires_at.is_none() {
+            return Ok(None);
+        }
+        if device.is_expired(now) {
+            device.trusted_at = Some(now);
+        }
+        device.expires_at = Some(expires_at);
+        self.save()?;
+        Ok(Some(expires_at))
+    }
+
+    /// Drop entries whose temporary trust has run out, returning their ids
+    pub fn prune_expired(&mut self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let now = chrono::Utc::now().timestamp();
+        let expired: Vec<String> = self
+            .trusted_devices
+            .iter()
+            .filter(|(_, device)| device.is_expired(now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !expired.is_empty() {
+            for id in &expired {
+                self.trusted_devices.remove(id);
+            }
+            self.save()?;
+        }
+        Ok(expired)
+    }
+
     pub fn remove_trusted(&mut self, device_id: &str) -> Result<(), Box<dyn std::error::Error>> {
         self.trusted_devices.remove(device_id);
         self.save()
     }
 
+    /// Write the trust store to `path` as portable JSON for `import_from`
+    pub fn export_to(&self, path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+        let content = serde_json::to_string_pretty(&TrustStore {
+            version: TRUST_STORE_VERSION,
+            devices: self.trusted_devices.clone(),
+            blocked: HashSet::new(),
+        })?;
+        write_atomic(path, content.as_bytes())?;
+        println!(
+            "[Security] Exported {} trusted devices to {}",
+            self.trusted_devices.len(),
+            path.display()
+        );
+        Ok(self.trusted_devices.len())
+    }
+
+    /// Merge an exported trust store into ours. Entries we already have are
+    /// left alone if they differ, unless `overwrite` is set. The whole file is
+    /// validated before anything changes.
+    pub fn import_from(
+        &mut self,
+        path: &Path,
+        overwrite: bool,
+    ) -> Result<TrustImport, Box<dyn std::error::Error>> {
+        let (store, _) =
+            Self::read_store(path).map_err(|e| format!("Not a valid trust store file: {}", e))?;
+        if store.version > TRUST_STORE_VERSION {
+            return Err(format!(
+                "Trust store version {} is newer than this build supports ({})",
+                store.version, TRUST_STORE_VERSION
+            )
+            .into());
+        }
+        if let Some(id) = store.devices.keys().find(|id| !is_valid_device_id(id)) {
+            return Err(format!("Trust store contains an invalid device id: {:?}", id).into());
+        }
+
+        let mut result = TrustImport::default();
+        for (id, device) in store.devices {
+            match self.trusted_devices.get(&id) {
+                None => result.added.push(id.clone()),
+                Some(existing) if *existing == device => continue,
+                Some(_) if overwrite => result.overwritten.push(id.clone()),
+                Some(_) => {
+                    result.conflicts.push(id);
+                    continue;
+                }
+            }
+            self.trusted_devices.insert(id, device);
+        }
+
+        if !result.added.is_empty() || !result.overwritten.is_empty() {
+            self.save()?;
+        }
+        println!(
+            "[Security] Imported trust store from {}: {} added, {} overwritten, {} conflicts kept",
+            path.display(),
+            result.added.len(),
+            result.overwritten.len(),
+            result.conflicts.len()
+        );
+        Ok(result)
+    }
+
     fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let content = serde_json::to_string(&self.trusted_devices)?;
-        fs::write(&self.store_path, content)?;
+        let content = serde_json::to_string(&TrustStore {
+            version: TRUST_STORE_VERSION,
+            devices: self.trusted_devices.clone(),
+            blocked: self.blocked_devices.clone(),
+        })?;
+
+        // Keep the previous good version around in case the new one gets lost
+        if self.store_path.exists() && Self::read_store(&self.store_path).is_ok() {
+            fs::copy(&self.store_path, backup_path(&self.store_path))?;
+        }
+
+        write_atomic(&self.store_path, content.as_bytes())?;
         Ok(())
     }
 }
+
+fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("json.bak")
+}
+
+/// Write to a temp file next to `path`, sync it and rename it over `path`,
+/// so readers only ever see the old or the new contents.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Bytes of the random salt each install derives its passphrase key with
+pub const PASSPHRASE_SALT_LEN: usize = 16;
+
+/// A fresh passphrase salt (hex)
+pub fn new_passphrase_salt() -> String {
+    hex(&rand::random::<[u8; PASSPHRASE_SALT_LEN]>())
+}
+
+/// Derive the key peers use to prove knowledge of a shared passphrase, with
+/// Argon2id under `salt_hex` so a leaked key or overheard MAC can't be
+/// checked against guesses cheaply. Only derived keys are stored; the
+/// passphrase itself never touches disk.
+pub fn derive_passphrase_key(passphrase: &str, salt_hex: &str) -> Result<String, ProxiError> {
+    let salt = unhex(salt_hex)
+        .filter(|salt| salt.len() >= argon2::RECOMMENDED_SALT_LEN)
+        .ok_or_else(|| ProxiError::Crypto("Invalid passphrase salt".into()))?;
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| ProxiError::Crypto(format!("Failed to derive passphrase key: {}", e)))?;
+    Ok(hex(&key))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// MAC over a receiver's challenge nonce, bound to the transfer it gates
+pub fn passphrase_mac(key_hex: &str, nonce: &[u8], transfer_id: &str) -> Option<Vec<u8>> {
+    let key = blake3::Hash::from_hex(key_hex).ok()?;
+    let mut hasher = blake3::Hasher::new_keyed(key.as_bytes());
+    hasher.update(nonce);
+    hasher.update(transfer_id.as_bytes());
+    Some(hasher.finalize().as_bytes().to_vec())
+}
+
+/// Constant-time check of a peer's challenge response
+pub fn verify_passphrase_mac(key_hex: &str, nonce: &[u8], transfer_id: &str, mac: &[u8]) -> bool {
+    let Some(expected) = passphrase_mac(key_hex, nonce, transfer_id) else {
+        return false;
+    };
+    let (Ok(expected), Ok(actual)) = (
+        <[u8; 32]>::try_from(expected.as_slice()),
+        <[u8; 32]>::try_from(mac),
+    ) else {
+        return false;
+    };
+    blake3::Hash::from(expected) == blake3::Hash::from(actual)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_sender_derives_the_receivers_key_from_its_salt() {
+        let receiver_salt = new_passphrase_salt();
+        let receiver_key = derive_passphrase_key("correct horse", &receiver_salt).unwrap();
+
+        // The sender has a salt of its own, but answers under the receiver's
+        let sender_salt = new_passphrase_salt();
+        assert_ne!(sender_salt, receiver_salt);
+        let answer_key = derive_passphrase_key("correct horse", &receiver_salt).unwrap();
+        let mac = passphrase_mac(&answer_key, b"nonce", "t1").unwrap();
+        assert!(verify_passphrase_mac(&receiver_key, b"nonce", "t1", &mac));
+
+        let wrong = derive_passphrase_key("wrong horse", &receiver_salt).unwrap();
+        let mac = passphrase_mac(&wrong, b"nonce", "t1").unwrap();
+        assert!(!verify_passphrase_mac(&receiver_key, b"nonce", "t1", &mac));
+    }
+
+    #[test]
+    fn the_same_passphrase_gives_different_keys_under_different_salts() {
+        let a = derive_passphrase_key("correct horse", &new_passphrase_salt()).unwrap();
+        let b = derive_passphrase_key("correct horse", &new_passphrase_salt()).unwrap();
+        assert_ne!(a, b);
+        assert!(derive_passphrase_key("correct horse", "").is_err());
+        assert!(derive_passphrase_key("correct horse", "not hex").is_err());
+    }
+}
@@ -0,0 +1,31 @@
+-- A history database as the first release left it: the initial tables,
+-- before schema versioning and every column added since
+CREATE TABLE transfers (
+    id TEXT PRIMARY KEY,
+    device_id TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    file_path TEXT NOT NULL,
+    total_size INTEGER NOT NULL,
+    direction TEXT NOT NULL,
+    status TEXT NOT NULL,
+    bytes_transferred INTEGER DEFAULT 0,
+    file_hash TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE TABLE transfer_chunks (
+    transfer_id TEXT NOT NULL,
+    chunk_index INTEGER NOT NULL,
+    received BOOLEAN DEFAULT TRUE,
+    PRIMARY KEY (transfer_id, chunk_index),
+    FOREIGN KEY (transfer_id) REFERENCES transfers(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_transfers_status ON transfers(status);
+CREATE INDEX idx_transfers_device ON transfers(device_id);
+
+INSERT INTO transfers VALUES
+    ('old-1', 'peer-1', 'report.pdf', '/downloads/report.pdf', 2048, 'receive',
+     'in_progress', 1024, '', 1700000000, 1700000100);
+INSERT INTO transfer_chunks (transfer_id, chunk_index) VALUES ('old-1', 0);
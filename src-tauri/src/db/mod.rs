@@ -1,9 +1,25 @@
 pub mod schema;
 
+use crate::transfer::protocol::HashAlgo;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqlitePoolOptions, FromRow, Pool, Sqlite};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    FromRow, Pool, Sqlite, SqliteConnection,
+};
 use std::path::Path;
+use std::str::FromStr;
+
+/// Every value the `transfers.status` column can hold
+pub const TRANSFER_STATUSES: &[&str] = &[
+    "pending",
+    "in_progress",
+    "paused",
+    "interrupted",
+    "completed",
+    "failed",
+    "cancelled",
+];
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct TransferRecord {
@@ -15,13 +31,149 @@ pub struct TransferRecord {
     pub file_path: String,
     pub total_size: i64,
     pub direction: String, // "send" or "receive"
-    pub status: String,    // "pending", "in_progress", "completed", "failed"
+    pub status: String,    // "pending", "in_progress", "interrupted", "completed", "failed"
     pub bytes_transferred: i64,
     pub file_hash: String,
     pub created_at: i64,
     pub updated_at: i64,
+    /// Address of the peer used for this transfer
+    #[sqlx(default)]
+    pub remote_ip: Option<String>,
+    #[sqlx(default)]
+    pub remote_port: Option<i64>,
+    /// JSON `TransferReceipt` the receiver signed for a completed send
+    #[sqlx(default)]
+    pub receipt: Option<String>,
+    /// User annotation, e.g. "project handoff"
+    #[sqlx(default)]
+    pub note: Option<String>,
+    /// Algorithm of `file_hash`, as `HashAlgo::as_str`; records from before
+    /// it was tracked are blake3
+    #[sqlx(default)]
+    #[serde(default = "default_hash_algo")]
+    pub hash_algo: String,
+}
+
+fn default_hash_algo() -> String {
+    HashAlgo::Blake3.as_str().to_string()
+}
+
+/// A transfer record as history sync sends it. Nested in `HistorySync`, so
+/// its fields are frozen (see `MessageType`); details that are only ours,
+/// like the address a transfer used, stay out of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRecordWire {
+    pub id: String,
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub file_name: String,
+    pub file_path: String,
+    pub total_size: i64,
+    pub direction: String,
+    pub status: String,
+    pub bytes_transferred: i64,
+    pub file_hash: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl From<TransferRecord> for HistoryRecordWire {
+    /// Peers read `file_hash` as blake3, so other hashes are left out
+    fn from(record: TransferRecord) -> Self {
+        let file_hash = if HashAlgo::from_name(&record.hash_algo) == HashAlgo::Blake3 {
+            record.file_hash
+        } else {
+            String::new()
+        };
+        Self {
+            id: record.id,
+            device_id: record.device_id,
+            device_name: record.device_name,
+            file_name: record.file_name,
+            file_path: record.file_path,
+            total_size: record.total_size,
+            direction: record.direction,
+            status: record.status,
+            bytes_transferred: record.bytes_transferred,
+            file_hash,
+            created_at: record.created_at,
+            updated_at: record.updated_at,
+        }
+    }
+}
+
+/// A local change queued for delivery to one sync peer
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SyncQueueEntry {
+    pub device_id: String,
+    pub relative_path: String,
+    pub status: String, // "pending" or "failed"
+    pub attempts: i64,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub updated_at: i64,
+}
+
+/// A device recently sent to, with the address that last worked
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RecentDestination {
+    pub device_id: String,
+    pub device_name: String,
+    pub ip: String,
+    pub port: i64,
+    pub last_used_at: i64,
+}
+
+/// Totals over our history with one device
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PeerSummary {
+    pub device_id: String,
+    /// Name from the recents list, if the device is still on it
+    pub device_name: Option<String>,
+    pub total_transfers: i64,
+    pub completed_transfers: i64,
+    pub failed_transfers: i64,
+    /// Bytes of completed receives from the device
+    pub bytes_received: i64,
+    /// Bytes of completed sends to the device
+    pub bytes_sent: i64,
+    pub last_transfer_at: i64,
+    /// Completed over finished (completed, failed or cancelled) transfers;
+    /// `None` until one has finished
+    pub success_rate: Option<f64>,
+}
+
+/// Result of `Database::check_health`
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseHealth {
+    pub schema_version: i64,
+    /// Version this build migrates to
+    pub latest_version: i64,
+    /// Problems reported by `PRAGMA integrity_check`; empty when healthy
+    pub integrity_errors: Vec<String>,
+    /// Columns that were missing and have been added back ("table.column")
+    pub repaired_columns: Vec<String>,
 }
 
+/// Outcome of `PRAGMA wal_checkpoint(TRUNCATE)`
+#[derive(Debug, Clone, Serialize)]
+pub struct WalCheckpoint {
+    /// Whether another connection kept the checkpoint from finishing
+    pub busy: bool,
+    /// Frames that were in the WAL
+    pub log_frames: i64,
+    /// Frames copied back into the database file
+    pub checkpointed_frames: i64,
+}
+
+/// Entries kept in `recent_destinations`; older ones are dropped
+pub const MAX_RECENT_DESTINATIONS: i64 = 10;
+
+/// Columns selected into a `TransferRecord`
+const TRANSFER_COLUMNS: &str = "id, device_id, NULL as device_name, file_name, file_path, \
+    total_size, direction, status, bytes_transferred, file_hash, \
+    created_at, updated_at, remote_ip, remote_port, receipt, note, hash_algo";
+
 pub struct Database {
     pool: Pool<Sqlite>,
 }
@@ -34,19 +186,141 @@ impl Database {
         }
 
         let db_url = format!("sqlite:{}?mode=rwc", db_path.display());
+        let options = SqliteConnectOptions::from_str(&db_url)?.journal_mode(SqliteJournalMode::Wal);
 
         let pool = SqlitePoolOptions::new()
             .max_connections(5)
-            .connect(&db_url)
+            .connect_with(options)
             .await?;
 
-        // Run migrations
-        sqlx::query(schema::SCHEMA).execute(&pool).await?;
-        println!("[Database] Initialized and migrations run");
+        let db = Self { pool };
+        db.migrate().await?;
+        println!(
+            "[Database] Initialized at schema version {}",
+            db.schema_version().await?
+        );
 
-        Ok(Self { pool })
+        Ok(db)
+    }
+
+    /// Apply every migration newer than the recorded schema version, each in
+    /// its own transaction. Safe to run repeatedly.
+    pub async fn migrate(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(schema::VERSION_TABLE)
+            .execute(&self.pool)
+            .await?;
+        let current = self.schema_version().await?;
+        if current > schema::latest_version() {
+            println!(
+                "[Database] Schema version {} is newer than this build's {}",
+                current,
+                schema::latest_version()
+            );
+            return Ok(());
+        }
+
+        for migration in schema::MIGRATIONS.iter().filter(|m| m.version > current) {
+            println!(
+                "[Database] Migrating to version {}: {}",
+                migration.version, migration.description
+            );
+            let mut tx = self.pool.begin().await?;
+            if !migration.sql.is_empty() {
+                sqlx::query(migration.sql).execute(&mut *tx).await?;
+            }
+            for (table, column, definition) in migration.added_columns {
+                Self::ensure_column(&mut tx, table, column, definition).await?;
+            }
+            sqlx::query(
+                "INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)",
+            )
+            .bind(migration.version)
+            .bind(migration.description)
+            .bind(Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    /// Highest migration applied, 0 for a database from before versioning
+    pub async fn schema_version(&self) -> Result<i64, sqlx::Error> {
+        let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_version")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(version.unwrap_or(0))
+    }
+
+    /// Copy the WAL back into the database file and truncate it, so the file
+    /// on disk holds every committed write
+    pub async fn checkpoint(&self) -> Result<WalCheckpoint, sqlx::Error> {
+        let (busy, log_frames, checkpointed_frames): (i64, i64, i64) =
+            sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(WalCheckpoint {
+            busy: busy != 0,
+            log_frames,
+            checkpointed_frames,
+        })
+    }
+
+    /// Run SQLite's integrity check and re-add any column the migrations
+    /// should have created but the database lacks
+    pub async fn check_health(&self) -> Result<DatabaseHealth, sqlx::Error> {
+        let integrity: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+            .fetch_all(&self.pool)
+            .await?;
+        let integrity_errors = integrity.into_iter().filter(|line| line != "ok").collect();
+
+        let mut conn = self.pool.acquire().await?;
+        let mut repaired_columns = Vec::new();
+        for migration in schema::MIGRATIONS {
+            for (table, column, definition) in migration.added_columns {
+                if Self::ensure_column(&mut conn, table, column, definition).await? {
+                    repaired_columns.push(format!("{}.{}", table, column));
+                }
+            }
+        }
+
+        Ok(DatabaseHealth {
+            schema_version: self.schema_version().await?,
+            latest_version: schema::latest_version(),
+            integrity_errors,
+            repaired_columns,
+        })
+    }
+
+    /// Add a column to a table created by an older version of the schema.
+    /// Returns whether it was missing.
+    async fn ensure_column(
+        conn: &mut SqliteConnection,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let columns: Vec<(String,)> =
+            sqlx::query_as(&format!("SELECT name FROM pragma_table_info('{}')", table))
+                .fetch_all(&mut *conn)
+                .await?;
+        if columns.iter().any(|(name,)| name == column) {
+            return Ok(false);
+        }
+        println!("[Database] Adding column {}.{}", table, column);
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(&mut *conn)
+        .await?;
+        Ok(true)
     }
 
+    /// Insert a transfer, or refresh the metadata of an existing one (resume,
+    /// retry, history sync). Status, progress and `created_at` are kept; an
+    /// empty hash or missing address doesn't overwrite a known one.
+    #[allow(clippy::too_many_arguments)]
     pub async fn record_transfer(
         &self,
         id: &str,
@@ -56,6 +330,9 @@ impl Database {
         total_size: i64,
         direction: &str,
         file_hash: &str,
+        hash_algo: HashAlgo,
+        remote_ip: Option<&str>,
+        remote_port: Option<u16>,
     ) -> Result<(), sqlx::Error> {
         let now = Utc::now().timestamp();
         println!(
@@ -65,8 +342,19 @@ impl Database {
 
         sqlx::query(
             r#"
-            INSERT OR IGNORE INTO transfers (id, device_id, file_name, file_path, total_size, direction, status, bytes_transferred, file_hash, created_at, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?, 'in_progress', 0, ?, ?, ?)
+            INSERT INTO transfers (id, device_id, file_name, file_path, total_size, direction, status, bytes_transferred, file_hash, hash_algo, created_at, updated_at, remote_ip, remote_port)
+            VALUES (?, ?, ?, ?, ?, ?, 'in_progress', 0, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                device_id = excluded.device_id,
+                file_name = excluded.file_name,
+                file_path = excluded.file_path,
+                total_size = excluded.total_size,
+                direction = excluded.direction,
+                file_hash = CASE WHEN excluded.file_hash != '' THEN excluded.file_hash ELSE transfers.file_hash END,
+                hash_algo = CASE WHEN excluded.file_hash != '' THEN excluded.hash_algo ELSE transfers.hash_algo END,
+                updated_at = excluded.updated_at,
+                remote_ip = COALESCE(excluded.remote_ip, transfers.remote_ip),
+                remote_port = COALESCE(excluded.remote_port, transfers.remote_port)
             "#,
         )
         .bind(id)
@@ -76,8 +364,11 @@ impl Database {
         .bind(total_size)
         .bind(direction)
         .bind(file_hash)
+        .bind(hash_algo.as_str())
         .bind(now)
         .bind(now)
+        .bind(remote_ip)
+        .bind(remote_port.map(|p| p as i64))
         .execute(&self.pool)
         .await?;
 
@@ -113,21 +404,64 @@ impl Database {
         Ok(())
     }
 
+    /// Persist how far a running transfer has got without touching its status
+    pub async fn update_transfer_progress(
+        &self,
+        id: &str,
+        bytes_transferred: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE transfers SET bytes_transferred = ?, updated_at = ? WHERE id = ?")
+            .bind(bytes_transferred)
+            .bind(Utc::now().timestamp())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Record the hash a send offered, once it has been computed
+    pub async fn set_transfer_hash(
+        &self,
+        id: &str,
+        file_hash: &str,
+        hash_algo: HashAlgo,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE transfers SET file_hash = ?, hash_algo = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(file_hash)
+        .bind(hash_algo.as_str())
+        .bind(Utc::now().timestamp())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Point a transfer at where its file ended up, e.g. after post-processing
+    pub async fn update_transfer_path(&self, id: &str, file_path: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE transfers SET file_path = ?, updated_at = ? WHERE id = ?")
+            .bind(file_path)
+            .bind(Utc::now().timestamp())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     pub async fn get_transfer_history(
         &self,
         limit: i32,
     ) -> Result<Vec<TransferRecord>, sqlx::Error> {
-        let records = sqlx::query_as::<_, TransferRecord>(
+        let records = sqlx::query_as::<_, TransferRecord>(&format!(
             r#"
-            SELECT 
-                id, device_id, NULL as device_name, file_name, file_path, 
-                total_size, direction, status, bytes_transferred, file_hash, 
-                created_at, updated_at
+            SELECT {}
             FROM transfers 
             ORDER BY created_at DESC 
             LIMIT ?
             "#,
-        )
+            TRANSFER_COLUMNS
+        ))
         .bind(limit)
         .fetch_all(&self.pool)
         .await?;
@@ -140,18 +474,16 @@ impl Database {
         device_id: &str,
         limit: i32,
     ) -> Result<Vec<TransferRecord>, sqlx::Error> {
-        let records = sqlx::query_as::<_, TransferRecord>(
+        let records = sqlx::query_as::<_, TransferRecord>(&format!(
             r#"
-            SELECT 
-                id, device_id, NULL as device_name, file_name, file_path, 
-                total_size, direction, status, bytes_transferred, file_hash, 
-                created_at, updated_at
+            SELECT {}
             FROM transfers 
             WHERE device_id = ?
             ORDER BY created_at DESC 
             LIMIT ?
             "#,
-        )
+            TRANSFER_COLUMNS
+        ))
         .bind(device_id)
         .bind(limit)
         .fetch_all(&self.pool)
@@ -160,6 +492,396 @@ impl Database {
         Ok(records)
     }
 
+    pub async fn get_transfers_by_status(
+        &self,
+        status: &str,
+        limit: i32,
+        offset: i32,
+    ) -> Result<Vec<TransferRecord>, sqlx::Error> {
+        let records = sqlx::query_as::<_, TransferRecord>(&format!(
+            r#"
+            SELECT {}
+            FROM transfers 
+            WHERE status = ?
+            ORDER BY created_at DESC 
+            LIMIT ? OFFSET ?
+            "#,
+            TRANSFER_COLUMNS
+        ))
+        .bind(status)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(records)
+    }
+
+    pub async fn get_transfer(&self, id: &str) -> Result<Option<TransferRecord>, sqlx::Error> {
+        sqlx::query_as::<_, TransferRecord>(&format!(
+            "SELECT {} FROM transfers WHERE id = ?",
+            TRANSFER_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Address of the most recent transfer with `device_id` that recorded one
+    pub async fn last_address(
+        &self,
+        device_id: &str,
+    ) -> Result<Option<(String, u16)>, sqlx::Error> {
+        let row = sqlx::query_as::<_, (String, i64)>(
+            "SELECT remote_ip, remote_port FROM transfers WHERE device_id = ? AND remote_ip IS NOT NULL AND remote_port IS NOT NULL ORDER BY updated_at DESC LIMIT 1",
+        )
+        .bind(device_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|(ip, port)| (ip, port as u16)))
+    }
+
+    /// Apply records from a peer's history sync. Returns how many were new
+    /// or changed our copy; our own addresses are kept.
+    pub async fn merge_history(&self, records: &[HistoryRecordWire]) -> Result<usize, sqlx::Error> {
+        let mut merged = 0;
+        for record in records {
+            let existing = self.get_transfer(&record.id).await?;
+            let unchanged = existing.as_ref().is_some_and(|e| {
+                e.status == record.status && e.bytes_transferred == record.bytes_transferred
+            });
+            if unchanged {
+                continue;
+            }
+            self.record_transfer(
+                &record.id,
+                &record.device_id,
+                &record.file_name,
+                &record.file_path,
+                record.total_size,
+                &record.direction,
+                &record.file_hash,
+                HashAlgo::Blake3,
+                None,
+                None,
+            )
+            .await?;
+            self.update_transfer_status(&record.id, &record.status, record.bytes_transferred)
+                .await?;
+            merged += 1;
+        }
+        Ok(merged)
+    }
+
+    /// Transfers whose file name or note contains `query` (case-insensitive)
+    pub async fn search_transfers(
+        &self,
+        query: &str,
+        limit: i32,
+    ) -> Result<Vec<TransferRecord>, sqlx::Error> {
+        let escaped = query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let pattern = format!("%{}%", escaped);
+        sqlx::query_as::<_, TransferRecord>(&format!(
+            r#"
+            SELECT {}
+            FROM transfers
+            WHERE file_name LIKE ?1 ESCAPE '\' OR note LIKE ?1 ESCAPE '\'
+            ORDER BY created_at DESC
+            LIMIT ?2
+            "#,
+            TRANSFER_COLUMNS
+        ))
+        .bind(pattern)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Set or clear (`None`) a transfer's note. Returns false if there's no such transfer.
+    pub async fn set_transfer_note(
+        &self,
+        id: &str,
+        note: Option<&str>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("UPDATE transfers SET note = ? WHERE id = ?")
+            .bind(note)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Store the receiver's signed receipt (JSON) for a completed send
+    pub async fn set_transfer_receipt(&self, id: &str, receipt: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE transfers SET receipt = ? WHERE id = ?")
+            .bind(receipt)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark a verified chunk as written for resumable receiving
+    pub async fn record_chunk(
+        &self,
+        transfer_id: &str,
+        chunk_index: u64,
+        chunk_hash: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO transfer_chunks (transfer_id, chunk_index, received, chunk_hash)
+            VALUES (?, ?, TRUE, ?)
+            "#,
+        )
+        .bind(transfer_id)
+        .bind(chunk_index as i64)
+        .bind(chunk_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Hashes of the chunks recorded for a transfer, in ascending index
+    /// order; `None` for chunks recorded before hashes were kept
+    pub async fn get_chunk_hashes(
+        &self,
+        transfer_id: &str,
+    ) -> Result<Vec<(u64, Option<String>)>, sqlx::Error> {
+        let rows: Vec<(i64, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT chunk_index, chunk_hash FROM transfer_chunks
+            WHERE transfer_id = ? AND received = TRUE
+            ORDER BY chunk_index ASC
+            "#,
+        )
+        .bind(transfer_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(index, hash)| (index as u64, hash))
+            .collect())
+    }
+
+    /// Remember the merkle root a transfer was offered with
+    pub async fn set_merkle_root(&self, transfer_id: &str, root: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE transfers SET merkle_root = ? WHERE id = ?")
+            .bind(root)
+            .bind(transfer_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_merkle_root(&self, transfer_id: &str) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar::<_, Option<String>>("SELECT merkle_root FROM transfers WHERE id = ?")
+            .bind(transfer_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map(Option::flatten)
+    }
+
+    /// Where unfinished receives keep their data, so it isn't cleaned up
+    /// while offering the transfer again could still resume it
+    pub async fn get_resumable_paths(&self) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar(
+            r#"
+            SELECT file_path FROM transfers
+            WHERE direction = 'receive'
+              AND status IN ('pending', 'in_progress', 'paused', 'interrupted', 'failed')
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn clear_chunks(&self, transfer_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM transfer_chunks WHERE transfer_id = ?")
+            .bind(transfer_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Queue `relative_path` for delivery to `device_id`. A newer change to a
+    /// queued or failed file starts over with a fresh set of attempts.
+    pub async fn enqueue_sync(
+        &self,
+        device_id: &str,
+        relative_path: &str,
+    ) -> Result<(), sqlx::Error> {
+        let now = Utc::now().timestamp();
+        sqlx::query(
+            r#"
+            INSERT INTO sync_queue (device_id, relative_path, status, attempts, next_attempt_at, last_error, updated_at)
+            VALUES (?, ?, 'pending', 0, ?, NULL, ?)
+            ON CONFLICT(device_id, relative_path) DO UPDATE SET
+                status = 'pending',
+                attempts = 0,
+                next_attempt_at = excluded.next_attempt_at,
+                last_error = NULL,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(device_id)
+        .bind(relative_path)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Pending entries whose next attempt is due, oldest first
+    pub async fn get_due_syncs(&self, limit: i32) -> Result<Vec<SyncQueueEntry>, sqlx::Error> {
+        sqlx::query_as::<_, SyncQueueEntry>(
+            r#"
+            SELECT * FROM sync_queue
+            WHERE status = 'pending' AND next_attempt_at <= ?
+            ORDER BY next_attempt_at ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(Utc::now().timestamp())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Entries moved to the dead-letter list after running out of attempts
+    pub async fn get_failed_syncs(&self) -> Result<Vec<SyncQueueEntry>, sqlx::Error> {
+        sqlx::query_as::<_, SyncQueueEntry>(
+            "SELECT * FROM sync_queue WHERE status = 'failed' ORDER BY updated_at DESC",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Drop a delivered entry. Like `fail_sync`, this leaves the entry alone if
+    /// the file changed again (was re-enqueued) since `entry` was read.
+    pub async fn complete_sync(&self, entry: &SyncQueueEntry) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "DELETE FROM sync_queue WHERE device_id = ? AND relative_path = ? AND updated_at = ?",
+        )
+        .bind(&entry.device_id)
+        .bind(&entry.relative_path)
+        .bind(entry.updated_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Record a failed delivery. With a `retry_at` the entry stays pending
+    /// until then; without one it moves to the dead-letter list.
+    pub async fn fail_sync(
+        &self,
+        entry: &SyncQueueEntry,
+        error: &str,
+        retry_at: Option<i64>,
+    ) -> Result<(), sqlx::Error> {
+        let now = Utc::now().timestamp();
+        sqlx::query(
+            r#"
+            UPDATE sync_queue
+            SET status = ?, attempts = attempts + 1, next_attempt_at = ?, last_error = ?, updated_at = ?
+            WHERE device_id = ? AND relative_path = ? AND updated_at = ?
+            "#,
+        )
+        .bind(if retry_at.is_some() { "pending" } else { "failed" })
+        .bind(retry_at.unwrap_or(now))
+        .bind(error)
+        .bind(now)
+        .bind(&entry.device_id)
+        .bind(&entry.relative_path)
+        .bind(entry.updated_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Move `device_id` to the top of the recents list, dropping the oldest
+    /// entries past `MAX_RECENT_DESTINATIONS`
+    pub async fn record_recent_destination(
+        &self,
+        device_id: &str,
+        device_name: &str,
+        ip: &str,
+        port: u16,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO recent_destinations (device_id, device_name, ip, port, last_used_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(device_id) DO UPDATE SET
+                device_name = excluded.device_name,
+                ip = excluded.ip,
+                port = excluded.port,
+                last_used_at = excluded.last_used_at
+            "#,
+        )
+        .bind(device_id)
+        .bind(device_name)
+        .bind(ip)
+        .bind(port as i64)
+        .bind(Utc::now().timestamp())
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM recent_destinations WHERE device_id NOT IN (
+                SELECT device_id FROM recent_destinations
+                ORDER BY last_used_at DESC
+                LIMIT ?
+            )
+            "#,
+        )
+        .bind(MAX_RECENT_DESTINATIONS)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Recently sent-to devices, most recent first
+    pub async fn get_recent_destinations(&self) -> Result<Vec<RecentDestination>, sqlx::Error> {
+        sqlx::query_as::<_, RecentDestination>(
+            "SELECT * FROM recent_destinations ORDER BY last_used_at DESC",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// One summary per device we have history with, most recently active first
+    pub async fn get_peer_summaries(&self) -> Result<Vec<PeerSummary>, sqlx::Error> {
+        sqlx::query_as::<_, PeerSummary>(
+            r#"
+            SELECT
+                t.device_id,
+                r.device_name,
+                COUNT(*) AS total_transfers,
+                SUM(t.status = 'completed') AS completed_transfers,
+                SUM(t.status = 'failed') AS failed_transfers,
+                COALESCE(SUM(CASE WHEN t.direction = 'receive' AND t.status = 'completed'
+                    THEN t.total_size END), 0) AS bytes_received,
+                COALESCE(SUM(CASE WHEN t.direction = 'send' AND t.status = 'completed'
+                    THEN t.total_size END), 0) AS bytes_sent,
+                MAX(t.updated_at) AS last_transfer_at,
+                CAST(SUM(t.status = 'completed') AS REAL)
+                    / NULLIF(SUM(t.status IN ('completed', 'failed', 'cancelled')), 0) AS success_rate
+            FROM transfers t
+            LEFT JOIN recent_destinations r ON r.device_id = t.device_id
+            GROUP BY t.device_id
+            ORDER BY last_transfer_at DESC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
     pub async fn clear_history(&self) -> Result<(), sqlx::Error> {
         sqlx::query("DELETE FROM transfers")
             .execute(&self.pool)
@@ -167,3 +889,82 @@ impl Database {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first release's schema and one of its in-progress receives
+    const V0_FIXTURE: &str = include_str!("fixtures/v0.sql");
+
+    #[tokio::test]
+    async fn a_database_from_before_versioning_is_migrated() {
+        let dir = std::env::temp_dir().join(format!("proxishare-db-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("proxishare.db");
+        {
+            let options =
+                SqliteConnectOptions::from_str(&format!("sqlite:{}?mode=rwc", path.display()))
+                    .unwrap();
+            let pool = SqlitePoolOptions::new()
+                .connect_with(options)
+                .await
+                .unwrap();
+            sqlx::query(V0_FIXTURE).execute(&pool).await.unwrap();
+            pool.close().await;
+        }
+
+        let db = Database::new(&path).await.unwrap();
+        assert_eq!(db.schema_version().await.unwrap(), schema::latest_version());
+
+        // The old row reads with defaults for every column added since
+        let record = db.get_transfer("old-1").await.unwrap().unwrap();
+        assert_eq!(record.file_name, "report.pdf");
+        assert_eq!(record.bytes_transferred, 1024);
+        assert_eq!(record.hash_algo, "blake3");
+        assert_eq!(record.remote_ip, None);
+        assert_eq!(record.note, None);
+        assert_eq!(db.get_chunk_hashes("old-1").await.unwrap(), vec![(0, None)]);
+
+        // The new columns and tables take writes
+        db.record_chunk("old-1", 1, "abc").await.unwrap();
+        db.set_merkle_root("old-1", "root").await.unwrap();
+        assert_eq!(
+            db.get_merkle_root("old-1").await.unwrap().as_deref(),
+            Some("root")
+        );
+        db.enqueue_sync("peer-1", "notes.txt").await.unwrap();
+
+        // Migrating again changes nothing
+        db.migrate().await.unwrap();
+        assert_eq!(db.schema_version().await.unwrap(), schema::latest_version());
+
+        db.pool.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_blake3_hashes_are_synced() {
+        let record = |hash_algo: &str| TransferRecord {
+            id: "t-1".into(),
+            device_id: "peer-1".into(),
+            device_name: None,
+            file_name: "report.pdf".into(),
+            file_path: "/tmp/report.pdf".into(),
+            total_size: 1024,
+            direction: "send".into(),
+            status: "completed".into(),
+            bytes_transferred: 1024,
+            file_hash: "abc".into(),
+            created_at: 0,
+            updated_at: 0,
+            remote_ip: Some("192.168.1.20".into()),
+            remote_port: Some(4000),
+            receipt: Some("{}".into()),
+            note: Some("handoff".into()),
+            hash_algo: hash_algo.into(),
+        };
+        assert_eq!(HistoryRecordWire::from(record("blake3")).file_hash, "abc");
+        assert_eq!(HistoryRecordWire::from(record("sha256")).file_hash, "");
+    }
+}
@@ -11,7 +11,13 @@ CREATE TABLE IF NOT EXISTS transfers (
     bytes_transferred INTEGER DEFAULT 0,
     file_hash TEXT NOT NULL,
     created_at INTEGER NOT NULL,
-    updated_at INTEGER NOT NULL
+    updated_at INTEGER NOT NULL,
+    remote_ip TEXT,
+    remote_port INTEGER,
+    receipt TEXT,
+    note TEXT, -- user annotation for organizing history
+    hash_algo TEXT NOT NULL DEFAULT 'blake3', -- algorithm of file_hash
+    merkle_root TEXT -- of the chunk hashes offered, to check a resume against
 );
 
 -- Chunk progress for resumable receiving
@@ -19,11 +25,93 @@ CREATE TABLE IF NOT EXISTS transfer_chunks (
     transfer_id TEXT NOT NULL,
     chunk_index INTEGER NOT NULL,
     received BOOLEAN DEFAULT TRUE,
+    chunk_hash TEXT, -- as verified on receipt, a leaf of the offer's merkle root
     PRIMARY KEY (transfer_id, chunk_index),
     FOREIGN KEY (transfer_id) REFERENCES transfers(id) ON DELETE CASCADE
 );
 
+-- Local changes waiting to be pushed to each sync peer. Entries that keep
+-- failing are parked with status 'failed' instead of being retried forever.
+CREATE TABLE IF NOT EXISTS sync_queue (
+    device_id TEXT NOT NULL,
+    relative_path TEXT NOT NULL,
+    status TEXT NOT NULL, -- 'pending' or 'failed'
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    last_error TEXT,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (device_id, relative_path)
+);
+
+-- Devices recently sent to, most recent first, for quick re-sending
+CREATE TABLE IF NOT EXISTS recent_destinations (
+    device_id TEXT PRIMARY KEY,
+    device_name TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    last_used_at INTEGER NOT NULL
+);
+
 -- Indices
 CREATE INDEX IF NOT EXISTS idx_transfers_status ON transfers(status);
 CREATE INDEX IF NOT EXISTS idx_transfers_device ON transfers(device_id);
 "#;
+
+/// Columns added after the initial schema, applied to existing databases
+/// as (table, column, definition)
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("transfers", "remote_ip", "TEXT"),
+    ("transfers", "remote_port", "INTEGER"),
+    ("transfers", "receipt", "TEXT"),
+    ("transfers", "note", "TEXT"),
+    ("transfers", "hash_algo", "TEXT NOT NULL DEFAULT 'blake3'"),
+];
+
+/// Applied versions, one row per migration
+pub const VERSION_TABLE: &str = r#"
+CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER PRIMARY KEY,
+    description TEXT NOT NULL,
+    applied_at INTEGER NOT NULL
+);
+"#;
+
+/// One step of the schema's history. Every step is idempotent, so a
+/// database from before versioning simply replays them all.
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+    /// (table, column, definition), added where missing
+    pub added_columns: &'static [(&'static str, &'static str, &'static str)],
+}
+
+/// Every migration in order; append to bump the schema version
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Initial tables",
+        sql: SCHEMA,
+        added_columns: &[],
+    },
+    Migration {
+        version: 2,
+        description: "Transfer address, receipt, note and hash algorithm",
+        sql: "",
+        added_columns: ADDED_COLUMNS,
+    },
+    Migration {
+        version: 3,
+        description: "Chunk hashes and merkle roots for resume checks",
+        sql: "",
+        added_columns: &[
+            ("transfer_chunks", "chunk_hash", "TEXT"),
+            ("transfers", "merkle_root", "TEXT"),
+        ],
+    },
+];
+
+/// Version of the newest migration
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
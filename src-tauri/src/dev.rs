@@ -0,0 +1,263 @@
+//! Development helpers, built only with the `dev-tools` feature
+
+use crate::crypto::encryption::CertificateManager;
+use crate::crypto::identity::DeviceIdentity;
+use crate::crypto::security::SecurityService;
+use crate::error::ProxiError;
+use crate::transfer::protocol::{self, MessageType};
+use crate::transfer::sender::{read_chunk, FileSender};
+use crate::transfer::TransferManager;
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long either side of the self-test waits for the other
+const ROUNDTRIP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of `pairing_roundtrip`
+#[derive(Serialize, Debug)]
+pub struct PairingRoundtrip {
+    pub requester_id: String,
+    pub responder_id: String,
+    pub elapsed_ms: u64,
+}
+
+/// Pair two throwaway devices with each other over loopback QUIC: the
+/// requester sends a signed `PairRequest`, the responder verifies and
+/// confirms it with a `PairResponse`, and each side trusts the other.
+/// Both trust stores are then reloaded from disk and must name exactly the
+/// other device. Nothing touches the app's own identity or trust store.
+pub async fn pairing_roundtrip() -> Result<PairingRoundtrip, ProxiError> {
+    let root = std::env::temp_dir().join(format!("proxishare-pairing-{}", uuid::Uuid::new_v4()));
+    let result = tokio::time::timeout(ROUNDTRIP_TIMEOUT, run_roundtrip(&root))
+        .await
+        .unwrap_or_else(|_| Err(ProxiError::Other("Pairing self-test timed out".into())));
+    let _ = std::fs::remove_dir_all(&root);
+    result
+}
+
+async fn run_roundtrip(root: &Path) -> Result<PairingRoundtrip, ProxiError> {
+    let started = Instant::now();
+    let requester_dir = root.join("requester");
+    let responder_dir = root.join("responder");
+    std::fs::create_dir_all(&requester_dir)?;
+    std::fs::create_dir_all(&responder_dir)?;
+
+    let requester = DeviceIdentity::load_or_generate(&requester_dir)?;
+    let responder = DeviceIdentity::load_or_generate(&responder_dir)?;
+    let cert_manager = CertificateManager::generate_self_signed(1)?;
+
+    let server = quinn::Endpoint::server(
+        TransferManager::server_config(&cert_manager)?,
+        "127.0.0.1:0".parse()?,
+    )?;
+    let server_addr = server.local_addr()?;
+    let mut client = quinn::Endpoint::client("127.0.0.1:0".parse()?)?;
+    client.set_default_client_config(TransferManager::client_config(&cert_manager)?);
+
+    let responder_store = responder_dir.clone();
+    let responder_task = tokio::spawn(async move {
+        let connection = server
+            .accept()
+            .await
+            .ok_or_else(|| ProxiError::Network("Self-test endpoint closed".into()))?
+            .await?;
+        respond(&connection, &responder, &responder_store).await
+    });
+
+    let connection = client.connect(server_addr, "proxishare.local")?.await?;
+    let confirmed = request(&connection, &requester, &requester_dir).await;
+    connection.close(quinn::VarInt::from_u32(0), b"self-test complete");
+    let confirmed_id = confirmed?;
+    responder_task
+        .await
+        .map_err(|e| ProxiError::Other(format!("Responder task failed: {}", e)))??;
+
+    let requester_id = requester.device_id().to_string();
+    let responder_id = confirmed_id;
+    expect_only_trusted(&requester_dir, "requester", &responder_id)?;
+    expect_only_trusted(&responder_dir, "responder", &requester_id)?;
+
+    println!(
+        "[Pairing] Self-test paired {} with {} in {:?}",
+        requester_id,
+        responder_id,
+        started.elapsed()
+    );
+    Ok(PairingRoundtrip {
+        requester_id,
+        responder_id,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+/// Requester side: send the request and trust the peer once it confirms.
+/// Returns the id the responder confirmed with.
+async fn request(
+    connection: &quinn::Connection,
+    identity: &DeviceIdentity,
+    app_dir: &Path,
+) -> Result<String, ProxiError> {
+    let (mut send, mut recv) = protocol::open_stream(connection).await?;
+    FileSender::write_message(
+        &mut send,
+        &MessageType::hello(identity, "Self-test requester", connection)?,
+    )
+    .await?;
+    FileSender::write_message(
+        &mut send,
+        &MessageType::pair_request(identity, "Self-test requester", "000000"),
+    )
+    .await?;
+    send.finish()?;
+
+    match protocol::read_frame(&mut recv).await? {
+        MessageType::PairResponse {
+            accepted: true,
+            device_id,
+        } => {
+            SecurityService::new(app_dir.to_path_buf())
+                .add_trusted(device_id.clone())
+                .map_err(|e| ProxiError::Other(e.to_string()))?;
+            Ok(device_id)
+        }
+        MessageType::PairResponse {
+            accepted: false, ..
+        } => Err(ProxiError::Rejected(
+            "Responder declined the pairing".into(),
+        )),
+        other => Err(ProxiError::Protocol(format!(
+            "Expected PairResponse, got {:?}",
+            other
+        ))),
+    }
+}
+
+/// Responder side: check the request's signature, trust the requester and
+/// confirm, as accepting the `pairing-request` prompt would
+async fn respond(
+    connection: &quinn::Connection,
+    identity: &DeviceIdentity,
+    app_dir: &Path,
+) -> Result<(), ProxiError> {
+    let (mut send, mut recv) = protocol::accept_stream(connection).await?;
+    loop {
+        match protocol::read_frame(&mut recv).await? {
+            MessageType::Hello { .. } => continue,
+            MessageType::PairRequest {
+                device_id,
+                device_name,
+                pairing_code,
+                public_key,
+                signature,
+            } => {
+                let accepted = protocol::verify_pair_request(
+                    &device_id,
+                    &device_name,
+                    &pairing_code,
+                    &public_key,
+                    &signature,
+                );
+                if accepted {
+                    SecurityService::new(app_dir.to_path_buf())
+                        .add_trusted(device_id)
+                        .map_err(|e| ProxiError::Other(e.to_string()))?;
+                }
+                FileSender::write_message(
+                    &mut send,
+                    &MessageType::PairResponse {
+                        accepted,
+                        device_id: identity.device_id().to_string(),
+                    },
+                )
+                .await?;
+                send.finish()?;
+                // Stay connected until the requester has read the response and hangs up
+                connection.closed().await;
+                return Ok(());
+            }
+            other => {
+                return Err(ProxiError::Protocol(format!(
+                    "Expected PairRequest, got {:?}",
+                    other
+                )))
+            }
+        }
+    }
+}
+
+/// Reload the trust store in `app_dir` from disk and check it trusts
+/// exactly `expected`
+fn expect_only_trusted(app_dir: &Path, side: &str, expected: &str) -> Result<(), ProxiError> {
+    let store = SecurityService::new(app_dir.to_path_buf());
+    if store.trusted_devices.len() != 1 || !store.is_trusted(expected) {
+        return Err(ProxiError::Other(format!(
+            "The {}'s trust store holds {:?}, expected only {}",
+            side,
+            store.trusted_devices.keys().collect::<Vec<_>>(),
+            expected
+        )));
+    }
+    Ok(())
+}
+
+/// Outcome of `read_throughput`, in megabytes per second
+#[derive(Serialize, Debug)]
+pub struct ReadThroughput {
+    pub bytes: u64,
+    pub chunk_size: usize,
+    pub read_buffer: usize,
+    pub direct_mbps: f64,
+    pub buffered_mbps: f64,
+}
+
+/// Read `path` in `chunk_size` chunks the way a send does, once straight
+/// from the file and once through a `read_buffer` sized `BufReader`. The
+/// file is read once beforehand so both passes start from the page cache.
+pub async fn read_throughput(
+    path: &Path,
+    chunk_size: usize,
+    read_buffer: usize,
+) -> Result<ReadThroughput, ProxiError> {
+    let chunk_size = chunk_size.max(1);
+    let bytes = read_all(tokio::fs::File::open(path).await?, chunk_size).await?;
+
+    let started = Instant::now();
+    read_all(tokio::fs::File::open(path).await?, chunk_size).await?;
+    let direct = started.elapsed();
+
+    let started = Instant::now();
+    let file = tokio::fs::File::open(path).await?;
+    read_all(
+        tokio::io::BufReader::with_capacity(read_buffer, file),
+        chunk_size,
+    )
+    .await?;
+    let buffered = started.elapsed();
+
+    let mbps = |elapsed: Duration| bytes as f64 / 1_000_000.0 / elapsed.as_secs_f64().max(1e-9);
+    let result = ReadThroughput {
+        bytes,
+        chunk_size,
+        read_buffer,
+        direct_mbps: mbps(direct),
+        buffered_mbps: mbps(buffered),
+    };
+    println!("[Dev] Read throughput: {:?}", result);
+    Ok(result)
+}
+
+async fn read_all<R: tokio::io::AsyncRead + Unpin>(
+    mut reader: R,
+    chunk_size: usize,
+) -> std::io::Result<u64> {
+    let mut buffer = vec![0u8; chunk_size];
+    let mut total = 0;
+    loop {
+        let n = read_chunk(&mut reader, &mut buffer).await?;
+        if n == 0 {
+            return Ok(total);
+        }
+        total += n as u64;
+    }
+}
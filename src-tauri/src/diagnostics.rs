@@ -0,0 +1,101 @@
+use crate::db::TransferRecord;
+use crate::discovery::mdns::NetworkDiagnostics;
+use crate::settings::Settings;
+use serde::Serialize;
+use std::path::Path;
+
+/// Transfer records included in a bundle, newest first
+pub const BUNDLE_TRANSFER_LIMIT: i32 = 50;
+
+/// Which services came up, for telling "nothing works" apart from one
+/// broken piece
+#[derive(Serialize, Debug)]
+pub struct ServiceHealth {
+    pub app_version: String,
+    pub protocol_version: u32,
+    pub os: String,
+    pub discovery_running: bool,
+    pub transfer_running: bool,
+    pub database_open: bool,
+    /// Why secure transport failed to start, if it did
+    pub transport_error: Option<String>,
+    pub discovered_devices: usize,
+    pub trusted_devices: usize,
+    pub active_transfers: usize,
+    pub sync_folder_configured: bool,
+    pub failed_syncs: usize,
+}
+
+/// Everything written by `write_bundle`, gathered by the caller
+pub struct Bundle {
+    pub network: NetworkDiagnostics,
+    pub health: ServiceHealth,
+    pub settings: Settings,
+    pub transfers: Vec<TransferRecord>,
+}
+
+/// Stable stand-in for a device id, so transfers with one peer can still be
+/// grouped without revealing which device it is
+pub fn redact_id(device_id: &str) -> String {
+    let hash = blake3::hash(device_id.as_bytes()).to_hex();
+    format!("device-{}", &hash[..12])
+}
+
+/// Settings with the passphrase keys and device ids removed
+fn sanitize_settings(mut settings: Settings) -> Settings {
+    if settings.passphrase_key.is_some() {
+        settings.passphrase_key = Some("<redacted>".to_string());
+    }
+    for key in settings.peer_passphrase_keys.values_mut() {
+        *key = "<redacted>".to_string();
+    }
+    settings.device_rate_limits = settings
+        .device_rate_limits
+        .into_iter()
+        .map(|(id, limit)| (redact_id(&id), limit))
+        .collect();
+    settings
+}
+
+/// Transfer records with device ids hashed and local paths and receipts
+/// (which carry the receiver's id and key) dropped
+fn sanitize_transfers(transfers: Vec<TransferRecord>) -> Vec<TransferRecord> {
+    transfers
+        .into_iter()
+        .map(|mut record| {
+            record.device_id = redact_id(&record.device_id);
+            record.device_name = None;
+            record.file_path = String::new();
+            record.receipt = None;
+            record
+        })
+        .collect()
+}
+
+/// Write the bundle into `dir` as one JSON file per section. Logs go to
+/// stdout and aren't kept by the app, so they have to be attached separately.
+pub fn write_bundle(dir: &Path, bundle: Bundle) -> Result<(), crate::GenericError> {
+    std::fs::create_dir_all(dir)?;
+    write_json(&dir.join("network.json"), &bundle.network)?;
+    write_json(&dir.join("health.json"), &bundle.health)?;
+    write_json(
+        &dir.join("settings.json"),
+        &sanitize_settings(bundle.settings),
+    )?;
+    write_json(
+        &dir.join("transfers.json"),
+        &sanitize_transfers(bundle.transfers),
+    )?;
+    std::fs::write(
+        dir.join("README.txt"),
+        "ProxiShare diagnostics bundle. Device ids are hashed; the passphrase key, \
+         local file paths and receipts are removed.\n\
+         Console logs aren't stored by the app; run it from a terminal to capture them.\n",
+    )?;
+    Ok(())
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), crate::GenericError> {
+    std::fs::write(path, serde_json::to_string_pretty(value)?)?;
+    Ok(())
+}
@@ -1,15 +1,26 @@
+use crate::discovery::scoreboard::IpScoreboard;
+use crate::error::ProxiError;
+use crate::events::{emit_event, DeviceDiscovered, DeviceLost, DuplicateDeviceId};
 use chrono::Utc;
 use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
-use std::collections::HashMap;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
+use tauri::async_runtime::JoinHandle;
+use tauri::AppHandle;
 use tokio::sync::RwLock;
 
 /// How long before a device is considered stale (5 minutes)
 /// mDNS doesn't continuously announce, so we need a longer timeout
 const DEVICE_TIMEOUT_SECS: i64 = 300;
 
+/// mDNS service type we advertise and browse for
+const SERVICE_TYPE: &str = "_proxishare._tcp.local.";
+
 /// How often to re-query for devices (seconds)
 const REQUERY_INTERVAL_SECS: u64 = 30;
 
@@ -22,14 +33,41 @@ pub struct Device {
     pub all_ips: Vec<String>,
     pub port: u16,
     pub last_seen: i64,
+    /// Added by hand rather than discovered, so it doesn't expire
+    #[serde(default)]
+    pub manual: bool,
+}
+
+/// Whether we're advertising and browsing, for a status indicator
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct DiscoveryState {
+    pub broadcasting: bool,
+    pub discovering: bool,
+    /// Full mDNS name of our service while broadcasting
+    pub registered_service_name: Option<String>,
+    pub discovered_count: usize,
 }
 
 pub struct DiscoveryService {
     device_id: String,
     device_name: String,
     port: u16,
+    app_handle: AppHandle,
     mdns: ServiceDaemon,
     discovered_devices: Arc<RwLock<HashMap<String, Device>>>,
+    /// Full name of our registered mDNS service, if broadcasting
+    registered_service: Arc<Mutex<Option<String>>>,
+    /// Whether we advertise ourselves at all; browsing is unaffected
+    broadcasting_enabled: AtomicBool,
+    /// Local IP advertised first, for machines where the first interface isn't
+    /// the one peers can reach
+    preferred_ip: Mutex<Option<String>>,
+    /// Event loop and cleanup tasks of the running discovery, if any
+    discovery_tasks: Mutex<Vec<JoinHandle<()>>>,
+    /// Whether we're browsing, set by `start_discovery` and cleared when it stops
+    discovering: AtomicBool,
+    /// Which of each device's addresses connected recently
+    ip_scores: Mutex<IpScoreboard>,
 }
 
 impl DiscoveryService {
@@ -37,24 +75,51 @@ impl DiscoveryService {
         device_id: String,
         device_name: String,
         port: u16,
-    ) -> Result<Self, crate::GenericError> {
+        app_handle: AppHandle,
+        app_dir: &Path,
+    ) -> Result<Self, ProxiError> {
         let mdns = ServiceDaemon::new()?;
 
         Ok(Self {
             device_id,
             device_name,
             port,
+            app_handle,
             mdns,
             discovered_devices: Arc::new(RwLock::new(HashMap::new())),
+            registered_service: Arc::new(Mutex::new(None)),
+            broadcasting_enabled: AtomicBool::new(true),
+            preferred_ip: Mutex::new(None),
+            discovery_tasks: Mutex::new(Vec::new()),
+            discovering: AtomicBool::new(false),
+            ip_scores: Mutex::new(IpScoreboard::new(app_dir)),
         })
     }
 
-    pub fn start_broadcasting(&self) -> Result<(), crate::GenericError> {
-        let service_type = "_proxishare._tcp.local.";
+    /// Register our mDNS service, returning the addresses it advertises.
+    /// Does nothing while broadcasting is disabled.
+    pub fn start_broadcasting(&self) -> Result<Vec<String>, ProxiError> {
+        if !self.broadcasting_enabled.load(Ordering::SeqCst) {
+            println!("[mDNS] Broadcasting disabled, not registering service");
+            return Ok(Vec::new());
+        }
+        let service_type = SERVICE_TYPE;
         let instance_name = format!("{}_{}", self.device_name, &self.device_id[..8]);
 
-        // Get all local IPs to register with mDNS
-        let local_ips = get_local_ips();
+        // Get all local IPs to register with mDNS, the preferred one first
+        let mut local_ips = get_local_ips();
+        if let Some(preferred) = self.preferred_ip.lock().as_deref() {
+            match local_ips.iter().position(|ip| ip == preferred) {
+                Some(index) => {
+                    let ip = local_ips.remove(index);
+                    local_ips.insert(0, ip);
+                }
+                None => println!(
+                    "[mDNS] Preferred IP {} is not on any local interface, ignoring",
+                    preferred
+                ),
+            }
+        }
         let ip_str = local_ips.first().map(|s| s.as_str()).unwrap_or("");
 
         println!("[mDNS] Broadcasting on interfaces: {:?}", local_ips);
@@ -74,11 +139,58 @@ impl DiscoveryService {
             Some(properties),
         )?;
 
+        let fullname = service_info.get_fullname().to_string();
         self.mdns.register(service_info)?;
+        *self.registered_service.lock() = Some(fullname);
         println!(
             "[mDNS] Service registered: {} on port {}",
             instance_name, self.port
         );
+        Ok(local_ips)
+    }
+
+    /// Unregister and immediately re-register our service with the current
+    /// local IPs, e.g. after sleep/wake or a network change
+    pub fn reannounce(&self) -> Result<Vec<String>, ProxiError> {
+        self.stop_broadcasting()?;
+        self.start_broadcasting()
+    }
+
+    fn stop_broadcasting(&self) -> Result<(), ProxiError> {
+        let previous = self.registered_service.lock().take();
+        if let Some(fullname) = previous {
+            println!("[mDNS] Unregistering {}", fullname);
+            let _ = self.mdns.unregister(&fullname)?;
+        }
+        Ok(())
+    }
+
+    /// Advertise ourselves or stay invisible while still discovering others.
+    /// Returns the addresses now advertised (none when disabled).
+    pub fn set_broadcasting(&self, enabled: bool) -> Result<Vec<String>, ProxiError> {
+        self.broadcasting_enabled.store(enabled, Ordering::SeqCst);
+        if enabled {
+            self.reannounce()
+        } else {
+            self.stop_broadcasting()?;
+            Ok(Vec::new())
+        }
+    }
+
+    /// Advertise `ip` ahead of the other local addresses, or go back to the
+    /// default order with `None`. Takes effect on the next (re)announce.
+    pub fn set_preferred_ip(&self, ip: Option<String>) -> Result<(), ProxiError> {
+        if let Some(ip) = &ip {
+            let ip: IpAddr = ip.parse()?;
+            if !get_local_ips().contains(&ip.to_string()) {
+                return Err(ProxiError::Network(format!(
+                    "{} is not an address of a local interface",
+                    ip
+                )));
+            }
+        }
+        println!("[mDNS] Preferred broadcast IP set to {:?}", ip);
+        *self.preferred_ip.lock() = ip;
         Ok(())
     }
 
@@ -108,20 +220,29 @@ impl DiscoveryService {
         }
     }
 
-    pub fn start_discovery(&self) -> Result<(), crate::GenericError> {
-        let service_type = "_proxishare._tcp.local.";
-        let receiver = self.mdns.browse(service_type)?;
+    /// Browse for peers. Restarting replaces the running discovery rather
+    /// than adding a second set of tasks.
+    pub fn start_discovery(&self) -> Result<(), ProxiError> {
+        self.stop_browsing();
+        let receiver = self.mdns.browse(SERVICE_TYPE)?;
 
         println!(
             "[mDNS] Discovery started, listening for {} services",
-            service_type
+            SERVICE_TYPE
         );
 
         let discovered_devices = Arc::clone(&self.discovered_devices);
         let own_device_id = self.device_id.clone();
+        let own_device_name = self.device_name.clone();
+        let own_service = Arc::clone(&self.registered_service);
+        let app_handle = self.app_handle.clone();
 
-        tauri::async_runtime::spawn(async move {
+        let event_loop = tauri::async_runtime::spawn(async move {
             println!("[mDNS] Event loop started");
+            // Resolved services by full name as (id, name), to spot one id under several names
+            let mut services: HashMap<String, (String, String)> = HashMap::new();
+            // Services already reported, so re-queries don't repeat the warning
+            let mut reported: HashSet<String> = HashSet::new();
             loop {
                 match receiver.recv_async().await {
                     Ok(event) => {
@@ -131,14 +252,54 @@ impl DiscoveryService {
                                     .get_property_val_str("id")
                                     .unwrap_or("unknown")
                                     .to_string();
-                                if id == own_device_id {
-                                    continue;
-                                }
-
                                 let name = info
                                     .get_property_val_str("name")
                                     .unwrap_or("Unknown Device")
                                     .to_string();
+                                let fullname = info.get_fullname().to_string();
+
+                                if id == own_device_id {
+                                    // Our own announcement echoed back is expected; anything
+                                    // else with our id is a copied install
+                                    let is_ours =
+                                        own_service.lock().as_deref() == Some(fullname.as_str());
+                                    if !is_ours && reported.insert(fullname) {
+                                        println!(
+                                            "[mDNS] Another device ({}) is using our device id {}",
+                                            name, id
+                                        );
+                                        emit_event(
+                                            &app_handle,
+                                            DuplicateDeviceId {
+                                                device_id: id,
+                                                names: vec![own_device_name.clone(), name],
+                                                is_own_id: true,
+                                            },
+                                        );
+                                    }
+                                    continue;
+                                }
+
+                                services.insert(fullname.clone(), (id.clone(), name.clone()));
+                                let names: Vec<String> = services
+                                    .values()
+                                    .filter(|(other_id, _)| *other_id == id)
+                                    .map(|(_, other_name)| other_name.clone())
+                                    .collect();
+                                if names.len() > 1 && reported.insert(fullname) {
+                                    println!(
+                                        "[mDNS] Device id {} is advertised by {:?}",
+                                        id, names
+                                    );
+                                    emit_event(
+                                        &app_handle,
+                                        DuplicateDeviceId {
+                                            device_id: id.clone(),
+                                            names,
+                                            is_own_id: false,
+                                        },
+                                    );
+                                }
 
                                 // Collect all IP addresses from mDNS response
                                 let mut all_ips: Vec<String> = info
@@ -170,21 +331,24 @@ impl DiscoveryService {
                                     name, id, all_ips
                                 );
 
+                                let device = Device {
+                                    id: id.clone(),
+                                    name,
+                                    ip,
+                                    all_ips,
+                                    port,
+                                    last_seen: Utc::now().timestamp(),
+                                    manual: false,
+                                };
                                 let mut devices = discovered_devices.write().await;
-                                devices.insert(
-                                    id.clone(),
-                                    Device {
-                                        id,
-                                        name,
-                                        ip,
-                                        all_ips,
-                                        port,
-                                        last_seen: Utc::now().timestamp(),
-                                    },
-                                );
+                                if devices.insert(id, device.clone()).is_none() {
+                                    emit_event(&app_handle, DeviceDiscovered { device });
+                                }
                             }
                             ServiceEvent::ServiceRemoved(_type, name) => {
                                 // Remove device when service is explicitly removed
+                                services.remove(&name);
+                                reported.remove(&name);
                                 let mut devices = discovered_devices.write().await;
                                 // Try to find and remove by matching the instance name prefix
                                 let id_to_remove: Option<String> = devices
@@ -211,7 +375,7 @@ impl DiscoveryService {
         // Start a background task to clean up stale devices and re-query
         let cleanup_devices = Arc::clone(&self.discovered_devices);
         let mdns_for_requery = self.mdns.clone();
-        tauri::async_runtime::spawn(async move {
+        let cleanup = tauri::async_runtime::spawn(async move {
             let mut requery_counter = 0u64;
             loop {
                 tokio::time::sleep(Duration::from_secs(10)).await;
@@ -221,7 +385,7 @@ impl DiscoveryService {
                 let mut devices = cleanup_devices.write().await;
                 let _before_count = devices.len();
                 devices.retain(|id, device| {
-                    let keep = now - device.last_seen < DEVICE_TIMEOUT_SECS;
+                    let keep = device.manual || now - device.last_seen < DEVICE_TIMEOUT_SECS;
                     if !keep {
                         println!(
                             "[mDNS] Removing stale device: {} (last seen {}s ago)",
@@ -239,48 +403,141 @@ impl DiscoveryService {
                     requery_counter = 0;
                     println!("[mDNS] Re-querying for devices...");
                     // Trigger a new query by browsing again (mdns-sd handles deduplication)
-                    let _ = mdns_for_requery.browse("_proxishare._tcp.local.");
+                    let _ = mdns_for_requery.browse(SERVICE_TYPE);
                 }
             }
         });
 
+        *self.discovery_tasks.lock() = vec![event_loop, cleanup];
+        self.discovering.store(true, Ordering::SeqCst);
         Ok(())
     }
 
+    /// Stop browsing and advertising. `start_discovery` can be called again afterwards.
+    pub async fn stop_discovery(&self) -> Result<(), ProxiError> {
+        self.stop_browsing();
+        self.stop_broadcasting()?;
+        self.discovered_devices.write().await.clear();
+        println!("[mDNS] Discovery stopped");
+        Ok(())
+    }
+
+    fn stop_browsing(&self) {
+        self.discovering.store(false, Ordering::SeqCst);
+        let tasks = std::mem::take(&mut *self.discovery_tasks.lock());
+        if tasks.is_empty() {
+            return;
+        }
+        for task in tasks {
+            task.abort();
+        }
+        let _ = self.mdns.stop_browse(SERVICE_TYPE);
+    }
+
     /// Test connectivity to a device by attempting a TCP connection
     pub async fn test_connectivity(&self, ip: &str, port: u16) -> bool {
         use std::net::SocketAddr;
-        let addr: SocketAddr = match format!("{}:{}", ip, port).parse() {
+        let addr: SocketAddr = match crate::transfer::socket_addr(ip, port) {
             Ok(a) => a,
             Err(_) => return false,
         };
 
-        match tokio::time::timeout(
-            Duration::from_millis(500),
-            tokio::net::TcpStream::connect(addr),
+        matches!(
+            tokio::time::timeout(
+                Duration::from_millis(500),
+                tokio::net::TcpStream::connect(addr),
+            )
+            .await,
+            Ok(Ok(_))
         )
-        .await
-        {
-            Ok(Ok(_)) => true,
-            _ => false,
-        }
     }
 
-    /// Find a reachable IP for a device from its list of addresses
+    /// Find a reachable IP for a device from its list of addresses, trying
+    /// the ones that connected recently first (the primary IP without history)
     pub async fn find_reachable_ip(&self, device: &Device) -> Option<String> {
-        // First try the primary IP
-        if self.test_connectivity(&device.ip, device.port).await {
-            return Some(device.ip.clone());
+        let mut candidates = vec![device.ip.clone()];
+        for ip in &device.all_ips {
+            if !candidates.contains(ip) {
+                candidates.push(ip.clone());
+            }
         }
+        self.first_reachable(&device.id, &candidates, device.port)
+            .await
+    }
 
-        // Try other IPs
-        for ip in &device.all_ips {
-            if ip != &device.ip && self.test_connectivity(ip, device.port).await {
-                return Some(ip.clone());
+    /// First of `candidates` that accepts a connection on `port`, best
+    /// scored first. Every probe's outcome is recorded in the scoreboard.
+    pub async fn first_reachable(
+        &self,
+        device_id: &str,
+        candidates: &[String],
+        port: u16,
+    ) -> Option<String> {
+        let ranked = self
+            .ip_scores
+            .lock()
+            .rank(device_id, candidates, Utc::now().timestamp());
+
+        let mut found = None;
+        for ip in ranked {
+            let reachable = self.test_connectivity(&ip, port).await;
+            self.ip_scores
+                .lock()
+                .record(device_id, &ip, reachable, Utc::now().timestamp());
+            if reachable {
+                found = Some(ip);
+                break;
             }
         }
+        self.ip_scores.lock().save();
+        found
+    }
+
+    /// Add a device that wasn't discovered over mDNS, e.g. one reached by
+    /// hostname. A later mDNS announcement from it replaces the entry.
+    pub async fn add_manual_device(&self, device: Device) {
+        println!(
+            "[mDNS] Adding manual device: {} ({}) at {}:{}",
+            device.name, device.id, device.ip, device.port
+        );
+        self.discovered_devices
+            .write()
+            .await
+            .insert(device.id.clone(), device);
+    }
 
-        None
+    /// Drop a device from the discovered list, e.g. one stuck on a stale
+    /// address, and emit `device-lost`. A device that is still announcing
+    /// comes back with fresh addresses on its next resolution. Returns
+    /// whether it was listed.
+    pub async fn forget_device(&self, device_id: &str) -> bool {
+        let removed = self.discovered_devices.write().await.remove(device_id);
+        let Some(device) = removed else {
+            return false;
+        };
+        println!(
+            "[mDNS] Forgot device {} ({}) at {}",
+            device.name, device.id, device.ip
+        );
+        emit_event(
+            &self.app_handle,
+            DeviceLost {
+                device_id: device.id,
+            },
+        );
+        true
+    }
+
+    /// What discovery is doing right now, as tracked by our own start and
+    /// stop calls rather than asked of the mDNS daemon
+    pub async fn state(&self) -> DiscoveryState {
+        let registered_service_name = self.registered_service.lock().clone();
+        DiscoveryState {
+            broadcasting: registered_service_name.is_some(),
+            discovering: self.discovering.load(Ordering::SeqCst),
+            registered_service_name,
+            discovered_count: self.discovered_devices.read().await.len(),
+        }
     }
 
     pub async fn get_devices(&self) -> Vec<Device> {
@@ -336,6 +593,11 @@ pub struct NetworkInterface {
     pub name: String,
     pub ip: String,
     pub is_loopback: bool,
+    /// Operational link state; `None` where the platform doesn't expose it
+    pub is_up: Option<bool>,
+    /// Best-effort guess whether this is a Wi-Fi interface
+    pub is_wireless: Option<bool>,
+    pub mtu: Option<u32>,
 }
 
 /// Get all network interfaces with their IP addresses
@@ -346,10 +608,14 @@ pub fn get_network_interfaces() -> Vec<NetworkInterface> {
         for iface in addrs {
             // Skip IPv6 for now, focus on IPv4 for discovery
             if let IpAddr::V4(ipv4) = iface.addr.ip() {
+                let link = link_info(&iface.name);
                 interfaces.push(NetworkInterface {
                     name: iface.name.clone(),
                     ip: ipv4.to_string(),
                     is_loopback: ipv4.is_loopback(),
+                    is_up: link.is_up,
+                    is_wireless: link.is_wireless,
+                    mtu: link.mtu,
                 });
             }
         }
@@ -358,6 +624,49 @@ pub fn get_network_interfaces() -> Vec<NetworkInterface> {
     interfaces
 }
 
+/// Link details the platform can tell us about an interface
+#[derive(Default)]
+struct LinkInfo {
+    is_up: Option<bool>,
+    is_wireless: Option<bool>,
+    mtu: Option<u32>,
+}
+
+#[cfg(target_os = "linux")]
+fn link_info(name: &str) -> LinkInfo {
+    let dir = std::path::Path::new("/sys/class/net").join(name);
+    let read = |file: &str| {
+        std::fs::read_to_string(dir.join(file))
+            .ok()
+            .map(|value| value.trim().to_string())
+    };
+
+    LinkInfo {
+        // "unknown" is common for loopback and tunnels, so don't guess there
+        is_up: read("operstate").and_then(|state| match state.as_str() {
+            "up" => Some(true),
+            "down" | "dormant" | "lowerlayerdown" | "notpresent" => Some(false),
+            _ => None,
+        }),
+        is_wireless: Some(dir.join("wireless").exists() || dir.join("phy80211").exists()),
+        mtu: read("mtu").and_then(|mtu| mtu.parse().ok()),
+    }
+}
+
+/// Without sysfs only the name hints at the link type ("Wi-Fi" on Windows,
+/// "awdl" for Apple's wireless direct link)
+#[cfg(not(target_os = "linux"))]
+fn link_info(name: &str) -> LinkInfo {
+    let lower = name.to_lowercase();
+    let wireless = ["wi-fi", "wlan", "wireless", "awdl"]
+        .iter()
+        .any(|hint| lower.contains(hint));
+    LinkInfo {
+        is_wireless: wireless.then_some(true),
+        ..LinkInfo::default()
+    }
+}
+
 /// Get all local IPv4 addresses (non-loopback)
 pub fn get_local_ips() -> Vec<String> {
     get_network_interfaces()
@@ -1 +1,3 @@
 pub mod mdns;
+pub mod scoreboard;
+pub mod sweep;
@@ -0,0 +1,95 @@
+use crate::crypto::security::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Time for a score to lose half its weight, so an address that failed a
+/// while ago gets tried early again once its penalty has worn off
+const SCORE_HALF_LIFE_SECS: f64 = 24.0 * 60.0 * 60.0;
+/// Added by a successful probe
+const SUCCESS_WEIGHT: f64 = 1.0;
+/// Subtracted by a failed one
+const FAILURE_WEIGHT: f64 = 0.5;
+/// Scores this close to zero carry no information and are dropped
+const FORGET_BELOW: f64 = 0.01;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct IpScore {
+    score: f64,
+    /// When `score` was last updated (seconds since the Unix epoch)
+    updated_at: i64,
+}
+
+impl IpScore {
+    /// The score as of `now`, decayed toward zero
+    fn decayed(&self, now: i64) -> f64 {
+        let age = (now - self.updated_at).max(0) as f64;
+        self.score * 0.5f64.powf(age / SCORE_HALF_LIFE_SECS)
+    }
+}
+
+/// Per-device record of which addresses connected recently, persisted so
+/// `find_reachable_ip` can try the one most likely to work first
+pub struct IpScoreboard {
+    store_path: PathBuf,
+    devices: HashMap<String, HashMap<String, IpScore>>,
+}
+
+impl IpScoreboard {
+    pub fn new(app_dir: &Path) -> Self {
+        let store_path = app_dir.join("ip_scores.json");
+        let devices = fs::read_to_string(&store_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            store_path,
+            devices,
+        }
+    }
+
+    /// `candidates` best first. Unscored addresses count as zero, and ties
+    /// keep their given order, so with no history the order is unchanged.
+    pub fn rank(&self, device_id: &str, candidates: &[String], now: i64) -> Vec<String> {
+        let scores = self.devices.get(device_id);
+        let score = |ip: &String| {
+            scores
+                .and_then(|s| s.get(ip))
+                .map_or(0.0, |s| s.decayed(now))
+        };
+        let mut ranked = candidates.to_vec();
+        ranked.sort_by(|a, b| score(b).total_cmp(&score(a)));
+        ranked
+    }
+
+    /// Note the outcome of probing `ip`
+    pub fn record(&mut self, device_id: &str, ip: &str, reachable: bool, now: i64) {
+        let scores = self.devices.entry(device_id.to_string()).or_default();
+        let current = scores.get(ip).map_or(0.0, |s| s.decayed(now));
+        let delta = if reachable {
+            SUCCESS_WEIGHT
+        } else {
+            -FAILURE_WEIGHT
+        };
+        scores.insert(
+            ip.to_string(),
+            IpScore {
+                score: current + delta,
+                updated_at: now,
+            },
+        );
+        scores.retain(|_, s| s.decayed(now).abs() >= FORGET_BELOW);
+    }
+
+    pub fn save(&self) {
+        let result = serde_json::to_string(&self.devices)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                write_atomic(&self.store_path, content.as_bytes()).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            println!("[mDNS] Failed to save address scores: {}", e);
+        }
+    }
+}
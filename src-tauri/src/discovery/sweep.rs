@@ -0,0 +1,94 @@
+use crate::discovery::mdns::DiscoveryService;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Devices probed at the same time
+const SWEEP_CONCURRENCY: usize = 8;
+/// Longest a whole sweep may take; devices not done by then are `Unchecked`
+const SWEEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A trusted device and the addresses it was last seen at
+pub struct KnownDevice {
+    pub id: String,
+    pub name: Option<String>,
+    /// Best first, as `find_reachable_ip` would try them
+    pub addresses: Vec<String>,
+    pub port: u16,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reachability {
+    Reachable,
+    Offline,
+    /// The sweep ran out of time before probing it
+    Unchecked,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceReachability {
+    pub device_id: String,
+    pub name: Option<String>,
+    pub status: Reachability,
+    /// The address that answered, when reachable
+    pub address: Option<String>,
+    pub port: u16,
+}
+
+/// Probe every device in `devices` on each of its addresses, a few devices
+/// at a time. Results come back in the order of `devices`.
+pub async fn sweep(
+    discovery: Arc<DiscoveryService>,
+    devices: Vec<KnownDevice>,
+) -> Vec<DeviceReachability> {
+    let mut results: Vec<DeviceReachability> = devices
+        .iter()
+        .map(|device| DeviceReachability {
+            device_id: device.id.clone(),
+            name: device.name.clone(),
+            status: Reachability::Unchecked,
+            address: None,
+            port: device.port,
+        })
+        .collect();
+
+    let mut probes = stream::iter(devices.into_iter().enumerate())
+        .map(|(index, device)| {
+            let discovery = discovery.clone();
+            async move {
+                let found = if device.addresses.is_empty() {
+                    None
+                } else {
+                    discovery
+                        .first_reachable(&device.id, &device.addresses, device.port)
+                        .await
+                };
+                (index, found)
+            }
+        })
+        .buffer_unordered(SWEEP_CONCURRENCY);
+
+    let deadline = Instant::now() + SWEEP_TIMEOUT;
+    let mut checked = 0;
+    while let Ok(Some((index, found))) = tokio::time::timeout_at(deadline, probes.next()).await {
+        let result = &mut results[index];
+        result.status = if found.is_some() {
+            Reachability::Reachable
+        } else {
+            Reachability::Offline
+        };
+        result.address = found;
+        checked += 1;
+    }
+    if checked < results.len() {
+        println!(
+            "[mDNS] Reachability sweep timed out with {} of {} devices checked",
+            checked,
+            results.len()
+        );
+    }
+    results
+}
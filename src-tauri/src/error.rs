@@ -0,0 +1,216 @@
+use crate::transfer::protocol::{CLOSE_CANCELLED, CLOSE_PROTOCOL_ERROR, CLOSE_REJECTED};
+use std::fmt;
+
+/// Errors raised by the transfer and discovery layers.
+/// Tauri commands still hand these to the UI as strings via `to_string()`.
+#[derive(Debug)]
+pub enum ProxiError {
+    Io(std::io::Error),
+    /// Connecting to or exchanging data with a peer failed
+    Network(String),
+    /// Certificate, TLS or signature handling failed
+    Crypto(String),
+    Db(sqlx::Error),
+    /// A peer sent something malformed or unexpected
+    Protocol(String),
+    /// The transfer was cancelled by either side
+    Cancelled(String),
+    /// The receiver declined the offer, with the reason it gave
+    Rejected(String),
+    /// The peer went away mid-exchange: its connection was closed, reset or
+    /// timed out, e.g. because its app quit or crashed
+    Interrupted(String),
+    /// A completed transfer delivered a different number of bytes than offered
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    /// No bytes moved for this many seconds while the transfer was running
+    Stalled(u64),
+    /// The receiver's save directory went away mid-transfer (e.g. an
+    /// unplugged drive); its received chunks are kept for resuming
+    DiskUnavailable(String),
+    /// Errors not classified more precisely yet
+    Other(String),
+}
+
+impl ProxiError {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, ProxiError::Cancelled(_))
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        matches!(self, ProxiError::Interrupted(_))
+    }
+
+    /// Failures a later attempt may get past: the connection broke, timed
+    /// out or stalled. Rejections, cancellations and corrupt data aren't.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ProxiError::Network(_) | ProxiError::Interrupted(_) | ProxiError::Stalled(_)
+        )
+    }
+}
+
+impl fmt::Display for ProxiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxiError::Io(e) => write!(f, "IO error: {}", e),
+            ProxiError::Network(msg) => write!(f, "Network error: {}", msg),
+            ProxiError::Crypto(msg) => write!(f, "Crypto error: {}", msg),
+            ProxiError::Db(e) => write!(f, "Database error: {}", e),
+            ProxiError::Protocol(msg) => write!(f, "Protocol error: {}", msg),
+            ProxiError::Rejected(reason) => write!(f, "Rejected by receiver: {}", reason),
+            ProxiError::Interrupted(msg) => write!(f, "Connection interrupted: {}", msg),
+            ProxiError::Stalled(secs) => {
+                write!(f, "Transfer stalled: no progress for {} seconds", secs)
+            }
+            ProxiError::DiskUnavailable(msg) => write!(f, "Disk unavailable: {}", msg),
+            ProxiError::SizeMismatch { expected, actual } => write!(
+                f,
+                "Size mismatch: expected {} bytes, received {}",
+                expected, actual
+            ),
+            ProxiError::Cancelled(msg) | ProxiError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProxiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProxiError::Io(e) => Some(e),
+            ProxiError::Db(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ProxiError {
+    fn from(e: std::io::Error) -> Self {
+        ProxiError::Io(e)
+    }
+}
+
+impl From<sqlx::Error> for ProxiError {
+    fn from(e: sqlx::Error) -> Self {
+        ProxiError::Db(e)
+    }
+}
+
+impl From<bincode::Error> for ProxiError {
+    fn from(e: bincode::Error) -> Self {
+        ProxiError::Protocol(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ProxiError {
+    fn from(e: serde_json::Error) -> Self {
+        ProxiError::Protocol(e.to_string())
+    }
+}
+
+/// Map network-level error types onto `ProxiError::Network`
+macro_rules! network_errors {
+    ($($ty:ty),* $(,)?) => {
+        $(impl From<$ty> for ProxiError {
+            fn from(e: $ty) -> Self {
+                ProxiError::Network(e.to_string())
+            }
+        })*
+    };
+}
+
+network_errors!(
+    quinn::ConnectError,
+    quinn::ClosedStream,
+    std::net::AddrParseError,
+    mdns_sd::Error,
+);
+
+/// A peer closing with `CLOSE_CANCELLED` cancelled the transfer; a peer that
+/// closed otherwise or stopped answering interrupted it. Anything else is a
+/// network failure.
+impl From<quinn::ConnectionError> for ProxiError {
+    fn from(e: quinn::ConnectionError) -> Self {
+        match &e {
+            quinn::ConnectionError::ApplicationClosed(close)
+                if close.error_code == quinn::VarInt::from_u32(CLOSE_CANCELLED) =>
+            {
+                ProxiError::Cancelled("Transfer cancelled by peer".into())
+            }
+            quinn::ConnectionError::ApplicationClosed(close)
+                if close.error_code == quinn::VarInt::from_u32(CLOSE_PROTOCOL_ERROR) =>
+            {
+                ProxiError::Protocol(format!(
+                    "Peer closed the connection after a protocol error: {}",
+                    String::from_utf8_lossy(&close.reason)
+                ))
+            }
+            quinn::ConnectionError::ApplicationClosed(close)
+                if close.error_code == quinn::VarInt::from_u32(CLOSE_REJECTED) =>
+            {
+                ProxiError::Rejected(String::from_utf8_lossy(&close.reason).into_owned())
+            }
+            quinn::ConnectionError::ApplicationClosed(_)
+            | quinn::ConnectionError::ConnectionClosed(_)
+            | quinn::ConnectionError::Reset
+            | quinn::ConnectionError::TimedOut => ProxiError::Interrupted(e.to_string()),
+            _ => ProxiError::Network(e.to_string()),
+        }
+    }
+}
+
+impl From<quinn::WriteError> for ProxiError {
+    fn from(e: quinn::WriteError) -> Self {
+        match e {
+            quinn::WriteError::ConnectionLost(e) => e.into(),
+            e => ProxiError::Network(e.to_string()),
+        }
+    }
+}
+
+impl From<quinn::ReadExactError> for ProxiError {
+    fn from(e: quinn::ReadExactError) -> Self {
+        match e {
+            quinn::ReadExactError::ReadError(quinn::ReadError::ConnectionLost(e)) => e.into(),
+            e => ProxiError::Network(e.to_string()),
+        }
+    }
+}
+
+/// Map TLS and certificate error types onto `ProxiError::Crypto`
+macro_rules! crypto_errors {
+    ($($ty:ty),* $(,)?) => {
+        $(impl From<$ty> for ProxiError {
+            fn from(e: $ty) -> Self {
+                ProxiError::Crypto(e.to_string())
+            }
+        })*
+    };
+}
+
+crypto_errors!(
+    rustls::Error,
+    rcgen::Error,
+    quinn::crypto::rustls::NoInitialCipherSuite,
+);
+
+impl From<String> for ProxiError {
+    fn from(msg: String) -> Self {
+        ProxiError::Other(msg)
+    }
+}
+
+impl From<&str> for ProxiError {
+    fn from(msg: &str) -> Self {
+        ProxiError::Other(msg.to_string())
+    }
+}
+
+impl From<crate::GenericError> for ProxiError {
+    fn from(e: crate::GenericError) -> Self {
+        ProxiError::Other(e.to_string())
+    }
+}
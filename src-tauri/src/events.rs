@@ -0,0 +1,293 @@
+//! Every event the backend emits, with its payload. The frontend listens
+//! for these names and fields, so changing a payload changes its contract;
+//! `src/events.ts` mirrors them.
+
+use crate::discovery::mdns::Device;
+use crate::transfer::history::HistorySyncSummary;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+/// A payload and the name it's emitted under
+pub trait Event: Serialize + Clone {
+    const NAME: &'static str;
+}
+
+macro_rules! event {
+    ($payload:ty, $name:literal) => {
+        impl Event for $payload {
+            const NAME: &'static str = $name;
+        }
+    };
+}
+
+/// Emit `event` to every window. Failures only mean no window could take
+/// it, so they're logged rather than returned.
+pub fn emit_event<E: Event>(app_handle: &AppHandle, event: E) {
+    if let Err(e) = app_handle.emit(E::NAME, event) {
+        println!("[Events] Failed to emit {}: {}", E::NAME, e);
+    }
+}
+
+/// A device showed up on mDNS that wasn't in the discovered list
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceDiscovered {
+    #[serde(flatten)]
+    pub device: Device,
+}
+event!(DeviceDiscovered, "device-discovered");
+
+/// A device was removed from the discovered list
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceLost {
+    pub device_id: String,
+}
+event!(DeviceLost, "device-lost");
+
+/// Another service advertises the same device id
+#[derive(Serialize, Debug, Clone)]
+pub struct DuplicateDeviceId {
+    pub device_id: String,
+    /// Names of every service advertising `device_id`, ours included when it's our id
+    pub names: Vec<String>,
+    /// Whether the id is ours, i.e. another install copied this one
+    pub is_own_id: bool,
+}
+event!(DuplicateDeviceId, "duplicate-device-id");
+
+/// Progress of one transfer in either direction
+#[derive(Serialize, Debug, Clone)]
+pub struct TransferProgress {
+    pub transfer_id: String,
+    pub file_name: String,
+    pub bytes_sent: u64,
+    pub total_bytes: u64,
+    pub direction: String,
+    pub status: String,
+    /// Set when the transfer is part of a multi-file batch
+    pub batch_id: Option<String>,
+    /// Chunk size the transfer uses, in bytes
+    pub chunk_size: u32,
+}
+event!(TransferProgress, "transfer-progress");
+
+/// A send failed on the network and will be offered again after `delay_secs`,
+/// resuming where the receiver kept its chunks
+#[derive(Serialize, Debug, Clone)]
+pub struct TransferRetrying {
+    pub transfer_id: String,
+    /// Which retry this is, from 1
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub delay_secs: u64,
+    pub error: String,
+}
+event!(TransferRetrying, "transfer-retrying");
+
+/// One chunk sent or received, only while verbose progress is on
+#[derive(Serialize, Debug, Clone)]
+pub struct ChunkProgress {
+    pub transfer_id: String,
+    pub direction: &'static str,
+    pub chunk_index: u64,
+    pub size: usize,
+    pub hash: String,
+    /// Whether the hash matched the data; `None` on the sending side
+    pub verified: Option<bool>,
+}
+event!(ChunkProgress, "chunk-progress");
+
+/// The sender dropped mid-file; its chunks are kept so it can be resumed
+#[derive(Serialize, Debug, Clone)]
+pub struct TransferInterrupted {
+    pub transfer_id: String,
+    pub device_id: Option<String>,
+    pub bytes_received: u64,
+    pub reason: String,
+}
+event!(TransferInterrupted, "transfer-interrupted");
+
+/// Outcome of a batch once every member transfer has resolved
+#[derive(Serialize, Debug, Clone)]
+pub struct BatchSummary {
+    pub batch_id: String,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+}
+event!(BatchSummary, "batch-complete");
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BatchCancelled {
+    pub batch_id: String,
+    pub cancelled: usize,
+}
+event!(BatchCancelled, "batch-cancelled");
+
+/// Running count of a directory send while its walk is still finding files;
+/// the batch's total is only known once `done`
+#[derive(Serialize, Debug, Clone)]
+pub struct DirectoryProgress {
+    pub batch_id: String,
+    pub files_found: usize,
+    pub bytes_found: u64,
+    pub done: bool,
+}
+event!(DirectoryProgress, "directory-progress");
+
+/// Running count of an `analyze_folder` walk of a large tree
+#[derive(Serialize, Debug, Clone)]
+pub struct FolderAnalysisProgress {
+    pub path: String,
+    pub files_found: usize,
+    pub bytes_found: u64,
+}
+event!(FolderAnalysisProgress, "folder-analysis-progress");
+
+/// The transfer history changed; reload it
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoryUpdated;
+event!(HistoryUpdated, "history-updated");
+
+/// Files are waiting in `take_pending_sends`
+#[derive(Serialize, Debug, Clone)]
+pub struct PendingSend;
+event!(PendingSend, "pending-send");
+
+/// Progress of a history sync with one peer
+#[derive(Serialize, Debug, Clone)]
+pub struct HistorySyncProgress {
+    pub device_id: String,
+    /// Records we're going to send
+    pub to_send: usize,
+    #[serde(flatten)]
+    pub summary: HistorySyncSummary,
+    pub done: bool,
+}
+event!(HistorySyncProgress, "history-sync-progress");
+
+/// Progress of hashing a sync folder
+#[derive(Serialize, Debug, Clone)]
+pub struct ScanProgress {
+    pub folder: String,
+    pub files_hashed: usize,
+    pub files_total: usize,
+    pub bytes_hashed: u64,
+    pub bytes_total: u64,
+}
+event!(ScanProgress, "sync-scan-progress");
+
+/// A queued sync send failed too often and was given up on
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncFailed {
+    pub device_id: String,
+    pub relative_path: String,
+    pub error: String,
+}
+event!(SyncFailed, "sync-failed");
+
+/// A peer sent a signed `PairRequest`
+#[derive(Serialize, Debug, Clone)]
+pub struct PairingRequest {
+    pub device: PairingDevice,
+    pub code: String,
+    /// Whether the device id is the one its key derives
+    pub key_verified: bool,
+    pub ip: String,
+    pub port: u16,
+}
+event!(PairingRequest, "pairing-request");
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PairingDevice {
+    pub id: String,
+    pub name: String,
+}
+
+/// A peer identified itself with a signed `Hello`
+#[derive(Serialize, Debug, Clone)]
+pub struct PeerConnected {
+    pub device_id: String,
+    pub device_name: String,
+    pub ip: String,
+    pub trusted: bool,
+    pub key_verified: bool,
+}
+event!(PeerConnected, "peer-connected");
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PeerDisconnected {
+    pub device_id: String,
+    pub device_name: String,
+    pub ip: String,
+}
+event!(PeerDisconnected, "peer-disconnected");
+
+/// A device completed a transfer to us and may be trusted with
+/// `accept_transfer_trust`
+#[derive(Serialize, Debug, Clone)]
+pub struct TrustOffered {
+    pub device_id: String,
+    pub device_name: String,
+    pub transfer_id: String,
+}
+event!(TrustOffered, "trust-offered");
+
+/// Temporary trust ran out for these devices
+#[derive(Serialize, Debug, Clone)]
+pub struct TrustExpired {
+    pub device_ids: Vec<String>,
+}
+event!(TrustExpired, "trust-expired");
+
+/// Do not disturb was turned on or off
+#[derive(Serialize, Debug, Clone)]
+pub struct DndChanged {
+    pub enabled: bool,
+    /// Transfers it paused or resumed
+    pub transfers: usize,
+}
+event!(DndChanged, "dnd-changed");
+
+/// The save directory went away (e.g. an unplugged drive) during a receive
+#[derive(Serialize, Debug, Clone)]
+pub struct DownloadDirUnavailable {
+    pub transfer_id: String,
+    pub path: String,
+}
+event!(DownloadDirUnavailable, "download-dir-unavailable");
+
+/// A peer connected to us; it's closed again with `IncomingConnectionClosed`
+#[derive(Serialize, Debug, Clone)]
+pub struct IncomingConnection {
+    pub id: usize,
+    pub address: String,
+}
+event!(IncomingConnection, "incoming-connection");
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IncomingConnectionClosed {
+    pub id: usize,
+}
+event!(IncomingConnectionClosed, "incoming-connection-closed");
+
+/// The transfer listener stopped accepting and is being rebound
+#[derive(Serialize, Debug, Clone)]
+pub struct ListenerDown {
+    pub port: u16,
+}
+event!(ListenerDown, "listener-down");
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ListenerRecovered {
+    pub port: u16,
+    pub attempts: u32,
+}
+event!(ListenerRecovered, "listener-recovered");
+
+/// Rebinding keeps failing; receiving is unavailable until it succeeds
+#[derive(Serialize, Debug, Clone)]
+pub struct ListenerFailed {
+    pub port: u16,
+    pub error: String,
+}
+event!(ListenerFailed, "listener-failed");
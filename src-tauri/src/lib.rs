@@ -1,22 +1,49 @@
 pub mod crypto;
 pub mod db;
+#[cfg(feature = "dev-tools")]
+pub mod dev;
+pub mod diagnostics;
 pub mod discovery;
+pub mod error;
+pub mod events;
+pub mod settings;
 pub mod sync;
 pub mod transfer;
 
-use crate::db::{Database, TransferRecord};
+use crate::db::{Database, PeerSummary, RecentDestination, SyncQueueEntry, TransferRecord};
 use crate::discovery::mdns::{
-    get_network_interfaces, Device, DiscoveryService, NetworkDiagnostics, NetworkInterface,
+    get_network_interfaces, Device, DiscoveryService, DiscoveryState, NetworkDiagnostics,
+    NetworkInterface,
 };
-use crate::transfer::TransferManager;
+use crate::error::ProxiError;
+use crate::events::{
+    emit_event, BatchCancelled, BatchSummary, DirectoryProgress, DndChanged,
+    FolderAnalysisProgress, HistoryUpdated, PendingSend, TransferRetrying, TrustExpired,
+};
+use crate::transfer::hash_pool::HashPool;
+use crate::transfer::partials::OrphanedPartial;
+use crate::transfer::protocol::{HashAlgo, PeerCapabilities};
+use crate::transfer::rate_limit::RateLimiter;
+use crate::transfer::selection::{
+    select_files, stream_files, FolderAnalysis, SelectedFiles, Selection, MANIFEST_CHUNK,
+    WALK_BUFFER,
+};
+use crate::transfer::sender::{BenchmarkResult, SendPayload};
+use crate::transfer::{disk_space, ConnectionStats, DiskSpace, TransferManager};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tauri::Manager;
+use tauri_plugin_notification::NotificationExt;
 use tokio::sync::RwLock;
 
-use crate::crypto::security::SecurityService;
-use crate::sync::SyncState;
-use std::collections::HashMap;
+use crate::crypto::encryption::CertificateManager;
+use crate::crypto::identity::DeviceIdentity;
+use crate::crypto::security::{SecurityService, TrustImport};
+use crate::settings::SettingsStore;
+use crate::sync::validation::FolderValidation;
+use crate::sync::{SyncDirection, SyncState};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum TransferStatus {
@@ -27,7 +54,132 @@ pub enum TransferStatus {
     Failed,
 }
 
-pub type TransferRegistry = Arc<RwLock<HashMap<String, TransferStatus>>>;
+/// A transfer tracked in the registry, along with the peer it's exchanged with
+#[derive(Debug, Clone)]
+pub struct TransferEntry {
+    pub status: TransferStatus,
+    pub device_id: String,
+    /// Batch the transfer was offered in, if it's part of a multi-file send
+    pub batch_id: Option<String>,
+}
+
+pub type TransferRegistry = Arc<RwLock<HashMap<String, TransferEntry>>>;
+
+/// Transient "do not disturb" mode; never persisted
+#[derive(Debug, Default)]
+pub struct DoNotDisturb {
+    pub enabled: bool,
+    /// Transfers the mode paused, resumed when it ends. Ones paused by hand
+    /// beforehand stay paused.
+    paused: Vec<String>,
+}
+
+/// Database file inside the app data directory
+const DB_FILE_NAME: &str = "proxishare.db";
+/// How often expired temporary trust is pruned
+const TRUST_EXPIRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Where the app keeps its files, for "open folder" actions and bug reports
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AppPaths {
+    pub app_data_dir: String,
+    pub download_dir: String,
+    pub db_path: String,
+    pub trust_store_path: String,
+    pub sync_folder: Option<String>,
+}
+
+/// Called whenever a transfer finishes. If it was the last unresolved member of
+/// its batch, emits `batch-complete` once and detaches the members from the batch.
+pub async fn finish_batch_member(
+    app_handle: &tauri::AppHandle,
+    registry: &TransferRegistry,
+    transfer_id: &str,
+) {
+    let summary = {
+        let mut transfers = registry.write().await;
+        let Some(batch_id) = transfers.get(transfer_id).and_then(|e| e.batch_id.clone()) else {
+            return;
+        };
+
+        let mut summary = BatchSummary {
+            batch_id: batch_id.clone(),
+            completed: 0,
+            failed: 0,
+            cancelled: 0,
+        };
+        for entry in transfers.values() {
+            if entry.batch_id.as_deref() != Some(batch_id.as_str()) {
+                continue;
+            }
+            match entry.status {
+                TransferStatus::Completed => summary.completed += 1,
+                TransferStatus::Failed => summary.failed += 1,
+                TransferStatus::Cancelled => summary.cancelled += 1,
+                TransferStatus::InProgress | TransferStatus::Paused => return,
+            }
+        }
+
+        for entry in transfers.values_mut() {
+            if entry.batch_id.as_deref() == Some(batch_id.as_str()) {
+                entry.batch_id = None;
+            }
+        }
+        summary
+    };
+
+    println!(
+        "[Transfer] Batch {} complete: {} completed, {} failed, {} cancelled",
+        summary.batch_id, summary.completed, summary.failed, summary.cancelled
+    );
+    emit_event(app_handle, summary);
+}
+
+/// Update a registered transfer's status, returning false if it isn't registered
+pub async fn set_transfer_status(
+    registry: &TransferRegistry,
+    transfer_id: &str,
+    status: TransferStatus,
+) -> bool {
+    let mut transfers = registry.write().await;
+    match transfers.get_mut(transfer_id) {
+        Some(entry) => {
+            entry.status = status;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Current status of a transfer; unknown transfers are treated as in progress
+pub async fn get_transfer_status(registry: &TransferRegistry, transfer_id: &str) -> TransferStatus {
+    registry
+        .read()
+        .await
+        .get(transfer_id)
+        .map(|entry| entry.status)
+        .unwrap_or(TransferStatus::InProgress)
+}
+
+/// Move every transfer with `device_id` whose status is in `from` to `to`,
+/// returning how many transfers changed
+async fn set_device_transfers_status(
+    registry: &TransferRegistry,
+    device_id: &str,
+    from: &[TransferStatus],
+    to: TransferStatus,
+) -> usize {
+    let mut transfers = registry.write().await;
+    let mut changed = 0;
+    for entry in transfers.values_mut() {
+        if entry.device_id == device_id && from.contains(&entry.status) {
+            entry.status = to;
+            changed += 1;
+        }
+    }
+    changed
+}
+
 pub type GenericError = Box<dyn std::error::Error + Send + Sync>;
 
 pub struct AppState {
@@ -35,15 +187,68 @@ pub struct AppState {
     pub transfer: Arc<RwLock<Option<Arc<TransferManager>>>>,
     pub sync: Arc<RwLock<SyncState>>,
     pub security: Arc<RwLock<SecurityService>>,
+    pub identity: Arc<DeviceIdentity>,
+    pub settings: Arc<RwLock<SettingsStore>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub hash_pool: Arc<HashPool>,
     pub database: Arc<RwLock<Option<Database>>>,
     pub transfers: TransferRegistry,
+    /// Why secure transport failed to start, leaving the app discovery-only
+    pub transport_error: Option<String>,
+    /// Files handed to the app to send (command line or OS open-file events),
+    /// held until the UI takes them
+    pub pending_sends: Arc<RwLock<Vec<PathBuf>>>,
+    pub do_not_disturb: Arc<RwLock<DoNotDisturb>>,
+    /// Emit `chunk-progress` for every chunk; transient, see `set_verbose_progress`
+    pub verbose_progress: Arc<AtomicBool>,
+    /// Cancel flags of running `analyze_folder` calls, by path
+    pub folder_analyses: Arc<RwLock<HashMap<String, Arc<AtomicBool>>>>,
+    /// The shared passphrase as last entered, kept in memory only, to derive
+    /// its key under the salt of a device this install hasn't answered yet
+    pub passphrase: Arc<RwLock<Option<String>>>,
+    /// Senders of completed transfers the user may trust with
+    /// `accept_transfer_trust`
+    pub trust_offers: Arc<RwLock<HashSet<String>>>,
+}
+
+/// Files passed on the command line, e.g. by a "Send with ProxiShare" entry.
+/// Arguments that aren't existing files are ignored.
+fn launch_file_args() -> Vec<PathBuf> {
+    std::env::args_os()
+        .skip(1)
+        .map(PathBuf::from)
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Queue files to send and tell the UI. The event may fire before the UI
+/// listens, so it only signals; the UI collects with `take_pending_sends`.
+async fn queue_pending_sends(app_handle: &tauri::AppHandle, paths: Vec<PathBuf>) {
+    if paths.is_empty() {
+        return;
+    }
+    let Some(state) = app_handle.try_state::<AppState>() else {
+        return;
+    };
+    println!("[Setup] Queued {} file(s) to send", paths.len());
+    state.pending_sends.write().await.extend(paths);
+    emit_event(app_handle, PendingSend);
+}
+
+/// Files waiting for the user to pick a destination, emptying the queue
+#[tauri::command]
+async fn take_pending_sends(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
+    Ok(std::mem::take(&mut *state.pending_sends.write().await)
+        .into_iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect())
 }
 
 #[tauri::command]
 async fn start_discovery(state: tauri::State<'_, AppState>) -> Result<bool, String> {
     let discovery_lock = state.discovery.read().await;
     if let Some(discovery) = &*discovery_lock {
-        discovery.start_broadcasting().map_err(|e| e.to_string())?;
+        discovery.reannounce().map_err(|e| e.to_string())?;
         discovery.start_discovery().map_err(|e| e.to_string())?;
         Ok(true)
     } else {
@@ -51,14 +256,143 @@ async fn start_discovery(state: tauri::State<'_, AppState>) -> Result<bool, Stri
     }
 }
 
+/// Whether discovery is browsing and broadcasting; all off when it never started
+#[tauri::command]
+async fn get_discovery_state(state: tauri::State<'_, AppState>) -> Result<DiscoveryState, String> {
+    let discovery = state.discovery.read().await.clone();
+    match discovery {
+        Some(ds) => Ok(ds.state().await),
+        None => Ok(DiscoveryState::default()),
+    }
+}
+
+/// Stop browsing and advertising over mDNS until `start_discovery` is called again
+#[tauri::command]
+async fn stop_discovery(state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let discovery = state.discovery.read().await.clone();
+    match discovery {
+        Some(ds) => ds.stop_discovery().await.map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Advertise this device over mDNS or hide it; discovery of others keeps running
+#[tauri::command]
+async fn set_broadcasting(
+    enabled: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.broadcasting_disabled = !enabled)
+        .map_err(|e| e.to_string())?;
+
+    let discovery = state.discovery.read().await.clone();
+    match discovery {
+        // Discovery-only mode can't be advertised; the setting applies next launch
+        Some(ds) => ds
+            .set_broadcasting(enabled && state.transport_error.is_none())
+            .map_err(|e| e.to_string()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Advertise `ip` first in our mDNS records (e.g. over a VPN adapter listed
+/// before the LAN one), or restore the default order with `None`.
+/// Returns the addresses now advertised.
+#[tauri::command]
+async fn set_preferred_broadcast_ip(
+    ip: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+    let ip = ip
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty());
+    let discovery = state.discovery.read().await.clone();
+    let Some(ds) = discovery else {
+        return Err("Discovery service not initialized".to_string());
+    };
+    ds.set_preferred_ip(ip.clone()).map_err(|e| e.to_string())?;
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.preferred_broadcast_ip = ip)
+        .map_err(|e| e.to_string())?;
+    ds.reannounce().map_err(|e| e.to_string())
+}
+
+/// Reason the app is running discovery-only or can't receive, or `None` if
+/// transfers work
+#[tauri::command]
+async fn get_transport_error(state: tauri::State<'_, AppState>) -> Result<Option<String>, String> {
+    Ok(transport_error(&state).await)
+}
+
+async fn transport_error(state: &AppState) -> Option<String> {
+    if let Some(error) = &state.transport_error {
+        return Some(error.clone());
+    }
+    let tm = state.transfer.read().await.clone();
+    tm.and_then(|tm| tm.listener_error())
+}
+
 #[tauri::command]
-async fn get_discovered_devices(state: tauri::State<'_, AppState>) -> Result<Vec<Device>, String> {
+async fn reannounce(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
     let discovery = state.discovery.read().await.clone();
     if let Some(ds) = discovery {
-        Ok(ds.get_devices().await)
+        ds.reannounce().map_err(|e| e.to_string())
     } else {
-        Ok(vec![])
+        Err("Discovery service not initialized".to_string())
+    }
+}
+
+/// Devices found on the network; with `hide_blocked`, blocked ones are left out
+#[tauri::command]
+async fn get_discovered_devices(
+    state: tauri::State<'_, AppState>,
+    hide_blocked: Option<bool>,
+) -> Result<Vec<Device>, String> {
+    let discovery = state.discovery.read().await.clone();
+    let Some(ds) = discovery else {
+        return Ok(vec![]);
+    };
+    let mut devices = ds.get_devices().await;
+    if hide_blocked.unwrap_or(false) {
+        let security = state.security.read().await;
+        devices.retain(|device| !security.is_blocked(&device.id));
     }
+    Ok(devices)
+}
+
+/// Remove one device from the discovered list so its cached addresses are
+/// dropped; it reappears if it's still announcing. Returns whether it was listed.
+#[tauri::command]
+async fn forget_device(
+    device_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<bool, String> {
+    let discovery = state.discovery.read().await.clone();
+    let Some(ds) = discovery else {
+        return Err("Discovery service not initialized".to_string());
+    };
+    Ok(ds.forget_device(&device_id).await)
+}
+
+/// Other addresses discovery knows for a device, for when `ip` stops working
+async fn alternate_ips(state: &AppState, device_id: &str, ip: &str) -> Vec<String> {
+    let Some(discovery) = state.discovery.read().await.clone() else {
+        return Vec::new();
+    };
+    discovery
+        .get_devices()
+        .await
+        .into_iter()
+        .find(|d| d.id == device_id)
+        .map(|d| d.all_ips.into_iter().filter(|a| a != ip).collect())
+        .unwrap_or_default()
 }
 
 #[tauri::command]
@@ -68,27 +402,476 @@ async fn send_file(
     ip: String,
     port: u16,
     path: String,
+    rename_to: Option<String>,
 ) -> Result<(), String> {
     println!("[Command] send_file called: {} to {}:{}", path, ip, port);
+    let path = PathBuf::from(path);
+    let payload = match rename_to {
+        Some(name) => SendPayload::RenamedFile {
+            path,
+            name: crate::transfer::filename::validate_rename(&name).map_err(|e| e.to_string())?,
+        },
+        None => SendPayload::File(path),
+    };
+
+    let transfer_id = uuid::Uuid::new_v4().to_string();
+
+    // Track transfer in registry
+    {
+        let mut transfers = state.transfers.write().await;
+        transfers.insert(
+            transfer_id.clone(),
+            TransferEntry {
+                status: TransferStatus::InProgress,
+                device_id: device_id.clone(),
+                batch_id: None,
+            },
+        );
+    }
+
+    run_send(&state, transfer_id, &device_id, &ip, port, payload).await
+}
+
+/// Send generated or pasted data as a file called `name`, through the same
+/// path as files on disk
+#[tauri::command]
+async fn send_bytes(
+    state: tauri::State<'_, AppState>,
+    device_id: String,
+    ip: String,
+    port: u16,
+    name: String,
+    data: Vec<u8>,
+) -> Result<(), String> {
+    println!(
+        "[Command] send_bytes called: {} bytes as {:?} to {}:{}",
+        data.len(),
+        name,
+        ip,
+        port
+    );
+
+    let transfer_id = uuid::Uuid::new_v4().to_string();
+    state.transfers.write().await.insert(
+        transfer_id.clone(),
+        TransferEntry {
+            status: TransferStatus::InProgress,
+            device_id: device_id.clone(),
+            batch_id: None,
+        },
+    );
+
+    let payload = SendPayload::Bytes {
+        name,
+        data: data.into(),
+    };
+    run_send(&state, transfer_id, &device_id, &ip, port, payload).await
+}
+
+/// Send several files to one device as a single batch. The receiver is told
+/// about all of them up front, progress events carry the batch id, and a
+/// `batch-complete` event fires once every file has resolved.
+/// Returns the batch id.
+#[tauri::command]
+async fn send_files(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    device_id: String,
+    ip: String,
+    port: u16,
+    paths: Vec<String>,
+) -> Result<String, String> {
+    println!(
+        "[Command] send_files called: {} files to {}:{}",
+        paths.len(),
+        ip,
+        port
+    );
+    if paths.is_empty() {
+        return Err("No files to send".to_string());
+    }
+
+    let batch_id = uuid::Uuid::new_v4().to_string();
+    let members: Vec<(String, String)> = paths
+        .into_iter()
+        .map(|path| (uuid::Uuid::new_v4().to_string(), path))
+        .collect();
+
+    {
+        let mut transfers = state.transfers.write().await;
+        for (transfer_id, _) in &members {
+            transfers.insert(
+                transfer_id.clone(),
+                TransferEntry {
+                    status: TransferStatus::InProgress,
+                    device_id: device_id.clone(),
+                    batch_id: Some(batch_id.clone()),
+                },
+            );
+        }
+    }
+
+    let tm_opt = state.transfer.read().await.clone();
+    let Some(tm) = tm_opt else {
+        return Err("Transfer manager not initialized".to_string());
+    };
+
+    let files: Vec<(String, PathBuf)> = members
+        .iter()
+        .map(|(transfer_id, path)| (transfer_id.clone(), PathBuf::from(path)))
+        .collect();
+    if let Err(e) = tm
+        .send_batch_offer(ip.clone(), port, batch_id.clone(), &files)
+        .await
+    {
+        // Members are still sent; the receiver just can't group them
+        println!("[Command] Failed to announce batch {}: {}", batch_id, e);
+    }
+
+    let mut failures = 0;
+    for (transfer_id, path) in members {
+        // Cancelled (e.g. by cancel_batch) before its turn came: record it, don't send it
+        if get_transfer_status(&state.transfers, &transfer_id).await == TransferStatus::Cancelled {
+            record_unsent(&state, &transfer_id, &device_id, &path, &ip, port).await;
+            finish_batch_member(tm.app_handle(), &state.transfers, &transfer_id).await;
+            continue;
+        }
+        let payload = SendPayload::File(PathBuf::from(path));
+        if run_send(&state, transfer_id, &device_id, &ip, port, payload)
+            .await
+            .is_err()
+        {
+            failures += 1;
+        }
+    }
+    emit_event(&app_handle, HistoryUpdated);
+
+    println!(
+        "[Command] send_files finished batch {} with {} failures",
+        batch_id, failures
+    );
+    Ok(batch_id)
+}
+
+/// Files of the directory at `path` that `send_directory` would send with
+/// the same patterns, with their count and total size, for confirming first
+#[tauri::command]
+async fn select_directory_files(
+    path: String,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+) -> Result<SelectedFiles, String> {
+    resolve_directory_selection(path, include, exclude).await
+}
+
+/// Send the files of a directory that match `include` (everything when
+/// empty) and none of `exclude`, as one batch. Files are sent as the walk
+/// finds them, announced to the receiver a chunk at a time, so a huge
+/// directory starts at once; `directory-progress` carries the running
+/// count. Returns the batch id.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn send_directory(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    device_id: String,
+    ip: String,
+    port: u16,
+    path: String,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+) -> Result<String, String> {
+    let selection = Selection::new(&include.unwrap_or_default(), &exclude.unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+    let root = PathBuf::from(&path);
+    if !root.is_dir() {
+        return Err(format!("{} is not a directory", path));
+    }
+    let tm_opt = state.transfer.read().await.clone();
+    let Some(tm) = tm_opt else {
+        return Err("Transfer manager not initialized".to_string());
+    };
+
+    let (found_tx, mut found_rx) = tokio::sync::mpsc::channel(WALK_BUFFER);
+    let walker = tokio::task::spawn_blocking(move || stream_files(&root, &selection, &found_tx));
+
+    let batch_id = uuid::Uuid::new_v4().to_string();
+    let mut progress = DirectoryProgress {
+        batch_id: batch_id.clone(),
+        files_found: 0,
+        bytes_found: 0,
+        done: false,
+    };
+    let mut pending: std::collections::VecDeque<(String, PathBuf)> = Default::default();
+    let mut failures = 0;
+    loop {
+        // Keep a member announced beyond the one being sent, so the batch
+        // can't look finished while the walk still has files to give
+        if !progress.done && pending.len() <= 1 {
+            let mut chunk = Vec::new();
+            if let Some(first) = found_rx.recv().await {
+                chunk.push(first);
+                while chunk.len() < MANIFEST_CHUNK {
+                    match found_rx.try_recv() {
+                        Ok(found) => chunk.push(found),
+                        Err(_) => break,
+                    }
+                }
+            }
+            if chunk.is_empty() {
+                progress.done = true;
+            } else {
+                let members: Vec<(String, PathBuf)> = chunk
+                    .into_iter()
+                    .map(|(file, size)| {
+                        progress.bytes_found += size;
+                        (uuid::Uuid::new_v4().to_string(), file)
+                    })
+                    .collect();
+                progress.files_found += members.len();
+                {
+                    let mut transfers = state.transfers.write().await;
+                    for (transfer_id, _) in &members {
+                        transfers.insert(
+                            transfer_id.clone(),
+                            TransferEntry {
+                                status: TransferStatus::InProgress,
+                                device_id: device_id.clone(),
+                                batch_id: Some(batch_id.clone()),
+                            },
+                        );
+                    }
+                }
+                if let Err(e) = tm
+                    .send_batch_offer(ip.clone(), port, batch_id.clone(), &members)
+                    .await
+                {
+                    // Members are still sent; the receiver just can't group them
+                    println!("[Command] Failed to announce batch {}: {}", batch_id, e);
+                }
+                pending.extend(members);
+            }
+            emit_event(&app_handle, progress.clone());
+            continue;
+        }
+
+        let Some((transfer_id, file)) = pending.pop_front() else {
+            break;
+        };
+        let file = file.to_string_lossy().to_string();
+        if get_transfer_status(&state.transfers, &transfer_id).await == TransferStatus::Cancelled {
+            // cancel_batch cancels every member announced so far; when none
+            // is left standing the whole batch was cancelled, so stop walking
+            if !progress.done && all_cancelled(&state.transfers, &pending).await {
+                println!("[Command] Batch {} cancelled mid-walk", batch_id);
+                found_rx.close();
+                progress.done = true;
+                emit_event(&app_handle, progress.clone());
+            }
+            record_unsent(&state, &transfer_id, &device_id, &file, &ip, port).await;
+            finish_batch_member(tm.app_handle(), &state.transfers, &transfer_id).await;
+            continue;
+        }
+        let payload = SendPayload::File(PathBuf::from(file));
+        if run_send(&state, transfer_id, &device_id, &ip, port, payload)
+            .await
+            .is_err()
+        {
+            failures += 1;
+        }
+    }
+    drop(found_rx);
+
+    let walked = walker.await.map_err(|e| e.to_string())?;
+    if progress.files_found == 0 {
+        walked.map_err(|e| e.to_string())?;
+        return Err(format!("No files in {} match the selection", path));
+    }
+    if let Err(e) = walked {
+        println!("[Command] Walk of {} stopped early: {}", path, e);
+    }
+    emit_event(&app_handle, HistoryUpdated);
+
+    println!(
+        "[Command] send_directory finished batch {} with {} files ({} bytes) and {} failures",
+        batch_id, progress.files_found, progress.bytes_found, failures
+    );
+    Ok(batch_id)
+}
+
+async fn all_cancelled(
+    registry: &TransferRegistry,
+    members: &std::collections::VecDeque<(String, PathBuf)>,
+) -> bool {
+    let transfers = registry.read().await;
+    members.iter().all(|(transfer_id, _)| {
+        transfers
+            .get(transfer_id)
+            .is_some_and(|entry| entry.status == TransferStatus::Cancelled)
+    })
+}
+
+/// File count, total size and largest files under `path`, for previewing a
+/// sync folder or directory send. No hashing is done. Large trees report `folder-analysis-progress` as they're walked;
+/// `cancel_folder_analysis` stops the walk.
+#[tauri::command]
+async fn analyze_folder(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<FolderAnalysis, String> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .folder_analyses
+        .write()
+        .await
+        .insert(path.clone(), cancelled.clone());
+
+    let walked = {
+        let path = path.clone();
+        let cancelled = cancelled.clone();
+        tokio::task::spawn_blocking(move || {
+            crate::transfer::selection::analyze_folder(
+                std::path::Path::new(&path),
+                &cancelled,
+                &mut |files_found, bytes_found| {
+                    emit_event(
+                        &app_handle,
+                        FolderAnalysisProgress {
+                            path: path.clone(),
+                            files_found,
+                            bytes_found,
+                        },
+                    );
+                },
+            )
+        })
+        .await
+    };
+
+    let mut analyses = state.folder_analyses.write().await;
+    if analyses
+        .get(&path)
+        .is_some_and(|flag| Arc::ptr_eq(flag, &cancelled))
+    {
+        analyses.remove(&path);
+    }
+    drop(analyses);
+
+    let analysis = walked
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    println!(
+        "[Command] analyze_folder found {} files ({} bytes) in {}",
+        analysis.file_count, analysis.total_size, analysis.root
+    );
+    Ok(analysis)
+}
+
+/// Stop a running `analyze_folder` of `path`, which then fails as
+/// cancelled. Returns whether one was running.
+#[tauri::command]
+async fn cancel_folder_analysis(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<bool, String> {
+    match state.folder_analyses.read().await.get(&path) {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::Relaxed);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+async fn resolve_directory_selection(
+    path: String,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+) -> Result<SelectedFiles, String> {
+    let selection = Selection::new(&include.unwrap_or_default(), &exclude.unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || select_files(std::path::Path::new(&path), &selection))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
 
-    let file_path = PathBuf::from(&path);
+/// Record a send that was cancelled before it started as "cancelled", so it
+/// shows in history instead of never appearing
+async fn record_unsent(
+    state: &AppState,
+    transfer_id: &str,
+    device_id: &str,
+    path: &str,
+    ip: &str,
+    port: u16,
+) {
+    let file_path = PathBuf::from(path);
     let file_name = file_path
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_else(|| "unknown".to_string());
-
-    // Get file size for logging
-    let file_size = std::fs::metadata(&path)
+    let file_size = std::fs::metadata(&file_path)
         .map(|m| m.len() as i64)
         .unwrap_or(0);
 
-    let transfer_id = uuid::Uuid::new_v4().to_string();
-
-    // Track transfer in registry
-    {
-        let mut transfers = state.transfers.write().await;
-        transfers.insert(transfer_id.clone(), TransferStatus::InProgress);
+    let db_lock = state.database.read().await;
+    if let Some(db) = &*db_lock {
+        let result = async {
+            db.record_transfer(
+                transfer_id,
+                device_id,
+                &file_name,
+                path,
+                file_size,
+                "send",
+                "",
+                HashAlgo::default(),
+                Some(ip),
+                Some(port),
+            )
+            .await?;
+            db.update_transfer_status(transfer_id, "cancelled", 0).await
+        }
+        .await;
+        if let Err(e) = result {
+            println!("[Database] Failed to record cancelled transfer: {:?}", e);
+        }
     }
+}
+
+/// Send one file whose transfer is already in the registry, recording it in
+/// the database and resolving its registry entry when done
+pub(crate) async fn run_send(
+    state: &AppState,
+    transfer_id: String,
+    device_id: &str,
+    ip: &str,
+    port: u16,
+    payload: SendPayload,
+) -> Result<(), String> {
+    let (file_name, path, file_size) = match &payload {
+        SendPayload::File(file_path) => (
+            file_path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            file_path.to_string_lossy().to_string(),
+            std::fs::metadata(file_path)
+                .map(|m| m.len() as i64)
+                .unwrap_or(0),
+        ),
+        SendPayload::RenamedFile { path, name } => (
+            name.clone(),
+            path.to_string_lossy().to_string(),
+            std::fs::metadata(path).map(|m| m.len() as i64).unwrap_or(0),
+        ),
+        // In-memory payloads have no path on this side
+        SendPayload::Bytes { name, data } => (name.clone(), String::new(), data.len() as i64),
+    };
+    // Recorded under the name the peer is offered, so both histories agree
+    let file_name = state.settings.read().await.settings.offer_name(&file_name);
 
     // Record the transfer start in database
     {
@@ -97,12 +880,15 @@ async fn send_file(
             if let Err(e) = db
                 .record_transfer(
                     &transfer_id,
-                    &device_id,
+                    device_id,
                     &file_name,
                     &path,
                     file_size,
                     "send",
                     "", // Hash will be calculated during transfer
+                    HashAlgo::default(),
+                    Some(ip),
+                    Some(port),
                 )
                 .await
             {
@@ -113,58 +899,833 @@ async fn send_file(
 
     let tm_opt = state.transfer.read().await.clone();
     if let Some(tm) = tm_opt {
-        // Convert result to Send-compatible type immediately
-        let send_result: Result<(), String> = tm
-            .send_file(
-                transfer_id.clone(),
-                ip.clone(),
-                port,
-                file_path,
-                state.transfers.clone(),
-            )
+        // If the path to the peer breaks without migrating (e.g. it switched
+        // networks), the transfer is offered again on its other addresses,
+        // then retried as configured; each offer resumes from what the
+        // receiver already has
+        let addresses = std::iter::once(ip.to_string())
+            .chain(alternate_ips(state, device_id, ip).await)
+            .collect();
+        let delays: Vec<_> = {
+            let settings = state.settings.read().await;
+            (1..=settings.settings.auto_retry_attempts())
+                .map(|attempt| settings.settings.auto_retry_delay(attempt))
+                .collect()
+        };
+        let max_attempts = delays.len() as u32;
+        let (send_result, used_ip) = crate::transfer::send_with_retries(
+            &state.transfers,
+            &transfer_id,
+            addresses,
+            &delays,
+            |attempt, delay, e| {
+                println!(
+                    "[Command] Retrying {} in {}s (attempt {}/{}): {}",
+                    transfer_id,
+                    delay.as_secs(),
+                    attempt,
+                    max_attempts,
+                    e
+                );
+                emit_event(
+                    tm.app_handle(),
+                    TransferRetrying {
+                        transfer_id: transfer_id.clone(),
+                        attempt,
+                        max_attempts,
+                        delay_secs: delay.as_secs(),
+                        error: e.to_string(),
+                    },
+                );
+            },
+            |address| {
+                tm.send_payload(
+                    transfer_id.clone(),
+                    address,
+                    port,
+                    payload.clone(),
+                    state.transfers.clone(),
+                )
+            },
+        )
+        .await;
+
+        {
+            let db_lock = state.database.read().await;
+            record_send_outcome(
+                db_lock.as_ref(),
+                &state.transfers,
+                &transfer_id,
+                file_size,
+                &send_result,
+            )
+            .await;
+            if let Some(db) = &*db_lock {
+                if send_result.is_ok() {
+                    let name = device_name(state, device_id).await;
+                    if let Err(e) = db
+                        .record_recent_destination(device_id, &name, &used_ip, port)
+                        .await
+                    {
+                        println!("[Database] Failed to record recent destination: {:?}", e);
+                    }
+                }
+            }
+        }
+        finish_batch_member(tm.app_handle(), &state.transfers, &transfer_id).await;
+
+        match send_result {
+            Ok(_) => {
+                println!("[Command] send_file completed successfully");
+                Ok(())
+            }
+            Err(e) => {
+                let error_msg = format!("Failed to send file: {}", e);
+                println!("[Command] {}", error_msg);
+                Err(error_msg)
+            }
+        }
+    } else {
+        let error_msg = "Transfer manager not initialized".to_string();
+        println!("[Command] {}", error_msg);
+        Err(error_msg)
+    }
+}
+
+/// Record how a send ended in the database and the registry: a
+/// cancellation by either side is "cancelled", any other error "failed"
+async fn record_send_outcome(
+    db: Option<&Database>,
+    transfers: &TransferRegistry,
+    transfer_id: &str,
+    file_size: i64,
+    send_result: &Result<(), ProxiError>,
+) {
+    let (status, registry_status) = match send_result {
+        Ok(_) => ("completed", TransferStatus::Completed),
+        Err(ProxiError::Cancelled(_)) => ("cancelled", TransferStatus::Cancelled),
+        Err(_) => ("failed", TransferStatus::Failed),
+    };
+    if let Some(db) = db {
+        if let Err(e) = db
+            .update_transfer_status(transfer_id, status, file_size)
+            .await
+        {
+            println!("[Database] Failed to update transfer status: {:?}", e);
+        }
+    }
+    set_transfer_status(transfers, transfer_id, registry_status).await;
+}
+
+/// Discovered name of a device, or its id when it isn't discovered right now
+async fn device_name(state: &AppState, device_id: &str) -> String {
+    let discovery = state.discovery.read().await.clone();
+    if let Some(discovery) = discovery {
+        if let Some(device) = discovery
+            .get_devices()
+            .await
+            .into_iter()
+            .find(|d| d.id == device_id)
+        {
+            return device.name;
+        }
+    }
+    device_id.to_string()
+}
+
+#[tauri::command]
+async fn get_trusted_devices(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
+    let security = state.security.read().await;
+    Ok(security.trusted_ids())
+}
+
+#[tauri::command]
+async fn get_blocked_devices(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
+    let security = state.security.read().await;
+    let mut blocked: Vec<String> = security.blocked_devices.iter().cloned().collect();
+    blocked.sort();
+    Ok(blocked)
+}
+
+/// Refuse a device outright, even if it's trusted: its connections are
+/// closed without prompting, and nothing is sent to it. Transfers with it
+/// that are running are cancelled. Returns whether it wasn't blocked already.
+#[tauri::command]
+async fn block_device(
+    device_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<bool, String> {
+    let added = state
+        .security
+        .write()
+        .await
+        .block_device(device_id.clone())
+        .map_err(|e| e.to_string())?;
+    let cancelled = set_device_transfers_status(
+        &state.transfers,
+        &device_id,
+        &[TransferStatus::InProgress, TransferStatus::Paused],
+        TransferStatus::Cancelled,
+    )
+    .await;
+    println!(
+        "[Security] Blocked {} ({} transfers cancelled)",
+        device_id, cancelled
+    );
+    Ok(added)
+}
+
+/// Returns whether the device was blocked
+#[tauri::command]
+async fn unblock_device(
+    device_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<bool, String> {
+    let removed = state
+        .security
+        .write()
+        .await
+        .unblock_device(&device_id)
+        .map_err(|e| e.to_string())?;
+    if removed {
+        println!("[Security] Unblocked {}", device_id);
+    }
+    Ok(removed)
+}
+
+/// Trust a device for `duration_secs` only, e.g. a guest's phone. Returns
+/// when the trust expires (Unix seconds), or `None` if the device was
+/// already trusted permanently.
+#[tauri::command]
+async fn add_temporary_trust(
+    device_id: String,
+    duration_secs: u64,
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<i64>, String> {
+    if duration_secs == 0 {
+        return Err("Temporary trust needs a duration".to_string());
+    }
+    let mut security = state.security.write().await;
+    let expires_at = security
+        .add_temporary_trust(
+            device_id.clone(),
+            std::time::Duration::from_secs(duration_secs),
+        )
+        .map_err(|e| e.to_string())?;
+    println!(
+        "[Security] Trusted {} temporarily, expires at {:?}",
+        device_id, expires_at
+    );
+    Ok(expires_at)
+}
+
+/// Every minute, remove trust that has expired and tell the UI
+fn spawn_trust_expiry(app_handle: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(TRUST_EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            let state = app_handle.state::<AppState>();
+            let expired = state.security.write().await.prune_expired();
+            match expired {
+                Ok(ids) if !ids.is_empty() => {
+                    println!("[Security] Temporary trust expired for {:?}", ids);
+                    emit_event(&app_handle, TrustExpired { device_ids: ids });
+                }
+                Ok(_) => {}
+                Err(e) => println!("[Security] Failed to prune expired trust: {}", e),
+            }
+        }
+    });
+}
+
+/// Save the trust store to a JSON file that can be imported on other machines.
+/// Returns the number of devices exported.
+#[tauri::command]
+async fn export_trusted_devices(
+    path: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    let security = state.security.read().await;
+    security
+        .export_to(&PathBuf::from(path))
+        .map_err(|e| e.to_string())
+}
+
+/// Merge an exported trust store into ours. Existing entries that differ are
+/// reported as conflicts and kept unless `overwrite` is set, so the UI can
+/// confirm before importing again with it.
+#[tauri::command]
+async fn import_trusted_devices(
+    path: String,
+    overwrite: Option<bool>,
+    state: tauri::State<'_, AppState>,
+) -> Result<TrustImport, String> {
+    let mut security = state.security.write().await;
+    security
+        .import_from(&PathBuf::from(path), overwrite.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
+/// Require peers to prove knowledge of a shared passphrase before they can
+/// send files. Passing `None` (or an empty string) disables the gate.
+#[tauri::command]
+async fn set_shared_passphrase(
+    passphrase: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    let passphrase = passphrase.filter(|p| !p.is_empty());
+    let mut settings = state.settings.write().await;
+    let salt = settings
+        .settings
+        .passphrase_salt
+        .clone()
+        .unwrap_or_else(crate::crypto::security::new_passphrase_salt);
+    let key = match &passphrase {
+        Some(p) => {
+            let (p, salt) = (p.clone(), salt.clone());
+            let derived = tokio::task::spawn_blocking(move || {
+                crate::crypto::security::derive_passphrase_key(&p, &salt)
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+            Some(derived.map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+    let enabled = key.is_some();
+
+    settings
+        .update(|s| {
+            s.passphrase_key = key;
+            s.passphrase_salt = Some(salt);
+            s.peer_passphrase_keys.clear();
+        })
+        .map_err(|e| e.to_string())?;
+    *state.passphrase.write().await = passphrase;
+    println!(
+        "[Security] Shared passphrase {}",
+        if enabled { "set" } else { "cleared" }
+    );
+    Ok(())
+}
+
+/// Replace the TLS certificate with a fresh one valid for `validity_days`
+/// (persisted as the new default). Returns the new fingerprint; pins of the
+/// old certificate stop matching.
+#[tauri::command]
+async fn regenerate_certificate(
+    validity_days: Option<u32>,
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<String, String> {
+    let mut settings = state.settings.write().await;
+    if validity_days.is_some() {
+        settings
+            .update(|s| s.certificate_validity_days = validity_days)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+    let cert_manager = CertificateManager::regenerate(
+        &app_data_dir,
+        settings.settings.certificate_validity_days(),
+    )
+    .map_err(|e| e.to_string())?;
+
+    let tm_guard = state.transfer.read().await;
+    if let Some(tm) = tm_guard.as_ref() {
+        tm.replace_certificate(&cert_manager)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(cert_manager.fingerprint())
+}
+
+/// Cap the combined bandwidth of all transfers to and from a device.
+/// `None` or 0 removes the limit. Persisted across restarts.
+#[tauri::command]
+async fn set_device_rate_limit(
+    device_id: String,
+    bytes_per_sec: Option<u64>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    let limit = bytes_per_sec.filter(|rate| *rate > 0);
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| match limit {
+            Some(rate) => {
+                s.device_rate_limits.insert(device_id.clone(), rate);
+            }
+            None => {
+                s.device_rate_limits.remove(&device_id);
+            }
+        })
+        .map_err(|e| e.to_string())?;
+    state.rate_limiter.set_limit(&device_id, limit);
+    println!("[Transfer] Rate limit for {} set to {:?}", device_id, limit);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_device_rate_limits(
+    state: tauri::State<'_, AppState>,
+) -> Result<HashMap<String, u64>, String> {
+    Ok(state
+        .settings
+        .read()
+        .await
+        .settings
+        .device_rate_limits
+        .clone())
+}
+
+/// Offer to trust devices that complete a transfer to us (`trust-offered`)
+#[tauri::command]
+async fn set_trust_on_first_transfer(
+    enabled: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.trust_on_first_transfer = enabled)
+        .map_err(|e| e.to_string())?;
+    if !enabled {
+        state.trust_offers.write().await.clear();
+    }
+    Ok(())
+}
+
+/// Trust the sender of a completed transfer, as the user accepted from a
+/// `trust-offered` prompt. Each offer can be accepted once.
+#[tauri::command]
+async fn accept_transfer_trust(
+    device_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    if !state.trust_offers.write().await.remove(&device_id) {
+        return Err(format!("No trust offer for device {}", device_id));
+    }
+    state
+        .security
+        .write()
+        .await
+        .add_trusted(device_id.clone())
+        .map_err(|e| e.to_string())?;
+    println!("[Security] Trusted {} after completed transfer", device_id);
+    Ok(())
+}
+
+/// Show an OS notification for each completed incoming file
+#[tauri::command]
+async fn set_notifications_enabled(
+    enabled: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.notifications_enabled = enabled)
+        .map_err(|e| e.to_string())
+}
+
+/// How many chunks may be hashed in parallel while sending; `None` uses one
+/// per core. Returns the size now in effect.
+#[tauri::command]
+async fn set_hashing_threads(
+    threads: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    let threads = threads.filter(|n| *n > 0);
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.hashing_threads = threads)
+        .map_err(|e| e.to_string())?;
+    state.hash_pool.resize(threads);
+    Ok(state.hash_pool.size())
+}
+
+/// Cap the chunk size picked for new transfers at `size` bytes, clamped to
+/// 64 KiB - 4 MiB; `None` goes back to sizing by file size alone. Returns
+/// the cap now in effect.
+#[tauri::command]
+async fn set_max_chunk_size(
+    size: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<usize>, String> {
+    let size = size.map(crate::transfer::sender::clamp_max_chunk_size);
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.max_chunk_size = size)
+        .map_err(|e| e.to_string())?;
+    Ok(size)
+}
+
+#[tauri::command]
+async fn get_max_chunk_size(state: tauri::State<'_, AppState>) -> Result<Option<usize>, String> {
+    Ok(state.settings.read().await.settings.max_chunk_size)
+}
+
+/// Bytes read from disk at a time when sending a file, clamped to 64 KiB -
+/// 16 MiB; `None` restores the 1 MiB default. Chunks on the wire keep their
+/// own size. Returns the buffer size now in effect.
+#[tauri::command]
+async fn set_send_read_buffer(
+    size: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    let mut settings = state.settings.write().await;
+    settings
+        .update(|s| s.send_read_buffer = size)
+        .map_err(|e| e.to_string())?;
+    Ok(settings.settings.send_read_buffer())
+}
+
+/// How many files a sync folder scan hashes in parallel; `None` uses half
+/// the cores. Returns the count now in effect.
+#[tauri::command]
+async fn set_sync_scan_workers(
+    workers: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    let mut settings = state.settings.write().await;
+    settings
+        .update(|s| s.sync_scan_workers = workers.filter(|n| *n > 0))
+        .map_err(|e| e.to_string())?;
+    Ok(settings.settings.sync_scan_workers())
+}
+
+/// Send file names as they are instead of composing them to NFC. Returns
+/// whether names are normalized now.
+#[tauri::command]
+async fn set_name_normalization(
+    enabled: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<bool, String> {
+    let mut settings = state.settings.write().await;
+    settings
+        .update(|s| s.name_normalization_disabled = !enabled)
+        .map_err(|e| e.to_string())?;
+    Ok(!settings.settings.name_normalization_disabled)
+}
+
+/// Longest file name offered to peers, in bytes, clamped to 32 - 255;
+/// `None` allows 255. Returns the limit now in effect.
+#[tauri::command]
+async fn set_max_name_bytes(
+    max_bytes: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    let mut settings = state.settings.write().await;
+    settings
+        .update(|s| s.max_name_bytes = max_bytes)
+        .map_err(|e| e.to_string())?;
+    Ok(settings.settings.max_name_bytes())
+}
+
+/// Free space in bytes a receive must leave on the download volume; `None`
+/// restores the 500 MiB default and 0 allows filling it. Returns the margin
+/// now in effect.
+#[tauri::command]
+async fn set_disk_space_margin(
+    margin_bytes: Option<u64>,
+    state: tauri::State<'_, AppState>,
+) -> Result<u64, String> {
+    let mut settings = state.settings.write().await;
+    settings
+        .update(|s| s.disk_space_margin = margin_bytes)
+        .map_err(|e| e.to_string())?;
+    Ok(settings.settings.disk_space_margin())
+}
+
+/// Most history records sent or accepted in one history sync; `None` uses
+/// the default of 100. Returns the limit now in effect.
+#[tauri::command]
+async fn set_history_sync_max_records(
+    max_records: Option<usize>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    let mut settings = state.settings.write().await;
+    settings
+        .update(|s| s.history_sync_max_records = max_records.filter(|n| *n > 0))
+        .map_err(|e| e.to_string())?;
+    Ok(settings.settings.history_sync_max_records())
+}
+
+/// Seconds without progress before a running transfer is failed as stalled;
+/// `None` restores the default and `Some(0)` disables the check. Applies to
+/// transfers started afterwards.
+#[tauri::command]
+async fn set_stall_timeout(
+    secs: Option<u64>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.stall_timeout_secs = secs)
+        .map_err(|e| e.to_string())
+}
+
+/// Report every chunk of every transfer, sent or received, with its index,
+/// size and hash check as `chunk-progress`, to see where a transfer on a
+/// flaky link gets stuck. Floods the UI with events, so it's off by default
+/// and not persisted.
+#[tauri::command]
+async fn set_verbose_progress(
+    enabled: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    state.verbose_progress.store(enabled, Ordering::Relaxed);
+    println!(
+        "[Transfer] Verbose chunk progress {}",
+        if enabled { "on" } else { "off" }
+    );
+    Ok(())
+}
+
+/// Retry sends that fail on the network (connection lost, timed out or
+/// stalled) up to `max_attempts` times, waiting `backoff_secs` (doubling
+/// each time) first; `None` attempts turns retrying off. Rejected,
+/// cancelled and corrupt transfers are never retried. Returns the attempts
+/// now in effect.
+#[tauri::command]
+async fn set_auto_retry(
+    max_attempts: Option<u32>,
+    backoff_secs: Option<u64>,
+    state: tauri::State<'_, AppState>,
+) -> Result<u32, String> {
+    let mut settings = state.settings.write().await;
+    settings
+        .update(|s| {
+            s.auto_retry_attempts = max_attempts.filter(|n| *n > 0);
+            s.auto_retry_backoff_secs = backoff_secs;
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(settings.settings.auto_retry_attempts())
+}
+
+/// Extension -> subfolder rules for moving completed files out of the
+/// download folder; an empty map turns post-processing off
+#[tauri::command]
+async fn set_post_process_folders(
+    rules: HashMap<String, String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    crate::transfer::post_process::validate_rules(&rules).map_err(|e| e.to_string())?;
+    let rules = rules
+        .into_iter()
+        .map(|(ext, folder)| (ext.to_ascii_lowercase(), folder))
+        .collect();
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.post_process_folders = rules)
+        .map_err(|e| e.to_string())
+}
+
+/// MIME types (`image/png`, `image/*`) received files must sniff as, checked
+/// on the first chunk; an empty list accepts any content
+#[tauri::command]
+async fn set_receive_mime_allowlist(
+    types: Vec<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    let types: Vec<String> = types
+        .into_iter()
+        .map(|t| t.trim().to_ascii_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    crate::transfer::content_type::validate_allowlist(&types).map_err(|e| e.to_string())?;
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.receive_mime_allowlist = types)
+        .map_err(|e| e.to_string())
+}
+
+/// Let receivers continue sends from a partial file they already have (the
+/// default); takes effect for transfers started afterwards
+#[tauri::command]
+async fn set_resumable(enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.resume_disabled = !enabled)
+        .map_err(|e| e.to_string())
+}
+
+/// Require application-layer encryption of chunk payloads, keyed from the
+/// shared passphrase, on top of QUIC's TLS. Needs a passphrase set on both
+/// devices; sends to peers without support fail instead of going out plain.
+#[tauri::command]
+async fn set_chunk_encryption(
+    enabled: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    let mut settings = state.settings.write().await;
+    if enabled && settings.settings.passphrase_key.is_none() {
+        return Err("Set a shared passphrase before enabling chunk encryption".to_string());
+    }
+    settings
+        .update(|s| s.encrypt_chunks = enabled)
+        .map_err(|e| e.to_string())
+}
+
+/// Hash algorithm offered for files we send; the receiver verifies with it
+#[tauri::command]
+async fn set_hash_algo(algo: HashAlgo, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    if !algo.is_supported() {
+        return Err("Unsupported hash algorithm".to_string());
+    }
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.hash_algo = algo)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_notifications_enabled(state: tauri::State<'_, AppState>) -> Result<bool, String> {
+    Ok(state.settings.read().await.settings.notifications_enabled)
+}
+
+/// Open the OS file manager with a received file selected
+#[tauri::command]
+async fn reveal_in_folder(path: String) -> Result<(), String> {
+    reveal_file(std::path::Path::new(&path))
+}
+
+fn reveal_file(path: &std::path::Path) -> Result<(), String> {
+    if !path.exists() {
+        return Err(format!("File not found: {}", path.display()));
+    }
+    tauri_plugin_opener::reveal_item_in_dir(path).map_err(|e| e.to_string())
+}
+
+/// The directory received files go to. The transfer manager holds the one
+/// actually in use, including one picked after the configured directory
+/// went away.
+async fn current_download_dir(
+    app_handle: &tauri::AppHandle,
+    state: &AppState,
+) -> Result<PathBuf, String> {
+    let tm_opt = state.transfer.read().await.clone();
+    if let Some(tm) = tm_opt {
+        return Ok(tm.save_directory().await);
+    }
+    let configured = state
+        .settings
+        .read()
+        .await
+        .settings
+        .download_directory
+        .clone();
+    match configured {
+        Some(dir) => Ok(dir),
+        None => app_handle.path().download_dir().map_err(|e| e.to_string()),
+    }
+}
+
+/// Leftover `.part` / `.partial` files in the download folder that no
+/// unfinished transfer can resume from, e.g. after a crash
+async fn orphaned_partials(
+    app_handle: &tauri::AppHandle,
+    state: &AppState,
+) -> Result<Vec<OrphanedPartial>, String> {
+    let dir = current_download_dir(app_handle, state).await?;
+    let keep: HashSet<PathBuf> = {
+        let db_lock = state.database.read().await;
+        let db = db_lock.as_ref().ok_or("Database not initialized")?;
+        db.get_resumable_paths()
             .await
-            .map_err(|e| e.to_string());
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(PathBuf::from)
+            .collect()
+    };
+    crate::transfer::partials::find_orphans(&dir, &keep).map_err(|e| e.to_string())
+}
 
-        // Update transfer status
-        {
-            let db_lock = state.database.read().await;
-            if let Some(db) = &*db_lock {
-                let status = match &send_result {
-                    Ok(_) => "completed",
-                    Err(e) if e.contains("cancelled") => "cancelled",
-                    Err(_) => "failed",
-                };
-                if let Err(e) = db
-                    .update_transfer_status(&transfer_id, status, file_size)
-                    .await
-                {
-                    println!("[Database] Failed to update transfer status: {:?}", e);
-                }
-            }
-        }
+#[tauri::command]
+async fn list_orphaned_partials(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<OrphanedPartial>, String> {
+    orphaned_partials(&app_handle, &state).await
+}
 
-        match send_result {
-            Ok(_) => {
-                println!("[Command] send_file completed successfully");
-                Ok(())
-            }
-            Err(e) => {
-                let error_msg = format!("Failed to send file: {}", e);
-                println!("[Command] {}", error_msg);
-                Err(error_msg)
-            }
-        }
-    } else {
-        let error_msg = "Transfer manager not initialized".to_string();
-        println!("[Command] {}", error_msg);
-        Err(error_msg)
-    }
+/// Delete the files `list_orphaned_partials` reports, returning the bytes freed
+#[tauri::command]
+async fn clean_orphaned_partials(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<u64, String> {
+    let orphans = orphaned_partials(&app_handle, &state).await?;
+    let freed = crate::transfer::partials::remove_orphans(&orphans);
+    println!(
+        "[Transfer] Removed {} orphaned partial files ({} bytes)",
+        orphans.len(),
+        freed
+    );
+    Ok(freed)
 }
 
 #[tauri::command]
-async fn get_trusted_devices(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
-    let security = state.security.read().await;
-    Ok(security.trusted_devices.iter().cloned().collect())
+async fn get_paths(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<AppPaths, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+
+    let download_dir = current_download_dir(&app_handle, &state).await?;
+
+    let display = |path: &std::path::Path| path.to_string_lossy().to_string();
+    Ok(AppPaths {
+        download_dir: display(&download_dir),
+        db_path: display(&app_data_dir.join(DB_FILE_NAME)),
+        trust_store_path: display(state.security.read().await.store_path()),
+        sync_folder: state
+            .sync
+            .read()
+            .await
+            .shared_folder
+            .as_deref()
+            .map(display),
+        app_data_dir: display(&app_data_dir),
+    })
+}
+
+/// Give this install a new keypair and device id after a `duplicate-device-id`
+/// warning. Devices we trust stay trusted; peers that trusted the old id have
+/// to pair again. Returns the new id, which is used after a restart.
+#[tauri::command]
+async fn regenerate_device_id(app_handle: tauri::AppHandle) -> Result<String, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+    let identity = DeviceIdentity::regenerate(&app_data_dir).map_err(|e| e.to_string())?;
+    Ok(identity.device_id().to_string())
 }
 
 #[tauri::command]
@@ -176,6 +1737,79 @@ async fn is_device_trusted(
     Ok(security.is_trusted(&device_id))
 }
 
+/// Add a peer known only by hostname (e.g. `laptop.local`). The name is
+/// resolved with the OS resolver, which covers `.local` where the system
+/// supports mDNS name lookup, and each address is asked to identify itself.
+#[tauri::command]
+async fn add_device_by_hostname(
+    hostname: String,
+    port: u16,
+    state: tauri::State<'_, AppState>,
+) -> Result<Device, String> {
+    let hostname = hostname.trim().trim_end_matches('.').to_string();
+    let addrs: Vec<std::net::SocketAddr> = tokio::net::lookup_host((hostname.as_str(), port))
+        .await
+        .map_err(|e| format!("Host {} not found: {}", hostname, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("Host {} not found: no addresses", hostname));
+    }
+    // IPv4 first, matching how discovered devices pick their primary address
+    let mut all_ips: Vec<String> = Vec::new();
+    for addr in addrs
+        .iter()
+        .filter(|a| a.is_ipv4())
+        .chain(addrs.iter().filter(|a| a.is_ipv6()))
+    {
+        let ip = addr.ip().to_string();
+        if !all_ips.contains(&ip) {
+            all_ips.push(ip);
+        }
+    }
+
+    let tm_opt = state.transfer.read().await.clone();
+    let Some(tm) = tm_opt else {
+        return Err("Transfer manager not initialized".to_string());
+    };
+    let mut last_error = None;
+    for ip in &all_ips {
+        match tm.identify_peer(ip.clone(), port).await {
+            Ok((id, name)) => {
+                if id == state.identity.device_id() {
+                    return Err(format!("{} is this device", hostname));
+                }
+                let device = Device {
+                    id,
+                    name,
+                    ip: ip.clone(),
+                    all_ips: all_ips.clone(),
+                    port,
+                    last_seen: chrono::Utc::now().timestamp(),
+                    manual: true,
+                };
+                if let Some(ds) = state.discovery.read().await.clone() {
+                    ds.add_manual_device(device.clone()).await;
+                }
+                return Ok(device);
+            }
+            Err(e) => {
+                println!(
+                    "[Discovery] {} ({}) did not identify itself: {}",
+                    hostname, ip, e
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(format!(
+        "Host {} was found at {} but is unreachable on port {}: {}",
+        hostname,
+        all_ips.join(", "),
+        port,
+        last_error.map(|e| e.to_string()).unwrap_or_default()
+    ))
+}
+
 #[tauri::command]
 async fn test_device_connectivity(
     ip: String,
@@ -190,6 +1824,34 @@ async fn test_device_connectivity(
     }
 }
 
+/// Largest payload a benchmark may request
+const MAX_BENCHMARK_MB: u32 = 1024;
+
+/// Send `size_mb` MiB of synthetic data to a peer, which discards it, and
+/// report the throughput and RTT. Nothing touches disk on either side.
+#[tauri::command]
+async fn run_transfer_benchmark(
+    ip: String,
+    port: u16,
+    size_mb: u32,
+    state: tauri::State<'_, AppState>,
+) -> Result<BenchmarkResult, String> {
+    if size_mb == 0 || size_mb > MAX_BENCHMARK_MB {
+        return Err(format!(
+            "Benchmark size must be between 1 and {} MB",
+            MAX_BENCHMARK_MB
+        ));
+    }
+
+    let tm_opt = state.transfer.read().await.clone();
+    let Some(tm) = tm_opt else {
+        return Err("Transfer manager not initialized".to_string());
+    };
+    tm.run_benchmark(ip, port, size_mb as u64 * 1024 * 1024)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn find_reachable_device_ip(
     device_id: String,
@@ -208,25 +1870,284 @@ async fn find_reachable_device_ip(
     }
 }
 
+/// Which trusted devices are online right now, for a dashboard. Each is
+/// probed on its discovered addresses, or failing that the address of its
+/// last transfer. Bounded in time: devices not reached by the deadline come
+/// back `unchecked`.
+#[tauri::command]
+async fn sweep_known_devices(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<crate::discovery::sweep::DeviceReachability>, String> {
+    let discovery = state.discovery.read().await.clone();
+    let Some(ds) = discovery else {
+        return Err("Discovery service not initialized".to_string());
+    };
+    let mut trusted = state.security.read().await.trusted_ids();
+    trusted.sort();
+
+    let discovered = ds.get_devices().await;
+    let mut known = Vec::with_capacity(trusted.len());
+    for id in trusted {
+        let known_device = match discovered.iter().find(|d| d.id == id) {
+            Some(device) => {
+                let mut addresses = vec![device.ip.clone()];
+                for ip in &device.all_ips {
+                    if !addresses.contains(ip) {
+                        addresses.push(ip.clone());
+                    }
+                }
+                crate::discovery::sweep::KnownDevice {
+                    id,
+                    name: Some(device.name.clone()),
+                    addresses,
+                    port: device.port,
+                }
+            }
+            None => {
+                let last = {
+                    let db_lock = state.database.read().await;
+                    match &*db_lock {
+                        Some(db) => db.last_address(&id).await.unwrap_or(None),
+                        None => None,
+                    }
+                };
+                let (addresses, port) = match last {
+                    Some((ip, port)) => (vec![ip], port),
+                    // Every install listens on the same port
+                    None => (Vec::new(), 51731),
+                };
+                crate::discovery::sweep::KnownDevice {
+                    id,
+                    name: None,
+                    addresses,
+                    port,
+                }
+            }
+        };
+        known.push(known_device);
+    }
+    Ok(crate::discovery::sweep::sweep(ds, known).await)
+}
+
+#[tauri::command]
+async fn get_device_capabilities(
+    ip: String,
+    port: u16,
+    state: tauri::State<'_, AppState>,
+) -> Result<PeerCapabilities, String> {
+    let tm_opt = state.transfer.read().await.clone();
+    if let Some(tm) = tm_opt {
+        tm.query_capabilities(ip, port)
+            .await
+            .map_err(|e| e.to_string())
+    } else {
+        Err("Transfer manager not initialized".to_string())
+    }
+}
+
+/// Dry run of a sync with `device_id`: exchange folder manifests and list
+/// the files only here, only there, or different, with what syncing would do
+#[tauri::command]
+async fn preview_sync(
+    device_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<crate::sync::preview::SyncPreview, String> {
+    let Some(manager) = state.sync.read().await.manager.clone() else {
+        return Err("No sync folder configured".to_string());
+    };
+    let tm_opt = state.transfer.read().await.clone();
+    let Some(tm) = tm_opt else {
+        return Err("Transfer manager not initialized".to_string());
+    };
+    let discovery = state.discovery.read().await.clone();
+    let Some(ds) = discovery else {
+        return Err("Discovery service not initialized".to_string());
+    };
+    let Some(device) = ds
+        .get_devices()
+        .await
+        .into_iter()
+        .find(|d| d.id == device_id)
+    else {
+        return Err(format!("Device {} not found", device_id));
+    };
+    let ip = ds
+        .find_reachable_ip(&device)
+        .await
+        .unwrap_or(device.ip.clone());
+
+    let local = crate::sync::preview::build_manifest(tm.app_handle(), manager.shared_folder())
+        .await
+        .map_err(|e| e.to_string())?;
+    let (peer_wants, remote) = tm
+        .exchange_manifests(
+            ip,
+            device.port,
+            manager.shared_folder().to_string_lossy().to_string(),
+            local.clone(),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    let we_want = manager.missing_files(&remote);
+    Ok(crate::sync::preview::diff(
+        &local,
+        &remote,
+        &peer_wants,
+        &we_want,
+    ))
+}
+
+#[tauri::command]
+async fn get_connection_stats(
+    transfer_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<ConnectionStats, String> {
+    let tm_opt = state.transfer.read().await.clone();
+    if let Some(tm) = tm_opt {
+        tm.connection_stats(&transfer_id)
+            .await
+            .ok_or_else(|| "No active connection for transfer".to_string())
+    } else {
+        Err("Transfer manager not initialized".to_string())
+    }
+}
+
 #[tauri::command]
 async fn get_network_diagnostics(
     state: tauri::State<'_, AppState>,
 ) -> Result<NetworkDiagnostics, String> {
+    Ok(network_diagnostics(&state).await)
+}
+
+async fn network_diagnostics(state: &AppState) -> NetworkDiagnostics {
     let discovery_lock = state.discovery.read().await;
     if let Some(discovery) = &*discovery_lock {
-        Ok(discovery.get_diagnostics())
+        discovery.get_diagnostics()
     } else {
         // Return basic diagnostics even without discovery service
-        Ok(NetworkDiagnostics {
+        NetworkDiagnostics {
             interfaces: get_network_interfaces(),
             local_ips: crate::discovery::mdns::get_local_ips(),
             mdns_port: 5353,
             app_port: 51731,
             subnet_info: "Unknown".to_string(),
-        })
+        }
+    }
+}
+
+/// Pair two throwaway in-process devices over loopback and check both trust
+/// stores agree. Only available in builds with the `dev-tools` feature.
+#[tauri::command]
+async fn test_pairing_roundtrip() -> Result<serde_json::Value, String> {
+    #[cfg(feature = "dev-tools")]
+    {
+        let result = dev::pairing_roundtrip().await.map_err(|e| e.to_string())?;
+        serde_json::to_value(result).map_err(|e| e.to_string())
+    }
+    #[cfg(not(feature = "dev-tools"))]
+    {
+        Err("This build was made without the dev-tools feature".to_string())
+    }
+}
+
+/// Time reading the file at `path` in `chunk_size` chunks straight from disk
+/// and through the send read buffer. Only available in builds with the
+/// `dev-tools` feature.
+#[tauri::command]
+async fn benchmark_send_reads(
+    path: String,
+    chunk_size: usize,
+    state: tauri::State<'_, AppState>,
+) -> Result<serde_json::Value, String> {
+    #[cfg(feature = "dev-tools")]
+    {
+        let read_buffer = state.settings.read().await.settings.send_read_buffer();
+        let result = dev::read_throughput(std::path::Path::new(&path), chunk_size, read_buffer)
+            .await
+            .map_err(|e| e.to_string())?;
+        serde_json::to_value(result).map_err(|e| e.to_string())
+    }
+    #[cfg(not(feature = "dev-tools"))]
+    {
+        let _ = (path, chunk_size, state);
+        Err("This build was made without the dev-tools feature".to_string())
     }
 }
 
+/// Write network diagnostics, service health, sanitized settings and recent
+/// transfers (device ids hashed) into the folder at `path`, for attaching to
+/// bug reports
+#[tauri::command]
+async fn export_diagnostics(path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let network = network_diagnostics(&state).await;
+    let discovery = state.discovery.read().await.clone();
+    let discovered_devices = match &discovery {
+        Some(discovery) => discovery.get_devices().await.len(),
+        None => 0,
+    };
+    let active_transfers = state
+        .transfers
+        .read()
+        .await
+        .values()
+        .filter(|entry| {
+            matches!(
+                entry.status,
+                TransferStatus::InProgress | TransferStatus::Paused
+            )
+        })
+        .count();
+
+    let (transfers, failed_syncs, database_open) = {
+        let db_lock = state.database.read().await;
+        match &*db_lock {
+            Some(db) => {
+                // So the bundle's history matches what the database file holds
+                if let Err(e) = db.checkpoint().await {
+                    println!("[Database] Failed to checkpoint before export: {:?}", e);
+                }
+                (
+                    db.get_transfer_history(crate::diagnostics::BUNDLE_TRANSFER_LIMIT)
+                        .await
+                        .unwrap_or_default(),
+                    db.get_failed_syncs().await.map(|f| f.len()).unwrap_or(0),
+                    true,
+                )
+            }
+            None => (Vec::new(), 0, false),
+        }
+    };
+
+    let health = crate::diagnostics::ServiceHealth {
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: crate::transfer::protocol::PROTOCOL_VERSION,
+        os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        discovery_running: discovery.is_some(),
+        transfer_running: state.transfer.read().await.is_some(),
+        database_open,
+        transport_error: transport_error(&state).await,
+        discovered_devices,
+        trusted_devices: state.security.read().await.trusted_ids().len(),
+        active_transfers,
+        sync_folder_configured: state.sync.read().await.shared_folder.is_some(),
+        failed_syncs,
+    };
+    let settings = state.settings.read().await.settings.clone();
+
+    crate::diagnostics::write_bundle(
+        std::path::Path::new(&path),
+        crate::diagnostics::Bundle {
+            network,
+            health,
+            settings,
+            transfers,
+        },
+    )
+    .map_err(|e| e.to_string())?;
+    println!("[Command] Wrote diagnostics bundle to {}", path);
+    Ok(())
+}
+
 #[tauri::command]
 fn get_local_network_interfaces() -> Vec<NetworkInterface> {
     get_network_interfaces()
@@ -239,6 +2160,9 @@ async fn request_pairing(
     ip: String,
     port: u16,
 ) -> Result<String, String> {
+    if state.security.read().await.is_blocked(&device_id) {
+        return Err(format!("Device {} is blocked", device_id));
+    }
     // Generate a random 6-digit pairing code
     let pairing_code = {
         use rand::Rng;
@@ -246,36 +2170,35 @@ async fn request_pairing(
         format!("{:06}", rng.gen_range(0..1_000_000))
     };
 
-    // Get our device info for the pairing request
-    let discovery_lock = state.discovery.read().await;
-    let my_id = discovery_lock
-        .as_ref()
-        .map(|d| d.get_my_id())
-        .unwrap_or_else(|| "unknown".to_string());
-    drop(discovery_lock);
-
     // Get hostname as device name
     let my_name = hostname::get()
         .ok()
         .and_then(|h| h.into_string().ok())
         .unwrap_or_else(|| "ProxiNode".to_string());
 
-    // Send pairing request message
-    let tm_opt = state.transfer.read().await.clone();
-    if let Some(tm) = tm_opt {
-        let _ = tm
-            .send_message(
-                ip.clone(),
-                port,
-                crate::transfer::protocol::MessageType::PairRequest {
-                    device_id: my_id,
-                    device_name: my_name,
-                    pairing_code: pairing_code.clone(),
-                },
-            )
-            .await
-            .map_err(|e| e.to_string())?;
-    }
+    // The peer must have the request before we trust it, or only one side
+    // would know about the pairing
+    let tm = state
+        .transfer
+        .read()
+        .await
+        .clone()
+        .ok_or("Transfer service not initialized")?;
+    tm.send_message(
+        ip.clone(),
+        port,
+        // Signed so the peer can verify it really comes from us
+        crate::transfer::protocol::MessageType::pair_request(
+            &state.identity,
+            &my_name,
+            &pairing_code,
+        ),
+    )
+    .await
+    .map_err(|e| {
+        println!("[Pairing] Pairing request to {} failed: {}", device_id, e);
+        format!("Pairing request couldn't be delivered: {}", e)
+    })?;
 
     // For now, just trust the device directly (simplified pairing)
     let mut security = state.security.write().await;
@@ -288,45 +2211,185 @@ async fn request_pairing(
 }
 
 #[tauri::command]
-async fn pause_transfer(
+async fn pause_transfer(
+    state: tauri::State<'_, AppState>,
+    transfer_id: String,
+) -> Result<(), String> {
+    if set_transfer_status(&state.transfers, &transfer_id, TransferStatus::Paused).await {
+        Ok(())
+    } else {
+        Err("Transfer not found".to_string())
+    }
+}
+
+#[tauri::command]
+async fn resume_transfer(
+    state: tauri::State<'_, AppState>,
+    transfer_id: String,
+) -> Result<(), String> {
+    if set_transfer_status(&state.transfers, &transfer_id, TransferStatus::InProgress).await {
+        Ok(())
+    } else {
+        Err("Transfer not found".to_string())
+    }
+}
+
+#[tauri::command]
+async fn cancel_transfer(
+    state: tauri::State<'_, AppState>,
+    transfer_id: String,
+) -> Result<(), String> {
+    if set_transfer_status(&state.transfers, &transfer_id, TransferStatus::Cancelled).await {
+        Ok(())
+    } else {
+        Err("Transfer not found".to_string())
+    }
+}
+
+/// Free the network for something else: pause every running transfer,
+/// refuse incoming connections and hold back notifications until disabled,
+/// which resumes the transfers it paused. Paused transfers keep their
+/// connections alive, so they pick up where they stopped. Returns how many
+/// transfers were paused or resumed; emits `dnd-changed`.
+#[tauri::command]
+async fn set_do_not_disturb(
+    enabled: bool,
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    let mut dnd = state.do_not_disturb.write().await;
+    if dnd.enabled == enabled {
+        return Ok(0);
+    }
+    let mut transfers = state.transfers.write().await;
+    let changed = if enabled {
+        dnd.paused = transfers
+            .iter_mut()
+            .filter(|(_, entry)| entry.status == TransferStatus::InProgress)
+            .map(|(id, entry)| {
+                entry.status = TransferStatus::Paused;
+                id.clone()
+            })
+            .collect();
+        dnd.paused.len()
+    } else {
+        let mut resumed = 0;
+        for id in std::mem::take(&mut dnd.paused) {
+            if let Some(entry) = transfers.get_mut(&id) {
+                if entry.status == TransferStatus::Paused {
+                    entry.status = TransferStatus::InProgress;
+                    resumed += 1;
+                }
+            }
+        }
+        resumed
+    };
+    drop(transfers);
+    dnd.enabled = enabled;
+    println!(
+        "[Transfer] Do not disturb {}, {} transfers {}",
+        if enabled { "on" } else { "off" },
+        changed,
+        if enabled { "paused" } else { "resumed" }
+    );
+    emit_event(
+        &app_handle,
+        DndChanged {
+            enabled,
+            transfers: changed,
+        },
+    );
+    Ok(changed)
+}
+
+#[tauri::command]
+async fn get_do_not_disturb(state: tauri::State<'_, AppState>) -> Result<bool, String> {
+    Ok(state.do_not_disturb.read().await.enabled)
+}
+
+#[tauri::command]
+async fn pause_device_transfers(
+    state: tauri::State<'_, AppState>,
+    device_id: String,
+) -> Result<usize, String> {
+    Ok(set_device_transfers_status(
+        &state.transfers,
+        &device_id,
+        &[TransferStatus::InProgress],
+        TransferStatus::Paused,
+    )
+    .await)
+}
+
+#[tauri::command]
+async fn resume_device_transfers(
     state: tauri::State<'_, AppState>,
-    transfer_id: String,
-) -> Result<(), String> {
-    let mut transfers = state.transfers.write().await;
-    if transfers.contains_key(&transfer_id) {
-        transfers.insert(transfer_id, TransferStatus::Paused);
-        Ok(())
-    } else {
-        Err("Transfer not found".to_string())
-    }
+    device_id: String,
+) -> Result<usize, String> {
+    Ok(set_device_transfers_status(
+        &state.transfers,
+        &device_id,
+        &[TransferStatus::Paused],
+        TransferStatus::InProgress,
+    )
+    .await)
 }
 
+/// Cancel every unfinished member of a batch: the one being sent stops, and
+/// the ones still queued are recorded as cancelled without being sent.
+/// Emits `batch-cancelled` and returns how many members were cancelled.
 #[tauri::command]
-async fn resume_transfer(
+async fn cancel_batch(
+    app_handle: tauri::AppHandle,
     state: tauri::State<'_, AppState>,
-    transfer_id: String,
-) -> Result<(), String> {
-    let mut transfers = state.transfers.write().await;
-    if transfers.contains_key(&transfer_id) {
-        transfers.insert(transfer_id, TransferStatus::InProgress);
-        Ok(())
-    } else {
-        Err("Transfer not found".to_string())
+    batch_id: String,
+) -> Result<usize, String> {
+    let cancelled = {
+        let mut transfers = state.transfers.write().await;
+        let mut cancelled = 0;
+        for entry in transfers.values_mut() {
+            if entry.batch_id.as_deref() == Some(batch_id.as_str())
+                && matches!(
+                    entry.status,
+                    TransferStatus::InProgress | TransferStatus::Paused
+                )
+            {
+                entry.status = TransferStatus::Cancelled;
+                cancelled += 1;
+            }
+        }
+        cancelled
+    };
+    if cancelled == 0 {
+        return Err(format!("Batch {} has no unfinished transfers", batch_id));
     }
+
+    println!(
+        "[Transfer] Cancelled batch {} ({} transfers)",
+        batch_id, cancelled
+    );
+    emit_event(
+        &app_handle,
+        BatchCancelled {
+            batch_id,
+            cancelled,
+        },
+    );
+    Ok(cancelled)
 }
 
 #[tauri::command]
-async fn cancel_transfer(
+async fn cancel_device_transfers(
     state: tauri::State<'_, AppState>,
-    transfer_id: String,
-) -> Result<(), String> {
-    let mut transfers = state.transfers.write().await;
-    if transfers.contains_key(&transfer_id) {
-        transfers.insert(transfer_id, TransferStatus::Cancelled);
-        Ok(())
-    } else {
-        Err("Transfer not found".to_string())
-    }
+    device_id: String,
+) -> Result<usize, String> {
+    Ok(set_device_transfers_status(
+        &state.transfers,
+        &device_id,
+        &[TransferStatus::InProgress, TransferStatus::Paused],
+        TransferStatus::Cancelled,
+    )
+    .await)
 }
 
 #[tauri::command]
@@ -353,51 +2416,131 @@ async fn accept_pairing(
     Ok(())
 }
 
+/// Exchange recent transfer history with a paired device
 #[tauri::command]
 async fn sync_history(
     state: tauri::State<'_, AppState>,
     device_id: String,
     ip: String,
     port: u16,
+) -> Result<crate::transfer::history::HistorySyncSummary, String> {
+    let tm_opt = state.transfer.read().await.clone();
+    if let Some(tm) = tm_opt {
+        tm.sync_history(ip, port, &device_id)
+            .await
+            .map_err(|e| e.to_string())
+    } else {
+        Err("Transfer manager not initialized".to_string())
+    }
+}
+
+/// Change where received files are saved, e.g. after the previous
+/// directory became unavailable. Persisted across restarts.
+#[tauri::command]
+async fn set_download_directory(
+    path: String,
+    state: tauri::State<'_, AppState>,
 ) -> Result<(), String> {
-    println!(
-        "[Sync] Syncing history with device: {} at {}:{}",
-        device_id, ip, port
-    );
+    let dir = PathBuf::from(&path);
+    if !dir.is_dir() {
+        return Err(format!("Not a directory: {}", path));
+    }
 
-    let records = {
-        let db_lock = state.database.read().await;
-        if let Some(db) = &*db_lock {
-            db.get_transfer_history(100)
-                .await
-                .map_err(|e| e.to_string())?
-        } else {
-            return Err("Database not initialized".to_string());
-        }
-    };
+    state
+        .settings
+        .write()
+        .await
+        .update(|s| s.download_directory = Some(dir.clone()))
+        .map_err(|e| e.to_string())?;
 
     let tm_opt = state.transfer.read().await.clone();
     if let Some(tm) = tm_opt {
-        tm.send_message(
-            ip,
-            port,
-            crate::transfer::protocol::MessageType::HistorySync { records },
-        )
-        .await
+        tm.set_save_directory(dir).await;
+    }
+    Ok(())
+}
+
+/// Total and available space where received files are saved, as checked
+/// before a transfer is accepted
+#[tauri::command]
+async fn get_download_disk_space(state: tauri::State<'_, AppState>) -> Result<DiskSpace, String> {
+    let tm_opt = state.transfer.read().await.clone();
+    let dir = match tm_opt {
+        Some(tm) => tm.save_directory().await,
+        None => state
+            .settings
+            .read()
+            .await
+            .settings
+            .download_directory
+            .clone()
+            .ok_or("Transfer manager not initialized")?,
+    };
+    disk_space(&dir).map_err(|e| e.to_string())
+}
+
+/// Space for a location the user is about to choose, e.g. in a "save to" dialog
+#[tauri::command]
+async fn get_disk_space(path: String) -> Result<DiskSpace, String> {
+    disk_space(&PathBuf::from(path)).map_err(|e| e.to_string())
+}
+
+/// Check a candidate sync folder without enabling it
+#[tauri::command]
+async fn validate_sync_folder(
+    path: String,
+    app_handle: tauri::AppHandle,
+) -> Result<FolderValidation, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
         .map_err(|e| e.to_string())?;
-        Ok(())
-    } else {
-        Err("Transfer manager not initialized".to_string())
+    Ok(crate::sync::validation::validate_sync_folder(
+        &PathBuf::from(path),
+        &app_data_dir,
+    ))
+}
+
+#[tauri::command]
+async fn set_sync_folder(
+    path: String,
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+    let validation =
+        crate::sync::validation::validate_sync_folder(&PathBuf::from(&path), &app_data_dir);
+    if !validation.valid {
+        return Err(validation
+            .message
+            .unwrap_or_else(|| "Invalid sync folder".to_string()));
     }
+
+    let mut sync = state.sync.write().await;
+    sync.configure(Some(PathBuf::from(validation.path)));
+    Ok(())
 }
 
+/// Choose whether sync pushes, pulls or goes both ways
 #[tauri::command]
-async fn set_sync_folder(path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+async fn set_sync_direction(
+    direction: SyncDirection,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
     let mut sync = state.sync.write().await;
-    sync.shared_folder = Some(PathBuf::from(path));
+    sync.set_direction(direction);
+    println!("[Sync] Direction set to {:?}", direction);
     Ok(())
 }
 
+#[tauri::command]
+async fn get_sync_direction(state: tauri::State<'_, AppState>) -> Result<SyncDirection, String> {
+    Ok(state.sync.read().await.direction)
+}
+
 #[tauri::command]
 async fn get_sync_status(state: tauri::State<'_, AppState>) -> Result<Option<String>, String> {
     let sync = state.sync.read().await;
@@ -407,6 +2550,57 @@ async fn get_sync_status(state: tauri::State<'_, AppState>) -> Result<Option<Str
         .map(|p| p.to_string_lossy().into_owned()))
 }
 
+/// Local changes that couldn't be delivered after every retry
+#[tauri::command]
+async fn get_failed_syncs(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<SyncQueueEntry>, String> {
+    let db_lock = state.database.read().await;
+    match &*db_lock {
+        Some(db) => db.get_failed_syncs().await.map_err(|e| e.to_string()),
+        None => Err("Database not initialized".to_string()),
+    }
+}
+
+/// Per-device totals over the transfer history, most recently active first.
+/// Names come from our last send to the device, or discovery if it's online.
+#[tauri::command]
+async fn get_peer_summaries(state: tauri::State<'_, AppState>) -> Result<Vec<PeerSummary>, String> {
+    let mut summaries = {
+        let db_lock = state.database.read().await;
+        match &*db_lock {
+            Some(db) => db.get_peer_summaries().await.map_err(|e| e.to_string())?,
+            None => return Err("Database not initialized".to_string()),
+        }
+    };
+    let discovery = state.discovery.read().await.clone();
+    if let Some(discovery) = discovery {
+        let devices = discovery.get_devices().await;
+        for summary in summaries.iter_mut().filter(|s| s.device_name.is_none()) {
+            summary.device_name = devices
+                .iter()
+                .find(|d| d.id == summary.device_id)
+                .map(|d| d.name.clone());
+        }
+    }
+    Ok(summaries)
+}
+
+/// Devices recently sent to, most recent first, for quick re-sending
+#[tauri::command]
+async fn get_recent_destinations(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<RecentDestination>, String> {
+    let db_lock = state.database.read().await;
+    match &*db_lock {
+        Some(db) => db
+            .get_recent_destinations()
+            .await
+            .map_err(|e| e.to_string()),
+        None => Err("Database not initialized".to_string()),
+    }
+}
+
 #[tauri::command]
 async fn get_transfer_history(
     state: tauri::State<'_, AppState>,
@@ -422,6 +2616,118 @@ async fn get_transfer_history(
     }
 }
 
+/// A single transfer record, including the receiver's signed receipt for
+/// completed sends
+#[tauri::command]
+async fn get_transfer(
+    transfer_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<TransferRecord>, String> {
+    let db_lock = state.database.read().await;
+    if let Some(db) = &*db_lock {
+        db.get_transfer(&transfer_id)
+            .await
+            .map_err(|e| e.to_string())
+    } else {
+        Ok(None)
+    }
+}
+
+/// Report the database's schema version and integrity, re-adding any
+/// columns an interrupted migration left out
+#[tauri::command]
+async fn check_database_health(
+    state: tauri::State<'_, AppState>,
+) -> Result<crate::db::DatabaseHealth, String> {
+    let db_lock = state.database.read().await;
+    let db = db_lock.as_ref().ok_or("Database not initialized")?;
+    db.check_health().await.map_err(|e| e.to_string())
+}
+
+/// Flush the SQLite WAL into the database file and truncate it
+#[tauri::command]
+async fn checkpoint_database(
+    state: tauri::State<'_, AppState>,
+) -> Result<crate::db::WalCheckpoint, String> {
+    let db_lock = state.database.read().await;
+    let db = db_lock.as_ref().ok_or("Database not initialized")?;
+    let result = db.checkpoint().await.map_err(|e| e.to_string())?;
+    println!(
+        "[Database] Checkpointed {} of {} WAL frames{}",
+        result.checkpointed_frames,
+        result.log_frames,
+        if result.busy { " (busy)" } else { "" }
+    );
+    Ok(result)
+}
+
+/// Re-hash a transfer's file with the algorithm recorded for it and compare
+/// against the stored hash. `false` means the file changed since.
+#[tauri::command]
+async fn verify_transfer(
+    transfer_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<bool, String> {
+    let record = {
+        let db_lock = state.database.read().await;
+        let db = db_lock.as_ref().ok_or("Database not initialized")?;
+        db.get_transfer(&transfer_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Transfer {} not found", transfer_id))?
+    };
+    if record.file_hash.is_empty() {
+        return Err("No hash was recorded for this transfer".to_string());
+    }
+    let algo = HashAlgo::from_name(&record.hash_algo);
+    if !algo.is_supported() {
+        return Err(format!("Unsupported hash algorithm {}", record.hash_algo));
+    }
+    let hash = crate::transfer::sender::hash_file(std::path::Path::new(&record.file_path), algo)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(hash == record.file_hash)
+}
+
+/// Annotate a transfer for later searching; an empty note clears it
+#[tauri::command]
+async fn set_transfer_note(
+    app_handle: tauri::AppHandle,
+    transfer_id: String,
+    note: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    let note = note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    let db_lock = state.database.read().await;
+    let db = db_lock.as_ref().ok_or("Database not initialized")?;
+    let found = db
+        .set_transfer_note(&transfer_id, note.as_deref())
+        .await
+        .map_err(|e| e.to_string())?;
+    if !found {
+        return Err(format!("Transfer {} not found", transfer_id));
+    }
+    emit_event(&app_handle, HistoryUpdated);
+    Ok(())
+}
+
+/// Transfers whose file name or note contains `query`
+#[tauri::command]
+async fn search_transfers(
+    query: String,
+    limit: Option<i32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<TransferRecord>, String> {
+    let db_lock = state.database.read().await;
+    if let Some(db) = &*db_lock {
+        db.search_transfers(&query, limit.unwrap_or(100))
+            .await
+            .map_err(|e| e.to_string())
+    } else {
+        Ok(vec![])
+    }
+}
+
 #[tauri::command]
 async fn get_device_transfers(
     state: tauri::State<'_, AppState>,
@@ -438,6 +2744,63 @@ async fn get_device_transfers(
     }
 }
 
+/// Transfers running or paused right now, with the progress last persisted,
+/// so a reloaded window can pick them back up before the next progress event
+#[tauri::command]
+async fn get_active_transfers(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<TransferRecord>, String> {
+    let active: Vec<(String, TransferStatus)> = state
+        .transfers
+        .read()
+        .await
+        .iter()
+        .filter(|(_, entry)| {
+            matches!(
+                entry.status,
+                TransferStatus::InProgress | TransferStatus::Paused
+            )
+        })
+        .map(|(id, entry)| (id.clone(), entry.status))
+        .collect();
+
+    let db_lock = state.database.read().await;
+    let Some(db) = &*db_lock else {
+        return Ok(Vec::new());
+    };
+    let mut records = Vec::with_capacity(active.len());
+    for (id, status) in active {
+        if let Some(mut record) = db.get_transfer(&id).await.map_err(|e| e.to_string())? {
+            if status == TransferStatus::Paused {
+                record.status = "paused".to_string();
+            }
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+#[tauri::command]
+async fn get_transfers_by_status(
+    state: tauri::State<'_, AppState>,
+    status: String,
+    limit: Option<i32>,
+    offset: Option<i32>,
+) -> Result<Vec<TransferRecord>, String> {
+    if !crate::db::TRANSFER_STATUSES.contains(&status.as_str()) {
+        return Err(format!("Unknown transfer status: {}", status));
+    }
+
+    let db_lock = state.database.read().await;
+    if let Some(db) = &*db_lock {
+        db.get_transfers_by_status(&status, limit.unwrap_or(100), offset.unwrap_or(0))
+            .await
+            .map_err(|e| e.to_string())
+    } else {
+        Ok(vec![])
+    }
+}
+
 #[tauri::command]
 async fn clear_transfer_history(state: tauri::State<'_, AppState>) -> Result<(), String> {
     let db_lock = state.database.read().await;
@@ -454,6 +2817,22 @@ pub fn run() {
         .setup(|app| {
             println!("Setup hook started");
             let app_handle = app.handle().clone();
+
+            // A click on a "File received" notification reveals the file
+            app_handle.notification().on_action(|action| {
+                if action.action_id() != "tap" {
+                    return;
+                }
+                let path = action
+                    .notification()
+                    .and_then(|n| n.extra().get("path"))
+                    .and_then(|path| path.as_str());
+                if let Some(path) = path {
+                    if let Err(e) = reveal_file(std::path::Path::new(path)) {
+                        println!("[Transfer] Failed to reveal {}: {}", path, e);
+                    }
+                }
+            })?;
             let downloads_dir = app_handle
                 .path()
                 .download_dir()
@@ -474,9 +2853,10 @@ pub fn run() {
                 let _ = std::fs::create_dir_all(&app_data_dir);
             }
             let security = SecurityService::new(app_data_dir.clone());
+            let settings = SettingsStore::new(app_data_dir.clone());
 
             // Initialize Database
-            let db_path = app_data_dir.join("proxishare.db");
+            let db_path = app_data_dir.join(DB_FILE_NAME);
             let database_opt = tauri::async_runtime::block_on(async {
                 match Database::new(&db_path).await {
                     Ok(db) => {
@@ -491,96 +2871,361 @@ pub fn run() {
             });
             let database = Arc::new(RwLock::new(database_opt));
 
-            // Initialize Device ID and Name
-            let device_id_path = app_data_dir.join("device_id.txt");
-            let device_id = if device_id_path.exists() {
-                std::fs::read_to_string(&device_id_path)
-                    .unwrap_or_else(|_| uuid::Uuid::new_v4().to_string())
-            } else {
-                let id = uuid::Uuid::new_v4().to_string();
-                let _ = std::fs::write(&device_id_path, &id);
-                id
-            };
+            // Initialize Device Identity (keypair-derived ID) and Name
+            let identity = Arc::new(
+                DeviceIdentity::load_or_generate(&app_data_dir)
+                    .map_err(|e| e as Box<dyn std::error::Error>)?,
+            );
+            let device_id = identity.device_id().to_string();
 
             let device_name = hostname::get()
                 .ok()
                 .and_then(|h| h.into_string().ok())
                 .unwrap_or_else(|| "ProxiNode".to_string());
 
+            // A chosen download directory wins unless its drive isn't there
+            let download_directory = settings
+                .settings
+                .download_directory
+                .clone()
+                .filter(|dir| dir.is_dir())
+                .unwrap_or(downloads_dir);
+
+            // Prefer ring explicitly; this fails harmlessly if a provider is already installed
+            let _ = rustls::crypto::ring::default_provider().install_default();
+
+            let port = 51731;
             println!("Initializing services with block_on");
-            let (discovery, transfer_manager) = tauri::async_runtime::block_on(async {
+            let (discovery, transfer_init) = tauri::async_runtime::block_on(async {
+                // Without secure transport the app still runs, discovery-only
                 println!("Inside block_on: Initializing TransferManager");
-                // Initialize Transfer Manager
-                let port = 51731;
-                let tm = TransferManager::new(
-                    port,
-                    app_handle.clone(),
-                    database.clone(),
-                    transfers.clone(),
-                    device_id.clone(),
-                    device_name.clone(),
-                )?;
+                let validity_days = settings.settings.certificate_validity_days();
+                let transfer_init = CertificateManager::load_or_generate(&app_data_dir, validity_days)
+                    .map_err(|e| ProxiError::Crypto(e.to_string()))
+                    .and_then(|cert_manager| {
+                        TransferManager::new(
+                            port,
+                            app_handle.clone(),
+                            database.clone(),
+                            transfers.clone(),
+                            device_id.clone(),
+                            device_name.clone(),
+                            &cert_manager,
+                        )
+                    })
+                    .map(Arc::new);
                 println!("Inside block_on: TransferManager initialized");
 
                 println!("Inside block_on: Initializing DiscoveryService");
                 // Initialize Discovery Service
-                let ds = DiscoveryService::new(device_id, device_name, port)?;
+                let ds = DiscoveryService::new(
+                    device_id,
+                    device_name,
+                    port,
+                    app_handle.clone(),
+                    &app_data_dir,
+                )?;
                 println!("Inside block_on: DiscoveryService initialized");
 
-                Ok::<(Arc<DiscoveryService>, Arc<TransferManager>), GenericError>((
-                    Arc::new(ds),
-                    Arc::new(tm),
-                ))
+                Ok::<(Arc<DiscoveryService>, Result<Arc<TransferManager>, ProxiError>), GenericError>(
+                    (Arc::new(ds), transfer_init),
+                )
             })
             .map_err(|e| e as Box<dyn std::error::Error>)?;
 
-            println!("Starting listening and broadcasting");
-            let tm_clone = Arc::clone(&transfer_manager);
-            let ds_downloads_dir = downloads_dir.clone();
-            tauri::async_runtime::spawn(async move {
-                tm_clone.start_listening(ds_downloads_dir).await;
-            });
+            let (transfer_manager, transport_error) = match transfer_init {
+                Ok(tm) => {
+                    println!("Starting listening");
+                    let tm_clone = Arc::clone(&tm);
+                    let ds_downloads_dir = download_directory;
+                    tauri::async_runtime::spawn(async move {
+                        tm_clone.start_listening(ds_downloads_dir).await;
+                    });
+                    (Some(tm), None)
+                }
+                Err(e) => {
+                    println!("[Setup] Secure transport unavailable, running discovery-only: {}", e);
+                    (
+                        None,
+                        Some(format!(
+                            "Secure transport couldn't be initialized, so files can't be sent or received: {}",
+                            e
+                        )),
+                    )
+                }
+            };
 
-            let _ = discovery.start_broadcasting();
+            if let Some(ip) = settings.settings.preferred_broadcast_ip.clone() {
+                if let Err(e) = discovery.set_preferred_ip(Some(ip)) {
+                    println!("[Setup] Ignoring preferred broadcast IP: {}", e);
+                }
+            }
+            // Don't advertise a port nobody is listening on. Broadcasting is on
+            // until turned off, and reannouncing (e.g. `start_discovery`) would
+            // otherwise bring it back.
+            let _ = discovery.set_broadcasting(
+                transfer_manager.is_some() && !settings.settings.broadcasting_disabled,
+            );
             let _ = discovery.start_discovery();
 
+            let (sync_tx, sync_rx) = tokio::sync::mpsc::channel(256);
             let app_state = AppState {
                 discovery: Arc::new(RwLock::new(Some(discovery))),
-                transfer: Arc::new(RwLock::new(Some(transfer_manager))),
-                sync: Arc::new(RwLock::new(SyncState::new())),
+                transfer: Arc::new(RwLock::new(transfer_manager)),
+                transport_error,
+                sync: Arc::new(RwLock::new(SyncState::with_change_sender(sync_tx))),
                 security: Arc::new(RwLock::new(security)),
+                identity,
+                rate_limiter: Arc::new(RateLimiter::new(&settings.settings.device_rate_limits)),
+                hash_pool: Arc::new(HashPool::new(settings.settings.hashing_threads)),
+                settings: Arc::new(RwLock::new(settings)),
                 database: database.clone(),
                 transfers,
+                pending_sends: Arc::new(RwLock::new(Vec::new())),
+                do_not_disturb: Arc::new(RwLock::new(DoNotDisturb::default())),
+                verbose_progress: Arc::new(AtomicBool::new(false)),
+                folder_analyses: Arc::new(RwLock::new(HashMap::new())),
+                passphrase: Arc::new(RwLock::new(None)),
+                trust_offers: Arc::new(RwLock::new(HashSet::new())),
             };
             app.manage(app_state);
+            let launch_files = launch_file_args();
+            let queue_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                queue_pending_sends(&queue_handle, launch_files).await;
+            });
+            crate::sync::queue::spawn_change_handler(app_handle.clone(), sync_rx);
+            crate::sync::queue::spawn_worker(app_handle.clone());
+            spawn_trust_expiry(app_handle.clone());
 
             println!("Setup hook finished");
             Ok(())
         })
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_notification::init())
         .invoke_handler(tauri::generate_handler![
             start_discovery,
+            stop_discovery,
+            get_discovery_state,
+            reannounce,
+            set_broadcasting,
+            set_preferred_broadcast_ip,
+            get_transport_error,
+            take_pending_sends,
             get_discovered_devices,
+            forget_device,
             send_file,
+            send_files,
+            select_directory_files,
+            analyze_folder,
+            cancel_folder_analysis,
+            send_directory,
+            send_bytes,
             get_trusted_devices,
+            get_blocked_devices,
+            block_device,
+            unblock_device,
+            add_temporary_trust,
+            export_trusted_devices,
+            import_trusted_devices,
             is_device_trusted,
+            set_shared_passphrase,
+            set_trust_on_first_transfer,
+            accept_transfer_trust,
+            set_notifications_enabled,
+            get_notifications_enabled,
+            set_hash_algo,
+            set_hashing_threads,
+            set_sync_scan_workers,
+            set_history_sync_max_records,
+            set_disk_space_margin,
+            set_name_normalization,
+            set_max_name_bytes,
+            set_max_chunk_size,
+            set_send_read_buffer,
+            get_max_chunk_size,
+            set_stall_timeout,
+            set_auto_retry,
+            set_verbose_progress,
+            set_post_process_folders,
+            set_receive_mime_allowlist,
+            set_resumable,
+            set_chunk_encryption,
+            reveal_in_folder,
+            get_paths,
+            add_device_by_hostname,
+            regenerate_device_id,
+            verify_transfer,
+            check_database_health,
+            checkpoint_database,
+            set_transfer_note,
+            search_transfers,
+            regenerate_certificate,
+            set_device_rate_limit,
+            get_device_rate_limits,
             test_device_connectivity,
+            run_transfer_benchmark,
             find_reachable_device_ip,
+            sweep_known_devices,
+            get_device_capabilities,
+            preview_sync,
+            get_connection_stats,
             get_network_diagnostics,
+            export_diagnostics,
+            test_pairing_roundtrip,
+            benchmark_send_reads,
             get_local_network_interfaces,
             request_pairing,
             accept_pairing,
+            set_download_directory,
+            list_orphaned_partials,
+            clean_orphaned_partials,
+            get_download_disk_space,
+            get_disk_space,
+            validate_sync_folder,
             set_sync_folder,
+            set_sync_direction,
+            get_sync_direction,
             get_sync_status,
+            get_failed_syncs,
+            get_recent_destinations,
+            get_peer_summaries,
             get_transfer_history,
+            get_transfer,
             get_device_transfers,
+            get_transfers_by_status,
+            get_active_transfers,
             clear_transfer_history,
             pause_transfer,
             resume_transfer,
             cancel_transfer,
+            set_do_not_disturb,
+            get_do_not_disturb,
+            pause_device_transfers,
+            resume_device_transfers,
+            cancel_device_transfers,
+            cancel_batch,
             sync_history
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app_handle, _event| {
+            // macOS delivers "Open with" and dropped-on-dock files as events
+            #[cfg(any(target_os = "macos", target_os = "ios"))]
+            if let tauri::RunEvent::Opened { urls } = _event {
+                let paths = urls
+                    .into_iter()
+                    .filter_map(|url| url.to_file_path().ok())
+                    .collect();
+                let app_handle = _app_handle.clone();
+                tauri::async_runtime::spawn(async move {
+                    queue_pending_sends(&app_handle, paths).await;
+                });
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::protocol::CLOSE_CANCELLED;
+    use crate::transfer::test_support;
+    use std::time::Duration;
+
+    /// Statuses a send with `send_result` leaves in a fresh database and registry
+    async fn outcome_of(send_result: Result<(), ProxiError>) -> (String, TransferStatus) {
+        let dir = std::env::temp_dir().join(format!("proxishare-lib-{}", uuid::Uuid::new_v4()));
+        let db = Database::new(&dir.join("history.db")).await.unwrap();
+        db.record_transfer(
+            "t1",
+            "peer",
+            "file.bin",
+            "/tmp/file.bin",
+            10,
+            "send",
+            "",
+            HashAlgo::Blake3,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let transfers = registry_with("t1");
+
+        record_send_outcome(Some(&db), &transfers, "t1", 10, &send_result).await;
+
+        let status = db.get_transfer("t1").await.unwrap().unwrap().status;
+        let registry_status = get_transfer_status(&transfers, "t1").await;
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+        (status, registry_status)
+    }
+
+    fn registry_with(transfer_id: &str) -> TransferRegistry {
+        let entry = TransferEntry {
+            status: TransferStatus::InProgress,
+            device_id: "peer".into(),
+            batch_id: None,
+        };
+        Arc::new(RwLock::new(HashMap::from([(
+            transfer_id.to_string(),
+            entry,
+        )])))
+    }
+
+    #[tokio::test]
+    async fn a_send_cancelled_by_the_user_is_recorded_as_cancelled() {
+        let transfers = registry_with("t1");
+        let cancel = {
+            let transfers = transfers.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                set_transfer_status(&transfers, "t1", TransferStatus::Cancelled).await;
+            }
+        };
+        let (result, _) = tokio::join!(
+            crate::transfer::until_cancelled(
+                &transfers,
+                "t1",
+                std::future::pending::<Result<(), _>>()
+            ),
+            cancel,
+        );
+
+        assert_eq!(
+            outcome_of(result).await,
+            ("cancelled".to_string(), TransferStatus::Cancelled)
+        );
+    }
+
+    #[tokio::test]
+    async fn a_send_cancelled_by_the_receiver_is_recorded_as_cancelled() {
+        let loopback = test_support::connect().await;
+        loopback
+            .server
+            .close(quinn::VarInt::from_u32(CLOSE_CANCELLED), b"cancelled");
+        let result = Err(ProxiError::from(loopback.client.closed().await));
+
+        assert_eq!(
+            outcome_of(result).await,
+            ("cancelled".to_string(), TransferStatus::Cancelled)
+        );
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_recorded_as_failed() {
+        let loopback = test_support::connect().await;
+        loopback.server.close(quinn::VarInt::from_u32(0), b"gone");
+        let result = Err(ProxiError::from(loopback.client.closed().await));
+
+        assert_eq!(
+            outcome_of(result).await,
+            ("failed".to_string(), TransferStatus::Failed)
+        );
+        assert_eq!(
+            outcome_of(Ok(())).await,
+            ("completed".to_string(), TransferStatus::Completed)
+        );
+    }
 }
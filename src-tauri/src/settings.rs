@@ -0,0 +1,185 @@
+use crate::crypto::security::write_atomic;
+use crate::transfer::protocol::HashAlgo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// User preferences persisted across restarts
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Settings {
+    /// Key derived from the shared passphrase under `passphrase_salt` (hex),
+    /// never the passphrase itself. `None` leaves the app open to any peer.
+    pub passphrase_key: Option<String>,
+    /// Random salt of this install (hex), sent with each passphrase challenge
+    /// so the sender derives the same key
+    pub passphrase_salt: Option<String>,
+    /// The passphrase's keys under other devices' salts, by salt, so their
+    /// challenges can still be answered after a restart
+    pub peer_passphrase_keys: HashMap<String, String>,
+    /// Validity of newly generated TLS certificates; `None` uses the default
+    pub certificate_validity_days: Option<u32>,
+    /// Where received files go; `None` uses the OS downloads folder
+    pub download_directory: Option<PathBuf>,
+    /// Aggregate bandwidth caps in bytes per second, keyed by device id.
+    /// Devices not listed are unlimited.
+    pub device_rate_limits: HashMap<String, u64>,
+    /// Offer to trust a device once it completes a transfer to us; trust is
+    /// only added when the user accepts. Off by default.
+    pub trust_on_first_transfer: bool,
+    /// Show an OS notification when a received file completes
+    pub notifications_enabled: bool,
+    /// Hash offered to peers for verifying sent files
+    pub hash_algo: HashAlgo,
+    /// Stay invisible on the network while still discovering other devices
+    pub broadcasting_disabled: bool,
+    /// Local IP advertised first over mDNS; `None` keeps interface order
+    pub preferred_broadcast_ip: Option<String>,
+    /// Chunks hashed in parallel while sending; `None` uses one per core
+    pub hashing_threads: Option<usize>,
+    /// Fail a running transfer after this long without progress; `None` uses
+    /// the default and `Some(0)` never gives up. Paused time doesn't count.
+    pub stall_timeout_secs: Option<u64>,
+    /// Subfolders of the download folder that completed files are moved
+    /// into, keyed by extension without the dot (e.g. "jpg" -> "Pictures")
+    pub post_process_folders: HashMap<String, String>,
+    /// Always send from the first byte, ignoring partial files the receiver has
+    pub resume_disabled: bool,
+    /// Also encrypt chunk payloads with a key from the shared passphrase,
+    /// on top of QUIC's TLS. Sends fail rather than go out without it.
+    pub encrypt_chunks: bool,
+    /// Only keep received files whose sniffed content matches one of these
+    /// MIME types (`image/png`, `image/*`); empty accepts anything
+    pub receive_mime_allowlist: Vec<String>,
+    /// Files hashed in parallel when scanning a sync folder; `None` uses
+    /// half the cores
+    pub sync_scan_workers: Option<usize>,
+    /// Upper bound on the automatically chosen chunk size, in bytes; `None`
+    /// leaves the choice to file size alone
+    pub max_chunk_size: Option<usize>,
+    /// Most records sent or accepted in one history sync; `None` uses the default
+    pub history_sync_max_records: Option<usize>,
+    /// Bytes read from disk at a time when sending a file, independent of
+    /// the chunk size; `None` uses the default
+    pub send_read_buffer: Option<usize>,
+    /// Free space in bytes that receiving must leave on the download
+    /// volume; `None` uses the default and `Some(0)` allows filling it
+    pub disk_space_margin: Option<u64>,
+    /// Offer file names as they are instead of composing them to NFC
+    pub name_normalization_disabled: bool,
+    /// Longest file name offered, in bytes; longer names are shortened
+    /// keeping their extension. `None` allows 255.
+    pub max_name_bytes: Option<usize>,
+    /// Times a send that failed on the network is retried automatically;
+    /// `None` doesn't retry
+    pub auto_retry_attempts: Option<u32>,
+    /// Wait before the first automatic retry, in seconds; `None` uses the default
+    pub auto_retry_backoff_secs: Option<u64>,
+}
+
+impl Settings {
+    pub fn certificate_validity_days(&self) -> u32 {
+        self.certificate_validity_days
+            .unwrap_or(crate::crypto::encryption::DEFAULT_CERT_VALIDITY_DAYS)
+    }
+
+    pub fn sync_scan_workers(&self) -> usize {
+        self.sync_scan_workers
+            .filter(|n| *n > 0)
+            .unwrap_or_else(crate::sync::scan::default_scan_workers)
+    }
+
+    pub fn send_read_buffer(&self) -> usize {
+        self.send_read_buffer
+            .map_or(crate::transfer::sender::DEFAULT_READ_BUFFER, |size| {
+                crate::transfer::sender::clamp_read_buffer(size)
+            })
+    }
+
+    pub fn history_sync_max_records(&self) -> usize {
+        self.history_sync_max_records
+            .unwrap_or(crate::transfer::history::DEFAULT_MAX_RECORDS)
+    }
+
+    pub fn max_name_bytes(&self) -> usize {
+        use crate::transfer::filename::{MAX_NAME_BYTES, MIN_NAME_BYTES};
+        self.max_name_bytes
+            .map_or(MAX_NAME_BYTES, |n| n.clamp(MIN_NAME_BYTES, MAX_NAME_BYTES))
+    }
+
+    /// `name` as it's offered to peers, see `filename::offer_name`
+    pub fn offer_name(&self, name: &str) -> String {
+        crate::transfer::filename::offer_name(
+            name,
+            !self.name_normalization_disabled,
+            self.max_name_bytes(),
+        )
+    }
+
+    pub fn disk_space_margin(&self) -> u64 {
+        self.disk_space_margin
+            .unwrap_or(crate::transfer::DEFAULT_DISK_SPACE_MARGIN)
+    }
+
+    pub fn auto_retry_attempts(&self) -> u32 {
+        self.auto_retry_attempts
+            .unwrap_or(0)
+            .min(crate::transfer::MAX_AUTO_RETRY_ATTEMPTS)
+    }
+
+    /// Wait before automatic retry number `attempt` (from 1): the backoff,
+    /// doubled for each retry before it
+    pub fn auto_retry_delay(&self, attempt: u32) -> Duration {
+        let base = self
+            .auto_retry_backoff_secs
+            .unwrap_or(crate::transfer::DEFAULT_AUTO_RETRY_BACKOFF_SECS);
+        Duration::from_secs(base.saturating_mul(1 << attempt.saturating_sub(1).min(16)))
+    }
+
+    pub fn stall_timeout(&self) -> Option<Duration> {
+        match self
+            .stall_timeout_secs
+            .unwrap_or(crate::transfer::DEFAULT_STALL_TIMEOUT_SECS)
+        {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+}
+
+pub struct SettingsStore {
+    store_path: PathBuf,
+    pub settings: Settings,
+}
+
+impl SettingsStore {
+    pub fn new(app_dir: PathBuf) -> Self {
+        let store_path = app_dir.join("settings.json");
+        let settings = fs::read_to_string(&store_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        Self {
+            store_path,
+            settings,
+        }
+    }
+
+    /// Apply a change and persist it
+    pub fn update<F: FnOnce(&mut Settings)>(
+        &mut self,
+        change: F,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        change(&mut self.settings);
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let content = serde_json::to_string_pretty(&self.settings)?;
+        write_atomic(&self.store_path, content.as_bytes())?;
+        Ok(())
+    }
+}
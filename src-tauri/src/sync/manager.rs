@@ -1,3 +1,5 @@
+use crate::sync::SyncDirection;
+use crate::transfer::filename::{resolve_within, sanitize_file_name};
 use crate::transfer::protocol::FileMetadata;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -6,10 +8,11 @@ use std::time::SystemTime;
 pub struct SyncManager {
     shared_folder: PathBuf,
     backup_folder: PathBuf,
+    direction: SyncDirection,
 }
 
 impl SyncManager {
-    pub fn new(shared_folder: PathBuf) -> Self {
+    pub fn new(shared_folder: PathBuf, direction: SyncDirection) -> Self {
         let backup_folder = shared_folder.join(".proxishare/backups");
         if !backup_folder.exists() {
             let _ = fs::create_dir_all(&backup_folder);
@@ -17,9 +20,18 @@ impl SyncManager {
         Self {
             shared_folder,
             backup_folder,
+            direction,
         }
     }
 
+    pub fn shared_folder(&self) -> &Path {
+        &self.shared_folder
+    }
+
+    pub fn direction(&self) -> SyncDirection {
+        self.direction
+    }
+
     /// Resolves a conflict between a local file and a remote modification.
     /// Returns true if the remote change should overwrite the local file.
     pub fn should_overwrite(
@@ -28,6 +40,13 @@ impl SyncManager {
         _remote_metadata: &FileMetadata,
         remote_timestamp: u64,
     ) -> bool {
+        // One-way modes always favor the source side
+        match self.direction {
+            SyncDirection::Push => return false,
+            SyncDirection::Pull => return true,
+            SyncDirection::Bidirectional => {}
+        }
+
         let local_path = self.shared_folder.join(relative_path);
         if !local_path.exists() {
             return true;
@@ -45,10 +64,52 @@ impl SyncManager {
             .unwrap_or_default()
             .as_secs();
 
-        // LWW Strategy
+        // LWW Strategy. Received files keep the sender's mtime, so a file we got
+        // from this peer compares equal and isn't overwritten again.
         remote_timestamp > local_timestamp
     }
 
+    /// Files from a peer's `SyncRequest` that we want it to send us. Names
+    /// that would resolve outside the shared folder are skipped.
+    pub fn missing_files(&self, files: &[FileMetadata]) -> Vec<String> {
+        if !self.direction.accepts_remote_changes() {
+            return Vec::new();
+        }
+        files
+            .iter()
+            .filter(|file| match self.resolve(&file.name) {
+                Some(relative) => {
+                    self.should_overwrite(&relative, file, file.modified.unwrap_or(0))
+                }
+                None => {
+                    println!("[Sync] Skipping unsafe path from peer: {:?}", file.name);
+                    false
+                }
+            })
+            .map(|file| file.name.clone())
+            .collect()
+    }
+
+    /// A peer's `/`-separated relative path, if every component is already
+    /// a safe file name and the path stays inside the shared folder (also
+    /// through symlinks that exist)
+    fn resolve(&self, name: &str) -> Option<PathBuf> {
+        let mut dir = self.shared_folder.clone();
+        let mut relative = PathBuf::new();
+        for component in name.split('/') {
+            if sanitize_file_name(component) != component {
+                return None;
+            }
+            if dir.is_dir() {
+                dir = resolve_within(&dir, component).ok()?;
+            } else {
+                dir.push(component);
+            }
+            relative.push(component);
+        }
+        Some(relative)
+    }
+
     /// Moves a file to the backup directory before it is overwritten.
     pub fn backup_file(&self, relative_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
         let source = self.shared_folder.join(relative_path);
@@ -100,3 +161,57 @@ impl SyncManager {
         Ok(new_path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::protocol::HashAlgo;
+
+    fn file(name: &str) -> FileMetadata {
+        FileMetadata {
+            name: name.to_string(),
+            size: 1,
+            hash: String::new(),
+            hash_algo: HashAlgo::Blake3,
+            chunk_size: 0,
+            modified: Some(1),
+            merkle_root: None,
+        }
+    }
+
+    #[test]
+    fn paths_leaving_the_shared_folder_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("proxishare-sync-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        let manager = SyncManager::new(dir.clone(), SyncDirection::Pull);
+
+        let files: Vec<_> = [
+            "notes.txt",
+            "docs/report.pdf",
+            "new/deeper/file.txt",
+            "../escape.txt",
+            "docs/../../escape.txt",
+            "/etc/passwd",
+            "docs\\..\\escape.txt",
+            "docs//report.pdf",
+        ]
+        .into_iter()
+        .map(file)
+        .collect();
+        assert_eq!(
+            manager.missing_files(&files),
+            ["notes.txt", "docs/report.pdf", "new/deeper/file.txt"]
+        );
+
+        #[cfg(unix)]
+        {
+            let outside =
+                std::env::temp_dir().join(format!("proxishare-out-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&outside).unwrap();
+            std::os::unix::fs::symlink(&outside, dir.join("link")).unwrap();
+            assert!(manager.missing_files(&[file("link/escape.txt")]).is_empty());
+            std::fs::remove_dir_all(&outside).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
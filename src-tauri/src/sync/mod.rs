@@ -1,20 +1,95 @@
-pub mod watcher;
 pub mod manager;
+pub mod preview;
+pub mod queue;
+pub mod scan;
+pub mod validation;
+pub mod watcher;
 
+use crate::sync::manager::SyncManager;
+use crate::sync::watcher::FolderWatcher;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Arc;
-use crate::sync::manager::SyncManager;
+use tokio::sync::mpsc;
+
+/// Which way changes flow between this device and its sync peers
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncDirection {
+    /// Mirror the local folder to peers; incoming changes are ignored
+    Push,
+    /// Mirror peers into the local folder; local changes aren't sent
+    Pull,
+    #[default]
+    Bidirectional,
+}
+
+impl SyncDirection {
+    /// Whether local changes picked up by the `FolderWatcher` are sent out
+    pub fn sends_local_changes(self) -> bool {
+        self != SyncDirection::Pull
+    }
+
+    /// Whether incoming sync requests are applied
+    pub fn accepts_remote_changes(self) -> bool {
+        self != SyncDirection::Push
+    }
+}
 
 pub struct SyncState {
     pub shared_folder: Option<PathBuf>,
+    pub direction: SyncDirection,
     pub manager: Option<Arc<SyncManager>>,
+    /// Where the folder watcher reports local changes for the sync queue
+    change_tx: Option<mpsc::Sender<notify::Event>>,
+    watcher: Option<FolderWatcher>,
+}
+
+impl Default for SyncState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SyncState {
     pub fn new() -> Self {
         Self {
             shared_folder: None,
+            direction: SyncDirection::default(),
             manager: None,
+            change_tx: None,
+            watcher: None,
         }
     }
+
+    /// Watch the sync folder for local changes and report them on `change_tx`
+    pub fn with_change_sender(change_tx: mpsc::Sender<notify::Event>) -> Self {
+        Self {
+            change_tx: Some(change_tx),
+            ..Self::new()
+        }
+    }
+
+    /// Point sync at `folder` (or stop syncing with `None`) using the current direction
+    pub fn configure(&mut self, folder: Option<PathBuf>) {
+        self.manager = folder
+            .clone()
+            .map(|folder| Arc::new(SyncManager::new(folder, self.direction)));
+        self.watcher = match (&folder, &self.change_tx) {
+            (Some(folder), Some(tx)) => {
+                FolderWatcher::for_direction(folder.clone(), self.direction, tx.clone())
+                    .unwrap_or_else(|e| {
+                        println!("[Sync] Failed to watch {:?}: {}", folder, e);
+                        None
+                    })
+            }
+            _ => None,
+        };
+        self.shared_folder = folder;
+    }
+
+    pub fn set_direction(&mut self, direction: SyncDirection) {
+        self.direction = direction;
+        self.configure(self.shared_folder.clone());
+    }
 }
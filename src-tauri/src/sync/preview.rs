@@ -0,0 +1,121 @@
+use crate::error::ProxiError;
+use crate::sync::scan;
+use crate::transfer::protocol::FileMetadata;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use tauri::AppHandle;
+
+/// Files listed in one manifest; a folder with more can't be previewed
+pub const MAX_MANIFEST_FILES: usize = 10_000;
+
+/// What syncing would do with one file
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncAction {
+    /// Our copy would be sent to the peer
+    SendToPeer,
+    /// The peer's copy would replace or add ours
+    FetchFromPeer,
+    /// Left alone on both sides under the current direction and strategy
+    None,
+}
+
+/// Size, mtime and hash of one side's copy
+#[derive(Serialize, Debug, Clone)]
+pub struct FileState {
+    pub size: u64,
+    pub modified: Option<u64>,
+    pub hash: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PreviewEntry {
+    /// Relative to the sync folder, with `/` separators
+    pub path: String,
+    pub local: Option<FileState>,
+    pub remote: Option<FileState>,
+    pub action: SyncAction,
+}
+
+/// Dry-run comparison of our sync folder with a peer's
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SyncPreview {
+    pub only_local: Vec<PreviewEntry>,
+    pub only_remote: Vec<PreviewEntry>,
+    /// On both sides with a different hash or modification time
+    pub differing: Vec<PreviewEntry>,
+}
+
+/// Manifest of every syncable file under `folder`, hashed by the sync scan
+pub async fn build_manifest(
+    app_handle: &AppHandle,
+    folder: &Path,
+) -> Result<Vec<FileMetadata>, ProxiError> {
+    let entries = scan::list_files(folder).await?;
+    if entries.len() > MAX_MANIFEST_FILES {
+        return Err(ProxiError::Other(format!(
+            "Sync folder has {} files, more than the {} a preview can compare",
+            entries.len(),
+            MAX_MANIFEST_FILES
+        )));
+    }
+    scan::hash_files(app_handle, folder, entries).await
+}
+
+/// Compare manifests. `peer_wants` is what the peer said it would take from
+/// us, `we_want` what our own sync manager would take from it.
+pub fn diff(
+    local: &[FileMetadata],
+    remote: &[FileMetadata],
+    peer_wants: &[String],
+    we_want: &[String],
+) -> SyncPreview {
+    let peer_wants: HashSet<&str> = peer_wants.iter().map(String::as_str).collect();
+    let we_want: HashSet<&str> = we_want.iter().map(String::as_str).collect();
+    let action = |path: &str| {
+        if peer_wants.contains(path) {
+            SyncAction::SendToPeer
+        } else if we_want.contains(path) {
+            SyncAction::FetchFromPeer
+        } else {
+            SyncAction::None
+        }
+    };
+
+    // Sorted by path so the preview reads like a directory listing
+    let mut paths: BTreeMap<&str, (Option<&FileMetadata>, Option<&FileMetadata>)> = BTreeMap::new();
+    for file in local {
+        paths.entry(&file.name).or_default().0 = Some(file);
+    }
+    for file in remote {
+        paths.entry(&file.name).or_default().1 = Some(file);
+    }
+
+    let mut preview = SyncPreview::default();
+    for (path, (ours, theirs)) in paths {
+        let entry = PreviewEntry {
+            path: path.to_string(),
+            local: ours.map(file_state),
+            remote: theirs.map(file_state),
+            action: action(path),
+        };
+        match (ours, theirs) {
+            (Some(_), None) => preview.only_local.push(entry),
+            (None, Some(_)) => preview.only_remote.push(entry),
+            (Some(a), Some(b)) if a.hash != b.hash || a.modified != b.modified => {
+                preview.differing.push(entry)
+            }
+            _ => {}
+        }
+    }
+    preview
+}
+
+fn file_state(file: &FileMetadata) -> FileState {
+    FileState {
+        size: file.size,
+        modified: file.modified,
+        hash: file.hash.clone(),
+    }
+}
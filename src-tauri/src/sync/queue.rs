@@ -0,0 +1,195 @@
+use crate::events::{emit_event, SyncFailed};
+use crate::transfer::sender::SendPayload;
+use crate::{AppState, TransferEntry, TransferStatus};
+use notify::{Event, EventKind};
+use std::path::Path;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::sync::mpsc;
+
+/// Failed deliveries of one change before it moves to the dead-letter list
+pub const MAX_SYNC_ATTEMPTS: i64 = 5;
+/// Wait after the first failure; doubles with every further one
+const BASE_RETRY_SECS: i64 = 30;
+const MAX_RETRY_SECS: i64 = 3600;
+/// How often the queue is checked for due entries
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// Entries attempted per poll
+const BATCH_SIZE: i32 = 20;
+
+/// Seconds to wait after a change has failed `attempts` times before
+/// (30s, 1m, 2m, ...), capped at an hour
+pub fn retry_delay(attempts: i64) -> i64 {
+    BASE_RETRY_SECS
+        .saturating_mul(1i64 << attempts.clamp(0, 16))
+        .min(MAX_RETRY_SECS)
+}
+
+/// Path of a changed file relative to the sync folder, with `/` separators.
+/// Directories and our own `.proxishare` metadata aren't synced.
+fn syncable_path(folder: &Path, path: &Path) -> Option<String> {
+    if !path.is_file() {
+        return None;
+    }
+    let relative = path.strip_prefix(folder).ok()?;
+    let first = relative.components().next()?;
+    if first.as_os_str() == ".proxishare" {
+        return None;
+    }
+    Some(relative.to_string_lossy().replace('\\', "/"))
+}
+
+/// Queue every file change the `FolderWatcher` reports for each trusted peer
+pub fn spawn_change_handler(app_handle: AppHandle, mut events: mpsc::Receiver<Event>) {
+    tauri::async_runtime::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+            let state = app_handle.state::<AppState>();
+            let Some(folder) = state.sync.read().await.shared_folder.clone() else {
+                continue;
+            };
+            let peers: Vec<String> = state
+                .security
+                .read()
+                .await
+                .trusted_devices
+                .keys()
+                .cloned()
+                .collect();
+
+            let db_lock = state.database.read().await;
+            let Some(db) = &*db_lock else {
+                continue;
+            };
+            for path in &event.paths {
+                let Some(relative) = syncable_path(&folder, path) else {
+                    continue;
+                };
+                for peer in &peers {
+                    if let Err(e) = db.enqueue_sync(peer, &relative).await {
+                        println!("[Sync] Failed to queue {} for {}: {:?}", relative, peer, e);
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Deliver queued changes in the background for as long as the app runs
+pub fn spawn_worker(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            process_due(&app_handle).await;
+        }
+    });
+}
+
+/// Try each due entry once. Peers that aren't discovered right now keep
+/// their entries without using up attempts, so changes made while a peer is
+/// offline go out when it comes back.
+async fn process_due(app_handle: &AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let (folder, direction) = {
+        let sync = state.sync.read().await;
+        (sync.shared_folder.clone(), sync.direction)
+    };
+    let Some(folder) = folder else {
+        return;
+    };
+    if !direction.sends_local_changes() {
+        return;
+    }
+
+    let due = {
+        let db_lock = state.database.read().await;
+        let Some(db) = &*db_lock else {
+            return;
+        };
+        match db.get_due_syncs(BATCH_SIZE).await {
+            Ok(due) => due,
+            Err(e) => {
+                println!("[Sync] Failed to read sync queue: {:?}", e);
+                return;
+            }
+        }
+    };
+    if due.is_empty() {
+        return;
+    }
+    let discovery = state.discovery.read().await.clone();
+    let Some(discovery) = discovery else {
+        return;
+    };
+    let devices = discovery.get_devices().await;
+
+    for entry in due {
+        let Some(device) = devices.iter().find(|d| d.id == entry.device_id) else {
+            continue;
+        };
+
+        let path = folder.join(&entry.relative_path);
+        let result = if path.is_file() {
+            let transfer_id = uuid::Uuid::new_v4().to_string();
+            state.transfers.write().await.insert(
+                transfer_id.clone(),
+                TransferEntry {
+                    status: TransferStatus::InProgress,
+                    device_id: device.id.clone(),
+                    batch_id: None,
+                },
+            );
+            println!(
+                "[Sync] Sending {} to {} (attempt {})",
+                entry.relative_path,
+                device.name,
+                entry.attempts + 1
+            );
+            crate::run_send(
+                &state,
+                transfer_id,
+                &device.id,
+                &device.ip,
+                device.port,
+                SendPayload::File(path),
+            )
+            .await
+        } else {
+            // Deleted or replaced by a directory since; deletions aren't synced
+            Ok(())
+        };
+
+        let db_lock = state.database.read().await;
+        let Some(db) = &*db_lock else {
+            return;
+        };
+        let recorded = match result {
+            Ok(()) => db.complete_sync(&entry).await,
+            Err(e) => {
+                let attempts = entry.attempts + 1;
+                let retry_at = (attempts < MAX_SYNC_ATTEMPTS)
+                    .then(|| chrono::Utc::now().timestamp() + retry_delay(entry.attempts));
+                if retry_at.is_none() {
+                    println!(
+                        "[Sync] Giving up on {} for {} after {} attempts: {}",
+                        entry.relative_path, device.name, attempts, e
+                    );
+                    emit_event(
+                        app_handle,
+                        SyncFailed {
+                            device_id: entry.device_id.clone(),
+                            relative_path: entry.relative_path.clone(),
+                            error: e.clone(),
+                        },
+                    );
+                }
+                db.fail_sync(&entry, &e, retry_at).await
+            }
+        };
+        if let Err(e) = recorded {
+            println!("[Sync] Failed to update sync queue: {:?}", e);
+        }
+    }
+}
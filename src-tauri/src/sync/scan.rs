@@ -0,0 +1,129 @@
+use crate::error::ProxiError;
+use crate::events::{emit_event, ScanProgress};
+use crate::transfer::protocol::{FileMetadata, HashAlgo};
+use crate::transfer::selection::{self, Selection};
+use futures::stream::{self, StreamExt};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+
+/// Least time between two `sync-scan-progress` events
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Half the cores, leaving the rest to transfers and the UI
+pub fn default_scan_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| (n.get() / 2).max(1))
+        .unwrap_or(1)
+}
+
+/// A file found by `list_files`, not yet hashed
+pub struct ScanEntry {
+    /// Relative to the scanned folder, with `/` separators
+    pub relative_path: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: Option<u64>,
+}
+
+/// Every syncable file under `folder`. Our own `.proxishare` metadata is
+/// skipped, as the folder watcher skips it.
+pub async fn list_files(folder: &Path) -> Result<Vec<ScanEntry>, ProxiError> {
+    let folder = folder.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut entries = Vec::new();
+        selection::walk(
+            &folder,
+            &folder,
+            &Selection::synced(),
+            &mut |relative, metadata| {
+                entries.push(ScanEntry {
+                    path: folder.join(&relative),
+                    relative_path: relative,
+                    size: metadata.len(),
+                    modified: metadata
+                        .modified()
+                        .ok()
+                        .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs()),
+                });
+                true
+            },
+        )?;
+        Ok(entries)
+    })
+    .await
+    .map_err(|e| ProxiError::Other(format!("Folder scan failed: {}", e)))?
+}
+
+/// Hash `entries` with blake3 into a manifest sorted by path. Files are
+/// hashed on tokio's blocking threads, at most `sync_scan_workers` at a
+/// time, so a large first scan doesn't hold up the async runtime.
+/// Progress is emitted as `sync-scan-progress`.
+pub async fn hash_files(
+    app_handle: &AppHandle,
+    folder: &Path,
+    entries: Vec<ScanEntry>,
+) -> Result<Vec<FileMetadata>, ProxiError> {
+    let workers = {
+        let app_state = app_handle.state::<crate::AppState>();
+        let settings = app_state.settings.read().await;
+        settings.settings.sync_scan_workers()
+    };
+    let mut progress = ScanProgress {
+        folder: folder.to_string_lossy().to_string(),
+        files_hashed: 0,
+        files_total: entries.len(),
+        bytes_hashed: 0,
+        bytes_total: entries.iter().map(|e| e.size).sum(),
+    };
+    println!(
+        "[Sync] Hashing {} files ({} bytes) in {:?} with {} workers",
+        progress.files_total, progress.bytes_total, folder, workers
+    );
+    emit_event(app_handle, progress.clone());
+
+    let mut hashed = stream::iter(entries)
+        .map(|entry| tokio::task::spawn_blocking(move || hash_entry(entry)))
+        .buffer_unordered(workers);
+    let mut manifest = Vec::with_capacity(progress.files_total);
+    let mut last_emit = Instant::now();
+    while let Some(result) = hashed.next().await {
+        let file =
+            result.map_err(|e| ProxiError::Other(format!("Hashing task failed: {}", e)))??;
+        progress.files_hashed += 1;
+        progress.bytes_hashed += file.size;
+        manifest.push(file);
+        if last_emit.elapsed() >= PROGRESS_INTERVAL {
+            last_emit = Instant::now();
+            emit_event(app_handle, progress.clone());
+        }
+    }
+    emit_event(app_handle, progress.clone());
+
+    manifest.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(manifest)
+}
+
+fn hash_entry(entry: ScanEntry) -> Result<FileMetadata, ProxiError> {
+    let mut file = std::fs::File::open(&entry.path)?;
+    let mut hasher = HashAlgo::Blake3.hasher()?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(FileMetadata {
+        name: entry.relative_path,
+        size: entry.size,
+        hash: hasher.finalize(),
+        hash_algo: HashAlgo::Blake3,
+        chunk_size: 0,
+        modified: entry.modified,
+        merkle_root: None,
+    })
+}
@@ -0,0 +1,165 @@
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Folders with more files than this get a warning about the initial sync
+const LARGE_FOLDER_FILES: u64 = 10_000;
+/// Counting stops here; the estimate is reported as "at least" this many
+const MAX_COUNTED_FILES: u64 = 100_000;
+
+/// Locations that must never be synced, nor anything inside them
+const SYSTEM_LOCATIONS: &[&str] = &[
+    "/bin",
+    "/boot",
+    "/dev",
+    "/etc",
+    "/lib",
+    "/proc",
+    "/sbin",
+    "/sys",
+    "/usr",
+    "/var",
+    "/System",
+    "/Library",
+    "C:\\Windows",
+    "C:\\Program Files",
+    "C:\\Program Files (x86)",
+];
+
+/// The check a sync folder failed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderCheck {
+    Missing,
+    NotADirectory,
+    NotWritable,
+    SystemLocation,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FolderValidation {
+    pub path: String,
+    pub valid: bool,
+    pub failed_check: Option<FolderCheck>,
+    pub message: Option<String>,
+    /// Files that the initial sync would cover (capped, so a lower bound)
+    pub estimated_file_count: u64,
+    /// Valid but big enough that the user should confirm the sync scope
+    pub large: bool,
+}
+
+impl FolderValidation {
+    fn failed(path: &Path, check: FolderCheck, message: &str) -> Self {
+        Self {
+            path: path.to_string_lossy().into_owned(),
+            valid: false,
+            failed_check: Some(check),
+            message: Some(message.to_string()),
+            estimated_file_count: 0,
+            large: false,
+        }
+    }
+}
+
+/// Check that `path` is a writable, non-system directory and estimate how many
+/// files it holds. `app_data_dir` is refused since sync would recurse into our
+/// own database and keys.
+pub fn validate_sync_folder(path: &Path, app_data_dir: &Path) -> FolderValidation {
+    if !path.exists() {
+        return FolderValidation::failed(path, FolderCheck::Missing, "Folder does not exist");
+    }
+    if !path.is_dir() {
+        return FolderValidation::failed(path, FolderCheck::NotADirectory, "Path is not a folder");
+    }
+
+    let canonical = match path.canonicalize() {
+        Ok(p) => p,
+        Err(_) => {
+            return FolderValidation::failed(path, FolderCheck::Missing, "Folder is not accessible")
+        }
+    };
+
+    if is_system_location(&canonical, app_data_dir) {
+        return FolderValidation::failed(
+            path,
+            FolderCheck::SystemLocation,
+            "Folder is a system or application location",
+        );
+    }
+
+    if !is_writable(&canonical) {
+        return FolderValidation::failed(path, FolderCheck::NotWritable, "Folder is not writable");
+    }
+
+    let estimated_file_count = count_files(&canonical);
+    let large = estimated_file_count > LARGE_FOLDER_FILES;
+    FolderValidation {
+        path: canonical.to_string_lossy().into_owned(),
+        valid: true,
+        failed_check: None,
+        message: large.then(|| {
+            format!(
+                "Folder contains at least {} files; the initial sync may take a while",
+                estimated_file_count
+            )
+        }),
+        estimated_file_count,
+        large,
+    }
+}
+
+fn is_system_location(canonical: &Path, app_data_dir: &Path) -> bool {
+    // The OS root (or a drive root on Windows)
+    if canonical.parent().is_none() {
+        return true;
+    }
+
+    let app_data_dir = app_data_dir
+        .canonicalize()
+        .unwrap_or_else(|_| app_data_dir.to_path_buf());
+    if canonical.starts_with(&app_data_dir) || app_data_dir.starts_with(canonical) {
+        return true;
+    }
+
+    SYSTEM_LOCATIONS
+        .iter()
+        .any(|location| canonical.starts_with(PathBuf::from(location)))
+}
+
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(".proxishare_write_test");
+    match fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+fn count_files(dir: &Path) -> u64 {
+    let mut count = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                // Our own backups aren't part of the sync scope
+                if entry.file_name() != ".proxishare" {
+                    pending.push(entry.path());
+                }
+            } else if file_type.is_file() {
+                count += 1;
+                if count >= MAX_COUNTED_FILES {
+                    return count;
+                }
+            }
+        }
+    }
+    count
+}
@@ -1,3 +1,4 @@
+use crate::sync::SyncDirection;
 use notify::{Event, RecursiveMode, Watcher};
 use std::path::PathBuf;
 use tokio::sync::mpsc;
@@ -21,4 +22,16 @@ impl FolderWatcher {
 
         Ok(Self { _watcher: watcher })
     }
+
+    /// Watch `path` only if `direction` sends local changes to peers
+    pub fn for_direction(
+        path: PathBuf,
+        direction: SyncDirection,
+        event_tx: mpsc::Sender<Event>,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if !direction.sends_local_changes() {
+            return Ok(None);
+        }
+        Self::new(path, event_tx).map(Some)
+    }
 }
@@ -0,0 +1,56 @@
+use crate::error::ProxiError;
+use std::io::Read;
+use std::path::Path;
+
+/// Bytes read from the start of a file to sniff its type
+const SNIFF_LEN: u64 = 8192;
+
+/// Check that every entry is a MIME type (`image/png`) or a whole top-level
+/// type (`image/*`)
+pub fn validate_allowlist(types: &[String]) -> Result<(), ProxiError> {
+    for entry in types {
+        let valid = match entry.split_once('/') {
+            Some((top, sub)) => {
+                !top.is_empty()
+                    && !sub.is_empty()
+                    && !top.contains('*')
+                    && (sub == "*" || !sub.contains('*'))
+                    && !entry.contains(char::is_whitespace)
+            }
+            None => false,
+        };
+        if !valid {
+            return Err(ProxiError::Other(format!(
+                "Invalid MIME type {:?}; use e.g. \"image/png\" or \"image/*\"",
+                entry
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// MIME type of the file at `path` judged from its leading bytes, or `None`
+/// when the content has no recognizable signature (plain text, for one)
+pub fn sniff(path: &Path) -> std::io::Result<Option<&'static str>> {
+    let mut head = Vec::new();
+    std::fs::File::open(path)?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut head)?;
+    Ok(infer::get(&head).map(|kind| kind.mime_type()))
+}
+
+/// Whether a sniffed type passes the allowlist. Unrecognized content never
+/// does, since it can't be shown to be one of the allowed types.
+pub fn is_allowed(mime: Option<&str>, allowlist: &[String]) -> bool {
+    let Some(mime) = mime else {
+        return false;
+    };
+    allowlist
+        .iter()
+        .any(|entry| match entry.strip_suffix("/*") {
+            Some(top) => mime
+                .split_once('/')
+                .is_some_and(|(mime_top, _)| mime_top.eq_ignore_ascii_case(top)),
+            None => mime.eq_ignore_ascii_case(entry),
+        })
+}
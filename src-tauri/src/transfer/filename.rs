@@ -0,0 +1,306 @@
+use crate::error::ProxiError;
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use unicode_normalization::UnicodeNormalization;
+
+/// Names Windows refuses to create regardless of extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters that are invalid in file names on at least one supported platform
+const INVALID_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Turn an untrusted file name from a peer into a single safe path component.
+/// Directory components are stripped, invalid/control characters replaced,
+/// reserved device names prefixed and overlong names shortened so the result
+/// is valid on every platform.
+pub fn sanitize_file_name(name: &str) -> String {
+    // Keep only the last component, whichever separator style the peer used
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+
+    let cleaned: String = base
+        .chars()
+        .map(|c| {
+            if c.is_control() || INVALID_CHARS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    // Windows silently drops trailing dots and spaces
+    let cleaned = cleaned
+        .trim_start()
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string();
+
+    if cleaned.is_empty() || cleaned.chars().all(|c| c == '.') {
+        return "unnamed".to_string();
+    }
+
+    let stem = cleaned.split('.').next().unwrap_or("").to_ascii_uppercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        return fit_name(&format!("_{}", cleaned), MAX_NAME_BYTES);
+    }
+
+    fit_name(&cleaned, MAX_NAME_BYTES)
+}
+
+/// Longest name most file systems accept, in bytes
+pub const MAX_NAME_BYTES: usize = 255;
+/// Shortest limit `fit_name` may be configured with, so an extension still fits
+pub const MIN_NAME_BYTES: usize = 32;
+
+/// Check a name the user chose for a file being sent and make it safe the
+/// way a receiver would. Unlike names from peers, paths are rejected rather
+/// than stripped, since they're more likely a mistake than intended.
+pub fn validate_rename(name: &str) -> Result<String, ProxiError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ProxiError::Other("The new name is empty".into()));
+    }
+    if name.contains(['/', '\\']) {
+        return Err(ProxiError::Other(format!(
+            "The new name {:?} can't contain path separators",
+            name
+        )));
+    }
+    if name.len() > MAX_NAME_BYTES {
+        return Err(ProxiError::Other(format!(
+            "The new name is longer than {} bytes",
+            MAX_NAME_BYTES
+        )));
+    }
+    Ok(sanitize_file_name(name))
+}
+
+/// Name to offer for a file called `name`: composed to NFC (macOS file
+/// systems hand out decomposed names, which other platforms treat as a
+/// different name) unless `normalize` is off, then cut to `max_bytes`
+pub fn offer_name(name: &str, normalize: bool, max_bytes: usize) -> String {
+    if normalize {
+        fit_name(&name.nfc().collect::<String>(), max_bytes)
+    } else {
+        fit_name(name, max_bytes)
+    }
+}
+
+/// Shorten `name` to at most `max_bytes` bytes on a character boundary,
+/// keeping its extension when that is short enough to
+fn fit_name(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_string();
+    }
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= max_bytes / 2 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut end = max_bytes - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", stem[..end].trim_end(), extension)
+}
+
+/// Join a sanitized name onto `dir`, verifying the result can't escape it
+/// (e.g. through a pre-existing symlink).
+pub fn resolve_within(dir: &Path, file_name: &str) -> Result<PathBuf, ProxiError> {
+    let canonical_dir = dir.canonicalize()?;
+    let path = canonical_dir.join(file_name);
+
+    if path.parent() != Some(canonical_dir.as_path()) {
+        return Err(ProxiError::Protocol(format!(
+            "Refusing to write outside save directory: {}",
+            file_name
+        )));
+    }
+
+    if path.exists() {
+        let resolved = path.canonicalize()?;
+        if !resolved.starts_with(&canonical_dir) {
+            return Err(ProxiError::Protocol(format!(
+                "Refusing to write outside save directory: {}",
+                file_name
+            )));
+        }
+    }
+
+    Ok(path)
+}
+
+/// Final paths of files being received right now, shared by every receiver
+/// so two transfers never write into the same file at once
+#[derive(Clone, Default)]
+pub struct DestinationClaims(Arc<Mutex<HashSet<PathBuf>>>);
+
+impl DestinationClaims {
+    /// Claim `path` for a new receive. When another transfer is already
+    /// writing it, diverge to the first `name (n).ext` beside it that is
+    /// neither claimed nor on disk instead.
+    pub fn claim(&self, path: PathBuf) -> DestinationClaim {
+        let mut claimed = self.0.lock();
+        let path = if claimed.contains(&path) {
+            (2..)
+                .map(|n| numbered(&path, n))
+                .find(|candidate| !claimed.contains(candidate) && !candidate.exists())
+                .unwrap_or(path)
+        } else {
+            path
+        };
+        claimed.insert(path.clone());
+        DestinationClaim {
+            claims: self.clone(),
+            path,
+        }
+    }
+}
+
+/// `dir/name (n).ext` for `dir/name.ext`
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// A claimed destination, released when dropped
+pub struct DestinationClaim {
+    claims: DestinationClaims,
+    path: PathBuf,
+}
+
+impl DestinationClaim {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for DestinationClaim {
+    fn drop(&mut self) {
+        self.claims.0.lock().remove(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir
+    fn temp_dir() -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("proxishare-filename-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parent_components_are_stripped() {
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name("..\\..\\boot.ini"), "boot.ini");
+        assert_eq!(sanitize_file_name("notes/../../secret.txt"), "secret.txt");
+        assert_eq!(sanitize_file_name(".."), "unnamed");
+        assert_eq!(sanitize_file_name("../"), "unnamed");
+        assert_eq!(sanitize_file_name("..."), "unnamed");
+    }
+
+    #[test]
+    fn absolute_paths_keep_only_the_file_name() {
+        assert_eq!(sanitize_file_name("/etc/shadow"), "shadow");
+        assert_eq!(
+            sanitize_file_name("C:\\Windows\\System32\\evil.dll"),
+            "evil.dll"
+        );
+        assert_eq!(sanitize_file_name("\\\\server\\share\\doc.pdf"), "doc.pdf");
+        assert_eq!(sanitize_file_name("C:evil.exe"), "C_evil.exe");
+        assert_eq!(sanitize_file_name("/"), "unnamed");
+    }
+
+    #[test]
+    fn windows_reserved_names_are_prefixed() {
+        assert_eq!(sanitize_file_name("CON"), "_CON");
+        assert_eq!(sanitize_file_name("con.txt"), "_con.txt");
+        assert_eq!(sanitize_file_name("Lpt9.tar.gz"), "_Lpt9.tar.gz");
+        assert_eq!(sanitize_file_name("nul."), "_nul");
+        // Only the exact device names are reserved
+        assert_eq!(sanitize_file_name("CONSOLE.txt"), "CONSOLE.txt");
+        assert_eq!(sanitize_file_name("COM10"), "COM10");
+    }
+
+    #[test]
+    fn nul_and_control_characters_are_replaced() {
+        assert_eq!(sanitize_file_name("a\0b.txt"), "a_b.txt");
+        assert_eq!(sanitize_file_name("line\nbreak\r.txt"), "line_break_.txt");
+        assert_eq!(
+            sanitize_file_name("bell\u{7}\u{1b}[31m.txt"),
+            "bell__[31m.txt"
+        );
+        assert_eq!(sanitize_file_name("what?<>|*\".txt"), "what______.txt");
+        // Trailing dots and spaces, which Windows drops, go too
+        assert_eq!(sanitize_file_name("report.pdf. . "), "report.pdf");
+    }
+
+    #[test]
+    fn overlong_names_are_shortened_keeping_the_extension() {
+        let name = format!("{}.txt", "a".repeat(400));
+        let safe = sanitize_file_name(&name);
+        assert_eq!(safe.len(), MAX_NAME_BYTES);
+        assert!(safe.ends_with(".txt"));
+
+        // Multi-byte characters are never split
+        let safe = sanitize_file_name(&"é".repeat(200));
+        assert!(safe.len() <= MAX_NAME_BYTES);
+        assert!(safe.chars().all(|c| c == 'é'));
+
+        let reserved = sanitize_file_name(&format!("CON.{}", "x".repeat(300)));
+        assert!(reserved.len() <= MAX_NAME_BYTES);
+        assert!(reserved.starts_with("_CON"));
+    }
+
+    #[test]
+    fn hostile_names_resolve_inside_the_directory() {
+        let dir = temp_dir();
+        let canonical = dir.canonicalize().unwrap();
+        for name in [
+            "../escape.txt",
+            "/etc/passwd",
+            "..\\..\\escape.txt",
+            "..",
+            "a\0b",
+            "CON",
+        ] {
+            let path = resolve_within(&dir, &sanitize_file_name(name)).unwrap();
+            assert_eq!(path.parent(), Some(canonical.as_path()), "{:?}", name);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unsanitized_names_are_refused() {
+        let dir = temp_dir();
+        assert!(resolve_within(&dir, "../escape.txt").is_err());
+        assert!(resolve_within(&dir, "nested/file.txt").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_directory_are_refused() {
+        let dir = temp_dir();
+        let outside = temp_dir();
+        std::os::unix::fs::symlink(outside.join("target.txt"), dir.join("link.txt")).unwrap();
+        std::fs::write(outside.join("target.txt"), b"outside").unwrap();
+        assert!(resolve_within(&dir, "link.txt").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use crate::error::ProxiError;
+use crate::transfer::protocol::HashAlgo;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Chunk hashing on tokio's blocking threads, so a sender can hash the next
+/// chunk while the previous one is on the wire. At most `size` chunks are
+/// hashed at once across all transfers.
+pub struct HashPool {
+    permits: Arc<Semaphore>,
+    size: Mutex<usize>,
+}
+
+/// One hashing thread per core
+pub fn default_pool_size() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+impl HashPool {
+    /// `None` uses `default_pool_size`
+    pub fn new(size: Option<usize>) -> Self {
+        let size = size.filter(|n| *n > 0).unwrap_or_else(default_pool_size);
+        Self {
+            permits: Arc::new(Semaphore::new(size)),
+            size: Mutex::new(size),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        *self.size.lock()
+    }
+
+    /// Change how many chunks may be hashed at once. Shrinking waits for
+    /// hashes in flight to finish before their slots go away.
+    pub fn resize(&self, size: Option<usize>) {
+        let size = size.filter(|n| *n > 0).unwrap_or_else(default_pool_size);
+        let mut current = self.size.lock();
+        if size > *current {
+            self.permits.add_permits(size - *current);
+        } else if size < *current {
+            let surplus = (*current - size) as u32;
+            let permits = self.permits.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Ok(permit) = permits.acquire_many(surplus).await {
+                    permit.forget();
+                }
+            });
+        }
+        *current = size;
+        println!("[Transfer] Hashing pool size set to {}", size);
+    }
+
+    /// Hash `data`, handing the buffer back so it can be reused
+    pub async fn hash(
+        &self,
+        algo: HashAlgo,
+        data: Vec<u8>,
+    ) -> Result<(Vec<u8>, String), ProxiError> {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| ProxiError::Other(e.to_string()))?;
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let hash = algo.hash(&data)?;
+            Ok((data, hash))
+        })
+        .await
+        .map_err(|e| ProxiError::Other(format!("Hashing task failed: {}", e)))?
+    }
+}
@@ -0,0 +1,65 @@
+use crate::db::HistoryRecordWire;
+use crate::events::{emit_event, HistoryUpdated};
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+/// Records sent or accepted in one history sync unless configured otherwise
+pub const DEFAULT_MAX_RECORDS: usize = 100;
+/// Records per `HistorySync` message, so no single message gets large
+pub const CHUNK_RECORDS: usize = 25;
+
+/// Result of a history sync with one peer
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct HistorySyncSummary {
+    /// Records we sent
+    pub sent: usize,
+    /// Records the peer sent back
+    pub received: usize,
+    /// Received records that were new or changed our copy
+    pub merged: usize,
+}
+
+/// Our most recent `limit` transfer records, as history sync sends them
+pub async fn local_records(app_handle: &AppHandle, limit: usize) -> Vec<HistoryRecordWire> {
+    let app_state = app_handle.state::<crate::AppState>();
+    let db_lock = app_state.database.read().await;
+    let Some(db) = &*db_lock else {
+        return Vec::new();
+    };
+    match db.get_transfer_history(limit as i32).await {
+        Ok(records) => records.into_iter().map(HistoryRecordWire::from).collect(),
+        Err(e) => {
+            println!("[Sync] Failed to read history: {:?}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Merge a peer's records into ours, returning how many changed anything
+pub async fn merge_records(app_handle: &AppHandle, records: &[HistoryRecordWire]) -> usize {
+    let merged = {
+        let app_state = app_handle.state::<crate::AppState>();
+        let db_lock = app_state.database.read().await;
+        let Some(db) = &*db_lock else {
+            return 0;
+        };
+        match db.merge_history(records).await {
+            Ok(merged) => merged,
+            Err(e) => {
+                println!("[Database] Failed to merge history: {:?}", e);
+                0
+            }
+        }
+    };
+    if merged > 0 {
+        emit_event(app_handle, HistoryUpdated);
+    }
+    merged
+}
+
+/// Configured cap on records per exchange
+pub async fn max_records(app_handle: &AppHandle) -> usize {
+    let app_state = app_handle.state::<crate::AppState>();
+    let settings = app_state.settings.read().await;
+    settings.settings.history_sync_max_records()
+}
@@ -0,0 +1,57 @@
+use crate::error::ProxiError;
+use crate::transfer::protocol::HashAlgo;
+use crate::transfer::sender::read_chunk;
+use tokio::io::AsyncRead;
+
+/// Prefix of an inner node's input, so a node can't pass for a chunk
+const NODE_PREFIX: &[u8] = b"proxishare-merkle-node";
+
+/// Root of the tree whose leaves are a file's chunk hashes, in order. Pairs
+/// are hashed level by level and an odd node out moves up unchanged, so a
+/// single chunk's hash is its own root. An empty file's root is the hash
+/// of nothing.
+pub fn root(algo: HashAlgo, leaves: &[String]) -> Result<String, ProxiError> {
+    if leaves.is_empty() {
+        return algo.hash(&[]);
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let mut next = Vec::with_capacity(level.len().div_ceil(2));
+        for pair in level.chunks(2) {
+            match pair {
+                [left, right] => {
+                    let mut hasher = algo.hasher()?;
+                    hasher.update(NODE_PREFIX);
+                    hasher.update(left.as_bytes());
+                    hasher.update(right.as_bytes());
+                    next.push(hasher.finalize());
+                }
+                [single] => next.push(single.clone()),
+                _ => unreachable!(),
+            }
+        }
+        level = next;
+    }
+    Ok(level.remove(0))
+}
+
+/// Hash `reader` as a whole and in `chunk_size` chunks in one pass,
+/// returning the file hash and the chunk hashes a send would produce
+pub async fn hash_chunks<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    chunk_size: usize,
+    algo: HashAlgo,
+) -> Result<(String, Vec<String>), ProxiError> {
+    let mut whole = algo.hasher()?;
+    let mut leaves = Vec::new();
+    let mut buffer = vec![0u8; chunk_size.max(1)];
+    loop {
+        let n = read_chunk(reader, &mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        whole.update(&buffer[..n]);
+        leaves.push(algo.hash(&buffer[..n])?);
+    }
+    Ok((whole.finalize(), leaves))
+}
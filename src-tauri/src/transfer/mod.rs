@@ -1,22 +1,334 @@
+pub mod content_type;
+pub mod filename;
+pub mod hash_pool;
+pub mod history;
+pub mod merkle;
+pub mod partials;
+pub mod post_process;
 pub mod protocol;
+pub mod rate_limit;
 pub mod receiver;
+pub mod selection;
 pub mod sender;
+#[cfg(test)]
+pub(crate) mod test_support;
 
 use crate::crypto::encryption::CertificateManager;
+use crate::error::ProxiError;
+use crate::events::{
+    emit_event, ChunkProgress, HistorySyncProgress, IncomingConnection, IncomingConnectionClosed,
+    ListenerDown, ListenerFailed, ListenerRecovered,
+};
+use crate::transfer::filename::DestinationClaims;
+use crate::transfer::history::HistorySyncSummary;
+use crate::transfer::protocol::{
+    FileMetadata, MessageType, PeerCapabilities, CLOSE_CANCELLED, CLOSE_PROTOCOL_ERROR,
+};
 use crate::transfer::receiver::FileReceiver;
-use crate::transfer::sender::FileSender;
-use quinn::{ClientConfig, Endpoint, ServerConfig};
-use std::path::PathBuf;
+use crate::transfer::sender::{BenchmarkResult, FileSender, SendPayload};
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig, TransportConfig, VarInt};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use tauri::{AppHandle, Manager};
 use tokio::sync::RwLock;
 
+/// Seconds a running transfer may go without moving any bytes before it's failed
+pub const DEFAULT_STALL_TIMEOUT_SECS: u64 = 60;
+/// Free bytes a receive leaves on the download volume unless configured otherwise
+pub const DEFAULT_DISK_SPACE_MARGIN: u64 = 500 * 1024 * 1024;
+/// Wait before the first automatic retry of a failed send; doubles with every further one
+pub const DEFAULT_AUTO_RETRY_BACKOFF_SECS: u64 = 5;
+/// Most automatic retries of one send that can be configured
+pub const MAX_AUTO_RETRY_ATTEMPTS: u32 = 10;
+/// Longest `send_message` waits for the peer to acknowledge the message
+const MESSAGE_ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// Longest an accepted connection may go without sending its first message
+pub const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Live QUIC connections of active transfers, keyed by transfer id
+pub type ConnectionRegistry = Arc<RwLock<HashMap<String, TrackedConnection>>>;
+
+/// Transfer and device a connection is carrying, so its log lines and stats
+/// can be told apart from those of other transfers running at the same time
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectionLabel {
+    pub transfer_id: String,
+    pub device_id: String,
+    /// quinn's id for the connection; transfers sharing one share it
+    pub connection_id: usize,
+}
+
+impl ConnectionLabel {
+    pub fn new(transfer_id: &str, device_id: &str, connection: &Connection) -> Self {
+        Self {
+            transfer_id: transfer_id.to_string(),
+            device_id: device_id.to_string(),
+            connection_id: connection.stable_id(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectionLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[transfer={} device={} conn={}]",
+            self.transfer_id, self.device_id, self.connection_id
+        )
+    }
+}
+
+/// An active transfer's connection, labeled with who it's for
+#[derive(Clone)]
+pub struct TrackedConnection {
+    pub connection: Connection,
+    pub label: ConnectionLabel,
+}
+
+impl TrackedConnection {
+    pub fn new(connection: Connection, label: ConnectionLabel) -> Self {
+        Self { connection, label }
+    }
+}
+
+/// How often a pending connect or offer checks whether it was cancelled
+const CANCEL_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Run `future` until it finishes or the transfer is cancelled in the
+/// registry, in which case it is dropped mid-flight. Covers the phases
+/// before the send loop starts polling its status (connecting, waiting for
+/// the offer to be accepted), so picking the wrong device can be undone at once.
+pub(crate) async fn until_cancelled<T>(
+    transfers: &crate::TransferRegistry,
+    transfer_id: &str,
+    future: impl std::future::Future<Output = Result<T, ProxiError>>,
+) -> Result<T, ProxiError> {
+    let cancelled = async {
+        loop {
+            if crate::get_transfer_status(transfers, transfer_id).await
+                == crate::TransferStatus::Cancelled
+            {
+                return;
+            }
+            tokio::time::sleep(CANCEL_POLL_INTERVAL).await;
+        }
+    };
+    tokio::select! {
+        result = future => result,
+        _ = cancelled => {
+            println!("[Transfer] {} cancelled before the transfer started", transfer_id);
+            Err(ProxiError::Cancelled("Transfer cancelled by user".into()))
+        }
+    }
+}
+
+/// Send to each of `addresses` in turn while the path to the peer is
+/// broken, then retry the last one once per entry of `delays` while the
+/// error is retryable (see `ProxiError::is_retryable`). Moving on to the
+/// next address doesn't use up a retry. Every attempt re-offers the same
+/// transfer id, so a receiver that kept its chunks resumes. This is the only
+/// place a send is retried; returns the outcome and the address it was last
+/// tried on.
+pub(crate) async fn send_with_retries<T, Fut>(
+    transfers: &crate::TransferRegistry,
+    transfer_id: &str,
+    addresses: Vec<String>,
+    delays: &[std::time::Duration],
+    mut on_retry: impl FnMut(u32, std::time::Duration, &ProxiError),
+    mut send: impl FnMut(String) -> Fut,
+) -> (Result<T, ProxiError>, String)
+where
+    Fut: std::future::Future<Output = Result<T, ProxiError>>,
+{
+    let mut addresses = addresses.into_iter();
+    let mut address = addresses.next().unwrap_or_default();
+    let mut delays = delays.iter().copied();
+    let mut attempt = 0;
+    loop {
+        let result = send(address.clone()).await;
+        let Err(e) = &result else {
+            return (result, address);
+        };
+        if matches!(e, ProxiError::Network(_) | ProxiError::Interrupted(_)) {
+            if let Some(next) = addresses.next() {
+                println!(
+                    "[Transfer] Retrying {} via alternate address {}",
+                    transfer_id, next
+                );
+                address = next;
+                continue;
+            }
+        }
+        let Some(delay) = delays.next().filter(|_| e.is_retryable()) else {
+            return (result, address);
+        };
+        attempt += 1;
+        on_retry(attempt, delay, e);
+        // A stalled send marked itself failed; a cancel during the wait ends it
+        if let Some(entry) = transfers.write().await.get_mut(transfer_id) {
+            if entry.status == crate::TransferStatus::Failed {
+                entry.status = crate::TransferStatus::InProgress;
+            }
+        }
+        let wait = until_cancelled(transfers, transfer_id, async {
+            tokio::time::sleep(delay).await;
+            Ok(())
+        });
+        if let Err(e) = wait.await {
+            return (Err(e), address);
+        }
+    }
+}
+
+/// Log and emit one chunk's `chunk-progress` while verbose progress is on;
+/// does nothing otherwise
+pub(crate) fn report_chunk(
+    app_handle: &AppHandle,
+    transfer_id: &str,
+    direction: &'static str,
+    chunk_index: u64,
+    size: usize,
+    hash: &str,
+    verified: Option<bool>,
+) {
+    let verbose = app_handle
+        .try_state::<crate::AppState>()
+        .is_some_and(|state| state.verbose_progress.load(Ordering::Relaxed));
+    if !verbose {
+        return;
+    }
+    println!(
+        "[Transfer] {} {} chunk {} ({} bytes, {}){}",
+        transfer_id,
+        direction,
+        chunk_index,
+        size,
+        hash,
+        match verified {
+            Some(true) => " verified",
+            Some(false) => " HASH MISMATCH",
+            None => "",
+        }
+    );
+    emit_event(
+        app_handle,
+        ChunkProgress {
+            transfer_id: transfer_id.to_string(),
+            direction,
+            chunk_index,
+            size,
+            hash: hash.to_string(),
+            verified,
+        },
+    );
+}
+
+/// A protocol error leaves the stream unusable, so close the connection with
+/// a code that tells the peer so instead of letting it wait for more data
+fn close_on_protocol_error(connection: &Connection, error: &ProxiError) {
+    if let ProxiError::Protocol(msg) = error {
+        connection.close(VarInt::from_u32(CLOSE_PROTOCOL_ERROR), msg.as_bytes());
+    }
+}
+
+/// Space on the volume holding a directory
+#[derive(Clone, Debug, Serialize)]
+pub struct DiskSpace {
+    pub path: String,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// Space on the volume `path` is (or would be) on. A path that doesn't exist
+/// yet is measured at its nearest existing ancestor.
+pub fn disk_space(path: &Path) -> Result<DiskSpace, ProxiError> {
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .ok_or_else(|| ProxiError::Other(format!("No such location: {}", path.display())))?;
+    Ok(DiskSpace {
+        path: path.to_string_lossy().to_string(),
+        total_bytes: fs2::total_space(existing)?,
+        available_bytes: fs2::available_space(existing)?,
+    })
+}
+
+/// Path statistics of a transfer's QUIC connection
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectionStats {
+    pub transfer_id: String,
+    pub device_id: String,
+    pub connection_id: usize,
+    pub remote_address: String,
+    pub rtt_ms: f64,
+    pub congestion_window: u64,
+    pub lost_packets: u64,
+    pub lost_bytes: u64,
+    pub sent_packets: u64,
+    pub congestion_events: u64,
+    pub current_mtu: u16,
+}
+
+impl ConnectionStats {
+    fn from_connection(tracked: &TrackedConnection) -> Self {
+        let connection = &tracked.connection;
+        let path = connection.stats().path;
+        Self {
+            transfer_id: tracked.label.transfer_id.clone(),
+            device_id: tracked.label.device_id.clone(),
+            connection_id: tracked.label.connection_id,
+            remote_address: connection.remote_address().to_string(),
+            rtt_ms: path.rtt.as_secs_f64() * 1000.0,
+            congestion_window: path.cwnd,
+            lost_packets: path.lost_packets,
+            lost_bytes: path.lost_bytes,
+            sent_packets: path.sent_packets,
+            congestion_events: path.congestion_events,
+            current_mtu: path.current_mtu,
+        }
+    }
+}
+
+/// Delay before the first attempt to rebind a listener that stopped
+const REBIND_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+const REBIND_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(60);
+/// Failed rebinds after which receiving is reported as broken
+const REBIND_ATTEMPTS_BEFORE_ERROR: u32 = 5;
+
+#[derive(Clone)]
+struct Endpoints {
+    /// Dual-stack where the platform supports it, otherwise IPv4 only
+    main: Endpoint,
+    /// Separate IPv6 endpoint, only when a dual-stack socket couldn't be bound
+    v6: Option<Endpoint>,
+}
+
 pub struct TransferManager {
-    endpoint: Endpoint,
+    /// Replaced when the listener dies and is rebound
+    endpoints: parking_lot::RwLock<Endpoints>,
+    port: u16,
+    /// Kept for rebinding; `replace_certificate` updates the server side
+    server_config: parking_lot::Mutex<ServerConfig>,
+    client_config: ClientConfig,
+    /// Why incoming connections can't be accepted, once rebinding has
+    /// failed repeatedly
+    listener_error: parking_lot::RwLock<Option<String>>,
     app_handle: tauri::AppHandle,
     database: Arc<RwLock<Option<crate::db::Database>>>,
     transfers: crate::TransferRegistry,
     device_id: String,
     device_name: String,
+    /// Capabilities learned from peers, keyed by "ip:port"
+    peer_capabilities: RwLock<HashMap<String, PeerCapabilities>>,
+    connections: ConnectionRegistry,
+    /// Files being received, shared by every connection's receiver
+    destinations: DestinationClaims,
+    /// Where incoming files are saved; read for every new connection
+    save_directory: RwLock<PathBuf>,
 }
 
 impl TransferManager {
@@ -27,58 +339,316 @@ impl TransferManager {
         transfers: crate::TransferRegistry,
         device_id: String,
         device_name: String,
-    ) -> Result<Self, crate::GenericError> {
-        let cert_manager = CertificateManager::generate_self_signed()?;
+        cert_manager: &CertificateManager,
+    ) -> Result<Self, ProxiError> {
+        let server_config = Self::server_config(cert_manager)?;
+        let client_config = Self::client_config(cert_manager)?;
 
-        let server_crypto = Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(
-            cert_manager.get_server_config()?,
-        )?);
-
-        // Fix: Create server config with just the crypto config
-        let server_config = ServerConfig::with_crypto(server_crypto);
-
-        let client_crypto = Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(
-            cert_manager.get_client_config()?,
-        )?);
-        let client_config = ClientConfig::new(client_crypto);
-
-        let addr = format!("0.0.0.0:{}", port).parse()?;
-        let mut endpoint = Endpoint::server(server_config, addr)?;
-        endpoint.set_default_client_config(client_config);
+        let endpoints = Self::bind_endpoints(port, server_config.clone(), &client_config)?;
 
         Ok(Self {
-            endpoint,
+            endpoints: parking_lot::RwLock::new(endpoints),
+            port,
+            server_config: parking_lot::Mutex::new(server_config),
+            client_config,
+            listener_error: parking_lot::RwLock::new(None),
             app_handle,
             database,
             transfers,
             device_id,
             device_name,
+            peer_capabilities: RwLock::new(HashMap::new()),
+            connections: Arc::new(RwLock::new(HashMap::new())),
+            destinations: DestinationClaims::default(),
+            save_directory: RwLock::new(PathBuf::new()),
+        })
+    }
+
+    /// Bind `[::]:port` accepting IPv4 too. Where dual-stack sockets aren't
+    /// available (IPv6 disabled, or platforms that force IPV6_V6ONLY) fall
+    /// back to an IPv4 endpoint plus, if possible, a separate IPv6 one.
+    fn bind_endpoints(
+        port: u16,
+        server_config: ServerConfig,
+        client_config: &ClientConfig,
+    ) -> Result<Endpoints, ProxiError> {
+        let mut endpoints = Self::bind_servers(port, server_config)?;
+        endpoints
+            .main
+            .set_default_client_config(client_config.clone());
+        if let Some(v6) = &mut endpoints.v6 {
+            v6.set_default_client_config(client_config.clone());
+        }
+        Ok(endpoints)
+    }
+
+    fn bind_servers(port: u16, server_config: ServerConfig) -> Result<Endpoints, ProxiError> {
+        let v6_addr = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port);
+        match bind_udp_v6(v6_addr, false) {
+            Ok(socket) => {
+                println!("[Transfer] Listening dual-stack on {}", v6_addr);
+                return Ok(Endpoints {
+                    main: Self::endpoint_on(socket, server_config)?,
+                    v6: None,
+                });
+            }
+            Err(e) => println!(
+                "[Transfer] Dual-stack socket unavailable ({}), using separate IPv4/IPv6 endpoints",
+                e
+            ),
+        }
+
+        let v4_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
+        let endpoint = Endpoint::server(server_config.clone(), v4_addr)?;
+        let endpoint_v6 = match bind_udp_v6(v6_addr, true)
+            .and_then(|socket| Self::endpoint_on(socket, server_config))
+        {
+            Ok(endpoint) => Some(endpoint),
+            Err(e) => {
+                println!("[Transfer] No IPv6 endpoint, IPv4 only: {}", e);
+                None
+            }
+        };
+        Ok(Endpoints {
+            main: endpoint,
+            v6: endpoint_v6,
         })
     }
 
+    fn endpoint_on(
+        socket: std::net::UdpSocket,
+        server_config: ServerConfig,
+    ) -> std::io::Result<Endpoint> {
+        let runtime = quinn::default_runtime()
+            .ok_or_else(|| std::io::Error::other("No async runtime for QUIC"))?;
+        Endpoint::new(
+            quinn::EndpointConfig::default(),
+            Some(server_config),
+            socket,
+            runtime,
+        )
+    }
+
+    /// Start connecting to a peer from the endpoint serving its address family
+    fn connect(&self, ip: &str, port: u16) -> Result<quinn::Connecting, ProxiError> {
+        let addr = socket_addr(ip, port)?;
+        let endpoints = self.endpoints.read();
+        let endpoint = match (&endpoints.v6, addr) {
+            (Some(v6), SocketAddr::V6(_)) => v6,
+            _ => &endpoints.main,
+        };
+        Ok(endpoint.connect(addr, "proxishare.local")?)
+    }
+
+    pub(crate) fn client_config(
+        cert_manager: &CertificateManager,
+    ) -> Result<ClientConfig, ProxiError> {
+        let client_crypto = Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(
+            cert_manager.get_client_config()?,
+        )?);
+        let mut config = ClientConfig::new(client_crypto);
+        config.transport_config(Self::transport_config());
+        Ok(config)
+    }
+
+    pub(crate) fn server_config(
+        cert_manager: &CertificateManager,
+    ) -> Result<ServerConfig, ProxiError> {
+        let server_crypto = Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(
+            cert_manager.get_server_config()?,
+        )?);
+
+        // quinn lets a client migrate by default, so a peer whose address
+        // changes mid-transfer (e.g. Wi-Fi to Ethernet) keeps its connection
+        let mut config = ServerConfig::with_crypto(server_crypto);
+        config.transport_config(Self::transport_config());
+        Ok(config)
+    }
+
+    /// Keep-alives make a path change show up well before the idle timeout,
+    /// so the connection migrates instead of timing out
+    fn transport_config() -> Arc<TransportConfig> {
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(std::time::Duration::from_secs(5)));
+        transport.max_idle_timeout(Some(VarInt::from_u32(30_000).into()));
+        Arc::new(transport)
+    }
+
+    /// Serve new connections with a different certificate.
+    /// Connections that are already established keep the old one.
+    pub fn replace_certificate(&self, cert_manager: &CertificateManager) -> Result<(), ProxiError> {
+        let server_config = Self::server_config(cert_manager)?;
+        let endpoints = self.endpoints.read();
+        if let Some(v6) = &endpoints.v6 {
+            v6.set_server_config(Some(server_config.clone()));
+        }
+        endpoints
+            .main
+            .set_server_config(Some(server_config.clone()));
+        *self.server_config.lock() = server_config;
+        Ok(())
+    }
+
+    /// Save future incoming files to `dir`, e.g. after the previous one went away.
+    /// Transfers already running keep writing where they started.
+    pub async fn set_save_directory(&self, dir: PathBuf) {
+        println!("[Transfer] Save directory set to {:?}", dir);
+        *self.save_directory.write().await = dir;
+    }
+
+    pub async fn save_directory(&self) -> PathBuf {
+        self.save_directory.read().await.clone()
+    }
+
+    /// Why receiving is broken, if the listener died and couldn't be rebound
+    pub fn listener_error(&self) -> Option<String> {
+        self.listener_error.read().clone()
+    }
+
+    /// Accept incoming transfers for as long as the app runs. If the
+    /// endpoint stops accepting (its socket failed), emit `listener-down`
+    /// and rebind on the same port with backoff, then `listener-recovered`.
+    /// After repeated failures `listener-failed` is emitted and
+    /// `listener_error` reports it until a rebind succeeds.
     pub async fn start_listening(&self, save_dir: PathBuf) {
         println!(
             "[Transfer] Server listening on port, save dir: {:?}",
             save_dir
         );
+        *self.save_directory.write().await = save_dir;
+        loop {
+            let endpoints = self.endpoints.read().clone();
+            self.accept_connections(&endpoints).await;
+
+            println!(
+                "[Transfer] Listener on port {} stopped, rebinding",
+                self.port
+            );
+            emit_event(&self.app_handle, ListenerDown { port: self.port });
+            endpoints.main.close(VarInt::from_u32(0), b"rebinding");
+            if let Some(v6) = &endpoints.v6 {
+                v6.close(VarInt::from_u32(0), b"rebinding");
+            }
+            drop(endpoints);
+            self.rebind_with_backoff().await;
+        }
+    }
+
+    async fn rebind_with_backoff(&self) {
+        let mut delay = REBIND_INITIAL_DELAY;
+        let mut attempts = 0;
+        loop {
+            tokio::time::sleep(delay).await;
+            attempts += 1;
+            let server_config = self.server_config.lock().clone();
+            match Self::bind_endpoints(self.port, server_config, &self.client_config) {
+                Ok(endpoints) => {
+                    *self.endpoints.write() = endpoints;
+                    *self.listener_error.write() = None;
+                    println!(
+                        "[Transfer] Listener rebound on port {} after {} attempts",
+                        self.port, attempts
+                    );
+                    emit_event(
+                        &self.app_handle,
+                        ListenerRecovered {
+                            port: self.port,
+                            attempts,
+                        },
+                    );
+                    return;
+                }
+                Err(e) => {
+                    println!("[Transfer] Rebinding port {} failed: {}", self.port, e);
+                    if attempts == REBIND_ATTEMPTS_BEFORE_ERROR {
+                        let message = format!(
+                            "Receiving is unavailable: port {} couldn't be reopened ({})",
+                            self.port, e
+                        );
+                        *self.listener_error.write() = Some(message.clone());
+                        emit_event(
+                            &self.app_handle,
+                            ListenerFailed {
+                                port: self.port,
+                                error: message,
+                            },
+                        );
+                    }
+                    delay = (delay * 2).min(REBIND_MAX_DELAY);
+                }
+            }
+        }
+    }
+
+    /// Hand each incoming connection to a `FileReceiver` until the endpoints
+    /// stop accepting
+    async fn accept_connections(&self, endpoints: &Endpoints) {
         let app_handle = self.app_handle.clone();
-        while let Some(conn) = self.endpoint.accept().await {
+        loop {
+            let incoming = match &endpoints.v6 {
+                Some(v6) => tokio::select! {
+                    conn = endpoints.main.accept() => conn,
+                    conn = v6.accept() => conn,
+                },
+                None => endpoints.main.accept().await,
+            };
+            let Some(conn) = incoming else {
+                break;
+            };
+            let app_state = app_handle.state::<crate::AppState>();
+            if app_state.do_not_disturb.read().await.enabled {
+                println!("[Transfer] Do not disturb is on, refusing incoming connection");
+                conn.refuse();
+                continue;
+            }
             println!("[Transfer] Incoming connection accepted");
-            let save_dir = save_dir.clone();
+            let save_dir = self.save_directory.read().await.clone();
             let app_handle = app_handle.clone();
             let database = self.database.clone();
             let transfers = self.transfers.clone();
+            let connections = self.connections.clone();
+            let destinations = self.destinations.clone();
+            let device_name = self.device_name.clone();
             tauri::async_runtime::spawn(async move {
                 match conn.await {
                     Ok(connection) => {
                         println!("[Transfer] Connection established from remote peer");
+                        let connection_id = connection.stable_id();
+                        let address = connection.remote_address().to_string();
+                        emit_event(
+                            &app_handle,
+                            IncomingConnection {
+                                id: connection_id,
+                                address,
+                            },
+                        );
                         let receiver = FileReceiver::new(
-                            save_dir, connection, app_handle, database, transfers,
+                            save_dir,
+                            connection.clone(),
+                            app_handle.clone(),
+                            database,
+                            transfers,
+                            connections.clone(),
+                            destinations,
+                            device_name,
                         );
-                        match receiver.handle_transfer().await {
-                            Ok(_) => println!("[Transfer] File received successfully"),
-                            Err(e) => println!("[Transfer] Error receiving file: {:?}", e),
+                        let result = receiver.handle_transfer().await;
+                        let mut connections = connections.write().await;
+                        let labels = connections
+                            .values()
+                            .filter(|c| c.connection.stable_id() == connection_id)
+                            .map(|c| c.label.to_string())
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        match result {
+                            Ok(_) => println!("[Transfer] {} File received successfully", labels),
+                            Err(e) => {
+                                println!("[Transfer] {} Error receiving file: {:?}", labels, e);
+                                close_on_protocol_error(&connection, &e);
+                            }
                         }
+                        connections.retain(|_, c| c.connection.stable_id() != connection_id);
+                        emit_event(&app_handle, IncomingConnectionClosed { id: connection_id });
                     }
                     Err(e) => {
                         println!("[Transfer] Failed to establish connection: {:?}", e);
@@ -93,43 +663,444 @@ impl TransferManager {
         target_ip: String,
         target_port: u16,
         message: crate::transfer::protocol::MessageType,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<(), ProxiError> {
         println!(
             "[Transfer] Sending message to {}:{}",
             target_ip, target_port
         );
 
-        let addr = format!("{}:{}", target_ip, target_port).parse()?;
-        let connecting = self.endpoint.connect(addr, "proxishare.local")?;
+        let connecting = self.connect(&target_ip, target_port)?;
 
         let connection =
             match tokio::time::timeout(std::time::Duration::from_secs(5), connecting).await {
                 Ok(Ok(conn)) => conn,
-                Ok(Err(e)) => return Err(format!("Connection failed: {}", e).into()),
-                Err(_) => return Err("Connection timed out".into()),
+                Ok(Err(e)) => return Err(ProxiError::Network(format!("Connection failed: {}", e))),
+                Err(_) => return Err(ProxiError::Network("Connection timed out".into())),
+            };
+
+        let (mut send_stream, _) = protocol::open_stream(&connection).await?;
+        FileSender::write_message(&mut send_stream, &self.hello(&connection)?).await?;
+        FileSender::write_message(&mut send_stream, &message).await?;
+
+        send_stream.finish()?;
+
+        // Wait for the peer to acknowledge the whole stream, so callers know
+        // the message arrived rather than just left
+        let delivered = tokio::time::timeout(MESSAGE_ACK_TIMEOUT, send_stream.stopped()).await;
+        connection.close(quinn::VarInt::from_u32(0), b"message sent");
+        match delivered {
+            Ok(Ok(None)) => Ok(()),
+            Ok(Ok(Some(code))) => Err(ProxiError::Network(format!(
+                "Peer stopped reading the message (code {})",
+                code
+            ))),
+            // A peer that read to the end may hang up before its ack reaches us
+            Ok(Err(quinn::StoppedError::ConnectionLost(
+                quinn::ConnectionError::ApplicationClosed(close),
+            ))) if close.error_code == VarInt::from_u32(0) => Ok(()),
+            Ok(Err(e)) => Err(ProxiError::Network(format!("Message not delivered: {}", e))),
+            Err(_) => Err(ProxiError::Network(
+                "Peer didn't acknowledge the message".into(),
+            )),
+        }
+    }
+
+    pub fn app_handle(&self) -> &tauri::AppHandle {
+        &self.app_handle
+    }
+
+    /// Tell the receiver which transfers make up a batch so it can group them
+    pub async fn send_batch_offer(
+        &self,
+        target_ip: String,
+        target_port: u16,
+        batch_id: String,
+        files: &[(String, PathBuf)],
+    ) -> Result<(), ProxiError> {
+        let mut offered = Vec::with_capacity(files.len());
+        for (transfer_id, path) in files {
+            let metadata = tokio::fs::metadata(path).await?;
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            // The same name the file's own offer will carry
+            let name = {
+                let app_state = self.app_handle.state::<crate::AppState>();
+                let settings = app_state.settings.read().await;
+                settings.settings.offer_name(&name)
             };
+            offered.push((
+                transfer_id.clone(),
+                FileMetadata {
+                    name,
+                    size: metadata.len(),
+                    hash: String::new(),
+                    hash_algo: Default::default(),
+                    chunk_size: sender::chunk_size_for(&self.app_handle, metadata.len()).await
+                        as u32,
+                    modified: metadata
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs()),
+                    merkle_root: None,
+                },
+            ));
+        }
 
-        let (mut send_stream, _) = connection.open_bi().await?;
+        self.send_message(
+            target_ip,
+            target_port,
+            MessageType::BatchOffer {
+                batch_id,
+                files: offered,
+            },
+        )
+        .await
+    }
 
-        // Use the existing write_message from sender module logic (we might need to expose it or duplicate it safely)
-        // For now, let's just implement a quick write since FileSender::write_message is private
-        // and we don't want to refactor everything.
-        // Better yet, let's make FileSender::write_message public or move it to protocol.
+    /// Signed `Hello` identifying this device on `connection`, sent first on
+    /// every stream
+    fn hello(&self, connection: &Connection) -> Result<MessageType, ProxiError> {
+        let app_state = self.app_handle.state::<crate::AppState>();
+        MessageType::hello(&app_state.identity, &self.device_name, connection)
+    }
 
-        // Quick implementation of write_message here for now
-        let data = bincode::serialize(&message)?;
-        let len = data.len() as u32;
-        send_stream.write_all(&len.to_be_bytes()).await?;
-        send_stream.write_all(&data).await?;
+    /// QUIC path statistics for an active transfer
+    pub async fn connection_stats(&self, transfer_id: &str) -> Option<ConnectionStats> {
+        self.connections
+            .read()
+            .await
+            .get(transfer_id)
+            .map(ConnectionStats::from_connection)
+    }
 
-        send_stream.finish()?;
+    /// Ask a peer which protocol features it supports and remember the answer
+    pub async fn query_capabilities(
+        &self,
+        target_ip: String,
+        target_port: u16,
+    ) -> Result<PeerCapabilities, ProxiError> {
+        println!(
+            "[Transfer] Querying capabilities of {}:{}",
+            target_ip, target_port
+        );
 
-        // Give it a moment to send
-        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        let connecting = self.connect(&target_ip, target_port)?;
 
-        connection.close(quinn::VarInt::from_u32(0), b"message sent");
+        let connection =
+            match tokio::time::timeout(std::time::Duration::from_secs(5), connecting).await {
+                Ok(Ok(conn)) => conn,
+                Ok(Err(e)) => return Err(ProxiError::Network(format!("Connection failed: {}", e))),
+                Err(_) => return Err(ProxiError::Network("Connection timed out".into())),
+            };
 
-        Ok(())
+        let (mut send_stream, mut recv_stream) = protocol::open_stream(&connection).await?;
+        FileSender::write_message(&mut send_stream, &self.hello(&connection)?).await?;
+        let local = PeerCapabilities::local();
+        FileSender::write_message(
+            &mut send_stream,
+            &MessageType::Capabilities {
+                features: local.features,
+                protocol_version: local.protocol_version,
+            },
+        )
+        .await?;
+
+        let response = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            FileSender::read_message(&mut recv_stream),
+        )
+        .await;
+        connection.close(quinn::VarInt::from_u32(0), b"capabilities received");
+
+        let capabilities = match response {
+            Ok(Ok(MessageType::Capabilities {
+                features,
+                protocol_version,
+            })) => PeerCapabilities {
+                protocol_version,
+                features,
+            },
+            Ok(Ok(_)) => {
+                return Err(ProxiError::Protocol(
+                    "Unexpected response to capabilities request".into(),
+                ))
+            }
+            Ok(Err(e)) => {
+                return Err(ProxiError::Network(format!(
+                    "Failed to read capabilities: {}",
+                    e
+                )))
+            }
+            Err(_) => {
+                return Err(ProxiError::Network(
+                    "Timed out waiting for capabilities".into(),
+                ))
+            }
+        };
+
+        self.peer_capabilities.write().await.insert(
+            format!("{}:{}", target_ip, target_port),
+            capabilities.clone(),
+        );
+        Ok(capabilities)
+    }
+
+    /// Send our sync folder's manifest in a `SyncRequest` and return the
+    /// peer's `SyncResponse`: the paths it would want from us, and its own
+    /// manifest (empty unless it trusts us)
+    pub async fn exchange_manifests(
+        &self,
+        target_ip: String,
+        target_port: u16,
+        folder_path: String,
+        files: Vec<FileMetadata>,
+    ) -> Result<(Vec<String>, Vec<FileMetadata>), ProxiError> {
+        let connecting = self.connect(&target_ip, target_port)?;
+
+        let connection =
+            match tokio::time::timeout(std::time::Duration::from_secs(5), connecting).await {
+                Ok(Ok(conn)) => conn,
+                Ok(Err(e)) => return Err(ProxiError::Network(format!("Connection failed: {}", e))),
+                Err(_) => return Err(ProxiError::Network("Connection timed out".into())),
+            };
+
+        let (mut send_stream, mut recv_stream) = protocol::open_stream(&connection).await?;
+        FileSender::write_message(&mut send_stream, &self.hello(&connection)?).await?;
+        FileSender::write_message(
+            &mut send_stream,
+            &MessageType::SyncRequest { folder_path, files },
+        )
+        .await?;
+
+        // The peer hashes its whole folder before answering
+        let response = tokio::time::timeout(
+            std::time::Duration::from_secs(60),
+            FileSender::read_message(&mut recv_stream),
+        )
+        .await;
+        connection.close(VarInt::from_u32(0), b"manifest received");
+
+        match response {
+            Ok(Ok(MessageType::SyncResponse {
+                missing_files,
+                files,
+            })) => Ok((missing_files, files)),
+            Ok(Ok(_)) => Err(ProxiError::Protocol(
+                "Unexpected response to sync request".into(),
+            )),
+            Ok(Err(e)) => Err(ProxiError::Network(format!(
+                "Failed to read sync response: {}",
+                e
+            ))),
+            Err(_) => Err(ProxiError::Network(
+                "Timed out waiting for sync response".into(),
+            )),
+        }
+    }
+
+    /// Exchange recent transfer history with a peer: our records go out in
+    /// chunks of `CHUNK_RECORDS`, then the peer answers with its own, up to
+    /// `history_sync_max_records` each way. Progress is emitted as
+    /// `history-sync-progress`.
+    pub async fn sync_history(
+        &self,
+        target_ip: String,
+        target_port: u16,
+        device_id: &str,
+    ) -> Result<HistorySyncSummary, ProxiError> {
+        let limit = history::max_records(&self.app_handle).await;
+        let records = history::local_records(&self.app_handle, limit).await;
+        let mut progress = HistorySyncProgress {
+            device_id: device_id.to_string(),
+            to_send: records.len(),
+            summary: HistorySyncSummary::default(),
+            done: false,
+        };
+        println!(
+            "[Sync] Syncing history with {} at {}:{} ({} records)",
+            device_id,
+            target_ip,
+            target_port,
+            records.len()
+        );
+        emit_event(&self.app_handle, progress.clone());
+
+        let connecting = self.connect(&target_ip, target_port)?;
+        let connection =
+            match tokio::time::timeout(std::time::Duration::from_secs(5), connecting).await {
+                Ok(Ok(conn)) => conn,
+                Ok(Err(e)) => return Err(ProxiError::Network(format!("Connection failed: {}", e))),
+                Err(_) => return Err(ProxiError::Network("Connection timed out".into())),
+            };
+
+        let (mut send_stream, mut recv_stream) = protocol::open_stream(&connection).await?;
+        FileSender::write_message(&mut send_stream, &self.hello(&connection)?).await?;
+        for chunk in records.chunks(history::CHUNK_RECORDS) {
+            FileSender::write_message(
+                &mut send_stream,
+                &MessageType::HistorySync {
+                    records: chunk.to_vec(),
+                },
+            )
+            .await?;
+            progress.summary.sent += chunk.len();
+            emit_event(&self.app_handle, progress.clone());
+        }
+        FileSender::write_message(
+            &mut send_stream,
+            &MessageType::HistorySyncEnd {
+                max_records: limit as u32,
+            },
+        )
+        .await?;
+
+        loop {
+            let message = tokio::time::timeout(
+                std::time::Duration::from_secs(10),
+                FileSender::read_message(&mut recv_stream),
+            )
+            .await;
+            match message {
+                Ok(Ok(MessageType::HistorySync { mut records })) => {
+                    records.truncate(limit.saturating_sub(progress.summary.received));
+                    progress.summary.received += records.len();
+                    progress.summary.merged +=
+                        history::merge_records(&self.app_handle, &records).await;
+                    emit_event(&self.app_handle, progress.clone());
+                }
+                Ok(Ok(MessageType::HistorySyncEnd { .. })) => break,
+                Ok(Ok(_)) => {
+                    connection.close(
+                        VarInt::from_u32(CLOSE_PROTOCOL_ERROR),
+                        b"unexpected message",
+                    );
+                    return Err(ProxiError::Protocol(
+                        "Unexpected message during history sync".into(),
+                    ));
+                }
+                Ok(Err(e)) => {
+                    println!("[Sync] {} sent no history back: {}", device_id, e);
+                    break;
+                }
+                Err(_) => {
+                    println!("[Sync] Timed out waiting for history from {}", device_id);
+                    break;
+                }
+            }
+        }
+        let _ = send_stream.finish();
+        connection.close(VarInt::from_u32(0), b"history synced");
+
+        progress.done = true;
+        emit_event(&self.app_handle, progress.clone());
+        println!(
+            "[Sync] History sync with {}: sent {}, received {}, merged {}",
+            device_id, progress.summary.sent, progress.summary.received, progress.summary.merged
+        );
+        Ok(progress.summary)
+    }
+
+    /// Connect to a peer and ask who it is, returning its verified device id
+    /// and name. Fails if nothing answers, the answer isn't properly signed or
+    /// the id isn't the fingerprint of the key it was signed with.
+    pub async fn identify_peer(
+        &self,
+        target_ip: String,
+        target_port: u16,
+    ) -> Result<(String, String), ProxiError> {
+        let connecting = self.connect(&target_ip, target_port)?;
+
+        let connection =
+            match tokio::time::timeout(std::time::Duration::from_secs(5), connecting).await {
+                Ok(Ok(conn)) => conn,
+                Ok(Err(e)) => return Err(ProxiError::Network(format!("Connection failed: {}", e))),
+                Err(_) => return Err(ProxiError::Network("Connection timed out".into())),
+            };
+
+        let (mut send_stream, mut recv_stream) = protocol::open_stream(&connection).await?;
+        FileSender::write_message(&mut send_stream, &self.hello(&connection)?).await?;
+        FileSender::write_message(&mut send_stream, &MessageType::IdentityRequest).await?;
+
+        let response = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            FileSender::read_message(&mut recv_stream),
+        )
+        .await;
+        connection.close(quinn::VarInt::from_u32(0), b"identity received");
+
+        match response {
+            Ok(Ok(MessageType::Hello {
+                device_id,
+                device_name,
+                public_key,
+                signature,
+            })) => {
+                if !protocol::hello_is_valid(
+                    &connection,
+                    &device_id,
+                    &device_name,
+                    &public_key,
+                    &signature,
+                ) {
+                    return Err(ProxiError::Crypto(
+                        "Peer's identity has an invalid signature".into(),
+                    ));
+                }
+                // A valid signature only proves the peer holds some key; the
+                // id must be that key's, or it could claim a trusted device's
+                if !crate::crypto::identity::id_matches_key(&device_id, &public_key) {
+                    return Err(ProxiError::Crypto(format!(
+                        "Peer's device id {} doesn't belong to its key",
+                        device_id
+                    )));
+                }
+                Ok((device_id, device_name))
+            }
+            Ok(Ok(_)) => Err(ProxiError::Protocol(
+                "Unexpected response to identity request".into(),
+            )),
+            Ok(Err(e)) => Err(ProxiError::Network(format!(
+                "Failed to read identity: {}",
+                e
+            ))),
+            Err(_) => Err(ProxiError::Network(
+                "Timed out waiting for the peer to identify itself".into(),
+            )),
+        }
+    }
+
+    /// Measure raw throughput to a peer with `size` bytes of in-memory data
+    pub async fn run_benchmark(
+        &self,
+        target_ip: String,
+        target_port: u16,
+        size: u64,
+    ) -> Result<BenchmarkResult, ProxiError> {
+        println!(
+            "[Transfer] Benchmarking {} bytes to {}:{}",
+            size, target_ip, target_port
+        );
+
+        let connecting = self.connect(&target_ip, target_port)?;
+
+        let connection =
+            match tokio::time::timeout(std::time::Duration::from_secs(10), connecting).await {
+                Ok(Ok(conn)) => conn,
+                Ok(Err(e)) => return Err(ProxiError::Network(format!("Connection failed: {}", e))),
+                Err(_) => return Err(ProxiError::Network("Connection timed out".into())),
+            };
+
+        let sender = FileSender::new(
+            connection,
+            self.app_handle.clone(),
+            self.device_id.clone(),
+            self.device_name.clone(),
+            PeerCapabilities::baseline(),
+        );
+        sender.send_benchmark(size).await
     }
 
     pub async fn send_file(
@@ -139,54 +1110,603 @@ impl TransferManager {
         target_port: u16,
         file_path: PathBuf,
         transfers: crate::TransferRegistry,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<(), ProxiError> {
+        self.send_payload(
+            transfer_id,
+            target_ip,
+            target_port,
+            SendPayload::File(file_path),
+            transfers,
+        )
+        .await
+    }
+
+    /// Send `payload` once; retries are up to the caller, see `send_with_retries`
+    pub async fn send_payload(
+        &self,
+        transfer_id: String,
+        target_ip: String,
+        target_port: u16,
+        payload: SendPayload,
+        transfers: crate::TransferRegistry,
+    ) -> Result<(), ProxiError> {
+        let device_id = transfers
+            .read()
+            .await
+            .get(&transfer_id)
+            .map(|entry| entry.device_id.clone())
+            .unwrap_or_default();
+        let blocked = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let security = app_state.security.read().await;
+            security.is_blocked(&device_id)
+        };
+        if blocked {
+            return Err(ProxiError::Rejected(format!(
+                "Device {} is blocked",
+                device_id
+            )));
+        }
+
+        let description = match &payload {
+            SendPayload::File(path) => format!("file {:?}", path),
+            SendPayload::RenamedFile { path, name } => format!("file {:?} as {:?}", path, name),
+            SendPayload::Bytes { name, data } => format!("{} bytes as {:?}", data.len(), name),
+        };
         println!(
-            "[Transfer] Attempting to send file {:?} to {}:{}",
-            file_path, target_ip, target_port
+            "[Transfer] Attempting to send {} to {}:{}",
+            description, target_ip, target_port
         );
 
-        let addr = format!("{}:{}", target_ip, target_port).parse()?;
-        println!("[Transfer] Connecting to {:?}...", addr);
+        println!(
+            "[Transfer] Connecting to {:?}...",
+            socket_addr(&target_ip, target_port)?
+        );
 
-        let connecting = self.endpoint.connect(addr, "proxishare.local")?;
+        let connecting = self.connect(&target_ip, target_port)?;
         println!("[Transfer] Connection initiated, waiting for handshake...");
 
-        let connection =
+        let connect = async {
             match tokio::time::timeout(std::time::Duration::from_secs(10), connecting).await {
                 Ok(Ok(conn)) => {
                     println!("[Transfer] Connection established!");
-                    conn
+                    Ok(conn)
                 }
                 Ok(Err(e)) => {
                     println!("[Transfer] Connection failed: {:?}", e);
-                    return Err(format!("Connection failed: {}", e).into());
+                    Err(ProxiError::Network(format!("Connection failed: {}", e)))
                 }
                 Err(_) => {
                     println!("[Transfer] Connection timed out after 10 seconds");
-                    return Err("Connection timed out".into());
+                    Err(ProxiError::Network("Connection timed out".into()))
                 }
-            };
+            }
+        };
+        let connection = until_cancelled(&transfers, &transfer_id, connect).await?;
+
+        // Peers that were never probed are assumed to speak the baseline
+        // protocol, unless chunk encryption is required and we need to know
+        let cached = self
+            .peer_capabilities
+            .read()
+            .await
+            .get(&format!("{}:{}", target_ip, target_port))
+            .cloned();
+        let encrypt_chunks = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let settings = app_state.settings.read().await;
+            settings.settings.encrypt_chunks
+        };
+        let peer_capabilities = match cached {
+            Some(capabilities) => capabilities,
+            None if encrypt_chunks => self
+                .query_capabilities(target_ip.clone(), target_port)
+                .await
+                .unwrap_or_else(|_| PeerCapabilities::baseline()),
+            None => PeerCapabilities::baseline(),
+        };
 
         let sender = FileSender::new(
-            connection,
+            connection.clone(),
             self.app_handle.clone(),
             self.device_id.clone(),
             self.device_name.clone(),
+            peer_capabilities,
         );
-        println!("[Transfer] Starting file transfer with ID: {}", transfer_id);
+        let label = ConnectionLabel::new(&transfer_id, &device_id, &connection);
+        println!("[Transfer] {} Starting file transfer", label);
 
-        match sender
-            .send_file(transfer_id.clone(), file_path.clone(), transfers)
-            .await
-        {
+        self.connections.write().await.insert(
+            transfer_id.clone(),
+            TrackedConnection::new(connection.clone(), label.clone()),
+        );
+        let result = sender
+            .send_payload(transfer_id.clone(), payload, transfers)
+            .await;
+        self.connections.write().await.remove(&transfer_id);
+        match &result {
+            Err(ProxiError::Cancelled(_)) => {
+                connection.close(VarInt::from_u32(CLOSE_CANCELLED), b"transfer cancelled")
+            }
+            Err(e) => close_on_protocol_error(&connection, e),
+            Ok(()) => {}
+        }
+
+        match result {
             Ok(_) => {
-                println!("[Transfer] File {:?} sent successfully!", file_path);
+                println!("[Transfer] {} Sent {} successfully!", label, description);
                 Ok(())
             }
             Err(e) => {
-                println!("[Transfer] Failed to send file: {:?}", e);
+                println!("[Transfer] {} Failed to send file: {:?}", label, e);
                 Err(e)
             }
         }
     }
 }
+
+/// Socket address for a peer IP as discovered, which for IPv6 may carry a
+/// numeric zone (`fe80::1%3`) and needs brackets before the port
+pub fn socket_addr(ip: &str, port: u16) -> Result<SocketAddr, ProxiError> {
+    let text = if ip.contains(':') {
+        format!("[{}]:{}", ip, port)
+    } else {
+        format!("{}:{}", ip, port)
+    };
+    Ok(text.parse()?)
+}
+
+/// UDP socket on an IPv6 address; `v6_only` false also accepts IPv4 as
+/// mapped addresses
+fn bind_udp_v6(addr: SocketAddr, v6_only: bool) -> std::io::Result<std::net::UdpSocket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::IPV6,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    socket.set_only_v6(v6_only)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::protocol::{read_frame, write_frame, FileMetadata};
+    use crate::transfer::test_support;
+    use parking_lot::Mutex;
+    use quinn::Endpoint;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    const CHUNK: usize = 4;
+    const CHUNKS: usize = 8;
+    /// Chunks the receiver takes before it goes away
+    const RECEIVED_BEFORE_RESTART: usize = 3;
+
+    fn file() -> Vec<u8> {
+        (0..(CHUNK * CHUNKS) as u8).collect()
+    }
+
+    /// A receiver that accepts one transfer into `path`, offering to resume
+    /// from what it recorded in `db` the way `FileReceiver` does. With
+    /// `stop_after`, its endpoint is dropped once it has written that many
+    /// chunks, as if the app quit mid-transfer. Returns the resume offset it
+    /// offered.
+    async fn receive(
+        endpoint: Endpoint,
+        db: &crate::db::Database,
+        path: &std::path::Path,
+        stop_after: Option<usize>,
+    ) -> u64 {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let connection = endpoint.accept().await.unwrap().await.unwrap();
+        let (mut send, mut recv) = protocol::accept_stream(&connection).await.unwrap();
+        let MessageType::FileOffer {
+            transfer_id,
+            metadata,
+            ..
+        } = read_frame(&mut recv).await.unwrap()
+        else {
+            panic!("expected an offer");
+        };
+        let resume = receiver::resume_point(db, &transfer_id, path, &metadata).await;
+        if resume.is_none() {
+            db.clear_chunks(&transfer_id).await.unwrap();
+        }
+        db.set_merkle_root(&transfer_id, metadata.merkle_root.as_deref().unwrap())
+            .await
+            .unwrap();
+        let (resume_offset, prefix_hash) = match resume {
+            Some((offset, prefix_hash)) => (offset, Some(prefix_hash)),
+            None => (0, None),
+        };
+        let accept = MessageType::FileAccept {
+            transfer_id: transfer_id.clone(),
+            resume_offset,
+            prefix_hash,
+        };
+        write_frame(&mut send, &accept).await.unwrap();
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let mut written = 0;
+        loop {
+            match read_frame(&mut recv).await.unwrap() {
+                MessageType::ChunkData {
+                    chunk_index,
+                    data,
+                    chunk_hash,
+                    ..
+                } => {
+                    assert_eq!(protocol::HashAlgo::Blake3.hash(&data).unwrap(), chunk_hash);
+                    file.seek(SeekFrom::Start(chunk_index * metadata.chunk_size as u64))
+                        .unwrap();
+                    file.write_all(&data).unwrap();
+                    db.record_chunk(&transfer_id, chunk_index, &chunk_hash)
+                        .await
+                        .unwrap();
+                    written += 1;
+                    if stop_after == Some(written) {
+                        endpoint.close(VarInt::from_u32(0), b"receiver quit");
+                        endpoint.wait_idle().await;
+                        return resume_offset;
+                    }
+                }
+                MessageType::TransferComplete { .. } => {
+                    let ack = MessageType::TransferCompleteAck {
+                        transfer_id,
+                        receipt: None,
+                    };
+                    write_frame(&mut send, &ack).await.unwrap();
+                    send.finish().unwrap();
+                    connection.closed().await;
+                    return resume_offset;
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    /// One offer and the chunks after the point the receiver resumes from,
+    /// if its prefix hash matches ours. Returns the chunk index it started from.
+    async fn send_once(
+        client: &Endpoint,
+        addr: SocketAddr,
+        data: &[u8],
+    ) -> Result<u64, ProxiError> {
+        let algo = protocol::HashAlgo::Blake3;
+        let (hash, chunk_hashes) = merkle::hash_chunks(&mut &data[..], CHUNK, algo).await?;
+        let connection = tokio::time::timeout(
+            Duration::from_secs(3),
+            client.connect(addr, "proxishare.local")?,
+        )
+        .await
+        .map_err(|_| ProxiError::Network("Connection timed out".into()))??;
+        let (mut send, mut recv) = protocol::open_stream(&connection).await?;
+        let offer = MessageType::FileOffer {
+            transfer_id: "t1".into(),
+            metadata: FileMetadata {
+                name: "file.bin".into(),
+                size: data.len() as u64,
+                hash,
+                hash_algo: algo,
+                chunk_size: CHUNK as u32,
+                modified: None,
+                merkle_root: Some(merkle::root(algo, &chunk_hashes)?),
+            },
+            sender_id: "sender".into(),
+            sender_name: "Laptop".into(),
+            benchmark: false,
+            resumable: true,
+            encrypted: false,
+        };
+        write_frame(&mut send, &offer).await?;
+        let MessageType::FileAccept {
+            resume_offset,
+            prefix_hash,
+            ..
+        } = read_frame(&mut recv).await?
+        else {
+            return Err(ProxiError::Protocol("expected an accept".into()));
+        };
+
+        let first = sender::resumed_chunks(
+            resume_offset,
+            prefix_hash.as_deref(),
+            &chunk_hashes,
+            CHUNK,
+            data.len() as u64,
+            algo,
+        )?
+        .unwrap_or(0);
+        let mut chunk_index = first;
+        for chunk in data[first as usize * CHUNK..].chunks(CHUNK) {
+            let msg = MessageType::ChunkData {
+                transfer_id: "t1".into(),
+                chunk_index,
+                data: chunk.to_vec(),
+                chunk_hash: chunk_hashes[chunk_index as usize].clone(),
+            };
+            write_frame(&mut send, &msg).await?;
+            chunk_index = protocol::next_chunk_index(chunk_index)?;
+        }
+        let complete = MessageType::TransferComplete {
+            transfer_id: "t1".into(),
+        };
+        write_frame(&mut send, &complete).await?;
+        match read_frame(&mut recv).await? {
+            MessageType::TransferCompleteAck { .. } => Ok(first),
+            other => Err(ProxiError::Protocol(format!("unexpected {:?}", other))),
+        }
+    }
+
+    /// A temporary directory with the receiving side's database, transfer
+    /// "t1" recorded in it, and the path its partial file goes to
+    async fn receiving_side() -> (std::path::PathBuf, crate::db::Database, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("proxishare-resume-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = crate::db::Database::new(&dir.join("proxishare.db"))
+            .await
+            .unwrap();
+        let path = dir.join("file.bin");
+        db.record_transfer(
+            "t1",
+            "sender",
+            "file.bin",
+            &path.to_string_lossy(),
+            (CHUNK * CHUNKS) as i64,
+            "receive",
+            "",
+            protocol::HashAlgo::Blake3,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        (dir, db, path)
+    }
+
+    #[tokio::test]
+    async fn a_transfer_continues_when_the_senders_address_changes() {
+        let server = test_support::server("127.0.0.1:0".parse().unwrap());
+        let addr = server.local_addr().unwrap();
+        let client = test_support::client();
+        let data = file();
+
+        let receiver = async {
+            let connection = server.accept().await.unwrap().await.unwrap();
+            let (_send, mut recv) = protocol::accept_stream(&connection).await.unwrap();
+            let mut received = Vec::new();
+            let mut addresses = Vec::new();
+            while received.len() < data.len() {
+                let MessageType::ChunkData { data, .. } = read_frame(&mut recv).await.unwrap()
+                else {
+                    panic!("expected a chunk");
+                };
+                received.extend_from_slice(&data);
+                addresses.push(connection.remote_address());
+            }
+            (received, addresses)
+        };
+        let sender = async {
+            let connection = client
+                .connect(addr, "proxishare.local")
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut send, _recv) = protocol::open_stream(&connection).await.unwrap();
+            for (chunk_index, chunk) in data.chunks(CHUNK).enumerate() {
+                if chunk_index == CHUNKS / 2 {
+                    // The laptop moved to another network: same peer, new local address
+                    client
+                        .rebind(std::net::UdpSocket::bind("127.0.0.1:0").unwrap())
+                        .unwrap();
+                }
+                let msg = MessageType::ChunkData {
+                    transfer_id: "t1".into(),
+                    chunk_index: chunk_index as u64,
+                    data: chunk.to_vec(),
+                    chunk_hash: String::new(),
+                };
+                write_frame(&mut send, &msg).await.unwrap();
+                // One chunk at a time, so each arrives from the address it was sent on
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            connection
+        };
+        let ((received, addresses), _connection) =
+            tokio::time::timeout(Duration::from_secs(10), async {
+                tokio::join!(receiver, sender)
+            })
+            .await
+            .expect("the transfer stalled after the address change");
+
+        assert_eq!(received, data);
+        assert_ne!(addresses.first(), addresses.last());
+    }
+
+    #[tokio::test]
+    async fn a_send_resumes_after_the_receiver_restarts() {
+        let (dir, db, path) = receiving_side().await;
+
+        let first = test_support::server("127.0.0.1:0".parse().unwrap());
+        let addr = first.local_addr().unwrap();
+        let client = test_support::client();
+        let receiver = {
+            let path = path.clone();
+            tokio::spawn(async move {
+                let before = receive(first, &db, &path, Some(RECEIVED_BEFORE_RESTART)).await;
+                // The restarted app binds the same port and keeps its chunks
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                let after = receive(test_support::server(addr), &db, &path, None).await;
+                (before, after)
+            })
+        };
+
+        let data = file();
+        let transfers = crate::TransferRegistry::default();
+        let (result, _) = tokio::time::timeout(
+            Duration::from_secs(20),
+            send_with_retries(
+                &transfers,
+                "t1",
+                vec![addr.to_string()],
+                &[Duration::from_millis(200); 5],
+                |_, _, _| {},
+                |_| send_once(&client, addr, &data),
+            ),
+        )
+        .await
+        .expect("the send never finished");
+        let (first_offset, resumed_offset) = receiver.await.unwrap();
+
+        // The retry picked up at the chunk boundary where the first receiver
+        // stopped, not at 0, and the file came out whole
+        assert_eq!(first_offset, 0);
+        assert_eq!(resumed_offset, (RECEIVED_BEFORE_RESTART * CHUNK) as u64);
+        assert_eq!(result.unwrap(), RECEIVED_BEFORE_RESTART as u64);
+        let received = std::fs::read(&path).unwrap();
+        assert_eq!(blake3::hash(&received), blake3::hash(&data));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn a_send_moves_to_the_receivers_other_address_and_resumes() {
+        let (dir, db, path) = receiving_side().await;
+        let old_address = test_support::server("127.0.0.1:0".parse().unwrap());
+        let new_address = test_support::server("127.0.0.1:0".parse().unwrap());
+        // As discovery would list them, the address in use first
+        let addresses = vec![
+            old_address.local_addr().unwrap().to_string(),
+            new_address.local_addr().unwrap().to_string(),
+        ];
+        let client = test_support::client();
+        let receiver = {
+            let path = path.clone();
+            tokio::spawn(async move {
+                // The receiver leaves the first network after a few chunks
+                receive(old_address, &db, &path, Some(RECEIVED_BEFORE_RESTART)).await;
+                receive(new_address, &db, &path, None).await
+            })
+        };
+
+        let data = file();
+        let transfers = crate::TransferRegistry::default();
+        // No automatic retries configured, as by default
+        let (result, used) = tokio::time::timeout(
+            Duration::from_secs(20),
+            send_with_retries(
+                &transfers,
+                "t1",
+                addresses.clone(),
+                &[],
+                |_, _, e| panic!("retried after {}", e),
+                |address: String| {
+                    let client = client.clone();
+                    let data = data.clone();
+                    async move { send_once(&client, address.parse().unwrap(), &data).await }
+                },
+            ),
+        )
+        .await
+        .expect("the send never finished");
+        let resumed_offset = receiver.await.unwrap();
+
+        assert_eq!(used, addresses[1]);
+        assert_eq!(resumed_offset, (RECEIVED_BEFORE_RESTART * CHUNK) as u64);
+        assert_eq!(result.unwrap(), RECEIVED_BEFORE_RESTART as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Hands out `outcomes` in order, recording each attempt's address in `tried`
+    fn scripted(
+        tried: &Arc<Mutex<Vec<String>>>,
+        outcomes: Vec<Result<(), ProxiError>>,
+    ) -> impl FnMut(String) -> std::future::Ready<Result<(), ProxiError>> {
+        let tried = tried.clone();
+        let mut outcomes = outcomes.into_iter();
+        move |address| {
+            tried.lock().push(address);
+            std::future::ready(outcomes.next().expect("too many attempts"))
+        }
+    }
+
+    fn addresses() -> Vec<String> {
+        vec!["10.0.0.2".into(), "192.168.1.2".into()]
+    }
+
+    #[tokio::test]
+    async fn a_broken_path_moves_on_without_using_up_retries() {
+        let transfers = crate::TransferRegistry::default();
+        let tried = Arc::default();
+        let send = scripted(
+            &tried,
+            vec![Err(ProxiError::Network("unreachable".into())), Ok(())],
+        );
+        let mut retries = 0;
+        let (result, used) = send_with_retries(
+            &transfers,
+            "t1",
+            addresses(),
+            &[],
+            |_, _, _| retries += 1,
+            send,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(used, "192.168.1.2");
+        assert_eq!(*tried.lock(), addresses());
+        assert_eq!(retries, 0);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_when_the_delays_run_out() {
+        let transfers = crate::TransferRegistry::default();
+        let lost = || Err(ProxiError::Interrupted("receiver went away".into()));
+        let tried = Arc::default();
+        let send = scripted(&tried, vec![lost(), lost(), lost(), lost()]);
+        let mut retries = Vec::new();
+        let (result, used) = send_with_retries(
+            &transfers,
+            "t1",
+            addresses(),
+            &[Duration::from_millis(10); 2],
+            |attempt, _, _| retries.push(attempt),
+            send,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ProxiError::Interrupted(_))));
+        assert_eq!(used, "192.168.1.2");
+        // Both addresses, then the two retries on the last one
+        assert_eq!(tried.lock().len(), 4);
+        assert_eq!(retries, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn a_rejected_send_is_not_retried() {
+        let transfers = crate::TransferRegistry::default();
+        let tried = Arc::default();
+        let send = scripted(&tried, vec![Err(ProxiError::Rejected("declined".into()))]);
+        let (result, _) = send_with_retries(
+            &transfers,
+            "t1",
+            addresses(),
+            &[Duration::from_millis(10); 3],
+            |_, _, _| panic!("retried a rejection"),
+            send,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ProxiError::Rejected(_))));
+        assert_eq!(tried.lock().len(), 1);
+    }
+}
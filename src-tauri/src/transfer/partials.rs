@@ -0,0 +1,104 @@
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Suffixes of files that are still being written: `.part` for interrupted
+/// downloads and `.partial` for post-processing copies
+pub const PARTIAL_SUFFIXES: &[&str] = &[".part", ".partial"];
+
+/// How deep below the download folder to look, enough for post-processing
+/// subfolders like "Documents/Inbox"
+const MAX_SCAN_DEPTH: usize = 4;
+
+/// Files touched more recently than this may still be in use (a move copy
+/// in progress), so they're never reported
+const MIN_ORPHAN_AGE: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OrphanedPartial {
+    pub path: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub modified: Option<i64>,
+}
+
+/// Partial files under `dir` that no transfer in `keep` can resume from.
+/// A path in `keep` protects itself and its partial names.
+pub fn find_orphans(dir: &Path, keep: &HashSet<PathBuf>) -> std::io::Result<Vec<OrphanedPartial>> {
+    let mut orphans = Vec::new();
+    scan(dir, keep, MAX_SCAN_DEPTH, &mut orphans)?;
+    orphans.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(orphans)
+}
+
+fn scan(
+    dir: &Path,
+    keep: &HashSet<PathBuf>,
+    depth: usize,
+    orphans: &mut Vec<OrphanedPartial>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        // Doesn't follow symlinks, so links out of the folder are skipped
+        let metadata = entry.metadata()?;
+        let path = entry.path();
+        if metadata.is_dir() {
+            if depth > 0 {
+                scan(&path, keep, depth - 1, orphans)?;
+            }
+            continue;
+        }
+        if !metadata.is_file() || !is_partial(&path) || is_kept(&path, keep) {
+            continue;
+        }
+        let modified = metadata.modified().ok();
+        let recent = modified
+            .and_then(|m| SystemTime::now().duration_since(m).ok())
+            .is_none_or(|age| age < MIN_ORPHAN_AGE);
+        if recent {
+            continue;
+        }
+        orphans.push(OrphanedPartial {
+            path: path.to_string_lossy().to_string(),
+            size: metadata.len(),
+            modified: modified
+                .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64),
+        });
+    }
+    Ok(())
+}
+
+fn is_partial(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    PARTIAL_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+fn is_kept(path: &Path, keep: &HashSet<PathBuf>) -> bool {
+    if keep.contains(path) {
+        return true;
+    }
+    let name = path.as_os_str().to_string_lossy();
+    PARTIAL_SUFFIXES.iter().any(|suffix| {
+        name.strip_suffix(suffix)
+            .is_some_and(|base| keep.contains(Path::new(base)))
+    })
+}
+
+/// Delete the given orphans, returning how many bytes were freed. Files that
+/// are already gone are skipped.
+pub fn remove_orphans(orphans: &[OrphanedPartial]) -> u64 {
+    let mut freed = 0;
+    for orphan in orphans {
+        match std::fs::remove_file(&orphan.path) {
+            Ok(()) => freed += orphan.size,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => println!("[Transfer] Failed to delete {}: {}", orphan.path, e),
+        }
+    }
+    freed
+}
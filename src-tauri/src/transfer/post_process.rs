@@ -0,0 +1,80 @@
+use crate::error::ProxiError;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Check that every rule maps an extension to a subfolder inside the save
+/// directory, e.g. `"jpg" -> "Pictures"` or `"pdf" -> "Documents/Inbox"`
+pub fn validate_rules(rules: &HashMap<String, String>) -> Result<(), ProxiError> {
+    for (extension, folder) in rules {
+        if extension.is_empty() || extension.contains(['.', '/', '\\']) {
+            return Err(ProxiError::Other(format!(
+                "Invalid extension {:?}; use e.g. \"jpg\"",
+                extension
+            )));
+        }
+        let path = Path::new(folder);
+        if folder.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(ProxiError::Other(format!(
+                "Folder for .{} must be a relative path inside the download folder: {:?}",
+                extension, folder
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Subfolder of `save_dir` that `file_name` belongs in, matching its
+/// extension case-insensitively. `None` leaves the file where it is.
+fn target_dir(
+    save_dir: &Path,
+    file_name: &str,
+    rules: &HashMap<String, String>,
+) -> Option<PathBuf> {
+    let extension = Path::new(file_name).extension()?.to_str()?;
+    rules
+        .iter()
+        .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+        .map(|(_, folder)| save_dir.join(folder))
+}
+
+/// Move a received, verified file into the subfolder its extension maps to.
+/// Returns the new location, or `None` when no rule applies.
+pub fn apply(
+    path: &Path,
+    save_dir: &Path,
+    rules: &HashMap<String, String>,
+) -> Result<Option<PathBuf>, ProxiError> {
+    let Some(file_name) = path.file_name() else {
+        return Ok(None);
+    };
+    let Some(dir) = target_dir(save_dir, &file_name.to_string_lossy(), rules) else {
+        return Ok(None);
+    };
+    std::fs::create_dir_all(&dir)?;
+    let target = dir.join(file_name);
+    move_file(path, &target)?;
+    Ok(Some(target))
+}
+
+/// Rename when both paths are on one filesystem. Otherwise copy to a
+/// temporary name next to `to`, rename that into place and remove `from`,
+/// so `to` never holds a partial file.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    let mut partial = to.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    if let Err(e) = std::fs::copy(from, &partial) {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    // A plain copy takes the current time; keep the sender's
+    if let Ok(modified) = std::fs::metadata(from).and_then(|m| m.modified()) {
+        let _ = filetime::set_file_mtime(&partial, filetime::FileTime::from_system_time(modified));
+    }
+    std::fs::rename(&partial, to)?;
+    std::fs::remove_file(from)
+}
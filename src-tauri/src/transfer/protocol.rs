@@ -1,19 +1,342 @@
+use crate::error::ProxiError;
+use bincode::Options;
 use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// Version of the wire protocol spoken by this build
+pub const PROTOCOL_VERSION: u32 = 12;
+
+/// Oldest peer version this build talks to: the first with
+/// `MessageType::ResumeRequest`'s tag reserved again, after the one whose
+/// `Hello` signs the connection binding. Newer peers are accepted too; see
+/// `MessageType` for what keeps them compatible.
+pub const MIN_PROTOCOL_VERSION: u32 = 12;
+
+/// TLS exporter label of the value a `Hello` signs
+const HELLO_EXPORTER_LABEL: &[u8] = b"proxishare hello";
+
+/// A value both ends of `connection` derive from its TLS session and no other
+/// connection shares, hex encoded. Signing it keeps a captured `Hello` from
+/// being replayed on a new connection.
+pub fn connection_binding(connection: &quinn::Connection) -> Result<String, ProxiError> {
+    let mut binding = [0u8; 32];
+    connection
+        .export_keying_material(&mut binding, HELLO_EXPORTER_LABEL, b"")
+        .map_err(|_| ProxiError::Crypto("Could not derive the connection binding".into()))?;
+    Ok(binding.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Whether a `Hello` received on `connection` was signed by `public_key` for
+/// this connection
+pub fn hello_is_valid(
+    connection: &quinn::Connection,
+    device_id: &str,
+    device_name: &str,
+    public_key: &[u8],
+    signature: &[u8],
+) -> bool {
+    let Ok(binding) = connection_binding(connection) else {
+        return false;
+    };
+    let payload = crate::crypto::identity::signing_payload(&[device_id, device_name, &binding]);
+    crate::crypto::identity::verify_signature(public_key, &payload, signature)
+}
+
+/// Largest chunk index either side accepts. Received chunks are recorded as
+/// SQLite integers (i64), so an index past this couldn't be resumed from.
+pub const MAX_CHUNK_INDEX: u64 = i64::MAX as u64;
+
+/// Index of the chunk after `index`, failing rather than wrapping around
+pub fn next_chunk_index(index: u64) -> Result<u64, ProxiError> {
+    if index >= MAX_CHUNK_INDEX {
+        return Err(ProxiError::Protocol(format!(
+            "Chunk index {} is at the limit of {}",
+            index, MAX_CHUNK_INDEX
+        )));
+    }
+    Ok(index + 1)
+}
+
+/// Application close code a peer uses when it cancels the transfer, so the
+/// other side can tell a cancellation from a dropped connection
+pub const CLOSE_CANCELLED: u32 = 2;
+
+/// Application close code for a connection whose stream became unreadable
+/// (truncated, oversized or undecodable frame), so the peer stops waiting on it
+pub const CLOSE_PROTOCOL_ERROR: u32 = 3;
+
+/// Application close code for a receiver that refused a transfer after it
+/// started (e.g. sniffed content type not allowed); the close reason says why
+pub const CLOSE_REJECTED: u32 = 4;
+
+/// Largest frame either side accepts; chunks are at most 4MB, history syncs
+/// can be larger
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// First byte of every frame body, ahead of the bincode message. A frame
+/// with another value is from an encoding this build can't read.
+pub const FRAME_FORMAT: u8 = 1;
+
+/// Number of `MessageType` variants. Frames with a higher tag are from a
+/// newer build and are skipped. Bump it when appending a variant.
+pub const MESSAGE_TAGS: u32 = 25;
+
+/// Tag of the reserved `MessageType::ResumeRequest`
+const RESUME_REQUEST_TAG: u32 = 10;
+
+/// Most zero bytes a frame body is extended by when its writer didn't know
+/// the variant's later fields
+const MAX_MISSING_FIELD_BYTES: u64 = 1024;
+
+/// Opens every stream in both directions, followed by `PROTOCOL_VERSION`
+/// as a big-endian u32, ahead of the first message
+pub const PREAMBLE_MAGIC: &[u8; 4] = b"PXSH";
+
+/// How long to wait for the other side's preamble
+const PREAMBLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Optional protocol features a peer may advertise
+pub const FEATURE_PAUSE_RESUME: &str = "pause_resume";
+pub const FEATURE_HISTORY_SYNC: &str = "history_sync";
+pub const FEATURE_SIGNED_PAIRING: &str = "signed_pairing";
+pub const FEATURE_CHUNK_ENCRYPTION: &str = "chunk_encryption";
+
+/// Features implemented by this build
+pub const SUPPORTED_FEATURES: &[&str] = &[
+    FEATURE_PAUSE_RESUME,
+    FEATURE_HISTORY_SYNC,
+    FEATURE_SIGNED_PAIRING,
+    FEATURE_CHUNK_ENCRYPTION,
+];
+
+/// Feature set advertised by a peer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerCapabilities {
+    pub protocol_version: u32,
+    pub features: Vec<String>,
+}
+
+impl PeerCapabilities {
+    /// Capabilities of this build
+    pub fn local() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            features: SUPPORTED_FEATURES.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// Assumed capabilities of a peer that hasn't been probed
+    pub fn baseline() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            features: Vec::new(),
+        }
+    }
+
+    /// Whether a feature can be used, i.e. both the peer and this build support it
+    pub fn supports(&self, feature: &str) -> bool {
+        SUPPORTED_FEATURES.contains(&feature) && self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// Hash used for chunk and whole-file verification, picked by the sender
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgo {
+    #[default]
+    Blake3,
+    Sha256,
+    /// An algorithm from a newer peer that this build can't compute
+    #[serde(other)]
+    Unsupported,
+}
+
+impl HashAlgo {
+    pub fn is_supported(self) -> bool {
+        self != HashAlgo::Unsupported
+    }
+
+    /// Name stored in the `transfers.hash_algo` column (same as the wire name)
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashAlgo::Blake3 => "blake3",
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::Unsupported => "unsupported",
+        }
+    }
+
+    /// Inverse of `as_str`; unknown names map to `Unsupported`
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "blake3" => HashAlgo::Blake3,
+            "sha256" => HashAlgo::Sha256,
+            _ => HashAlgo::Unsupported,
+        }
+    }
+
+    pub fn hasher(self) -> Result<Hasher, ProxiError> {
+        match self {
+            HashAlgo::Blake3 => Ok(Hasher::Blake3(Box::default())),
+            HashAlgo::Sha256 => Ok(Hasher::Sha256(sha2::Sha256::default())),
+            HashAlgo::Unsupported => Err(ProxiError::Protocol("Unsupported hash algorithm".into())),
+        }
+    }
+
+    /// Hex digest of `data`
+    pub fn hash(self, data: &[u8]) -> Result<String, ProxiError> {
+        let mut hasher = self.hasher()?;
+        hasher.update(data);
+        Ok(hasher.finalize())
+    }
+}
+
+/// Incremental hasher for a `HashAlgo`
+pub enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Sha256(hasher) => sha2::Digest::update(hasher, data),
+        }
+    }
+
+    /// Hex digest of everything passed to `update`
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Sha256(hasher) => format!("{:x}", sha2::Digest::finalize(hasher)),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FileMetadata {
     pub name: String,
     pub size: u64,
     pub hash: String,
+    /// Algorithm of `hash` and of every chunk hash of the transfer
+    pub hash_algo: HashAlgo,
     pub chunk_size: u32,
+    /// Source file's modification time (seconds since the Unix epoch)
+    pub modified: Option<u64>,
+    /// `merkle::root` of the chunk hashes, for offers a receiver may resume;
+    /// `None` in manifests and batch announcements
+    pub merkle_root: Option<String>,
+}
+
+/// Receiver's signed statement that it stored a file with `file_hash`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransferReceipt {
+    pub transfer_id: String,
+    /// Hash of the file as written to the receiver's disk, in `hash_algo`
+    pub file_hash: String,
+    pub receiver_id: String,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+    /// Seconds since the Unix epoch
+    pub received_at: i64,
+    /// The transfer's algorithm, which `file_hash` is in. Unlike other nested
+    /// structs this one may grow: the receipt ends its `TransferCompleteAck`
+    /// frame, so receipts from older builds read this as zero, `Blake3`.
+    #[serde(default)]
+    pub hash_algo: HashAlgo,
+}
+
+impl TransferReceipt {
+    pub fn sign(
+        identity: &crate::crypto::identity::DeviceIdentity,
+        transfer_id: &str,
+        file_hash: String,
+        hash_algo: HashAlgo,
+    ) -> Self {
+        let mut receipt = Self {
+            transfer_id: transfer_id.to_string(),
+            file_hash,
+            receiver_id: identity.device_id().to_string(),
+            public_key: identity.public_key(),
+            signature: Vec::new(),
+            received_at: chrono::Utc::now().timestamp(),
+            hash_algo,
+        };
+        receipt.signature = identity.sign(&receipt.signing_payload());
+        receipt
+    }
+
+    /// Blake3 receipts sign the same fields as before the algorithm was
+    /// recorded, so older senders still verify them; any other algorithm
+    /// is signed too, so a receipt can't be passed off as another algorithm's
+    fn signing_payload(&self) -> Vec<u8> {
+        let received_at = self.received_at.to_string();
+        let mut fields = vec![
+            self.transfer_id.as_str(),
+            &self.file_hash,
+            &self.receiver_id,
+            &received_at,
+        ];
+        if self.hash_algo != HashAlgo::Blake3 {
+            fields.push(self.hash_algo.as_str());
+        }
+        crate::crypto::identity::signing_payload(&fields)
+    }
+
+    /// Whether the signature is valid for the embedded public key
+    pub fn verify(&self) -> bool {
+        crate::crypto::identity::verify_signature(
+            &self.public_key,
+            &self.signing_payload(),
+            &self.signature,
+        )
+    }
 }
 
+/// Why a peer aborted a transfer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TransferErrorCode {
+    /// The receiver's save directory disappeared (e.g. an unplugged drive).
+    /// Received chunks are kept, so offering the transfer again resumes it.
+    DiskUnavailable,
+    /// The bytes received don't add up to the offered size; the partial
+    /// progress is discarded, so offering it again starts over
+    SizeMismatch,
+    /// No data moved for longer than the stall timeout. Received chunks are
+    /// kept, so offering the transfer again resumes it.
+    Stalled,
+    Internal,
+}
+
+/// A message on the wire: `u32` big-endian length, then `FRAME_FORMAT`,
+/// then the bincode encoding (a `u32` variant tag and the variant's fields).
+///
+/// Compatibility contract, for peers of `MIN_PROTOCOL_VERSION` and later:
+/// - Variants are only appended, never reordered or removed, so a tag means
+///   the same message in every build. A reader skips tags it doesn't know
+///   (`MESSAGE_TAGS`), so a new one-way message is ignored by older peers;
+///   one that expects an answer must be gated on the peer's capabilities.
+/// - Fields are only appended to the end of a variant, never inserted or
+///   retyped. Readers ignore bytes after the fields they know, and read
+///   fields missing from a shorter frame as zero: `None`, empty, `0` or
+///   `false`. A new field must make sense with that value.
+/// - Structs nested in a variant (`FileMetadata`, `HistoryRecordWire`, ...)
+///   are frozen, since their fields aren't at the end of the frame; a change
+///   there needs a new field or variant instead.
+/// - Anything else bumps `MIN_PROTOCOL_VERSION`.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum MessageType {
     // Handshake
     Hello {
         device_id: String,
         device_name: String,
+        /// Ed25519 public key of the sender; the device id is derived from it
+        public_key: Vec<u8>,
+        /// Signature over `device_id`, `device_name` and the connection's
+        /// `connection_binding`
+        signature: Vec<u8>,
     },
     HelloAck,
 
@@ -23,9 +346,41 @@ pub enum MessageType {
         metadata: FileMetadata,
         sender_id: String,
         sender_name: String,
+        /// Synthetic throughput test: the receiver discards the data
+        benchmark: bool,
+        /// The sender can start from an offset the receiver proposes
+        resumable: bool,
+        /// Chunk payloads are additionally sealed with a `ChunkCipher` keyed
+        /// from the shared passphrase; hashes stay over the plaintext
+        encrypted: bool,
     },
+    /// Announces the files of a multi-file send before their individual
+    /// offers arrive. Hashes are left empty; each `FileOffer` carries its own.
+    BatchOffer {
+        batch_id: String,
+        files: Vec<(String, FileMetadata)>,
+    },
+    /// Accepts an offer. A non-zero `resume_offset` (a multiple of the chunk
+    /// size) proposes continuing from a partial file the receiver has, whose
+    /// first `resume_offset` bytes hash to `prefix_hash` with the offer's
+    /// algorithm. The sender only skips ahead if its own prefix matches;
+    /// otherwise it sends from chunk 0 and the receiver starts over.
     FileAccept {
         transfer_id: String,
+        resume_offset: u64,
+        /// `merkle::root` of the chunk hashes before `resume_offset`
+        prefix_hash: Option<String>,
+    },
+    // Shared-passphrase gate: the receiver challenges, the sender proves knowledge
+    AuthChallenge {
+        transfer_id: String,
+        nonce: Vec<u8>,
+        /// The receiver's passphrase salt (hex), which the key is derived under
+        salt: String,
+    },
+    AuthResponse {
+        transfer_id: String,
+        mac: Vec<u8>,
     },
     FileReject {
         transfer_id: String,
@@ -35,16 +390,18 @@ pub enum MessageType {
     // Data transfer
     ChunkData {
         transfer_id: String,
-        chunk_index: u32,
+        chunk_index: u64,
         data: Vec<u8>,
         chunk_hash: String,
     },
     ChunkAck {
         transfer_id: String,
-        chunk_index: u32,
+        chunk_index: u64,
     },
 
-    // Resumability
+    /// Reserved: asked the sender to resume after a chunk before resuming
+    /// moved into `FileAccept`. Kept so its tag isn't reused; never sent,
+    /// and skipped like an unknown message when received.
     ResumeRequest {
         transfer_id: String,
         last_chunk_index: u32,
@@ -56,9 +413,12 @@ pub enum MessageType {
     },
     TransferCompleteAck {
         transfer_id: String,
+        /// `None` from peers that predate receipts ("legacy ack")
+        receipt: Option<TransferReceipt>,
     },
     TransferError {
         transfer_id: String,
+        code: TransferErrorCode,
         message: String,
     },
     TransferPause {
@@ -76,6 +436,9 @@ pub enum MessageType {
         device_id: String,
         device_name: String,
         pairing_code: String,
+        public_key: Vec<u8>,
+        /// Signature over `device_id`, `device_name` and `pairing_code`
+        signature: Vec<u8>,
     },
     PairResponse {
         accepted: bool,
@@ -83,8 +446,16 @@ pub enum MessageType {
     },
 
     // Sync (placeholder for Phase 4)
+    /// One chunk of a history sync; a sync is any number of these followed
+    /// by `HistorySyncEnd`
     HistorySync {
-        records: Vec<crate::db::TransferRecord>,
+        records: Vec<crate::db::HistoryRecordWire>,
+    },
+    /// Ends the records sent by one side. From the initiator, asks for up
+    /// to `max_records` of the responder's history in return; the responder
+    /// ends its reply with `max_records: 0`.
+    HistorySyncEnd {
+        max_records: u32,
     },
     SyncRequest {
         folder_path: String,
@@ -92,5 +463,796 @@ pub enum MessageType {
     },
     SyncResponse {
         missing_files: Vec<String>,
+        /// The responder's own sync folder, sent only to trusted peers
+        files: Vec<FileMetadata>,
     },
+
+    /// Ask the receiver who it is; it answers with its own signed `Hello`
+    IdentityRequest,
+
+    /// Feature negotiation: sent as a request, answered with the receiver's own set
+    Capabilities {
+        features: Vec<String>,
+        protocol_version: u32,
+    },
+}
+
+impl MessageType {
+    /// Signed greeting that opens every stream so the receiver knows who is
+    /// connecting. The signature covers `connection_binding`, so the Hello is
+    /// only valid on `connection`.
+    pub fn hello(
+        identity: &crate::crypto::identity::DeviceIdentity,
+        device_name: &str,
+        connection: &quinn::Connection,
+    ) -> Result<Self, ProxiError> {
+        let device_id = identity.device_id().to_string();
+        let binding = connection_binding(connection)?;
+        let signature = identity.sign(&crate::crypto::identity::signing_payload(&[
+            &device_id,
+            device_name,
+            &binding,
+        ]));
+        Ok(MessageType::Hello {
+            device_id,
+            device_name: device_name.to_string(),
+            public_key: identity.public_key(),
+            signature,
+        })
+    }
+
+    /// Signed request to pair with the receiver using `pairing_code`
+    pub fn pair_request(
+        identity: &crate::crypto::identity::DeviceIdentity,
+        device_name: &str,
+        pairing_code: &str,
+    ) -> Self {
+        let device_id = identity.device_id().to_string();
+        let signature = identity.sign(&crate::crypto::identity::signing_payload(&[
+            &device_id,
+            device_name,
+            pairing_code,
+        ]));
+        MessageType::PairRequest {
+            device_id,
+            device_name: device_name.to_string(),
+            pairing_code: pairing_code.to_string(),
+            public_key: identity.public_key(),
+            signature,
+        }
+    }
+}
+
+/// Whether a `PairRequest`'s signature covers its device id, name and code
+pub fn verify_pair_request(
+    device_id: &str,
+    device_name: &str,
+    pairing_code: &str,
+    public_key: &[u8],
+    signature: &[u8],
+) -> bool {
+    let payload = crate::crypto::identity::signing_payload(&[device_id, device_name, pairing_code]);
+    crate::crypto::identity::verify_signature(public_key, &payload, signature)
+}
+
+/// Encode `msg` as a whole frame, length prefix included, into `frame`
+/// (cleared first, so it can be reused across calls)
+pub fn encode_frame(msg: &MessageType, frame: &mut Vec<u8>) -> Result<(), ProxiError> {
+    frame.clear();
+    frame.extend_from_slice(&[0u8; 4]);
+    frame.push(FRAME_FORMAT);
+    bincode::serialize_into(&mut *frame, msg)?;
+    let len = (frame.len() - 4) as u32;
+    frame[..4].copy_from_slice(&len.to_be_bytes());
+    Ok(())
+}
+
+/// Write one message as a frame
+pub async fn write_frame(
+    send: &mut quinn::SendStream,
+    msg: &MessageType,
+) -> Result<(), ProxiError> {
+    let mut frame = Vec::new();
+    encode_frame(msg, &mut frame)?;
+    send.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Decode a frame body (everything after the length prefix) under the
+/// contract on `MessageType`. `None` for a message from a newer build.
+pub fn decode_body(body: &[u8]) -> Result<Option<MessageType>, ProxiError> {
+    let len = body.len();
+    let Some((&format, message)) = body.split_first() else {
+        return Err(ProxiError::Protocol("Empty frame".into()));
+    };
+    if format != FRAME_FORMAT {
+        return Err(ProxiError::Protocol(format!(
+            "Unsupported frame format {} (this build reads {})",
+            format, FRAME_FORMAT
+        )));
+    }
+    let Some(tag) = message.get(..4) else {
+        return Err(ProxiError::Protocol(format!(
+            "Malformed {}-byte frame",
+            len
+        )));
+    };
+    let tag = u32::from_le_bytes([tag[0], tag[1], tag[2], tag[3]]);
+    if tag >= MESSAGE_TAGS {
+        println!("[Transfer] Skipping message {} from a newer build", tag);
+        return Ok(None);
+    }
+    if tag == RESUME_REQUEST_TAG {
+        println!("[Transfer] Skipping reserved message {}", tag);
+        return Ok(None);
+    }
+    let padded = message.chain(std::io::repeat(0).take(MAX_MISSING_FIELD_BYTES));
+    // The limit keeps a bogus length inside the body from allocating more
+    // than the frame could hold
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(len as u64 + MAX_MISSING_FIELD_BYTES)
+        .deserialize_from(padded)
+        .map(Some)
+        .map_err(|e| ProxiError::Protocol(format!("Malformed {}-byte frame: {}", len, e)))
+}
+
+/// Read the length prefix of the next frame
+async fn read_frame_len(recv: &mut quinn::RecvStream) -> Result<usize, ProxiError> {
+    let mut len_buf = [0u8; 4];
+    read_field(recv, &mut len_buf, "length prefix", true).await?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(ProxiError::Protocol(format!(
+            "Frame length {} exceeds the {}-byte limit",
+            len, MAX_FRAME_LEN
+        )));
+    }
+    Ok(len)
+}
+
+/// Read one message, skipping any a newer build added. Short, oversized or
+/// undecodable frames come back as `ProxiError::Protocol` saying what went
+/// wrong; the stream is out of sync after one and can't be read further.
+pub async fn read_frame(recv: &mut quinn::RecvStream) -> Result<MessageType, ProxiError> {
+    loop {
+        let len = read_frame_len(recv).await?;
+        let mut data = vec![0u8; len];
+        read_field(recv, &mut data, "message body", false).await?;
+        if let Some(message) = decode_body(&data)? {
+            return Ok(message);
+        }
+    }
+}
+
+/// Bytes of a chunk payload held in memory at once while it is copied off
+/// the stream
+pub const CHUNK_READ_BUFFER: usize = 64 * 1024;
+
+/// A frame read by `read_frame_streaming`
+pub enum Frame {
+    Message(MessageType),
+    /// A `ChunkData` frame whose payload is still on the stream
+    Chunk(ChunkHeader),
+}
+
+/// The fields of a `ChunkData` frame that come before its payload. The
+/// caller reads the `data_len` payload bytes, then `read_hash`.
+pub struct ChunkHeader {
+    pub transfer_id: String,
+    pub chunk_index: u64,
+    pub data_len: usize,
+    /// Frame bytes after the payload, i.e. the encoded chunk hash
+    trailer_len: usize,
+}
+
+impl ChunkHeader {
+    /// Read the next `buf.len()` bytes of the payload
+    pub async fn read_payload(
+        &self,
+        recv: &mut quinn::RecvStream,
+        buf: &mut [u8],
+    ) -> Result<(), ProxiError> {
+        read_field(recv, buf, "chunk payload", false).await
+    }
+
+    /// Discard the payload and hash, for chunks nobody is writing
+    pub async fn skip(self, recv: &mut quinn::RecvStream) -> Result<(), ProxiError> {
+        let mut sink = vec![0u8; self.data_len.min(CHUNK_READ_BUFFER)];
+        let mut left = self.data_len;
+        while left > 0 {
+            let n = left.min(sink.len());
+            self.read_payload(recv, &mut sink[..n]).await?;
+            left -= n;
+        }
+        self.read_hash(recv).await?;
+        Ok(())
+    }
+
+    /// Read the chunk hash that follows the payload, ending the frame
+    pub async fn read_hash(self, recv: &mut quinn::RecvStream) -> Result<String, ProxiError> {
+        let mut trailer = vec![0u8; self.trailer_len];
+        read_field(recv, &mut trailer, "chunk hash", false).await?;
+        bincode::deserialize(&trailer)
+            .map_err(|e| ProxiError::Protocol(format!("Malformed chunk hash: {}", e)))
+    }
+}
+
+/// Encoded enum tag of `MessageType::ChunkData`, taken from bincode itself
+/// so it can't drift from the variant order
+fn chunk_data_tag() -> [u8; 4] {
+    static TAG: std::sync::OnceLock<[u8; 4]> = std::sync::OnceLock::new();
+    *TAG.get_or_init(|| {
+        let encoded = bincode::serialize(&MessageType::ChunkData {
+            transfer_id: String::new(),
+            chunk_index: 0,
+            data: Vec::new(),
+            chunk_hash: String::new(),
+        })
+        .expect("ChunkData always encodes");
+        [encoded[0], encoded[1], encoded[2], encoded[3]]
+    })
+}
+
+/// Like `read_frame`, but stops a `ChunkData` frame before its payload so
+/// the payload can be copied to disk in `CHUNK_READ_BUFFER` pieces instead
+/// of being buffered whole
+pub async fn read_frame_streaming(recv: &mut quinn::RecvStream) -> Result<Frame, ProxiError> {
+    // Format byte and variant tag
+    let mut head = [0u8; 5];
+    let len = loop {
+        let len = read_frame_len(recv).await?;
+        let data = if len < head.len() {
+            let mut data = vec![0u8; len];
+            read_field(recv, &mut data, "message body", false).await?;
+            data
+        } else {
+            read_field(recv, &mut head, "message body", false).await?;
+            if head[0] == FRAME_FORMAT && head[1..] == chunk_data_tag() {
+                break len;
+            }
+            // Only frames that aren't chunks are buffered whole
+            let mut data = vec![0u8; len];
+            data[..head.len()].copy_from_slice(&head);
+            read_field(recv, &mut data[head.len()..], "message body", false).await?;
+            data
+        };
+        if let Some(message) = decode_body(&data)? {
+            return Ok(Frame::Message(message));
+        }
+    };
+
+    // bincode layout: u64 length + bytes for strings and vectors, u64 for integers
+    let mut remaining = len - head.len();
+    let id_len = read_header_u64(recv, &mut remaining, len).await? as usize;
+    if id_len > remaining {
+        return Err(malformed_chunk(len));
+    }
+    let mut id = vec![0u8; id_len];
+    read_field(recv, &mut id, "chunk header", false).await?;
+    remaining -= id_len;
+    let transfer_id = String::from_utf8(id).map_err(|_| malformed_chunk(len))?;
+    let chunk_index = read_header_u64(recv, &mut remaining, len).await?;
+    let data_len = read_header_u64(recv, &mut remaining, len).await? as usize;
+    if data_len > remaining {
+        return Err(malformed_chunk(len));
+    }
+
+    Ok(Frame::Chunk(ChunkHeader {
+        transfer_id,
+        chunk_index,
+        data_len,
+        trailer_len: remaining - data_len,
+    }))
+}
+
+/// Read one little-endian u64 (bincode's integer and length encoding) of a
+/// `len`-byte chunk frame with `remaining` bytes left
+async fn read_header_u64(
+    recv: &mut quinn::RecvStream,
+    remaining: &mut usize,
+    len: usize,
+) -> Result<u64, ProxiError> {
+    *remaining = remaining
+        .checked_sub(8)
+        .ok_or_else(|| malformed_chunk(len))?;
+    let mut word = [0u8; 8];
+    read_field(recv, &mut word, "chunk header", false).await?;
+    Ok(u64::from_le_bytes(word))
+}
+
+fn malformed_chunk(len: usize) -> ProxiError {
+    ProxiError::Protocol(format!("Malformed {}-byte chunk frame", len))
+}
+
+/// Fill `buf` with the frame's `field`. A stream that ends before the first
+/// byte of a frame isn't a framing error (`clean_eof`); one that ends partway
+/// through is.
+async fn read_field(
+    recv: &mut quinn::RecvStream,
+    buf: &mut [u8],
+    field: &str,
+    clean_eof: bool,
+) -> Result<(), ProxiError> {
+    match recv.read_exact(buf).await {
+        Ok(()) => Ok(()),
+        Err(quinn::ReadExactError::FinishedEarly(read)) if read > 0 || !clean_eof => {
+            Err(ProxiError::Protocol(format!(
+                "Truncated frame: expected {} bytes of {}, stream ended after {}",
+                buf.len(),
+                field,
+                read
+            )))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Open a stream to the peer and exchange preambles, so a service that isn't
+/// ProxiShare or a build with an incompatible wire format fails here with a
+/// clear error instead of an opaque decode failure later
+pub async fn open_stream(
+    connection: &quinn::Connection,
+) -> Result<(quinn::SendStream, quinn::RecvStream), ProxiError> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    write_preamble(&mut send).await?;
+    read_preamble(&mut recv).await?;
+    Ok((send, recv))
+}
+
+/// Accept the peer's next stream and exchange preambles, the counterpart of
+/// `open_stream`
+pub async fn accept_stream(
+    connection: &quinn::Connection,
+) -> Result<(quinn::SendStream, quinn::RecvStream), ProxiError> {
+    let (mut send, mut recv) = connection.accept_bi().await?;
+    read_preamble(&mut recv).await?;
+    write_preamble(&mut send).await?;
+    Ok((send, recv))
+}
+
+async fn write_preamble(send: &mut quinn::SendStream) -> Result<(), ProxiError> {
+    let mut preamble = [0u8; 8];
+    preamble[..4].copy_from_slice(PREAMBLE_MAGIC);
+    preamble[4..].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    send.write_all(&preamble).await?;
+    Ok(())
+}
+
+async fn read_preamble(recv: &mut quinn::RecvStream) -> Result<(), ProxiError> {
+    let mut preamble = [0u8; 8];
+    match tokio::time::timeout(PREAMBLE_TIMEOUT, recv.read_exact(&mut preamble)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(quinn::ReadExactError::FinishedEarly(_))) => {
+            return Err(ProxiError::Protocol(
+                "Not a ProxiShare peer or incompatible version: stream ended during the handshake"
+                    .into(),
+            ))
+        }
+        Ok(Err(e)) => return Err(e.into()),
+        Err(_) => {
+            return Err(ProxiError::Network(
+                "Peer did not answer the ProxiShare handshake".into(),
+            ))
+        }
+    }
+
+    if &preamble[..4] != PREAMBLE_MAGIC {
+        return Err(ProxiError::Protocol(
+            "Not a ProxiShare peer or incompatible version".into(),
+        ));
+    }
+    let version = u32::from_be_bytes([preamble[4], preamble[5], preamble[6], preamble[7]]);
+    if version < MIN_PROTOCOL_VERSION {
+        return Err(ProxiError::Protocol(format!(
+            "Incompatible ProxiShare protocol version {} (this build needs {} or later)",
+            version, MIN_PROTOCOL_VERSION
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::test_support;
+
+    fn frame_of(message: &MessageType) -> Vec<u8> {
+        let mut frame = Vec::new();
+        encode_frame(message, &mut frame).unwrap();
+        frame
+    }
+
+    fn decode(body: &[u8]) -> MessageType {
+        decode_body(body)
+            .unwrap()
+            .expect("known variant was skipped")
+    }
+
+    /// `MessageType` has no `PartialEq`; equal messages encode the same
+    fn assert_same(expected: &MessageType, got: &MessageType) {
+        assert_eq!(
+            frame_of(expected),
+            frame_of(got),
+            "{:?} != {:?}",
+            expected,
+            got
+        );
+    }
+
+    fn metadata() -> FileMetadata {
+        FileMetadata {
+            name: "report.pdf".into(),
+            size: 3 * 1024 * 1024 + 17,
+            hash: "abc123".into(),
+            hash_algo: HashAlgo::Sha256,
+            chunk_size: 1024 * 1024,
+            modified: Some(1_700_000_000),
+            merkle_root: Some("root".into()),
+        }
+    }
+
+    fn samples() -> Vec<MessageType> {
+        vec![
+            MessageType::HelloAck,
+            MessageType::FileOffer {
+                transfer_id: "t1".into(),
+                metadata: metadata(),
+                sender_id: "sender".into(),
+                sender_name: "Laptop".into(),
+                benchmark: false,
+                resumable: true,
+                encrypted: false,
+            },
+            MessageType::BatchOffer {
+                batch_id: "b1".into(),
+                files: vec![("t1".into(), metadata()), ("t2".into(), metadata())],
+            },
+            MessageType::ChunkData {
+                transfer_id: "t1".into(),
+                chunk_index: 7,
+                data: vec![1, 2, 3, 0, 255],
+                chunk_hash: "hash".into(),
+            },
+            MessageType::TransferError {
+                transfer_id: "t1".into(),
+                code: TransferErrorCode::Stalled,
+                message: "No data".into(),
+            },
+            MessageType::HistorySyncEnd { max_records: 25 },
+            MessageType::IdentityRequest,
+            MessageType::Capabilities {
+                features: vec![FEATURE_PAUSE_RESUME.into()],
+                protocol_version: PROTOCOL_VERSION,
+            },
+        ]
+    }
+
+    #[test]
+    fn chunk_indexes_advance_up_to_the_limit() {
+        assert_eq!(next_chunk_index(0).unwrap(), 1);
+        assert_eq!(
+            next_chunk_index(MAX_CHUNK_INDEX - 1).unwrap(),
+            MAX_CHUNK_INDEX
+        );
+        // The last index still fits an SQLite integer
+        assert_eq!(MAX_CHUNK_INDEX, i64::MAX as u64);
+    }
+
+    #[test]
+    fn chunk_indexes_never_pass_the_limit() {
+        assert!(matches!(
+            next_chunk_index(MAX_CHUNK_INDEX),
+            Err(ProxiError::Protocol(_))
+        ));
+        assert!(matches!(
+            next_chunk_index(u64::MAX),
+            Err(ProxiError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for message in samples() {
+            let frame = frame_of(&message);
+            let len = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize;
+            assert_eq!(len, frame.len() - 4);
+            assert_eq!(frame[4], FRAME_FORMAT);
+            assert_same(&message, &decode(&frame[4..]));
+        }
+    }
+
+    #[test]
+    fn encode_frame_reuses_the_buffer() {
+        let mut frame = Vec::new();
+        encode_frame(&samples()[1], &mut frame).unwrap();
+        encode_frame(&MessageType::HelloAck, &mut frame).unwrap();
+        assert_eq!(frame, frame_of(&MessageType::HelloAck));
+    }
+
+    #[test]
+    fn trailing_fields_from_newer_builds_are_ignored() {
+        let cancel = MessageType::TransferCancel {
+            transfer_id: "t1".into(),
+        };
+        let mut frame = frame_of(&cancel);
+        frame.extend_from_slice(&[7u8; 16]);
+        assert_same(&cancel, &decode(&frame[4..]));
+    }
+
+    #[test]
+    fn fields_missing_from_older_builds_read_as_zero() {
+        let ack = MessageType::TransferCompleteAck {
+            transfer_id: "t1".into(),
+            receipt: None,
+        };
+        let frame = frame_of(&ack);
+        // An older build didn't write the trailing `receipt` tag byte
+        assert_same(&ack, &decode(&frame[4..frame.len() - 1]));
+    }
+
+    #[test]
+    fn receipts_are_bound_to_their_hash_algorithm() {
+        let dir = std::env::temp_dir().join(format!("proxishare-receipt-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let identity = crate::crypto::identity::DeviceIdentity::load_or_generate(&dir).unwrap();
+        let mut receipt = TransferReceipt::sign(&identity, "t1", "abc".into(), HashAlgo::Sha256);
+        assert!(receipt.verify());
+        receipt.hash_algo = HashAlgo::Blake3;
+        assert!(!receipt.verify());
+
+        let ack = MessageType::TransferCompleteAck {
+            transfer_id: "t1".into(),
+            receipt: Some(TransferReceipt::sign(
+                &identity,
+                "t1",
+                "abc".into(),
+                HashAlgo::Blake3,
+            )),
+        };
+        let frame = frame_of(&ack);
+        // An older build's receipt ended before the algorithm's u32 tag
+        let MessageType::TransferCompleteAck {
+            receipt: Some(old), ..
+        } = decode(&frame[4..frame.len() - 4])
+        else {
+            panic!("expected an ack with a receipt");
+        };
+        assert_eq!(old.hash_algo, HashAlgo::Blake3);
+        assert!(old.verify());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unknown_variants_are_skipped() {
+        let mut frame = frame_of(&MessageType::HelloAck);
+        frame[5..9].copy_from_slice(&MESSAGE_TAGS.to_le_bytes());
+        assert!(decode_body(&frame[4..]).unwrap().is_none());
+        frame[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decode_body(&frame[4..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn the_reserved_resume_request_is_skipped() {
+        let frame = frame_of(&MessageType::ResumeRequest {
+            transfer_id: "t1".into(),
+            last_chunk_index: 3,
+        });
+        assert_eq!(frame[5..9], RESUME_REQUEST_TAG.to_le_bytes());
+        assert!(decode_body(&frame[4..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn message_tags_counts_the_variants() {
+        let last = frame_of(&MessageType::Capabilities {
+            features: Vec::new(),
+            protocol_version: PROTOCOL_VERSION,
+        });
+        assert_eq!(last[5..9], (MESSAGE_TAGS - 1).to_le_bytes());
+    }
+
+    #[test]
+    fn chunk_data_tag_matches_bincode() {
+        let frame = frame_of(&samples()[3]);
+        assert_eq!(frame[5..9], chunk_data_tag());
+    }
+
+    #[test]
+    fn unknown_formats_are_rejected() {
+        let mut frame = frame_of(&MessageType::HelloAck);
+        frame[4] = FRAME_FORMAT + 1;
+        assert!(matches!(
+            decode_body(&frame[4..]),
+            Err(ProxiError::Protocol(_))
+        ));
+    }
+
+    /// Every sample, with an unknown variant ahead of each
+    fn stream_of_samples() -> Vec<u8> {
+        let mut unknown = frame_of(&MessageType::HelloAck);
+        unknown[5..9].copy_from_slice(&MESSAGE_TAGS.to_le_bytes());
+        let mut bytes = Vec::new();
+        for message in samples() {
+            bytes.extend_from_slice(&unknown);
+            bytes.extend(frame_of(&message));
+        }
+        bytes
+    }
+
+    #[tokio::test]
+    async fn read_frame_skips_unknown_variants() {
+        let loopback = test_support::connect().await;
+        let ((mut send, _), (_, mut recv)) = loopback.stream().await;
+        send.write_all(&stream_of_samples()).await.unwrap();
+        send.finish().unwrap();
+
+        for message in samples() {
+            assert_same(&message, &read_frame(&mut recv).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn read_frame_streaming_stops_chunks_before_their_payload() {
+        let loopback = test_support::connect().await;
+        let ((mut send, _), (_, mut recv)) = loopback.stream().await;
+        send.write_all(&stream_of_samples()).await.unwrap();
+        send.finish().unwrap();
+
+        for message in samples() {
+            match (read_frame_streaming(&mut recv).await.unwrap(), &message) {
+                (
+                    Frame::Chunk(header),
+                    MessageType::ChunkData {
+                        transfer_id,
+                        chunk_index,
+                        data,
+                        chunk_hash,
+                    },
+                ) => {
+                    assert_eq!(&header.transfer_id, transfer_id);
+                    assert_eq!(header.chunk_index, *chunk_index);
+                    assert_eq!(header.data_len, data.len());
+                    let mut payload = vec![0u8; header.data_len];
+                    header.read_payload(&mut recv, &mut payload).await.unwrap();
+                    assert_eq!(&payload, data);
+                    assert_eq!(&header.read_hash(&mut recv).await.unwrap(), chunk_hash);
+                }
+                (Frame::Message(got), expected) => assert_same(expected, &got),
+                (Frame::Chunk(_), expected) => panic!("expected {:?}, got a chunk", expected),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn skipped_chunks_leave_the_stream_in_sync() {
+        let loopback = test_support::connect().await;
+        let ((mut send, _), (_, mut recv)) = loopback.stream().await;
+        let chunk = MessageType::ChunkData {
+            transfer_id: "t1".into(),
+            chunk_index: 0,
+            data: vec![9u8; 3 * CHUNK_READ_BUFFER + 5],
+            chunk_hash: "hash".into(),
+        };
+        write_frame(&mut send, &chunk).await.unwrap();
+        write_frame(&mut send, &MessageType::HelloAck)
+            .await
+            .unwrap();
+        send.finish().unwrap();
+
+        let Frame::Chunk(header) = read_frame_streaming(&mut recv).await.unwrap() else {
+            panic!("expected a chunk");
+        };
+        header.skip(&mut recv).await.unwrap();
+        assert!(matches!(
+            read_frame_streaming(&mut recv).await.unwrap(),
+            Frame::Message(MessageType::HelloAck)
+        ));
+    }
+
+    /// Write `bytes` on a fresh stream and end it there
+    async fn stream_of(loopback: &test_support::Loopback, bytes: &[u8]) -> quinn::RecvStream {
+        let ((mut send, _), (_, recv)) = loopback.stream().await;
+        send.write_all(bytes).await.unwrap();
+        send.finish().unwrap();
+        recv
+    }
+
+    /// Read one frame with `read_frame_streaming`, a chunk's payload and
+    /// hash included
+    async fn read_streamed(recv: &mut quinn::RecvStream) -> Result<(), ProxiError> {
+        if let Frame::Chunk(header) = read_frame_streaming(recv).await? {
+            let mut payload = vec![0u8; header.data_len];
+            header.read_payload(recv, &mut payload).await?;
+            header.read_hash(recv).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn truncated_frames_are_protocol_errors() {
+        let loopback = test_support::connect().await;
+        let frames = [
+            frame_of(&MessageType::TransferCancel {
+                transfer_id: "t1".into(),
+            }),
+            frame_of(&MessageType::ChunkData {
+                transfer_id: "t1".into(),
+                chunk_index: 2,
+                data: vec![5u8; 12],
+                chunk_hash: "hash".into(),
+            }),
+        ];
+        for frame in &frames {
+            for cut in 1..frame.len() {
+                let mut recv = stream_of(&loopback, &frame[..cut]).await;
+                let error = read_frame(&mut recv).await.unwrap_err();
+                assert!(
+                    matches!(&error, ProxiError::Protocol(msg) if msg.starts_with("Truncated frame")),
+                    "cut at {}: {:?}",
+                    cut,
+                    error
+                );
+
+                let mut recv = stream_of(&loopback, &frame[..cut]).await;
+                let error = read_streamed(&mut recv).await.unwrap_err();
+                assert!(
+                    matches!(&error, ProxiError::Protocol(msg) if msg.starts_with("Truncated frame")),
+                    "streaming, cut at {}: {:?}",
+                    cut,
+                    error
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_stream_ending_between_frames_is_not_a_framing_error() {
+        let loopback = test_support::connect().await;
+        let mut recv = stream_of(&loopback, &frame_of(&MessageType::HelloAck)).await;
+        read_frame(&mut recv).await.unwrap();
+        assert!(!matches!(
+            read_frame(&mut recv).await,
+            Err(ProxiError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_bodies_never_panic() {
+        for message in samples() {
+            let frame = frame_of(&message);
+            let body = &frame[4..];
+            for cut in 0..body.len() {
+                let result = decode_body(&body[..cut]);
+                // Without its format byte and tag a body can't be read at
+                // all; past those, missing fields read as zero by contract
+                if cut < 5 {
+                    assert!(
+                        matches!(result, Err(ProxiError::Protocol(_))),
+                        "cut at {}",
+                        cut
+                    );
+                } else {
+                    assert!(
+                        matches!(result, Ok(_) | Err(ProxiError::Protocol(_))),
+                        "cut at {}",
+                        cut
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_lengths_inside_a_body_are_protocol_errors() {
+        let mut frame = frame_of(&MessageType::TransferCancel {
+            transfer_id: "t1".into(),
+        });
+        // The transfer id's length prefix claims far more bytes than exist
+        frame[9..17].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            decode_body(&frame[4..]),
+            Err(ProxiError::Protocol(_))
+        ));
+    }
 }
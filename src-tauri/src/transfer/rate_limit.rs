@@ -0,0 +1,80 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Byte budget refilled at `rate` bytes per second, holding at most one
+/// second's worth. Takes larger than the balance put the bucket into debt,
+/// which later callers wait off, so chunk size doesn't matter.
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            rate: bytes_per_sec as f64,
+            tokens: bytes_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take `bytes`, returning how long the caller must wait before using them
+    fn take(&mut self, bytes: u64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Aggregate bandwidth limits per device, shared by every transfer to or
+/// from that device. Devices without a limit are unthrottled.
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &HashMap<String, u64>) -> Self {
+        let buckets = limits
+            .iter()
+            .filter(|(_, rate)| **rate > 0)
+            .map(|(device_id, rate)| (device_id.clone(), TokenBucket::new(*rate)))
+            .collect();
+        Self {
+            buckets: Mutex::new(buckets),
+        }
+    }
+
+    /// Set or clear (`None`/0) a device's limit in bytes per second
+    pub fn set_limit(&self, device_id: &str, bytes_per_sec: Option<u64>) {
+        let mut buckets = self.buckets.lock();
+        match bytes_per_sec.filter(|rate| *rate > 0) {
+            Some(rate) => {
+                buckets.insert(device_id.to_string(), TokenBucket::new(rate));
+            }
+            None => {
+                buckets.remove(device_id);
+            }
+        }
+    }
+
+    /// Wait until `bytes` may be exchanged with `device_id`
+    pub async fn acquire(&self, device_id: &str, bytes: u64) {
+        let wait = match self.buckets.lock().get_mut(device_id) {
+            Some(bucket) => bucket.take(bytes),
+            None => return,
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
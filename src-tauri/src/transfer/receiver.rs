@@ -1,12 +1,88 @@
-use crate::transfer::protocol::MessageType;
-use crate::transfer::sender::TransferProgress;
+use crate::crypto::chunk_cipher::ChunkCipher;
+use crate::crypto::{identity, security};
+use crate::error::ProxiError;
+use crate::events::{
+    emit_event, DownloadDirUnavailable, HistoryUpdated, PairingDevice, PairingRequest,
+    PeerConnected, PeerDisconnected, TransferInterrupted, TransferProgress, TrustOffered,
+};
+use crate::transfer::content_type;
+use crate::transfer::filename::{
+    resolve_within, sanitize_file_name, DestinationClaim, DestinationClaims,
+};
+use crate::transfer::merkle;
+use crate::transfer::protocol::{
+    hello_is_valid, read_frame_streaming, verify_pair_request, FileMetadata, Frame, HashAlgo,
+    MessageType, TransferErrorCode, TransferReceipt, CHUNK_READ_BUFFER, CLOSE_CANCELLED,
+    CLOSE_REJECTED, MAX_CHUNK_INDEX,
+};
+use crate::transfer::sender::hash_file;
+use crate::transfer::{report_chunk, HANDSHAKE_TIMEOUT};
 use quinn::Connection;
-use std::path::PathBuf;
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
-use tauri::Emitter;
+use tauri::Manager;
+use tauri_plugin_notification::NotificationExt;
+
+/// How often a receive writes its byte count to the database, so a reloaded
+/// UI shows where it's at
+const PROGRESS_PERSIST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a connection's message loop learned about its peer
+#[derive(Default)]
+struct Session {
+    /// Device id and name from a verified `Hello`
+    peer: Option<(String, String)>,
+    /// Whether the peer's id is the fingerprint of the key it signed with
+    peer_key_verified: bool,
+    /// Transfer offered on this connection, and who the offer claims sent it
+    transfer_id: Option<String>,
+    sender_id: Option<String>,
+    /// Bytes of the current file received and verified so far
+    bytes_received: u64,
+    /// History records accepted from the peer on this connection
+    history_received: usize,
+}
+
+/// The peer's id from its valid `Hello`, which a `what` message needs first.
+/// An id the message claims for itself must be that same id, so a peer can't
+/// speak for a device it didn't sign in as.
+fn verified_peer<'a>(
+    session: &'a Session,
+    claimed: Option<&str>,
+    what: &str,
+) -> Result<&'a str, ProxiError> {
+    let Some((peer_id, _)) = &session.peer else {
+        return Err(ProxiError::Protocol(format!(
+            "{} before a valid Hello",
+            what
+        )));
+    };
+    match claimed {
+        Some(claimed) if claimed != peer_id => Err(ProxiError::Protocol(format!(
+            "{} claims to be from {}, but the peer said hello as {}",
+            what, claimed, peer_id
+        ))),
+        _ => Ok(peer_id),
+    }
+}
+
+/// Outcome of the shared-passphrase check on an offer
+enum OfferAuth {
+    /// No passphrase is configured
+    Open,
+    /// The sender proved it knows the passphrase `key` was derived from, in
+    /// answer to `nonce`
+    Verified {
+        key: String,
+        nonce: Vec<u8>,
+    },
+    Failed,
+}
 
 pub struct FileReceiver {
     save_directory: PathBuf,
@@ -14,15 +90,23 @@ pub struct FileReceiver {
     app_handle: tauri::AppHandle,
     database: Arc<tokio::sync::RwLock<Option<crate::db::Database>>>,
     transfers: crate::TransferRegistry,
+    connections: crate::transfer::ConnectionRegistry,
+    destinations: DestinationClaims,
+    /// Our name, for answering identity requests
+    device_name: String,
 }
 
 impl FileReceiver {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         save_directory: PathBuf,
         connection: Connection,
         app_handle: tauri::AppHandle,
         database: Arc<tokio::sync::RwLock<Option<crate::db::Database>>>,
         transfers: crate::TransferRegistry,
+        connections: crate::transfer::ConnectionRegistry,
+        destinations: DestinationClaims,
+        device_name: String,
     ) -> Self {
         Self {
             save_directory,
@@ -30,383 +114,1801 @@ impl FileReceiver {
             app_handle,
             database,
             transfers,
+            connections,
+            destinations,
+            device_name,
+        }
+    }
+
+    /// If a shared passphrase is configured, challenge the sender to prove it
+    /// knows it
+    async fn authenticate_offer(
+        &self,
+        transfer_id: &str,
+        send_stream: &mut quinn::SendStream,
+        recv_stream: &mut quinn::RecvStream,
+    ) -> Result<OfferAuth, ProxiError> {
+        let (key, salt) = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let settings = app_state.settings.read().await;
+            (
+                settings.settings.passphrase_key.clone(),
+                settings.settings.passphrase_salt.clone(),
+            )
+        };
+        let Some(key) = key else {
+            return Ok(OfferAuth::Open);
+        };
+        // The key was derived under a salt we no longer have (e.g. saved
+        // before keys were salted), so no sender could ever answer
+        let Some(salt) = salt else {
+            println!(
+                "[Security] Rejecting offer {}: the shared passphrase has no salt; set it again",
+                transfer_id
+            );
+            let _ = Self::write_message(
+                send_stream,
+                &MessageType::FileReject {
+                    transfer_id: transfer_id.to_string(),
+                    reason: "Receiver's shared passphrase needs to be set again".to_string(),
+                },
+            )
+            .await;
+            let _ = send_stream.finish();
+            return Err(ProxiError::Crypto(
+                "Shared passphrase is configured without its salt; set the passphrase again".into(),
+            ));
+        };
+
+        let nonce: [u8; 32] = rand::random();
+        Self::write_message(
+            send_stream,
+            &MessageType::AuthChallenge {
+                transfer_id: transfer_id.to_string(),
+                nonce: nonce.to_vec(),
+                salt,
+            },
+        )
+        .await?;
+
+        match tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            Self::read_message(recv_stream),
+        )
+        .await
+        {
+            Ok(Ok(MessageType::AuthResponse { mac, .. }))
+                if security::verify_passphrase_mac(&key, &nonce, transfer_id, &mac) =>
+            {
+                Ok(OfferAuth::Verified {
+                    key,
+                    nonce: nonce.to_vec(),
+                })
+            }
+            _ => Ok(OfferAuth::Failed),
+        }
+    }
+
+    /// Accept a benchmark offer and throw its data away, acknowledging once
+    /// it has all arrived so the sender can time the delivery
+    async fn discard_benchmark(
+        &self,
+        transfer_id: String,
+        send_stream: &mut quinn::SendStream,
+        recv_stream: &mut quinn::RecvStream,
+    ) -> Result<(), ProxiError> {
+        println!("[Transfer] Running benchmark {}", transfer_id);
+        Self::write_message(
+            send_stream,
+            &MessageType::FileAccept {
+                transfer_id: transfer_id.clone(),
+                resume_offset: 0,
+                prefix_hash: None,
+            },
+        )
+        .await?;
+
+        let mut received: u64 = 0;
+        loop {
+            match read_frame_streaming(recv_stream).await? {
+                Frame::Chunk(chunk) => {
+                    received += chunk.data_len as u64;
+                    chunk.skip(recv_stream).await?;
+                }
+                Frame::Message(MessageType::TransferComplete { .. }) => break,
+                Frame::Message(_) => {}
+            }
+        }
+
+        println!(
+            "[Transfer] Benchmark {} discarded {} bytes",
+            transfer_id, received
+        );
+        Self::write_message(
+            send_stream,
+            &MessageType::TransferCompleteAck {
+                transfer_id,
+                receipt: None,
+            },
+        )
+        .await?;
+        send_stream.finish()?;
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        self.connection
+            .close(quinn::VarInt::from_u32(0), b"benchmark complete");
+        Ok(())
+    }
+
+    /// Classify a file IO error: if the save directory is gone this is a
+    /// `DiskUnavailable` failure, anything else is returned as is
+    async fn io_failure(
+        &self,
+        error: std::io::Error,
+        transfer_id: &str,
+        bytes_received: u64,
+        send_stream: &mut quinn::SendStream,
+    ) -> ProxiError {
+        if self.save_directory.is_dir() {
+            return error.into();
+        }
+        self.disk_unavailable(transfer_id, bytes_received, send_stream)
+            .await
+    }
+
+    /// Mark a transfer failed at `bytes_received` in the database and the
+    /// registry, then tell the sender why with `message` and, if `close` is
+    /// set, close the connection with that code and reason. Received chunks
+    /// are kept for resuming unless `clear_chunks`.
+    async fn fail_transfer(
+        &self,
+        transfer_id: &str,
+        bytes_received: u64,
+        clear_chunks: bool,
+        message: MessageType,
+        close: Option<(u32, &[u8])>,
+        send_stream: &mut quinn::SendStream,
+    ) {
+        {
+            let db_lock = self.database.read().await;
+            if let Some(db) = &*db_lock {
+                if let Err(e) = db
+                    .update_transfer_status(transfer_id, "failed", bytes_received as i64)
+                    .await
+                {
+                    println!("[Database] Failed to update transfer status: {:?}", e);
+                }
+                if clear_chunks {
+                    let _ = db.clear_chunks(transfer_id).await;
+                }
+            }
+        }
+        crate::set_transfer_status(&self.transfers, transfer_id, crate::TransferStatus::Failed)
+            .await;
+        emit_event(&self.app_handle, HistoryUpdated);
+
+        let _ = Self::write_message(send_stream, &message).await;
+        let _ = send_stream.finish();
+        if let Some((code, reason)) = close {
+            self.connection.close(quinn::VarInt::from_u32(code), reason);
+        }
+    }
+
+    /// The save directory vanished (unplugged or unmounted drive). Fails the
+    /// transfer with a distinct code, keeping its received chunks so offering it
+    /// again resumes, and asks the user to pick another directory.
+    async fn disk_unavailable(
+        &self,
+        transfer_id: &str,
+        bytes_received: u64,
+        send_stream: &mut quinn::SendStream,
+    ) -> ProxiError {
+        println!(
+            "[Transfer] Save directory {:?} is unavailable, failing {}",
+            self.save_directory, transfer_id
+        );
+
+        emit_event(
+            &self.app_handle,
+            DownloadDirUnavailable {
+                transfer_id: transfer_id.to_string(),
+                path: self.save_directory.to_string_lossy().to_string(),
+            },
+        );
+        self.fail_transfer(
+            transfer_id,
+            bytes_received,
+            false,
+            MessageType::TransferError {
+                transfer_id: transfer_id.to_string(),
+                code: TransferErrorCode::DiskUnavailable,
+                message: "Receiver's download directory is unavailable".to_string(),
+            },
+            Some((1, b"disk unavailable")),
+            send_stream,
+        )
+        .await;
+
+        ProxiError::DiskUnavailable(format!(
+            "save directory {} is unavailable",
+            self.save_directory.display()
+        ))
+    }
+
+    /// Fail a transfer whose sender finished after more or fewer bytes than
+    /// it offered, e.g. because chunks were lost but acknowledged
+    async fn size_mismatch(
+        &self,
+        transfer_id: &str,
+        expected: u64,
+        actual: u64,
+        send_stream: &mut quinn::SendStream,
+    ) -> ProxiError {
+        println!(
+            "[Transfer] Size mismatch for {}: expected {} bytes, received {}",
+            transfer_id, expected, actual
+        );
+
+        // The received data can't be trusted, so don't resume from it
+        self.fail_transfer(
+            transfer_id,
+            actual,
+            true,
+            MessageType::TransferError {
+                transfer_id: transfer_id.to_string(),
+                code: TransferErrorCode::SizeMismatch,
+                message: format!("Expected {} bytes, received {}", expected, actual),
+            },
+            None,
+            send_stream,
+        )
+        .await;
+
+        ProxiError::SizeMismatch { expected, actual }
+    }
+
+    /// Give up on a connection that sent no message within `HANDSHAKE_TIMEOUT`
+    fn abandoned(&self) -> ProxiError {
+        println!(
+            "[Transfer] Abandoned connection attempt from {}: no message within {} seconds",
+            self.connection.remote_address(),
+            HANDSHAKE_TIMEOUT.as_secs()
+        );
+        ProxiError::Protocol(format!(
+            "No message within {} seconds of connecting",
+            HANDSHAKE_TIMEOUT.as_secs()
+        ))
+    }
+
+    /// Fail a transfer that moved no bytes for `timeout`, e.g. because the
+    /// sender vanished without closing. Received chunks are kept for resuming.
+    async fn stalled(
+        &self,
+        transfer_id: &str,
+        bytes_received: u64,
+        timeout: Duration,
+        send_stream: &mut quinn::SendStream,
+    ) -> ProxiError {
+        println!(
+            "[Transfer] {} made no progress for {}s, failing it",
+            transfer_id,
+            timeout.as_secs()
+        );
+
+        self.fail_transfer(
+            transfer_id,
+            bytes_received,
+            false,
+            MessageType::TransferError {
+                transfer_id: transfer_id.to_string(),
+                code: TransferErrorCode::Stalled,
+                message: format!("No data received for {} seconds", timeout.as_secs()),
+            },
+            Some((1, b"transfer stalled")),
+            send_stream,
+        )
+        .await;
+
+        ProxiError::Stalled(timeout.as_secs())
+    }
+
+    /// The sniffed type of the file at `path` if an allowlist is configured
+    /// and the type isn't on it (`"unknown"` when nothing was recognized)
+    async fn disallowed_content_type(&self, path: &Path) -> Option<&'static str> {
+        let allowlist = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let settings = app_state.settings.read().await;
+            settings.settings.receive_mime_allowlist.clone()
+        };
+        if allowlist.is_empty() {
+            return None;
+        }
+        let mime = match content_type::sniff(path) {
+            Ok(mime) => mime,
+            Err(e) => {
+                println!("[Transfer] Could not sniff {:?}: {}", path, e);
+                None
+            }
+        };
+        if content_type::is_allowed(mime, &allowlist) {
+            None
+        } else {
+            Some(mime.unwrap_or("unknown"))
+        }
+    }
+
+    /// Refuse a transfer whose content type isn't allowed: tell the sender,
+    /// delete the partial file and forget its progress
+    async fn type_not_allowed(
+        &self,
+        transfer_id: &str,
+        path: &Path,
+        mime: &str,
+        send_stream: &mut quinn::SendStream,
+    ) -> ProxiError {
+        println!(
+            "[Transfer] Rejecting {}: content type {} is not allowed",
+            transfer_id, mime
+        );
+        if let Err(e) = std::fs::remove_file(path) {
+            println!(
+                "[Transfer] Failed to delete rejected file {:?}: {}",
+                path, e
+            );
         }
+
+        let reason = "type not allowed";
+        self.fail_transfer(
+            transfer_id,
+            0,
+            true,
+            MessageType::FileReject {
+                transfer_id: transfer_id.to_string(),
+                reason: reason.to_string(),
+            },
+            Some((CLOSE_REJECTED, reason.as_bytes())),
+            send_stream,
+        )
+        .await;
+
+        ProxiError::Rejected(format!("{} ({})", reason, mime))
     }
 
-    fn check_disk_space(&self, required_bytes: u64) -> Result<(), crate::GenericError> {
-        let parent = self.save_directory.parent().unwrap_or(&self.save_directory);
-        let space = fs2::available_space(parent)?;
-        if space < required_bytes {
-            return Err("Insufficient disk space".into());
+    /// Make sure the file fits with the configured margin still free, so a
+    /// receive never fills the disk completely
+    async fn check_disk_space(&self, required_bytes: u64) -> Result<(), ProxiError> {
+        let margin = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let settings = app_state.settings.read().await;
+            settings.settings.disk_space_margin()
+        };
+        let space = crate::transfer::disk_space(&self.save_directory)?.available_bytes;
+        if space < required_bytes.saturating_add(margin) {
+            return Err(ProxiError::Other(format!(
+                "Insufficient disk space (reserving a {} byte margin): {} bytes needed, {} available",
+                margin, required_bytes, space
+            )));
         }
         Ok(())
     }
 
-    pub async fn handle_transfer(&self) -> Result<(), crate::GenericError> {
+    pub async fn handle_transfer(&self) -> Result<(), ProxiError> {
+        let mut session = Session::default();
+        let result = self.receive(&mut session).await;
+
+        // Resolve the transfer in the registry so batches can complete
+        if let Some(transfer_id) = &session.transfer_id {
+            if let Err(e) = &result {
+                let status = crate::get_transfer_status(&self.transfers, transfer_id).await;
+                if !matches!(
+                    status,
+                    crate::TransferStatus::Cancelled | crate::TransferStatus::Completed
+                ) {
+                    // A sender that cancels may close the connection before
+                    // its TransferCancel is read
+                    let status = if e.is_cancelled() {
+                        crate::TransferStatus::Cancelled
+                    } else {
+                        if e.is_interrupted() && status == crate::TransferStatus::InProgress {
+                            self.interrupted(transfer_id, &session, e).await;
+                        }
+                        crate::TransferStatus::Failed
+                    };
+                    crate::set_transfer_status(&self.transfers, transfer_id, status).await;
+                }
+            }
+            crate::finish_batch_member(&self.app_handle, &self.transfers, transfer_id).await;
+
+            if result.is_ok() {
+                self.offer_trust(&session, transfer_id).await;
+            }
+        }
+
+        if let Some((device_id, device_name)) = session.peer {
+            println!(
+                "[Transfer] Peer {} ({}) disconnected",
+                device_name, device_id
+            );
+            emit_event(
+                &self.app_handle,
+                PeerDisconnected {
+                    device_id,
+                    device_name,
+                    ip: self.connection.remote_address().ip().to_string(),
+                },
+            );
+        }
+
+        result
+    }
+
+    async fn is_blocked(&self, device_id: &str) -> bool {
+        let app_state = self.app_handle.state::<crate::AppState>();
+        let security = app_state.security.read().await;
+        security.is_blocked(device_id)
+    }
+
+    /// The id from the peer's signed `Hello`, if that device is blocked.
+    /// Unlike ids claimed in offers, it holds for the whole connection.
+    async fn blocked_peer(&self, session: &Session) -> Option<String> {
+        let (peer_id, _) = session.peer.as_ref()?;
+        self.is_blocked(peer_id).await.then(|| peer_id.clone())
+    }
+
+    /// Close a blocked device's connection without emitting anything, so it
+    /// can't prompt the user
+    fn blocked(&self, device_id: &str) -> ProxiError {
+        println!(
+            "[Security] Closing connection from blocked device {}",
+            device_id
+        );
+        self.connection
+            .close(quinn::VarInt::from_u32(CLOSE_REJECTED), b"device blocked");
+        ProxiError::Rejected(format!("Device {} is blocked", device_id))
+    }
+
+    /// Whether the peer proved its id with its key and that id is trusted
+    async fn peer_is_trusted(&self, session: &Session) -> bool {
+        let Some((peer_id, _)) = &session.peer else {
+            return false;
+        };
+        if !session.peer_key_verified {
+            return false;
+        }
+        let app_state = self.app_handle.state::<crate::AppState>();
+        let security = app_state.security.read().await;
+        security.is_trusted(peer_id)
+    }
+
+    /// Start a history sync with the sender in the background. The sender
+    /// connects from the endpoint it listens on, so its address is also
+    /// where it accepts connections.
+    fn sync_history_later(&self, session: &Session) {
+        let Some((peer_id, _)) = session.peer.clone() else {
+            return;
+        };
+        let address = self.connection.remote_address();
+        let app_handle = self.app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            let app_state = app_handle.state::<crate::AppState>();
+            let manager = app_state.transfer.read().await.clone();
+            if let Some(manager) = manager {
+                if let Err(e) = manager
+                    .sync_history(address.ip().to_string(), address.port(), &peer_id)
+                    .await
+                {
+                    println!("[Sync] History sync with {} failed: {}", peer_id, e);
+                }
+            }
+        });
+    }
+
+    /// The sender's connection dropped mid-file without a `TransferComplete`,
+    /// e.g. because its app quit or crashed. The received chunks stay
+    /// recorded and the transfer is marked `interrupted` rather than failed,
+    /// so the UI can offer a resume once the sender is back.
+    async fn interrupted(&self, transfer_id: &str, session: &Session, reason: &ProxiError) {
+        println!(
+            "[Transfer] {} interrupted after {} bytes: {}",
+            transfer_id, session.bytes_received, reason
+        );
+
+        {
+            let db_lock = self.database.read().await;
+            if let Some(db) = &*db_lock {
+                if let Err(e) = db
+                    .update_transfer_status(
+                        transfer_id,
+                        "interrupted",
+                        session.bytes_received as i64,
+                    )
+                    .await
+                {
+                    println!("[Database] Failed to update transfer status: {:?}", e);
+                }
+            }
+        }
+        emit_event(
+            &self.app_handle,
+            TransferInterrupted {
+                transfer_id: transfer_id.to_string(),
+                device_id: session.sender_id.clone(),
+                bytes_received: session.bytes_received,
+                reason: reason.to_string(),
+            },
+        );
+        emit_event(&self.app_handle, HistoryUpdated);
+    }
+
+    /// With `trust_on_first_transfer` enabled, offer the user to trust the
+    /// sender of a completed transfer. Offers accepted here were never
+    /// approved by the user, so trust is only added once they accept with
+    /// `accept_transfer_trust`. Only senders whose id is proven by their key
+    /// qualify, so a device can't get another device's id trusted by
+    /// claiming it.
+    async fn offer_trust(&self, session: &Session, transfer_id: &str) {
+        let Some((peer_id, peer_name)) = &session.peer else {
+            return;
+        };
+        if !session.peer_key_verified || session.sender_id.as_deref() != Some(peer_id.as_str()) {
+            return;
+        }
+        if crate::get_transfer_status(&self.transfers, transfer_id).await
+            != crate::TransferStatus::Completed
+        {
+            return;
+        }
+
+        let app_state = self.app_handle.state::<crate::AppState>();
+        if !app_state
+            .settings
+            .read()
+            .await
+            .settings
+            .trust_on_first_transfer
+        {
+            return;
+        }
+        if app_state.security.read().await.is_trusted(peer_id) {
+            return;
+        }
+        app_state.trust_offers.write().await.insert(peer_id.clone());
+        println!(
+            "[Security] Offering to trust {} after completed transfer",
+            peer_id
+        );
+        emit_event(
+            &self.app_handle,
+            TrustOffered {
+                device_id: peer_id.clone(),
+                device_name: peer_name.clone(),
+                transfer_id: transfer_id.to_string(),
+            },
+        );
+    }
+
+    /// Move a completed file into the subfolder configured for its extension.
+    /// A failed move leaves the file in the save directory.
+    async fn post_process(&self, path: &mut PathBuf) {
+        let rules = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let settings = app_state.settings.read().await;
+            settings.settings.post_process_folders.clone()
+        };
+        if rules.is_empty() {
+            return;
+        }
+        match crate::transfer::post_process::apply(path, &self.save_directory, &rules) {
+            Ok(Some(moved)) => {
+                println!("[Transfer] Moved {:?} to {:?}", path, moved);
+                *path = moved;
+            }
+            Ok(None) => {}
+            Err(e) => println!("[Transfer] Could not move {:?} to its folder: {}", path, e),
+        }
+    }
+
+    /// Show an OS notification for a completed file, if the user enabled
+    /// them. The path lets a click reveal the file.
+    async fn notify_received(&self, file_name: &str, sender_name: &str, path: &Path) {
+        let app_state = self.app_handle.state::<crate::AppState>();
+        if !app_state
+            .settings
+            .read()
+            .await
+            .settings
+            .notifications_enabled
+            || app_state.do_not_disturb.read().await.enabled
+        {
+            return;
+        }
+        let shown = self
+            .app_handle
+            .notification()
+            .builder()
+            .title("File received")
+            .body(format!("{} from {}", file_name, sender_name))
+            .extra("path", path.to_string_lossy())
+            .show();
+        if let Err(e) = shown {
+            println!("[Transfer] Failed to show notification: {}", e);
+        }
+    }
+
+    /// Run the message loop of the connection's stream, noting in `session`
+    /// who the peer is and which transfer it offered
+    async fn receive(&self, session: &mut Session) -> Result<(), ProxiError> {
+        // A peer that connects but never says anything (a port scanner, a
+        // sender that stalled) is dropped once the handshake deadline passes
+        let handshake_deadline = tokio::time::Instant::now() + HANDSHAKE_TIMEOUT;
+        let mut handshake_done = false;
+
         // Accept the single bidirectional stream from the sender
-        let (mut send_stream, mut recv_stream) = self.connection.accept_bi().await?;
+        let accepted = tokio::time::timeout_at(
+            handshake_deadline,
+            crate::transfer::protocol::accept_stream(&self.connection),
+        )
+        .await;
+        let (mut send_stream, mut recv_stream) = match accepted {
+            Ok(streams) => streams?,
+            Err(_) => return Err(self.abandoned()),
+        };
 
         let mut file: Option<File> = None;
         let mut bytes_received: u64 = 0;
         let mut current_transfer_id = String::new();
         let mut current_file_name = String::new();
         let mut current_file_size: u64 = 0;
+        let mut current_file_path = PathBuf::new();
+        let mut current_modified: Option<u64> = None;
+        let mut current_hash_algo = HashAlgo::default();
+        let mut current_chunk_size: u32 = 0;
+        // Keeps concurrent receives of the same name out of the current file
+        let mut destination: Option<DestinationClaim> = None;
+        let mut current_batch_id: Option<String> = None;
+        // Per-device bandwidth caps follow the id the peer said hello as,
+        // never one an offer claims; before a Hello, its address
+        let mut rate_limit_key = self.connection.remote_address().ip().to_string();
+        let mut current_sender_name = String::new();
+        let rate_limiter = self
+            .app_handle
+            .state::<crate::AppState>()
+            .rate_limiter
+            .clone();
         let mut last_status = crate::TransferStatus::InProgress;
+        let mut last_progress_persist = Instant::now();
+        let stall_timeout = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let settings = app_state.settings.read().await;
+            settings.settings.stall_timeout()
+        };
+        // When bytes last arrived; only counts down while a file is being received
+        let mut last_progress_at = Instant::now();
+        // A resume offset was proposed and the first chunk hasn't arrived yet
+        let mut resume_pending = false;
+        let mut current_cipher: Option<ChunkCipher> = None;
+        // The current file's content type has been checked against the allowlist
+        let mut type_checked = false;
 
         loop {
-            tokio::select! {
-                // 1. Listen for network messages
-                msg_result = Self::read_message(&mut recv_stream) => {
-                    let msg = msg_result?;
-                    match msg {
-                        MessageType::FileOffer {
-                            transfer_id,
-                            metadata,
-                            sender_id,
-                            sender_name: _,
-                        } => {
-                            self.check_disk_space(metadata.size)?;
-
-                            let path = self.save_directory.join(&metadata.name);
-                            current_transfer_id = transfer_id.clone();
-                            current_file_name = metadata.name.clone();
-                            current_file_size = metadata.size;
-
-                            // Record the transfer start in database
-                            {
-                                let db_lock = self.database.read().await;
-                                if let Some(db) = &*db_lock {
-                                    if let Err(e) = db
-                                        .record_transfer(
-                                            &current_transfer_id,
-                                            &sender_id,
-                                            &current_file_name,
-                                            &path.to_string_lossy(),
-                                            current_file_size as i64,
-                                            "receive",
-                                            &metadata.hash,
-                                        )
-                                        .await
-                                    {
-                                        println!("[Database] Failed to record transfer: {:?}", e);
-                                    }
+            session.bytes_received = bytes_received;
+            // The read stays pending across status ticks: it isn't cancel-safe,
+            // and dropping it partway through a frame would leave the stream
+            // reading payload bytes as the next length prefix
+            let frame_result = {
+                let read = read_frame_streaming(&mut recv_stream);
+                tokio::pin!(read);
+                loop {
+                    tokio::select! {
+                        frame_result = &mut read => break frame_result,
+
+                        _ = tokio::time::sleep_until(handshake_deadline), if !handshake_done => {
+                            return Err(self.abandoned());
+                        }
+
+                        // Poll local status changes every 500ms
+                        _ = tokio::time::sleep(std::time::Duration::from_millis(500)) => {
+                            if !current_transfer_id.is_empty() {
+                                let status = crate::get_transfer_status(&self.transfers, &current_transfer_id).await;
+
+                                // A paused transfer legitimately moves nothing
+                                if file.is_none() || status != crate::TransferStatus::InProgress || last_status != crate::TransferStatus::InProgress {
+                                    last_progress_at = Instant::now();
+                                } else if let Some(timeout) = stall_timeout.filter(|t| last_progress_at.elapsed() >= *t) {
+                                    return Err(self.stalled(&current_transfer_id, bytes_received, timeout, &mut send_stream).await);
                                 }
-                            }
 
-                            // Update registry
-                            {
-                                let mut transfers = self.transfers.write().await;
-                                transfers.insert(current_transfer_id.clone(), crate::TransferStatus::InProgress);
+                                if status != last_status {
+                                    match status {
+                                        crate::TransferStatus::Cancelled => {
+                                            println!("[Receiver] Sending TransferCancel to sender...");
+                                            let _ = Self::write_message(
+                                                &mut send_stream,
+                                                &MessageType::TransferCancel {
+                                                    transfer_id: current_transfer_id.clone(),
+                                                },
+                                            ).await;
+                                            self.connection.close(
+                                                quinn::VarInt::from_u32(CLOSE_CANCELLED),
+                                                b"transfer cancelled",
+                                            );
+                                            return Err(ProxiError::Cancelled("Transfer cancelled by receiver".into()));
+                                        }
+                                        crate::TransferStatus::Paused => {
+                                            println!("[Receiver] Sending TransferPause to sender...");
+                                            let _ = Self::write_message(
+                                                &mut send_stream,
+                                                &MessageType::TransferPause {
+                                                    transfer_id: current_transfer_id.clone(),
+                                                },
+                                            ).await;
+                                        }
+                                        crate::TransferStatus::InProgress if last_status == crate::TransferStatus::Paused => {
+                                            println!("[Receiver] Sending TransferResume to sender...");
+                                            let _ = Self::write_message(
+                                                &mut send_stream,
+                                                &MessageType::TransferResume {
+                                                    transfer_id: current_transfer_id.clone(),
+                                                },
+                                            ).await;
+                                        }
+                                        _ => {}
+                                    }
+                                    // Emit progress update when local status changes
+                                    emit_event(&self.app_handle, TransferProgress {
+                                            transfer_id: current_transfer_id.clone(),
+                                            file_name: current_file_name.clone(),
+                                            bytes_sent: bytes_received,
+                                            total_bytes: current_file_size,
+                                            direction: "receive".to_string(),
+                                            batch_id: current_batch_id.clone(),
+                                            status: match status {
+                                                crate::TransferStatus::Paused => "paused",
+                                                crate::TransferStatus::Cancelled => "cancelled",
+                                                _ => "in_progress",
+                                            }.to_string(),
+                                            chunk_size: current_chunk_size,
+                                        },
+                                    );
+                                    last_status = status;
+                                }
                             }
+                        }
+                    }
+                }
+            };
+            let frame = frame_result?;
+            // Checked on every frame, so a peer blocked mid-connection
+            // can't keep sending either
+            if let Some(peer_id) = self.blocked_peer(session).await {
+                return Err(self.blocked(&peer_id));
+            }
+            let msg = match frame {
+                Frame::Message(msg) => msg,
+                Frame::Chunk(chunk) => {
+                    let chunk_index = chunk.chunk_index;
+                    if chunk_index > MAX_CHUNK_INDEX {
+                        return Err(ProxiError::Protocol(format!(
+                            "Chunk index {} exceeds the limit of {}",
+                            chunk_index, MAX_CHUNK_INDEX
+                        )));
+                    }
+                    let Some(ref mut f) = file else {
+                        chunk.skip(&mut recv_stream).await?;
+                        continue;
+                    };
 
-                            // Use std::fs to create and allocate to avoid tokio/fs2 complexity
-                            let std_file = std::fs::OpenOptions::new()
-                                .write(true)
-                                .create(true)
-                                .open(&path)?;
+                    // The sender didn't accept our resume offset: overwrite from the start
+                    if std::mem::take(&mut resume_pending) && chunk_index == 0 && bytes_received > 0
+                    {
+                        println!(
+                            "[Transfer] Sender restarted {} from the beginning",
+                            current_transfer_id
+                        );
+                        f.seek(SeekFrom::Start(0)).await?;
+                        bytes_received = 0;
+                        let db_lock = self.database.read().await;
+                        if let Some(db) = &*db_lock {
+                            let _ = db.clear_chunks(&current_transfer_id).await;
+                        }
+                    }
 
-                            use fs2::FileExt;
-                            let _ = std_file.allocate(metadata.size);
+                    let data_len = chunk.data_len;
+                    let chunk_hash = if let Some(cipher) = &current_cipher {
+                        // The tag covers the whole ciphertext, so encrypted chunks are buffered
+                        let mut data = vec![0u8; data_len];
+                        chunk.read_payload(&mut recv_stream, &mut data).await?;
+                        let chunk_hash = chunk.read_hash(&mut recv_stream).await?;
+                        cipher.open(chunk_index, &mut data)?;
+                        let verified = current_hash_algo.hash(&data)? == chunk_hash;
+                        report_chunk(
+                            &self.app_handle,
+                            &current_transfer_id,
+                            "receive",
+                            chunk_index,
+                            data_len,
+                            &chunk_hash,
+                            Some(verified),
+                        );
+                        if !verified {
+                            return Err(ProxiError::Protocol("Chunk hash mismatch".into()));
+                        }
+                        if let Err(e) = f.write_all(&data).await {
+                            return Err(self
+                                .io_failure(
+                                    e,
+                                    &current_transfer_id,
+                                    bytes_received,
+                                    &mut send_stream,
+                                )
+                                .await);
+                        }
+                        rate_limiter
+                            .acquire(&rate_limit_key, data.len() as u64)
+                            .await;
+                        bytes_received += data.len() as u64;
+                        chunk_hash
+                    } else {
+                        // Copy through a small buffer, hashing as we go; not reading
+                        // while throttled pushes back on the sender via QUIC flow control
+                        let mut hasher = current_hash_algo.hasher()?;
+                        let mut buffer = vec![0u8; data_len.min(CHUNK_READ_BUFFER)];
+                        let mut left = data_len;
+                        while left > 0 {
+                            let n = left.min(buffer.len());
+                            chunk
+                                .read_payload(&mut recv_stream, &mut buffer[..n])
+                                .await?;
+                            hasher.update(&buffer[..n]);
+                            if let Err(e) = f.write_all(&buffer[..n]).await {
+                                return Err(self
+                                    .io_failure(
+                                        e,
+                                        &current_transfer_id,
+                                        bytes_received,
+                                        &mut send_stream,
+                                    )
+                                    .await);
+                            }
+                            rate_limiter.acquire(&rate_limit_key, n as u64).await;
+                            left -= n;
+                        }
+                        let chunk_hash = chunk.read_hash(&mut recv_stream).await?;
+                        let verified = hasher.finalize() == chunk_hash;
+                        report_chunk(
+                            &self.app_handle,
+                            &current_transfer_id,
+                            "receive",
+                            chunk_index,
+                            data_len,
+                            &chunk_hash,
+                            Some(verified),
+                        );
+                        if !verified {
+                            // Drop the bad bytes so a resume doesn't trust them
+                            let _ = f.set_len(bytes_received).await;
+                            return Err(ProxiError::Protocol("Chunk hash mismatch".into()));
+                        }
+                        bytes_received += data_len as u64;
+                        chunk_hash
+                    };
+                    last_progress_at = Instant::now();
 
-                            // Then convert to tokio file
-                            file = Some(File::from_std(std_file));
+                    // Sniff once the start of the file is on disk; extensions are easy to fake
+                    if !std::mem::replace(&mut type_checked, true) {
+                        f.flush().await?;
+                        if let Some(mime) = self.disallowed_content_type(&current_file_path).await {
+                            drop(file.take()); // Windows can't delete an open file
+                            return Err(self
+                                .type_not_allowed(
+                                    &current_transfer_id,
+                                    &current_file_path,
+                                    mime,
+                                    &mut send_stream,
+                                )
+                                .await);
                         }
-                        MessageType::ChunkData {
-                            transfer_id: _,
-                            chunk_index: _,
-                            data,
-                            chunk_hash,
-                        } => {
-                            if let Some(ref mut f) = file {
-                                // Verify chunk
-                                let actual_hash = blake3::hash(&data).to_hex().to_string();
-                                if actual_hash != chunk_hash {
-                                    return Err("Chunk hash mismatch".into());
-                                }
+                    }
 
-                                f.write_all(&data).await?;
-                                bytes_received += data.len() as u64;
-
-                                // Emit progress event
-                                let _ = self.app_handle.emit(
-                                    "transfer-progress",
-                                    TransferProgress {
-                                        transfer_id: current_transfer_id.clone(),
-                                        file_name: current_file_name.clone(),
-                                        bytes_sent: bytes_received,
-                                        total_bytes: current_file_size,
-                                        direction: "receive".to_string(),
-                                        status: "in_progress".to_string(),
-                                    },
-                                );
+                    // Persist progress so the transfer can resume after a restart
+                    {
+                        let db_lock = self.database.read().await;
+                        if let Some(db) = &*db_lock {
+                            if let Err(e) = db
+                                .record_chunk(&current_transfer_id, chunk_index, &chunk_hash)
+                                .await
+                            {
+                                println!("[Database] Failed to record chunk: {:?}", e);
+                            }
+                            if last_progress_persist.elapsed() >= PROGRESS_PERSIST_INTERVAL {
+                                last_progress_persist = Instant::now();
+                                if let Err(e) = db
+                                    .update_transfer_progress(
+                                        &current_transfer_id,
+                                        bytes_received as i64,
+                                    )
+                                    .await
+                                {
+                                    println!("[Database] Failed to record progress: {:?}", e);
+                                }
                             }
                         }
-                        MessageType::TransferPause { transfer_id: _ } => {
-                            println!("[Receiver] Transfer paused by sender");
-                            {
-                                let mut transfers = self.transfers.write().await;
-                                transfers.insert(current_transfer_id.clone(), crate::TransferStatus::Paused);
+                    }
+
+                    // Emit progress event
+                    emit_event(
+                        &self.app_handle,
+                        TransferProgress {
+                            transfer_id: current_transfer_id.clone(),
+                            file_name: current_file_name.clone(),
+                            bytes_sent: bytes_received,
+                            total_bytes: current_file_size,
+                            direction: "receive".to_string(),
+                            batch_id: current_batch_id.clone(),
+                            status: "in_progress".to_string(),
+                            chunk_size: current_chunk_size,
+                        },
+                    );
+                    continue;
+                }
+            };
+            handshake_done = true;
+            match msg {
+                MessageType::Hello {
+                    device_id,
+                    device_name,
+                    public_key,
+                    signature,
+                } => {
+                    if !hello_is_valid(
+                        &self.connection,
+                        &device_id,
+                        &device_name,
+                        &public_key,
+                        &signature,
+                    ) {
+                        println!(
+                            "[Transfer] Ignoring Hello from {} with invalid signature",
+                            device_id
+                        );
+                        continue;
+                    }
+                    if self.is_blocked(&device_id).await {
+                        return Err(self.blocked(&device_id));
+                    }
+                    // One identity per connection
+                    if let Some((peer_id, _)) = &session.peer {
+                        if *peer_id != device_id {
+                            return Err(ProxiError::Protocol(format!(
+                                "Peer {} sent a second Hello as {}",
+                                peer_id, device_id
+                            )));
+                        }
+                    }
+                    let trusted = {
+                        let app_state = self.app_handle.state::<crate::AppState>();
+                        let security = app_state.security.read().await;
+                        security.is_trusted(&device_id)
+                    };
+                    println!(
+                        "[Transfer] Peer {} ({}) connected, trusted: {}",
+                        device_name, device_id, trusted
+                    );
+                    emit_event(
+                        &self.app_handle,
+                        PeerConnected {
+                            device_id: device_id.clone(),
+                            device_name: device_name.clone(),
+                            ip: self.connection.remote_address().ip().to_string(),
+                            trusted,
+                            key_verified: identity::id_matches_key(&device_id, &public_key),
+                        },
+                    );
+                    session.peer_key_verified = identity::id_matches_key(&device_id, &public_key);
+                    rate_limit_key = device_id.clone();
+                    session.peer = Some((device_id, device_name));
+                }
+                MessageType::BatchOffer { batch_id, files } => {
+                    println!(
+                        "[Transfer] Peer announced batch {} of {} files",
+                        batch_id,
+                        files.len()
+                    );
+                    let device_id = session
+                        .peer
+                        .as_ref()
+                        .map(|(id, _)| id.clone())
+                        .unwrap_or_default();
+                    let mut transfers = self.transfers.write().await;
+                    for (transfer_id, _) in files {
+                        transfers
+                            .entry(transfer_id)
+                            .or_insert_with(|| crate::TransferEntry {
+                                status: crate::TransferStatus::InProgress,
+                                device_id: device_id.clone(),
+                                batch_id: Some(batch_id.clone()),
+                            });
+                    }
+                }
+                MessageType::FileOffer {
+                    transfer_id,
+                    metadata,
+                    sender_id,
+                    sender_name,
+                    benchmark,
+                    resumable,
+                    encrypted,
+                } => {
+                    verified_peer(session, Some(&sender_id), "FileOffer")?;
+                    let auth = self
+                        .authenticate_offer(&transfer_id, &mut send_stream, &mut recv_stream)
+                        .await?;
+                    if matches!(auth, OfferAuth::Failed) {
+                        println!(
+                            "[Security] Rejecting offer {} from {}: passphrase check failed",
+                            transfer_id, sender_id
+                        );
+                        let _ = Self::write_message(
+                            &mut send_stream,
+                            &MessageType::FileReject {
+                                transfer_id,
+                                reason: "Shared passphrase verification failed".to_string(),
+                            },
+                        )
+                        .await;
+                        let _ = send_stream.finish();
+                        return Err(ProxiError::Crypto(
+                            "Peer failed shared passphrase verification".into(),
+                        ));
+                    }
+
+                    if !metadata.hash_algo.is_supported() {
+                        println!(
+                            "[Transfer] Rejecting offer {}: unsupported hash algorithm",
+                            transfer_id
+                        );
+                        let _ = Self::write_message(
+                            &mut send_stream,
+                            &MessageType::FileReject {
+                                transfer_id,
+                                reason: "Unsupported hash algorithm; use blake3 or sha256"
+                                    .to_string(),
+                            },
+                        )
+                        .await;
+                        let _ = send_stream.finish();
+                        return Err(ProxiError::Protocol(
+                            "Offer used an unsupported hash algorithm".into(),
+                        ));
+                    }
+
+                    if benchmark {
+                        return self
+                            .discard_benchmark(transfer_id, &mut send_stream, &mut recv_stream)
+                            .await;
+                    }
+
+                    current_cipher = None;
+                    if encrypted {
+                        let cipher = match &auth {
+                            OfferAuth::Verified { key, nonce } => {
+                                ChunkCipher::derive(key, &transfer_id, nonce)
                             }
-                            last_status = crate::TransferStatus::Paused;
-                            // Emit progress event
-                            let _ = self.app_handle.emit(
-                                "transfer-progress",
-                                TransferProgress {
-                                    transfer_id: current_transfer_id.clone(),
-                                    file_name: current_file_name.clone(),
-                                    bytes_sent: bytes_received,
-                                    total_bytes: current_file_size,
-                                    direction: "receive".to_string(),
-                                    status: "paused".to_string(),
-                                },
+                            _ => None,
+                        };
+                        let Some(cipher) = cipher else {
+                            println!(
+                                "[Security] Rejecting encrypted offer {}: no shared passphrase set",
+                                transfer_id
                             );
+                            let _ = Self::write_message(
+                                &mut send_stream,
+                                &MessageType::FileReject {
+                                    transfer_id,
+                                    reason: "Encrypted transfers need the same shared passphrase on both devices".to_string(),
+                                },
+                            )
+                            .await;
+                            let _ = send_stream.finish();
+                            return Err(ProxiError::Crypto(
+                                "Encrypted offer without a shared passphrase".into(),
+                            ));
+                        };
+                        current_cipher = Some(cipher);
+                    }
+
+                    if !self.save_directory.is_dir() {
+                        return Err(self
+                            .disk_unavailable(&transfer_id, 0, &mut send_stream)
+                            .await);
+                    }
+                    self.check_disk_space(metadata.size).await?;
+
+                    // Never trust the peer-supplied name as a path
+                    let safe_name = sanitize_file_name(&metadata.name);
+                    if safe_name != metadata.name {
+                        println!(
+                            "[Transfer] Sanitized incoming file name {:?} -> {:?}",
+                            metadata.name, safe_name
+                        );
+                    }
+                    let path = resolve_within(&self.save_directory, &safe_name)?;
+                    // Another transfer writing the same name gets the file to itself
+                    drop(destination.take());
+                    let claim = self.destinations.claim(path.clone());
+                    let path = claim.path().to_path_buf();
+                    destination = Some(claim);
+                    let safe_name = match path.file_name() {
+                        Some(name) if name.to_string_lossy() != safe_name => {
+                            let name = name.to_string_lossy().to_string();
+                            println!("[Transfer] {:?} is being received by another transfer, saving {} as {:?}", safe_name, transfer_id, name);
+                            name
                         }
-                        MessageType::TransferResume { transfer_id: _ } => {
-                            println!("[Receiver] Transfer resumed by sender");
+                        _ => safe_name,
+                    };
+                    current_transfer_id = transfer_id.clone();
+                    current_file_name = safe_name;
+                    current_file_size = metadata.size;
+                    current_file_path = path.clone();
+                    current_modified = metadata.modified;
+                    current_hash_algo = metadata.hash_algo;
+                    current_chunk_size = metadata.chunk_size;
+                    type_checked = false;
+
+                    // Record the transfer start in database
+                    {
+                        let db_lock = self.database.read().await;
+                        if let Some(db) = &*db_lock {
+                            if let Err(e) = db
+                                .record_transfer(
+                                    &current_transfer_id,
+                                    &sender_id,
+                                    &current_file_name,
+                                    &path.to_string_lossy(),
+                                    current_file_size as i64,
+                                    "receive",
+                                    &metadata.hash,
+                                    metadata.hash_algo,
+                                    Some(&self.connection.remote_address().ip().to_string()),
+                                    Some(self.connection.remote_address().port()),
+                                )
+                                .await
                             {
-                                let mut transfers = self.transfers.write().await;
-                                transfers.insert(current_transfer_id.clone(), crate::TransferStatus::InProgress);
+                                println!("[Database] Failed to record transfer: {:?}", e);
                             }
-                            last_status = crate::TransferStatus::InProgress;
-                            // Emit progress event
-                            let _ = self.app_handle.emit(
-                                "transfer-progress",
-                                TransferProgress {
-                                    transfer_id: current_transfer_id.clone(),
-                                    file_name: current_file_name.clone(),
-                                    bytes_sent: bytes_received,
-                                    total_bytes: current_file_size,
-                                    direction: "receive".to_string(),
-                                    status: "in_progress".to_string(),
-                                },
-                            );
                         }
-                        MessageType::TransferCancel { transfer_id: _ } => {
-                            println!("[Receiver] Transfer cancelled by sender");
-                            {
-                                let mut transfers = self.transfers.write().await;
-                                transfers.insert(current_transfer_id.clone(), crate::TransferStatus::Cancelled);
+                    }
+
+                    // Update registry, keeping the batch membership announced earlier
+                    {
+                        let mut transfers = self.transfers.write().await;
+                        current_batch_id = transfers
+                            .get(&current_transfer_id)
+                            .and_then(|entry| entry.batch_id.clone());
+                        transfers.insert(
+                            current_transfer_id.clone(),
+                            crate::TransferEntry {
+                                status: crate::TransferStatus::InProgress,
+                                device_id: sender_id.clone(),
+                                batch_id: current_batch_id.clone(),
+                            },
+                        );
+                    }
+                    session.transfer_id = Some(current_transfer_id.clone());
+                    session.sender_id = Some(sender_id.clone());
+                    current_sender_name = sender_name;
+                    let label = crate::transfer::ConnectionLabel::new(
+                        &current_transfer_id,
+                        &sender_id,
+                        &self.connection,
+                    );
+                    println!("[Transfer] {} Accepted offer for {:?}", label, path);
+                    self.connections.write().await.insert(
+                        current_transfer_id.clone(),
+                        crate::transfer::TrackedConnection::new(self.connection.clone(), label),
+                    );
+
+                    let resume = match resumable {
+                        true => match &*self.database.read().await {
+                            Some(db) => resume_point(db, &transfer_id, &path, &metadata).await,
+                            None => None,
+                        },
+                        false => None,
+                    };
+                    {
+                        let db_lock = self.database.read().await;
+                        if let Some(db) = &*db_lock {
+                            if resume.is_none() {
+                                let _ = db.clear_chunks(&transfer_id).await;
+                            }
+                            if let Some(root) = &metadata.merkle_root {
+                                if let Err(e) = db.set_merkle_root(&transfer_id, root).await {
+                                    println!("[Database] Failed to record merkle root: {:?}", e);
+                                }
                             }
-                            // Emit progress event
-                            let _ = self.app_handle.emit(
-                                "transfer-progress",
-                                TransferProgress {
-                                    transfer_id: current_transfer_id.clone(),
-                                    file_name: current_file_name.clone(),
-                                    bytes_sent: bytes_received,
-                                    total_bytes: current_file_size,
-                                    direction: "receive".to_string(),
-                                    status: "cancelled".to_string(),
-                                },
-                            );
-                            let _ = self.app_handle.emit("history-updated", ());
-                            return Err("Transfer cancelled by sender".into());
                         }
-                        MessageType::TransferComplete { transfer_id } => {
-                            println!("[Transfer] Received TransferComplete, flushing file...");
-                            if let Some(mut f) = file.take() {
-                                f.flush().await?;
+                    }
+                    if let Some((offset, prefix_hash)) = resume {
+                        // Continue writing after the bytes we already have, unless
+                        // the sender's first chunk says it is starting over
+                        println!(
+                            "[Transfer] Proposing to resume {} at offset {}",
+                            transfer_id, offset
+                        );
+                        let opened = std::fs::OpenOptions::new().write(true).open(&path);
+                        let mut std_file = match opened {
+                            Ok(f) => f,
+                            Err(e) => {
+                                return Err(self
+                                    .io_failure(e, &transfer_id, offset, &mut send_stream)
+                                    .await)
                             }
-                            // Update status in database
-                            {
-                                let db_lock = self.database.read().await;
-                                    if let Some(db) = &*db_lock {
-                                        if let Err(e) = db
-                                            .update_transfer_status(
-                                                &transfer_id,
-                                                "completed",
-                                                current_file_size as i64,
-                                            )
-                                            .await
-                                        {
-                                            println!("[Database] Failed to update transfer status: {:?}", e);
-                                        }
+                        };
+                        std_file.seek(SeekFrom::Start(offset))?;
+                        file = Some(File::from_std(std_file));
+                        bytes_received = offset;
+                        resume_pending = true;
 
-                                        // Automatic History Sync after completion
-                                        println!("[Transfer] Preparing automatic history sync...");
-                                        if let Ok(records) = db.get_transfer_history(50).await {
-                                            println!("[Transfer] Sending {} history records to sender...", records.len());
-                                            let _ = Self::write_message(
-                                                &mut send_stream,
-                                                &MessageType::HistorySync { records },
-                                            ).await;
-                                        }
-                                    }
-                                }
+                        Self::write_message(
+                            &mut send_stream,
+                            &MessageType::FileAccept {
+                                transfer_id,
+                                resume_offset: offset,
+                                prefix_hash: Some(prefix_hash),
+                            },
+                        )
+                        .await?;
+                    } else {
+                        // Use std::fs to create and allocate to avoid tokio/fs2 complexity
+                        let opened = std::fs::OpenOptions::new()
+                            .write(true)
+                            .create(true)
+                            .truncate(true)
+                            .open(&path);
+                        let std_file = match opened {
+                            Ok(f) => f,
+                            Err(e) => {
+                                return Err(self
+                                    .io_failure(e, &transfer_id, 0, &mut send_stream)
+                                    .await)
+                            }
+                        };
+
+                        // Nothing to reserve for an empty file (and fallocate rejects a zero length)
+                        if metadata.size > 0 {
+                            use fs2::FileExt;
+                            let _ = std_file.allocate(metadata.size);
+                        }
+
+                        // Then convert to tokio file
+                        file = Some(File::from_std(std_file));
+                        bytes_received = 0;
 
-                                // Notify frontend that history changed
-                                let _ = self.app_handle.emit("history-updated", ());
-                                let _ = self.app_handle.emit(
-                                    "transfer-progress",
-                                    TransferProgress {
-                                        transfer_id: transfer_id.clone(),
-                                        file_name: current_file_name.clone(),
-                                        bytes_sent: current_file_size,
-                                        total_bytes: current_file_size,
-                                        direction: "receive".to_string(),
-                                        status: "completed".to_string(),
-                                    },
-                                );
-
-                            println!("[Transfer] Sending TransferCompleteAck...");
-                            // Send acknowledgment on the same stream
-                            Self::write_message(
+                        Self::write_message(
+                            &mut send_stream,
+                            &MessageType::FileAccept {
+                                transfer_id,
+                                resume_offset: 0,
+                                prefix_hash: None,
+                            },
+                        )
+                        .await?;
+                    }
+
+                    // Initial event so the UI sees the transfer before the first chunk lands
+                    emit_event(
+                        &self.app_handle,
+                        TransferProgress {
+                            transfer_id: current_transfer_id.clone(),
+                            file_name: current_file_name.clone(),
+                            bytes_sent: bytes_received,
+                            total_bytes: current_file_size,
+                            direction: "receive".to_string(),
+                            batch_id: current_batch_id.clone(),
+                            status: "in_progress".to_string(),
+                            chunk_size: current_chunk_size,
+                        },
+                    );
+                }
+                MessageType::TransferPause { transfer_id: _ } => {
+                    println!("[Receiver] Transfer paused by sender");
+                    crate::set_transfer_status(
+                        &self.transfers,
+                        &current_transfer_id,
+                        crate::TransferStatus::Paused,
+                    )
+                    .await;
+                    last_status = crate::TransferStatus::Paused;
+                    // Emit progress event
+                    emit_event(
+                        &self.app_handle,
+                        TransferProgress {
+                            transfer_id: current_transfer_id.clone(),
+                            file_name: current_file_name.clone(),
+                            bytes_sent: bytes_received,
+                            total_bytes: current_file_size,
+                            direction: "receive".to_string(),
+                            batch_id: current_batch_id.clone(),
+                            status: "paused".to_string(),
+                            chunk_size: current_chunk_size,
+                        },
+                    );
+                }
+                MessageType::TransferResume { transfer_id: _ } => {
+                    println!("[Receiver] Transfer resumed by sender");
+                    crate::set_transfer_status(
+                        &self.transfers,
+                        &current_transfer_id,
+                        crate::TransferStatus::InProgress,
+                    )
+                    .await;
+                    last_status = crate::TransferStatus::InProgress;
+                    // Emit progress event
+                    emit_event(
+                        &self.app_handle,
+                        TransferProgress {
+                            transfer_id: current_transfer_id.clone(),
+                            file_name: current_file_name.clone(),
+                            bytes_sent: bytes_received,
+                            total_bytes: current_file_size,
+                            direction: "receive".to_string(),
+                            batch_id: current_batch_id.clone(),
+                            status: "in_progress".to_string(),
+                            chunk_size: current_chunk_size,
+                        },
+                    );
+                }
+                MessageType::TransferCancel { transfer_id: _ } => {
+                    println!("[Receiver] Transfer cancelled by sender");
+                    crate::set_transfer_status(
+                        &self.transfers,
+                        &current_transfer_id,
+                        crate::TransferStatus::Cancelled,
+                    )
+                    .await;
+                    // Emit progress event
+                    emit_event(
+                        &self.app_handle,
+                        TransferProgress {
+                            transfer_id: current_transfer_id.clone(),
+                            file_name: current_file_name.clone(),
+                            bytes_sent: bytes_received,
+                            total_bytes: current_file_size,
+                            direction: "receive".to_string(),
+                            batch_id: current_batch_id.clone(),
+                            status: "cancelled".to_string(),
+                            chunk_size: current_chunk_size,
+                        },
+                    );
+                    emit_event(&self.app_handle, HistoryUpdated);
+                    return Err(ProxiError::Cancelled("Transfer cancelled by sender".into()));
+                }
+                MessageType::TransferComplete { transfer_id } => {
+                    if bytes_received != current_file_size {
+                        return Err(self
+                            .size_mismatch(
+                                &transfer_id,
+                                current_file_size,
+                                bytes_received,
                                 &mut send_stream,
-                                &MessageType::TransferCompleteAck { transfer_id },
                             )
-                            .await?;
+                            .await);
+                    }
+                    println!("[Transfer] Received TransferComplete, flushing file...");
+                    if let Some(mut f) = file.take() {
+                        f.flush().await?;
+                    }
+                    if let Some(modified) = current_modified {
+                        preserve_mtime(&current_file_path, modified);
+                    }
+                    // Sign for the bytes actually on disk, not the ones we were promised
+                    let receipt = match hash_file(&current_file_path, current_hash_algo).await {
+                        Ok(file_hash) => {
+                            let app_state = self.app_handle.state::<crate::AppState>();
+                            Some(TransferReceipt::sign(
+                                &app_state.identity,
+                                &transfer_id,
+                                file_hash,
+                                current_hash_algo,
+                            ))
+                        }
+                        Err(e) => {
+                            println!("[Transfer] Could not hash received file for receipt: {}", e);
+                            None
+                        }
+                    };
+
+                    self.post_process(&mut current_file_path).await;
+                    crate::set_transfer_status(
+                        &self.transfers,
+                        &transfer_id,
+                        crate::TransferStatus::Completed,
+                    )
+                    .await;
+                    // Update status in database
+                    {
+                        let db_lock = self.database.read().await;
+                        if let Some(db) = &*db_lock {
+                            if let Err(e) = db
+                                .update_transfer_status(
+                                    &transfer_id,
+                                    "completed",
+                                    current_file_size as i64,
+                                )
+                                .await
+                            {
+                                println!("[Database] Failed to update transfer status: {:?}", e);
+                            }
+                            if let Err(e) = db
+                                .update_transfer_path(
+                                    &transfer_id,
+                                    &current_file_path.to_string_lossy(),
+                                )
+                                .await
+                            {
+                                println!("[Database] Failed to update transfer path: {:?}", e);
+                            }
+                            let _ = db.clear_chunks(&transfer_id).await;
+                        }
+                    }
+
+                    // Notify frontend that history changed
+                    emit_event(&self.app_handle, HistoryUpdated);
+                    emit_event(
+                        &self.app_handle,
+                        TransferProgress {
+                            transfer_id: transfer_id.clone(),
+                            file_name: current_file_name.clone(),
+                            bytes_sent: current_file_size,
+                            total_bytes: current_file_size,
+                            direction: "receive".to_string(),
+                            batch_id: current_batch_id.clone(),
+                            status: "completed".to_string(),
+                            chunk_size: current_chunk_size,
+                        },
+                    );
+                    self.notify_received(
+                        &current_file_name,
+                        &current_sender_name,
+                        &current_file_path,
+                    )
+                    .await;
+
+                    println!("[Transfer] Sending TransferCompleteAck...");
+                    // Send acknowledgment on the same stream
+                    Self::write_message(
+                        &mut send_stream,
+                        &MessageType::TransferCompleteAck {
+                            transfer_id,
+                            receipt,
+                        },
+                    )
+                    .await?;
 
-                            println!("[Transfer] Finishing send stream...");
-                            send_stream.finish()?;
+                    println!("[Transfer] Finishing send stream...");
+                    send_stream.finish()?;
 
-                            // Give QUIC time to flush the ACK bytes over the wire
-                            // before we return and the connection gets dropped
-                            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                    // Give QUIC time to flush the ACK bytes over the wire
+                    // before we return and the connection gets dropped
+                    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
 
-                            // Explicitly close the connection gracefully
-                            self.connection
-                                .close(quinn::VarInt::from_u32(0), b"transfer complete");
+                    // Explicitly close the connection gracefully
+                    self.connection
+                        .close(quinn::VarInt::from_u32(0), b"transfer complete");
 
-                            println!("[Transfer] Transfer complete, breaking loop");
-                            break;
+                    // Reconcile history separately, so a long history
+                    // never holds up the ack
+                    if self.peer_is_trusted(session).await {
+                        self.sync_history_later(session);
+                    }
+
+                    println!("[Transfer] Transfer complete, breaking loop");
+                    break;
+                }
+                MessageType::HistorySync { mut records } => {
+                    verified_peer(session, None, "HistorySync")?;
+                    println!(
+                        "[Transfer] Received HistorySync with {} records",
+                        records.len()
+                    );
+                    let limit = crate::transfer::history::max_records(&self.app_handle).await;
+                    records.truncate(limit.saturating_sub(session.history_received));
+                    session.history_received += records.len();
+                    crate::transfer::history::merge_records(&self.app_handle, &records).await;
+                }
+                MessageType::HistorySyncEnd { max_records } => {
+                    verified_peer(session, None, "HistorySyncEnd")?;
+                    let records = if max_records > 0 && self.peer_is_trusted(session).await {
+                        let limit = crate::transfer::history::max_records(&self.app_handle).await;
+                        crate::transfer::history::local_records(
+                            &self.app_handle,
+                            limit.min(max_records as usize),
+                        )
+                        .await
+                    } else {
+                        Vec::new()
+                    };
+                    println!(
+                        "[Sync] Peer sent {} history records, replying with {}",
+                        session.history_received,
+                        records.len()
+                    );
+                    for chunk in records.chunks(crate::transfer::history::CHUNK_RECORDS) {
+                        Self::write_message(
+                            &mut send_stream,
+                            &MessageType::HistorySync {
+                                records: chunk.to_vec(),
+                            },
+                        )
+                        .await?;
+                    }
+                    Self::write_message(
+                        &mut send_stream,
+                        &MessageType::HistorySyncEnd { max_records: 0 },
+                    )
+                    .await?;
+                }
+                MessageType::IdentityRequest => {
+                    let hello = {
+                        let app_state = self.app_handle.state::<crate::AppState>();
+                        MessageType::hello(
+                            &app_state.identity,
+                            &self.device_name,
+                            &self.connection,
+                        )?
+                    };
+                    Self::write_message(&mut send_stream, &hello).await?;
+                }
+                MessageType::Capabilities {
+                    features,
+                    protocol_version,
+                } => {
+                    println!(
+                        "[Transfer] Peer advertised protocol v{} with features {:?}",
+                        protocol_version, features
+                    );
+                    let local = crate::transfer::protocol::PeerCapabilities::local();
+                    Self::write_message(
+                        &mut send_stream,
+                        &MessageType::Capabilities {
+                            features: local.features,
+                            protocol_version: local.protocol_version,
+                        },
+                    )
+                    .await?;
+                }
+                MessageType::SyncRequest { folder_path, files } => {
+                    verified_peer(session, None, "SyncRequest")?;
+                    // Only a trusted peer may see or change the sync folder
+                    let trusted = self.peer_is_trusted(session).await;
+                    let manager = if trusted {
+                        let app_state = self.app_handle.state::<crate::AppState>();
+                        let sync = app_state.sync.read().await;
+                        sync.manager.clone()
+                    } else {
+                        None
+                    };
+                    let missing_files = match &manager {
+                        Some(manager) if manager.direction().accepts_remote_changes() => {
+                            manager.missing_files(&files)
                         }
-                        MessageType::HistorySync { records } => {
+                        Some(_) => {
                             println!(
-                                "[Transfer] Received HistorySync with {} records",
-                                records.len()
+                                "[Sync] Push-only mode, ignoring sync request for {}",
+                                folder_path
                             );
-                            let db_lock = self.database.read().await;
-                            if let Some(db) = &*db_lock {
-                                for record in records {
-                                    let _ = db
-                                        .record_transfer(
-                                            &record.id,
-                                            &record.device_id,
-                                            &record.file_name,
-                                            &record.file_path,
-                                            record.total_size,
-                                            &record.direction,
-                                            &record.file_hash,
-                                        )
-                                        .await;
-                                    let _ = db
-                                        .update_transfer_status(
-                                            &record.id,
-                                            &record.status,
-                                            record.bytes_transferred,
-                                        )
-                                        .await;
-                                }
-                            }
-                            // Notify frontend that history changed
-                            let _ = self.app_handle.emit("history-updated", ());
+                            Vec::new()
                         }
-                        MessageType::PairRequest {
-                            device_id,
-                            device_name,
-                            pairing_code,
-                        } => {
-                            let _ = self.app_handle.emit(
-                                "pairing-request",
-                                serde_json::json!({
-                                    "device": { "id": device_id, "name": device_name },
-                                    "code": pairing_code,
-                                    "ip": self.connection.remote_address().ip().to_string(),
-                                    "port": self.connection.remote_address().port()
-                                }),
-                            );
+                        None if !trusted => {
+                            println!("[Sync] Ignoring sync request from an untrusted peer");
+                            Vec::new()
                         }
-                        _ => {}
-                    }
-                }
-
-                // 2. Poll local status changes every 500ms
-                _ = tokio::time::sleep(std::time::Duration::from_millis(500)) => {
-                    if !current_transfer_id.is_empty() {
-                        let status = {
-                            let registry = self.transfers.read().await;
-                            registry.get(&current_transfer_id).cloned().unwrap_or(crate::TransferStatus::InProgress)
-                        };
-
-                        if status != last_status {
-                            match status {
-                                crate::TransferStatus::Cancelled => {
-                                    println!("[Receiver] Sending TransferCancel to sender...");
-                                    let _ = Self::write_message(
-                                        &mut send_stream,
-                                        &MessageType::TransferCancel {
-                                            transfer_id: current_transfer_id.clone(),
-                                        },
-                                    ).await;
-                                    return Err("Transfer cancelled by receiver".into());
-                                }
-                                crate::TransferStatus::Paused => {
-                                    println!("[Receiver] Sending TransferPause to sender...");
-                                    let _ = Self::write_message(
-                                        &mut send_stream,
-                                        &MessageType::TransferPause {
-                                            transfer_id: current_transfer_id.clone(),
-                                        },
-                                    ).await;
-                                }
-                                crate::TransferStatus::InProgress if last_status == crate::TransferStatus::Paused => {
-                                    println!("[Receiver] Sending TransferResume to sender...");
-                                    let _ = Self::write_message(
-                                        &mut send_stream,
-                                        &MessageType::TransferResume {
-                                            transfer_id: current_transfer_id.clone(),
-                                        },
-                                    ).await;
-                                }
-                                _ => {}
-                            }
-                            // Emit progress update when local status changes
-                            let _ = self.app_handle.emit(
-                                "transfer-progress",
-                                TransferProgress {
-                                    transfer_id: current_transfer_id.clone(),
-                                    file_name: current_file_name.clone(),
-                                    bytes_sent: bytes_received,
-                                    total_bytes: current_file_size,
-                                    direction: "receive".to_string(),
-                                    status: match status {
-                                        crate::TransferStatus::Paused => "paused",
-                                        crate::TransferStatus::Cancelled => "cancelled",
-                                        _ => "in_progress",
-                                    }.to_string(),
-                                },
-                            );
-                            last_status = status;
+                        None => {
+                            println!("[Sync] No sync folder configured, ignoring sync request");
+                            Vec::new()
                         }
+                    };
+                    let files = match &manager {
+                        Some(manager) => crate::sync::preview::build_manifest(
+                            &self.app_handle,
+                            manager.shared_folder(),
+                        )
+                        .await
+                        .unwrap_or_else(|e| {
+                            println!("[Sync] Failed to list sync folder: {}", e);
+                            Vec::new()
+                        }),
+                        None => Vec::new(),
+                    };
+                    Self::write_message(
+                        &mut send_stream,
+                        &MessageType::SyncResponse {
+                            missing_files,
+                            files,
+                        },
+                    )
+                    .await?;
+                }
+                MessageType::PairRequest {
+                    device_id,
+                    device_name,
+                    pairing_code,
+                    public_key,
+                    signature,
+                } => {
+                    verified_peer(session, Some(&device_id), "PairRequest")?;
+                    if !verify_pair_request(
+                        &device_id,
+                        &device_name,
+                        &pairing_code,
+                        &public_key,
+                        &signature,
+                    ) {
+                        println!(
+                            "[Pairing] Rejecting pairing request from {} with invalid signature",
+                            device_id
+                        );
+                        continue;
                     }
+                    let key_verified = identity::id_matches_key(&device_id, &public_key);
+                    emit_event(
+                        &self.app_handle,
+                        PairingRequest {
+                            device: PairingDevice {
+                                id: device_id,
+                                name: device_name,
+                            },
+                            code: pairing_code,
+                            key_verified,
+                            ip: self.connection.remote_address().ip().to_string(),
+                            port: self.connection.remote_address().port(),
+                        },
+                    );
                 }
+                _ => {}
             }
         }
         Ok(())
     }
 
-    async fn read_message(
-        recv: &mut quinn::RecvStream,
-    ) -> Result<MessageType, crate::GenericError> {
-        let mut len_buf = [0u8; 4];
-        recv.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf) as usize;
-
-        let mut data = vec![0u8; len];
-        recv.read_exact(&mut data).await?;
-
-        let msg = bincode::deserialize(&data)?;
-        Ok(msg)
+    async fn read_message(recv: &mut quinn::RecvStream) -> Result<MessageType, ProxiError> {
+        crate::transfer::protocol::read_frame(recv).await
     }
 
     async fn write_message(
         send: &mut quinn::SendStream,
         msg: &MessageType,
-    ) -> Result<(), crate::GenericError> {
-        let data = bincode::serialize(msg)?;
-        let len = data.len() as u32;
-        send.write_all(&len.to_be_bytes()).await?;
-        send.write_all(&data).await?;
-        Ok(())
+    ) -> Result<(), ProxiError> {
+        crate::transfer::protocol::write_frame(send, msg).await
+    }
+}
+
+/// Bytes already on disk from an earlier attempt at this transfer, in
+/// whole chunks, and the merkle root of their chunk hashes. The hashes
+/// recorded as the chunks arrived are used when the new offer has the
+/// same merkle root as the earlier one, so the partial file isn't read
+/// again; any inconsistency means starting over.
+pub(crate) async fn resume_point(
+    db: &crate::db::Database,
+    transfer_id: &str,
+    path: &Path,
+    metadata: &FileMetadata,
+) -> Option<(u64, String)> {
+    let chunk_size = metadata.chunk_size as u64;
+    let offered_root = metadata.merkle_root.as_deref()?;
+    if chunk_size == 0 {
+        return None;
+    }
+    let actual_len = std::fs::metadata(path).map(|m| m.len()).ok()?;
+
+    let (recorded, stored_root) = {
+        let chunks = db.get_chunk_hashes(transfer_id).await.ok()?;
+        let stored_root = db.get_merkle_root(transfer_id).await.ok()?;
+        let recorded: Vec<String> = chunks
+            .into_iter()
+            .enumerate()
+            .take_while(|(i, (index, _))| *i as u64 == *index)
+            .map_while(|(_, (_, hash))| hash)
+            .collect();
+
+        // The partial file must hold at least the bytes the records claim
+        let expected_len = (recorded.len() as u64)
+            .saturating_mul(chunk_size)
+            .min(metadata.size);
+        if actual_len < expected_len {
+            println!(
+                "[Transfer] Partial file for {} is {} bytes, expected at least {}; restarting",
+                transfer_id, actual_len, expected_len
+            );
+            let _ = db.clear_chunks(transfer_id).await;
+            return None;
+        }
+        (recorded, stored_root)
+    };
+
+    let leaves = if recorded.is_empty() {
+        // Without chunk records (e.g. a new transfer id for the same file),
+        // offer the whole chunks of whatever is on disk; the sender's
+        // root check keeps unrelated content from being trusted
+        let whole_chunks = actual_len.min(metadata.size) / chunk_size;
+        if whole_chunks == 0 {
+            return None;
+        }
+        let mut partial = match File::open(path).await {
+            Ok(file) => file.take(whole_chunks * chunk_size),
+            Err(e) => {
+                println!(
+                    "[Transfer] Could not open partial file for {}: {}",
+                    transfer_id, e
+                );
+                return None;
+            }
+        };
+        match merkle::hash_chunks(&mut partial, chunk_size as usize, metadata.hash_algo).await {
+            Ok((_, leaves)) => leaves,
+            Err(e) => {
+                println!(
+                    "[Transfer] Could not hash partial file for {}: {}",
+                    transfer_id, e
+                );
+                return None;
+            }
+        }
+    } else if stored_root.as_deref() == Some(offered_root) {
+        // A final short chunk would misalign the offset; leave it to be resent
+        let whole = (recorded.len() as u64).min(metadata.size / chunk_size) as usize;
+        let mut recorded = recorded;
+        recorded.truncate(whole);
+        recorded
+    } else {
+        println!(
+            "[Transfer] {} was offered with a different merkle root; restarting",
+            transfer_id
+        );
+        let _ = db.clear_chunks(transfer_id).await;
+        return None;
+    };
+    if leaves.is_empty() {
+        return None;
+    }
+
+    let offset = leaves.len() as u64 * chunk_size;
+    match merkle::root(metadata.hash_algo, &leaves) {
+        Ok(prefix_root) => Some((offset, prefix_root)),
+        Err(e) => {
+            println!(
+                "[Transfer] Could not build merkle root for {}: {}",
+                transfer_id, e
+            );
+            None
+        }
+    }
+}
+
+/// Best-effort: give the received file the sender's modification time so
+/// sync's last-writer-wins comparison sees the original timestamp
+fn preserve_mtime(path: &Path, modified: u64) {
+    let mtime = filetime::FileTime::from_unix_time(modified as i64, 0);
+    if let Err(e) = filetime::set_file_mtime(path, mtime) {
+        println!("[Transfer] Could not preserve modification time: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_need_the_id_the_peer_said_hello_as() {
+        let mut session = Session::default();
+        assert!(verified_peer(&session, None, "SyncRequest").is_err());
+        assert!(verified_peer(&session, Some("a1"), "FileOffer").is_err());
+
+        session.peer = Some(("a1".into(), "Laptop".into()));
+        assert_eq!(verified_peer(&session, None, "SyncRequest").unwrap(), "a1");
+        assert_eq!(
+            verified_peer(&session, Some("a1"), "FileOffer").unwrap(),
+            "a1"
+        );
+        assert!(verified_peer(&session, Some("b2"), "PairRequest").is_err());
     }
 }
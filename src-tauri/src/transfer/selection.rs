@@ -0,0 +1,265 @@
+use crate::error::ProxiError;
+use glob::{MatchOptions, Pattern};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Files the walk of a streamed directory send may get ahead of its sends
+pub const WALK_BUFFER: usize = 256;
+/// Most files announced in one `BatchOffer` of a streamed directory send
+pub const MANIFEST_CHUNK: usize = 64;
+/// Largest files `analyze_folder` reports
+const LARGEST_FILES: usize = 5;
+/// Least time between two progress reports of `analyze_folder`
+const ANALYSIS_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Include/exclude globs for picking files out of a directory. A pattern
+/// without a `/` (`*.jpg`) matches a name at any depth; one with a `/`
+/// (`raw/**/*.cr2`) matches the path relative to the directory. An excluded
+/// directory isn't walked at all.
+pub struct Selection {
+    include: Vec<(Pattern, bool)>,
+    exclude: Vec<(Pattern, bool)>,
+}
+
+impl Selection {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, ProxiError> {
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// No include patterns selects everything not excluded
+    fn includes(&self, relative: &str) -> bool {
+        self.include.is_empty() || matches_any(&self.include, relative)
+    }
+
+    /// What a sync scan covers: everything but our own `.proxishare`
+    /// metadata at the top of the folder
+    pub fn synced() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: vec![(Pattern::new(".proxishare").expect("literal pattern"), true)],
+        }
+    }
+
+    fn excludes(&self, relative: &str) -> bool {
+        matches_any(&self.exclude, relative)
+    }
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<(Pattern, bool)>, ProxiError> {
+    patterns
+        .iter()
+        .map(|p| {
+            let pattern = p.trim().trim_start_matches("./");
+            Pattern::new(pattern)
+                .map(|compiled| (compiled, pattern.contains('/')))
+                .map_err(|e| ProxiError::Other(format!("Invalid pattern {:?}: {}", p, e)))
+        })
+        .collect()
+}
+
+fn matches_any(patterns: &[(Pattern, bool)], relative: &str) -> bool {
+    let name = relative.rsplit('/').next().unwrap_or(relative);
+    patterns.iter().any(|(pattern, anchored)| {
+        let subject = if *anchored { relative } else { name };
+        pattern.matches_with(subject, MATCH_OPTIONS)
+    })
+}
+
+/// Files of a directory that a selection picks
+#[derive(Serialize, Debug, Clone)]
+pub struct SelectedFiles {
+    pub root: String,
+    /// Relative to `root`, with `/` separators, sorted
+    pub files: Vec<String>,
+    pub file_count: usize,
+    pub total_size: u64,
+}
+
+impl SelectedFiles {
+    /// Absolute paths of the selected files
+    pub fn paths(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        let root = Path::new(&self.root);
+        self.files.iter().map(move |relative| root.join(relative))
+    }
+}
+
+/// Walk `dir` and collect the files `selection` picks. Symlinks aren't
+/// followed. An empty result is an error, so nothing is sent by accident.
+pub fn select_files(dir: &Path, selection: &Selection) -> Result<SelectedFiles, ProxiError> {
+    if !dir.is_dir() {
+        return Err(ProxiError::Other(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    let mut found = Vec::new();
+    walk(dir, dir, selection, &mut |relative, metadata| {
+        found.push((relative, metadata.len()));
+        true
+    })?;
+    if found.is_empty() {
+        return Err(ProxiError::Other(format!(
+            "No files in {} match the selection",
+            dir.display()
+        )));
+    }
+    found.sort();
+
+    let mut selected = SelectedFiles {
+        root: dir.to_string_lossy().to_string(),
+        files: Vec::with_capacity(found.len()),
+        file_count: found.len(),
+        total_size: 0,
+    };
+    for (relative, size) in found {
+        selected.files.push(relative);
+        selected.total_size += size;
+    }
+    Ok(selected)
+}
+
+/// A file among the largest `analyze_folder` found
+#[derive(Serialize, Debug, Clone)]
+pub struct LargeFile {
+    /// Relative to the analyzed folder, with `/` separators
+    pub relative_path: String,
+    pub size: u64,
+}
+
+/// Size of a folder's tree, as a sync or directory send would see it
+#[derive(Serialize, Debug, Clone)]
+pub struct FolderAnalysis {
+    pub root: String,
+    pub file_count: usize,
+    pub total_size: u64,
+    /// Largest first
+    pub largest: Vec<LargeFile>,
+}
+
+/// Count and size the files under `dir` without hashing them, walking it
+/// the way a sync scan does. `progress` gets the running file count and
+/// size every so often; setting `cancelled` stops the walk with
+/// `ProxiError::Cancelled`.
+pub fn analyze_folder(
+    dir: &Path,
+    cancelled: &AtomicBool,
+    progress: &mut dyn FnMut(usize, u64),
+) -> Result<FolderAnalysis, ProxiError> {
+    if !dir.is_dir() {
+        return Err(ProxiError::Other(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    let mut analysis = FolderAnalysis {
+        root: dir.to_string_lossy().to_string(),
+        file_count: 0,
+        total_size: 0,
+        largest: Vec::with_capacity(LARGEST_FILES + 1),
+    };
+    let mut last_report = Instant::now();
+    let finished = walk(dir, dir, &Selection::synced(), &mut |relative, metadata| {
+        if cancelled.load(Ordering::Relaxed) {
+            return false;
+        }
+        let size = metadata.len();
+        analysis.file_count += 1;
+        analysis.total_size += size;
+        if analysis.largest.len() < LARGEST_FILES
+            || analysis
+                .largest
+                .last()
+                .is_some_and(|smallest| size > smallest.size)
+        {
+            let at = analysis.largest.partition_point(|file| file.size >= size);
+            analysis.largest.insert(
+                at,
+                LargeFile {
+                    relative_path: relative,
+                    size,
+                },
+            );
+            analysis.largest.truncate(LARGEST_FILES);
+        }
+        if last_report.elapsed() >= ANALYSIS_PROGRESS_INTERVAL {
+            progress(analysis.file_count, analysis.total_size);
+            last_report = Instant::now();
+        }
+        true
+    })?;
+    if !finished {
+        return Err(ProxiError::Cancelled("Folder analysis cancelled".into()));
+    }
+    Ok(analysis)
+}
+
+/// Walk `dir` like `select_files`, handing each picked file with its size
+/// to `found` as it's discovered rather than collecting them, so memory
+/// stays bounded by the channel. Each directory's entries are visited in
+/// name order. Blocks while `found` is full; stops early, without error,
+/// once its receiver is dropped.
+pub fn stream_files(
+    dir: &Path,
+    selection: &Selection,
+    found: &mpsc::Sender<(PathBuf, u64)>,
+) -> Result<(), ProxiError> {
+    if !dir.is_dir() {
+        return Err(ProxiError::Other(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    walk(dir, dir, selection, &mut |relative, metadata| {
+        found
+            .blocking_send((dir.join(relative), metadata.len()))
+            .is_ok()
+    })?;
+    Ok(())
+}
+
+/// Calls `visit` with each picked file, relative to `root`, until it
+/// returns false; returns whether the walk ran to the end. Directory sends,
+/// sync scans and `analyze_folder` all walk through this.
+pub fn walk(
+    root: &Path,
+    dir: &Path,
+    selection: &Selection,
+    visit: &mut dyn FnMut(String, &std::fs::Metadata) -> bool,
+) -> std::io::Result<bool> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        if selection.excludes(&relative) {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if !walk(root, &path, selection, visit)? {
+                return Ok(false);
+            }
+        } else if file_type.is_file()
+            && selection.includes(&relative)
+            && !visit(relative, &entry.metadata()?)
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -1,33 +1,86 @@
-use crate::transfer::protocol::{FileMetadata, MessageType};
-use bincode;
+use crate::crypto::chunk_cipher::ChunkCipher;
+use crate::crypto::security;
+use crate::error::ProxiError;
+use crate::events::{emit_event, HistoryUpdated, TransferProgress};
+use crate::transfer::hash_pool::HashPool;
+use crate::transfer::merkle;
+use crate::transfer::protocol::{
+    next_chunk_index, FileMetadata, HashAlgo, MessageType, PeerCapabilities, TransferErrorCode,
+    TransferReceipt, FEATURE_CHUNK_ENCRYPTION,
+};
+use crate::transfer::{report_chunk, until_cancelled};
 use quinn::{Connection, RecvStream, SendStream};
 use serde::Serialize;
-use std::path::PathBuf;
-use tauri::{Emitter, Manager};
+use std::future::Future;
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Manager;
 use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, BufReader};
 
 /// Maximum chunk size (4MB) - used for large files
 const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
 /// Minimum chunk size (64KB) - used for small files or slow connections
 const MIN_CHUNK_SIZE: usize = 64 * 1024;
 /// Default chunk size (1MB) - balanced for most scenarios
-const DEFAULT_CHUNK_SIZE: usize = 1 * 1024 * 1024;
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+/// Default disk read size when sending a file (1MB). Chunks are cut from
+/// this buffer, so small chunks don't mean small reads: each tokio file
+/// read is a round trip to a blocking thread.
+pub const DEFAULT_READ_BUFFER: usize = 1024 * 1024;
+/// Largest configurable read buffer (16MB)
+const MAX_READ_BUFFER: usize = 16 * 1024 * 1024;
 
-#[derive(Clone, Serialize)]
-pub struct TransferProgress {
-    pub transfer_id: String,
-    pub file_name: String,
+/// Outcome of a network-only throughput test
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchmarkResult {
     pub bytes_sent: u64,
-    pub total_bytes: u64,
-    pub direction: String,
-    pub status: String,
+    pub duration_ms: f64,
+    /// Megabits per second
+    pub throughput_mbps: f64,
+    pub rtt_ms: f64,
+}
+
+/// Hash of a file's contents (hex)
+pub async fn hash_file(path: &Path, algo: HashAlgo) -> Result<String, ProxiError> {
+    let mut file = File::open(path).await?;
+    let mut hasher = algo.hasher()?;
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+/// Read until `buf` is full or EOF. A plain `read` may return short, which
+/// would misalign chunk indices from file offsets and break resuming.
+pub(crate) async fn read_chunk<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
 }
 
 /// Calculate optimal chunk size based on file size
 /// Smaller files use smaller chunks to reduce overhead
 /// Larger files use larger chunks for efficiency
-fn calculate_chunk_size(file_size: u64) -> usize {
+pub(crate) fn calculate_chunk_size(file_size: u64) -> usize {
     if file_size < 1024 * 1024 {
         // Files < 1MB: use 64KB chunks
         MIN_CHUNK_SIZE
@@ -40,11 +93,149 @@ fn calculate_chunk_size(file_size: u64) -> usize {
     }
 }
 
+/// Keep a user's chunk size cap within the sizes `calculate_chunk_size` picks from
+pub(crate) fn clamp_max_chunk_size(size: usize) -> usize {
+    size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+}
+
+/// Keep a read buffer setting between the smallest chunk and 16MB
+pub(crate) fn clamp_read_buffer(size: usize) -> usize {
+    size.clamp(MIN_CHUNK_SIZE, MAX_READ_BUFFER)
+}
+
+/// `calculate_chunk_size`, capped by the `max_chunk_size` setting
+pub(crate) async fn chunk_size_for(app_handle: &tauri::AppHandle, file_size: u64) -> usize {
+    let max = {
+        let app_state = app_handle.state::<crate::AppState>();
+        let settings = app_state.settings.read().await;
+        settings.settings.max_chunk_size
+    };
+    let size = calculate_chunk_size(file_size);
+    max.map_or(size, |max| size.min(clamp_max_chunk_size(max)))
+}
+
+/// Chunks the sender can skip because the receiver already has them: the
+/// receiver's `resume_offset` must fall on a chunk boundary within the file
+/// and its `prefix_hash` must be the merkle root of our first chunks
+pub(crate) fn resumed_chunks(
+    resume_offset: u64,
+    prefix_hash: Option<&str>,
+    chunk_hashes: &[String],
+    chunk_size: usize,
+    file_size: u64,
+    hash_algo: HashAlgo,
+) -> Result<Option<u64>, ProxiError> {
+    let chunks = resume_offset / chunk_size as u64;
+    if !resume_offset.is_multiple_of(chunk_size as u64)
+        || resume_offset > file_size
+        || chunks as usize > chunk_hashes.len()
+    {
+        return Ok(None);
+    }
+    let root = merkle::root(hash_algo, &chunk_hashes[..chunks as usize])?;
+    Ok((prefix_hash == Some(root.as_str())).then_some(chunks))
+}
+
+/// Something a transfer can read its data from: a file, an in-memory
+/// payload or any other reader of known length
+pub trait SendSource: AsyncRead + Unpin + Send {
+    /// Skip bytes the receiver already has when resuming. The default reads
+    /// and discards them; seekable sources override it.
+    fn skip(&mut self, bytes: u64) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move {
+            tokio::io::copy(&mut (&mut *self).take(bytes), &mut tokio::io::sink()).await?;
+            Ok(())
+        }
+    }
+
+    /// Go back to the first byte, after the source was hashed for the offer
+    fn rewind(&mut self) -> impl Future<Output = std::io::Result<()>> + Send;
+}
+
+impl SendSource for File {
+    async fn skip(&mut self, bytes: u64) -> std::io::Result<()> {
+        self.seek(SeekFrom::Current(bytes as i64)).await?;
+        Ok(())
+    }
+
+    async fn rewind(&mut self) -> std::io::Result<()> {
+        self.seek(SeekFrom::Start(0)).await?;
+        Ok(())
+    }
+}
+
+/// A file read through a buffer larger than the chunk size. Seeking
+/// discards the buffer, and `SeekFrom::Current` accounts for what's left
+/// in it, so resume offsets stay exact.
+impl SendSource for BufReader<File> {
+    async fn skip(&mut self, bytes: u64) -> std::io::Result<()> {
+        self.seek(SeekFrom::Current(bytes as i64)).await?;
+        Ok(())
+    }
+
+    async fn rewind(&mut self) -> std::io::Result<()> {
+        self.seek(SeekFrom::Start(0)).await?;
+        Ok(())
+    }
+}
+
+impl SendSource for Cursor<Arc<[u8]>> {
+    async fn skip(&mut self, bytes: u64) -> std::io::Result<()> {
+        self.seek(SeekFrom::Current(bytes as i64)).await?;
+        Ok(())
+    }
+
+    async fn rewind(&mut self) -> std::io::Result<()> {
+        self.set_position(0);
+        Ok(())
+    }
+}
+
+/// How the receiver answered an offer
+struct OfferResponse {
+    /// Offset the receiver proposed resuming from, verified by `prefix_hash`
+    resume_offset: u64,
+    prefix_hash: Option<String>,
+    /// Nonce of the passphrase challenge answered on the way, if any, and
+    /// the key it was answered with
+    challenge: Option<(Vec<u8>, String)>,
+}
+
+/// What the offer for a `SendSource` says about its data
+pub struct SourceInfo {
+    pub name: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub modified: Option<u64>,
+    pub hash: String,
+    pub hash_algo: HashAlgo,
+    pub chunk_size: usize,
+    /// Hash of each `chunk_size` chunk, the leaves of the offer's merkle root
+    pub chunk_hashes: Vec<String>,
+}
+
+/// Data a send transfers
+#[derive(Clone)]
+pub enum SendPayload {
+    File(PathBuf),
+    /// A file on disk, offered under `name` instead of its own name
+    RenamedFile {
+        path: PathBuf,
+        name: String,
+    },
+    /// Generated or pasted data, offered under `name`
+    Bytes {
+        name: String,
+        data: Arc<[u8]>,
+    },
+}
+
 pub struct FileSender {
     connection: Connection,
     app_handle: tauri::AppHandle,
     device_id: String,
     device_name: String,
+    peer_capabilities: PeerCapabilities,
 }
 
 impl FileSender {
@@ -53,48 +244,214 @@ impl FileSender {
         app_handle: tauri::AppHandle,
         device_id: String,
         device_name: String,
+        peer_capabilities: PeerCapabilities,
     ) -> Self {
         Self {
             connection,
             app_handle,
             device_id,
             device_name,
+            peer_capabilities,
         }
     }
 
-    pub async fn calculate_hash(&self, path: &PathBuf) -> Result<String, crate::GenericError> {
-        let mut file = File::open(path).await?;
-        let mut hasher = blake3::Hasher::new();
-        let mut buffer = vec![0u8; 64 * 1024];
+    pub async fn calculate_hash(&self, path: &Path, algo: HashAlgo) -> Result<String, ProxiError> {
+        hash_file(path, algo).await
+    }
 
-        loop {
-            let n = file.read(&mut buffer).await?;
-            if n == 0 {
-                break;
-            }
-            hasher.update(&buffer[..n]);
+    /// Hash algorithm to offer, from the settings
+    async fn preferred_hash_algo(&self) -> HashAlgo {
+        let app_state = self.app_handle.state::<crate::AppState>();
+        let algo = app_state.settings.read().await.settings.hash_algo;
+        if algo.is_supported() {
+            algo
+        } else {
+            HashAlgo::default()
         }
+    }
 
-        Ok(hasher.finalize().to_hex().to_string())
+    pub async fn send_payload(
+        &self,
+        transfer_id: String,
+        payload: SendPayload,
+        transfers: crate::TransferRegistry,
+    ) -> Result<(), ProxiError> {
+        match payload {
+            SendPayload::File(path) => self.send_file(transfer_id, path, None, transfers).await,
+            SendPayload::RenamedFile { path, name } => {
+                self.send_file(transfer_id, path, Some(name), transfers)
+                    .await
+            }
+            SendPayload::Bytes { name, data } => {
+                self.send_bytes(transfer_id, name, data, transfers).await
+            }
+        }
     }
 
+    /// Send the file at `path`, offered as `name` if given
     pub async fn send_file(
         &self,
         transfer_id: String,
         path: PathBuf,
+        name: Option<String>,
         transfers: crate::TransferRegistry,
-    ) -> Result<(), crate::GenericError> {
+    ) -> Result<(), ProxiError> {
+        let file = File::open(&path).await?;
+        let metadata = file.metadata().await?;
+        let hash_algo = self.preferred_hash_algo().await;
+        let chunk_size = chunk_size_for(&self.app_handle, metadata.len()).await;
+        let read_buffer = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let settings = app_state.settings.read().await;
+            settings.settings.send_read_buffer()
+        };
+        let mut source = BufReader::with_capacity(read_buffer, file);
+        let (hash, chunk_hashes) = merkle::hash_chunks(&mut source, chunk_size, hash_algo).await?;
+        SendSource::rewind(&mut source).await?;
+        let info = SourceInfo {
+            name: name.unwrap_or_else(|| path.file_name().unwrap().to_string_lossy().to_string()),
+            size: metadata.len(),
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            hash,
+            hash_algo,
+            chunk_size,
+            chunk_hashes,
+        };
+        self.send_from(transfer_id, source, info, transfers).await
+    }
+
+    /// Send an in-memory payload as a file called `name`
+    pub async fn send_bytes(
+        &self,
+        transfer_id: String,
+        name: String,
+        data: Arc<[u8]>,
+        transfers: crate::TransferRegistry,
+    ) -> Result<(), ProxiError> {
+        let hash_algo = self.preferred_hash_algo().await;
+        let chunk_size = chunk_size_for(&self.app_handle, data.len() as u64).await;
+        let info = SourceInfo {
+            name,
+            size: data.len() as u64,
+            modified: None,
+            hash: hash_algo.hash(&data)?,
+            hash_algo,
+            chunk_size,
+            chunk_hashes: data
+                .chunks(chunk_size)
+                .map(|chunk| hash_algo.hash(chunk))
+                .collect::<Result<_, _>>()?,
+        };
+        self.send_from(transfer_id, Cursor::new(data), info, transfers)
+            .await
+    }
+
+    /// Offer `source` to the receiver and stream it in chunks, honouring
+    /// pause, cancel, resume and rate limits
+    pub async fn send_from<S: SendSource>(
+        &self,
+        transfer_id: String,
+        mut source: S,
+        info: SourceInfo,
+        transfers: crate::TransferRegistry,
+    ) -> Result<(), ProxiError> {
         // Open a single bidirectional stream for the entire transfer
-        let (mut send_stream, mut recv_stream) = self.connection.open_bi().await?;
+        let (mut send_stream, mut recv_stream) = until_cancelled(
+            &transfers,
+            &transfer_id,
+            crate::transfer::protocol::open_stream(&self.connection),
+        )
+        .await?;
 
-        let mut file = File::open(&path).await?;
-        let metadata = file.metadata().await?;
-        let file_size = metadata.len();
-        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
-        let file_hash = self.calculate_hash(&path).await?;
+        let hello = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            MessageType::hello(&app_state.identity, &self.device_name, &self.connection)?
+        };
+        Self::write_message(&mut send_stream, &hello).await?;
+
+        let SourceInfo {
+            name: file_name,
+            size: file_size,
+            modified,
+            hash: file_hash,
+            hash_algo,
+            chunk_size,
+            chunk_hashes,
+        } = info;
+        let file_name = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let settings = app_state.settings.read().await;
+            settings.settings.offer_name(&file_name)
+        };
+        let (batch_id, peer_id) = transfers
+            .read()
+            .await
+            .get(&transfer_id)
+            .map(|entry| (entry.batch_id.clone(), entry.device_id.clone()))
+            .unwrap_or_default();
+        let rate_limiter = self
+            .app_handle
+            .state::<crate::AppState>()
+            .rate_limiter
+            .clone();
+        let (stall_timeout, resumable, encrypt_chunks, passphrase_key) = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let settings = app_state.settings.read().await;
+            (
+                settings.settings.stall_timeout(),
+                !settings.settings.resume_disabled,
+                settings.settings.encrypt_chunks,
+                settings.settings.passphrase_key.clone(),
+            )
+        };
+        // Never fall back to sending unencrypted once the user asked for it
+        let encrypted = match (encrypt_chunks, &passphrase_key) {
+            (false, _) => false,
+            (true, None) => {
+                return Err(ProxiError::Crypto(
+                    "Chunk encryption needs a shared passphrase".into(),
+                ))
+            }
+            (true, Some(_)) if !self.peer_capabilities.supports(FEATURE_CHUNK_ENCRYPTION) => {
+                return Err(ProxiError::Crypto(
+                    "Receiver doesn't support chunk encryption".into(),
+                ))
+            }
+            (true, Some(_)) => true,
+        };
+
+        // Every transfer starts with a 0% event, even tiny ones that finish in one chunk
+        emit_event(
+            &self.app_handle,
+            TransferProgress {
+                transfer_id: transfer_id.clone(),
+                file_name: file_name.clone(),
+                bytes_sent: 0,
+                total_bytes: file_size,
+                direction: "send".to_string(),
+                batch_id: batch_id.clone(),
+                status: "in_progress".to_string(),
+                chunk_size: chunk_size as u32,
+            },
+        );
 
-        // Calculate optimal chunk size based on file size
-        let chunk_size = calculate_chunk_size(file_size);
+        // Keep the offered hash so the sent file can be verified later
+        {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            let db_lock = app_state.database.read().await;
+            if let Some(db) = &*db_lock {
+                if let Err(e) = db
+                    .set_transfer_hash(&transfer_id, &file_hash, hash_algo)
+                    .await
+                {
+                    println!("[Database] Failed to store file hash: {:?}", e);
+                }
+            }
+        }
 
         // 1. Send File Offer
         let offer = MessageType::FileOffer {
@@ -102,31 +459,94 @@ impl FileSender {
             metadata: FileMetadata {
                 name: file_name.clone(),
                 size: file_size,
-                hash: file_hash,
+                hash: file_hash.clone(),
+                hash_algo,
                 chunk_size: chunk_size as u32,
+                modified,
+                merkle_root: Some(merkle::root(hash_algo, &chunk_hashes)?),
             },
             sender_id: self.device_id.clone(),
             sender_name: self.device_name.clone(),
+            benchmark: false,
+            resumable,
+            encrypted,
         };
         Self::write_message(&mut send_stream, &offer).await?;
 
-        // 2. Send Chunks
-        let mut buffer = vec![0u8; chunk_size];
-        let mut chunk_index = 0;
+        // 2. Wait for the receiver to accept the offer or ask to resume
+        let OfferResponse {
+            resume_offset,
+            prefix_hash,
+            challenge,
+        } = until_cancelled(
+            &transfers,
+            &transfer_id,
+            self.await_offer_response(&transfer_id, &mut send_stream, &mut recv_stream),
+        )
+        .await?;
+        let cipher = match (encrypted, challenge) {
+            (false, _) => None,
+            (true, Some((challenge, key))) => Some(
+                ChunkCipher::derive(&key, &transfer_id, &challenge)
+                    .ok_or_else(|| ProxiError::Crypto("Invalid shared passphrase key".into()))?,
+            ),
+            // The receiver accepted without challenging, so it has no passphrase to derive from
+            (true, None) => {
+                return Err(ProxiError::Crypto(
+                    "Receiver accepted an encrypted offer without a shared passphrase".into(),
+                ))
+            }
+        };
+
+        // 3. Send Chunks
+        // All buffers live for the whole transfer: two chunk buffers take
+        // turns being moved into a ChunkData message and being read and hashed
+        // into while that message is written, and frames are serialized into
+        // one reused Vec. Per chunk this drops the two heap allocations
+        // (`to_vec` and bincode's output) and one full copy of the chunk bytes
+        // the naive path made, and hashing overlaps the network write.
+        let mut spare = vec![0u8; chunk_size];
+        let mut frame = Vec::with_capacity(chunk_size + 256);
+        let hash_pool = self.app_handle.state::<crate::AppState>().hash_pool.clone();
+        let mut chunk_index: u64 = 0;
         let mut total_sent: u64 = 0;
 
+        // Only trust the receiver's partial file if the merkle root of its
+        // chunks matches the root of our first chunks, so neither side reads
+        // the already-transferred data again
+        if resume_offset > 0 && resumable {
+            let resumed = resumed_chunks(
+                resume_offset,
+                prefix_hash.as_deref(),
+                &chunk_hashes,
+                chunk_size,
+                file_size,
+                hash_algo,
+            )?;
+            if let Some(resumed_chunks) = resumed {
+                source.skip(resume_offset).await?;
+                chunk_index = resumed_chunks;
+                total_sent = resume_offset;
+                println!(
+                    "[Transfer] Resuming {} from chunk {} (offset {})",
+                    transfer_id, chunk_index, resume_offset
+                );
+            } else {
+                println!(
+                    "[Transfer] Receiver's partial file for {} doesn't match; sending from the start",
+                    transfer_id
+                );
+            }
+        }
+
+        let mut next =
+            Self::prepare_chunk(&mut source, vec![0u8; chunk_size], hash_algo, &hash_pool).await?;
         let mut last_status = crate::TransferStatus::InProgress;
 
         loop {
             // Check status for pause/cancel
             {
-                let mut status = {
-                    let registry = transfers.read().await;
-                    registry
-                        .get(&transfer_id)
-                        .cloned()
-                        .unwrap_or(crate::TransferStatus::InProgress)
-                };
+                let mut status = crate::get_transfer_status(&transfers, &transfer_id).await;
 
                 // Notify receiver if status changed
                 if status != last_status {
@@ -141,7 +561,7 @@ impl FileSender {
                             )
                             .await;
                             println!("[Transfer] Transfer {} cancelled by sender", transfer_id);
-                            return Err("Transfer cancelled by user".into());
+                            return Err(ProxiError::Cancelled("Transfer cancelled by user".into()));
                         }
                         crate::TransferStatus::Paused => {
                             println!("[Transfer] Sending TransferPause to receiver...");
@@ -172,11 +592,7 @@ impl FileSender {
 
                 while status == crate::TransferStatus::Paused {
                     tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-                    let registry = transfers.read().await;
-                    status = registry
-                        .get(&transfer_id)
-                        .cloned()
-                        .unwrap_or(crate::TransferStatus::InProgress);
+                    status = crate::get_transfer_status(&transfers, &transfer_id).await;
 
                     if status == crate::TransferStatus::Cancelled {
                         println!("[Transfer] Sending TransferCancel to receiver while paused...");
@@ -187,7 +603,7 @@ impl FileSender {
                             },
                         )
                         .await;
-                        return Err("Transfer cancelled by user".into());
+                        return Err(ProxiError::Cancelled("Transfer cancelled by user".into()));
                     }
 
                     if status == crate::TransferStatus::InProgress {
@@ -204,44 +620,96 @@ impl FileSender {
                 }
             }
 
-            let n = file.read(&mut buffer).await?;
-            if n == 0 {
+            let Some((data, chunk_hash)) = next.take() else {
+                // Empty files and files that are an exact multiple of the
+                // chunk size both end here, on a read at EOF.
                 // Mark as completed in registry
-                let mut registry = transfers.write().await;
-                registry.insert(transfer_id.clone(), crate::TransferStatus::Completed);
+                crate::set_transfer_status(
+                    &transfers,
+                    &transfer_id,
+                    crate::TransferStatus::Completed,
+                )
+                .await;
                 break;
+            };
+            let n = data.len();
+            let mut data = data;
+            if let Some(cipher) = &cipher {
+                cipher.seal(chunk_index, &mut data)?;
             }
 
-            let chunk_data = &buffer[..n];
-            let chunk_hash = blake3::hash(chunk_data).to_hex().to_string();
+            rate_limiter.acquire(&peer_id, n as u64).await;
 
             let chunk_msg = MessageType::ChunkData {
                 transfer_id: transfer_id.clone(),
                 chunk_index,
-                data: chunk_data.to_vec(),
+                data,
                 chunk_hash,
             };
 
-            Self::write_message(&mut send_stream, &chunk_msg).await?;
+            // Read and hash the following chunk while this one is written.
+            // A write that can't finish means the receiver stopped reading.
+            let step = async {
+                tokio::join!(
+                    Self::write_framed(&mut send_stream, &chunk_msg, &mut frame),
+                    Self::prepare_chunk(
+                        &mut source,
+                        std::mem::take(&mut spare),
+                        hash_algo,
+                        &hash_pool
+                    ),
+                )
+            };
+            let (written, prepared) = match stall_timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, step).await {
+                    Ok(result) => result,
+                    Err(_) => return Err(self.stalled(&transfer_id, &transfers, timeout).await),
+                },
+                None => step.await,
+            };
+            // Take the buffer and hash back out of the message; neither is copied
+            let mut sent_hash = String::new();
+            if let MessageType::ChunkData {
+                data, chunk_hash, ..
+            } = chunk_msg
+            {
+                spare = data;
+                // Capacity is kept, so this doesn't reallocate
+                spare.resize(chunk_size, 0);
+                sent_hash = chunk_hash;
+            }
+            written?;
+            next = prepared?;
+            report_chunk(
+                &self.app_handle,
+                &transfer_id,
+                "send",
+                chunk_index,
+                n,
+                &sent_hash,
+                None,
+            );
 
             total_sent += n as u64;
-            chunk_index += 1;
+            chunk_index = next_chunk_index(chunk_index)?;
 
             // Emit progress event
-            let _ = self.app_handle.emit(
-                "transfer-progress",
+            emit_event(
+                &self.app_handle,
                 TransferProgress {
                     transfer_id: transfer_id.clone(),
                     file_name: file_name.clone(),
                     bytes_sent: total_sent,
                     total_bytes: file_size,
                     direction: "send".to_string(),
+                    batch_id: batch_id.clone(),
                     status: "in_progress".to_string(),
+                    chunk_size: chunk_size as u32,
                 },
             );
         }
 
-        // 3. Send Completion
+        // 4. Send Completion
         Self::write_message(
             &mut send_stream,
             &MessageType::TransferComplete {
@@ -250,10 +718,10 @@ impl FileSender {
         )
         .await?;
 
-        // 4. Signal that we're done sending data (but keep stream open for reading ACK)
+        // 5. Signal that we're done sending data (but keep stream open for reading ACK)
         send_stream.finish()?;
 
-        // 5. Wait for acknowledgment or history sync from receiver
+        // 6. Wait for acknowledgment from receiver
         let mut completion_received = false;
         while !completion_received {
             match tokio::time::timeout(
@@ -264,7 +732,15 @@ impl FileSender {
             {
                 Ok(Ok(MessageType::TransferCompleteAck {
                     transfer_id: ack_id,
+                    receipt,
                 })) if ack_id == transfer_id => {
+                    match receipt {
+                        Some(receipt) => {
+                            self.store_receipt(&transfer_id, &file_hash, hash_algo, receipt)
+                                .await?
+                        }
+                        None => println!("[Transfer] Legacy ack for {}, no receipt", transfer_id),
+                    }
                     completion_received = true;
                 }
                 Ok(Ok(MessageType::HistorySync { records })) => {
@@ -272,85 +748,488 @@ impl FileSender {
                         "[Transfer] Received HistorySync ({} records) during completion",
                         records.len()
                     );
-                    let app_state = self.app_handle.state::<crate::AppState>();
-                    let db_lock = app_state.database.read().await;
-                    if let Some(db) = &*db_lock {
-                        for record in records {
-                            // Update local database with synced records
-                            let _ = db
-                                .record_transfer(
-                                    &record.id,
-                                    &record.device_id,
-                                    &record.file_name,
-                                    &record.file_path,
-                                    record.total_size,
-                                    &record.direction,
-                                    &record.file_hash,
-                                )
-                                .await
-                                .map_err(|e| println!("[Database] Record error: {:?}", e));
-                            let _ = db
-                                .update_transfer_status(
-                                    &record.id,
-                                    &record.status,
-                                    record.bytes_transferred,
-                                )
-                                .await
-                                .map_err(|e| println!("[Database] Status error: {:?}", e));
-                        }
-                    }
-                    // Notify frontend that history changed
-                    let _ = self.app_handle.emit("history-updated", ());
+                    // Receivers before protocol 6 push history before the ack
+                    crate::transfer::history::merge_records(&self.app_handle, &records).await;
+                }
+                Ok(Ok(MessageType::TransferError { code, message, .. })) => {
+                    return Err(Self::receiver_aborted(code, message))
                 }
                 Ok(Ok(_)) => {
-                    return Err("Unexpected message while waiting for completion ack".into())
+                    return Err(ProxiError::Protocol(
+                        "Unexpected message while waiting for completion ack".into(),
+                    ))
+                }
+                Ok(Err(e)) => {
+                    return Err(ProxiError::Network(format!(
+                        "Failed to receive completion ack: {}",
+                        e
+                    )))
                 }
-                Ok(Err(e)) => return Err(format!("Failed to receive completion ack: {}", e).into()),
                 Err(_) => {
-                    return Err("Timeout waiting for transfer completion acknowledgment".into())
+                    return Err(ProxiError::Network(
+                        "Timeout waiting for transfer completion acknowledgment".into(),
+                    ))
                 }
             }
         }
 
         // Emit final progress as completed
-        let _ = self.app_handle.emit(
-            "transfer-progress",
+        emit_event(
+            &self.app_handle,
             TransferProgress {
                 transfer_id: transfer_id.clone(),
                 file_name: file_name.clone(),
                 bytes_sent: file_size,
                 total_bytes: file_size,
                 direction: "send".to_string(),
+                batch_id: batch_id.clone(),
                 status: "completed".to_string(),
+                chunk_size: chunk_size as u32,
             },
         );
-        let _ = self.app_handle.emit("history-updated", ());
+        emit_event(&self.app_handle, HistoryUpdated);
 
         // Give the receiver a moment to finish its side cleanly
         tokio::time::sleep(std::time::Duration::from_millis(200)).await;
         Ok(())
     }
 
-    async fn write_message(
+    /// Read the next chunk into `buffer` and hash it on the pool. `None` at EOF.
+    async fn prepare_chunk<S: SendSource>(
+        source: &mut S,
+        mut buffer: Vec<u8>,
+        hash_algo: HashAlgo,
+        hash_pool: &HashPool,
+    ) -> Result<Option<(Vec<u8>, String)>, ProxiError> {
+        let n = read_chunk(source, &mut buffer).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buffer.truncate(n);
+        Ok(Some(hash_pool.hash(hash_algo, buffer).await?))
+    }
+
+    /// Check a receipt against the hash we sent and keep it for auditing.
+    /// A hash mismatch fails the transfer; a bad signature or a receipt in
+    /// another algorithm (from older receivers, which didn't say) only skips
+    /// storing.
+    async fn store_receipt(
+        &self,
+        transfer_id: &str,
+        sent_hash: &str,
+        hash_algo: HashAlgo,
+        receipt: TransferReceipt,
+    ) -> Result<(), ProxiError> {
+        if receipt.file_hash != sent_hash {
+            return Err(format!(
+                "Receiver stored different bytes (hash {}, sent {})",
+                receipt.file_hash, sent_hash
+            )
+            .into());
+        }
+        if receipt.transfer_id != transfer_id || receipt.hash_algo != hash_algo || !receipt.verify()
+        {
+            println!(
+                "[Transfer] Ignoring receipt for {}: bad signature or hash algorithm",
+                transfer_id
+            );
+            return Ok(());
+        }
+
+        let app_state = self.app_handle.state::<crate::AppState>();
+        let db_lock = app_state.database.read().await;
+        if let Some(db) = &*db_lock {
+            let json = serde_json::to_string(&receipt)?;
+            if let Err(e) = db.set_transfer_receipt(transfer_id, &json).await {
+                println!("[Database] Failed to store receipt: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The error for a receiver that aborted with `TransferError`
+    fn receiver_aborted(code: TransferErrorCode, message: String) -> ProxiError {
+        match code {
+            TransferErrorCode::DiskUnavailable => ProxiError::DiskUnavailable(message),
+            code => format!("Receiver aborted ({:?}): {}", code, message).into(),
+        }
+    }
+
+    /// Fail a send whose receiver stopped taking data for `timeout`
+    async fn stalled(
+        &self,
+        transfer_id: &str,
+        transfers: &crate::TransferRegistry,
+        timeout: Duration,
+    ) -> ProxiError {
+        println!(
+            "[Transfer] {} made no progress for {}s, failing it",
+            transfer_id,
+            timeout.as_secs()
+        );
+        crate::set_transfer_status(transfers, transfer_id, crate::TransferStatus::Failed).await;
+        self.connection
+            .close(quinn::VarInt::from_u32(1), b"transfer stalled");
+        ProxiError::Stalled(timeout.as_secs())
+    }
+
+    /// The shared passphrase's key under a receiver's salt: our own when the
+    /// salt is ours, one derived before, or one derived now from the
+    /// passphrase entered since this app started
+    async fn passphrase_key_for(&self, salt: &str) -> Result<String, ProxiError> {
+        let app_state = self.app_handle.state::<crate::AppState>();
+        {
+            let settings = app_state.settings.read().await;
+            let settings = &settings.settings;
+            let Some(own_key) = &settings.passphrase_key else {
+                return Err("Receiver requires a shared passphrase, but none is set".into());
+            };
+            if salt.is_empty() {
+                return Err(ProxiError::Crypto(
+                    "Receiver's shared passphrase predates salted keys; set it there again".into(),
+                ));
+            }
+            if settings.passphrase_salt.as_deref() == Some(salt) {
+                return Ok(own_key.clone());
+            }
+            if let Some(key) = settings.peer_passphrase_keys.get(salt) {
+                return Ok(key.clone());
+            }
+        }
+
+        let passphrase = app_state.passphrase.read().await.clone().ok_or_else(|| {
+            ProxiError::Crypto(
+                "Enter the shared passphrase again to answer this receiver for the first time"
+                    .into(),
+            )
+        })?;
+        let salt_hex = salt.to_string();
+        let key = tokio::task::spawn_blocking(move || {
+            security::derive_passphrase_key(&passphrase, &salt_hex)
+        })
+        .await
+        .map_err(|e| ProxiError::Other(e.to_string()))??;
+        let mut settings = app_state.settings.write().await;
+        if let Err(e) = settings.update(|s| {
+            s.peer_passphrase_keys.insert(salt.to_string(), key.clone());
+        }) {
+            println!("[Security] Failed to store passphrase key: {}", e);
+        }
+        Ok(key)
+    }
+
+    /// Wait for the receiver to accept an offer. Answers a passphrase
+    /// challenge first if the receiver sends one.
+    async fn await_offer_response(
+        &self,
+        transfer_id: &str,
+        send_stream: &mut SendStream,
+        recv_stream: &mut RecvStream,
+    ) -> Result<OfferResponse, ProxiError> {
+        let mut challenge = None;
+        loop {
+            match tokio::time::timeout(
+                std::time::Duration::from_secs(30),
+                Self::read_message(recv_stream),
+            )
+            .await
+            {
+                Ok(Ok(MessageType::FileAccept {
+                    resume_offset,
+                    prefix_hash,
+                    ..
+                })) => {
+                    return Ok(OfferResponse {
+                        resume_offset,
+                        prefix_hash,
+                        challenge,
+                    })
+                }
+                Ok(Ok(MessageType::AuthChallenge { nonce, salt, .. })) => {
+                    let key = self.passphrase_key_for(&salt).await?;
+                    let mac =
+                        security::passphrase_mac(&key, &nonce, transfer_id).ok_or_else(|| {
+                            ProxiError::Crypto("Invalid shared passphrase key".into())
+                        })?;
+                    Self::write_message(
+                        send_stream,
+                        &MessageType::AuthResponse {
+                            transfer_id: transfer_id.to_string(),
+                            mac,
+                        },
+                    )
+                    .await?;
+                    challenge = Some((nonce, key));
+                }
+                Ok(Ok(MessageType::FileReject { reason, .. })) => {
+                    println!(
+                        "[Transfer] {} rejected by receiver: {}",
+                        transfer_id, reason
+                    );
+                    return Err(ProxiError::Rejected(reason));
+                }
+                Ok(Ok(MessageType::TransferError { code, message, .. })) => {
+                    return Err(Self::receiver_aborted(code, message))
+                }
+                Ok(Ok(_)) => {
+                    return Err(ProxiError::Protocol(
+                        "Unexpected response to file offer".into(),
+                    ))
+                }
+                Ok(Err(e)) => {
+                    return Err(ProxiError::Network(format!(
+                        "Failed to receive offer response: {}",
+                        e
+                    )))
+                }
+                Err(_) => {
+                    return Err(ProxiError::Network(
+                        "Timeout waiting for receiver to accept the offer".into(),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Send `size` bytes of synthetic data that the receiver discards, timing
+    /// the transfer to measure network throughput without any disk IO.
+    pub async fn send_benchmark(&self, size: u64) -> Result<BenchmarkResult, ProxiError> {
+        let (mut send_stream, mut recv_stream) =
+            crate::transfer::protocol::open_stream(&self.connection).await?;
+
+        let hello = {
+            let app_state = self.app_handle.state::<crate::AppState>();
+            MessageType::hello(&app_state.identity, &self.device_name, &self.connection)?
+        };
+        Self::write_message(&mut send_stream, &hello).await?;
+
+        let transfer_id = format!("benchmark-{}", uuid::Uuid::new_v4());
+        let chunk_size = chunk_size_for(&self.app_handle, size).await;
+        let offer = MessageType::FileOffer {
+            transfer_id: transfer_id.clone(),
+            metadata: FileMetadata {
+                name: "benchmark".to_string(),
+                size,
+                hash: String::new(),
+                hash_algo: HashAlgo::default(),
+                chunk_size: chunk_size as u32,
+                modified: None,
+                merkle_root: None,
+            },
+            sender_id: self.device_id.clone(),
+            sender_name: self.device_name.clone(),
+            benchmark: true,
+            resumable: false,
+            encrypted: false,
+        };
+        Self::write_message(&mut send_stream, &offer).await?;
+        self.await_offer_response(&transfer_id, &mut send_stream, &mut recv_stream)
+            .await?;
+
+        // Same framing path as real transfers so the numbers are comparable
+        let mut payload = vec![0u8; chunk_size];
+        let mut frame = Vec::with_capacity(chunk_size + 256);
+        let started = std::time::Instant::now();
+        let mut sent: u64 = 0;
+        let mut chunk_index: u64 = 0;
+        while sent < size {
+            let n = (size - sent).min(chunk_size as u64) as usize;
+            payload.truncate(n);
+            let chunk_msg = MessageType::ChunkData {
+                transfer_id: transfer_id.clone(),
+                chunk_index,
+                data: std::mem::take(&mut payload),
+                chunk_hash: String::new(),
+            };
+            let written = Self::write_framed(&mut send_stream, &chunk_msg, &mut frame).await;
+            if let MessageType::ChunkData { data, .. } = chunk_msg {
+                payload = data;
+            }
+            written?;
+            sent += n as u64;
+            chunk_index = next_chunk_index(chunk_index)?;
+        }
+
+        Self::write_message(
+            &mut send_stream,
+            &MessageType::TransferComplete {
+                transfer_id: transfer_id.clone(),
+            },
+        )
+        .await?;
+        send_stream.finish()?;
+
+        // The ack means everything arrived, so the timing covers delivery
+        match tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            Self::read_message(&mut recv_stream),
+        )
+        .await
+        {
+            Ok(Ok(MessageType::TransferCompleteAck { .. })) => {}
+            Ok(Ok(_)) => {
+                return Err(ProxiError::Protocol(
+                    "Unexpected message while waiting for benchmark ack".into(),
+                ))
+            }
+            Ok(Err(e)) => {
+                return Err(ProxiError::Network(format!(
+                    "Failed to receive benchmark ack: {}",
+                    e
+                )))
+            }
+            Err(_) => {
+                return Err(ProxiError::Network(
+                    "Timeout waiting for benchmark acknowledgment".into(),
+                ))
+            }
+        }
+
+        let elapsed = started.elapsed().as_secs_f64();
+        Ok(BenchmarkResult {
+            bytes_sent: sent,
+            duration_ms: elapsed * 1000.0,
+            throughput_mbps: if elapsed > 0.0 {
+                sent as f64 * 8.0 / 1_000_000.0 / elapsed
+            } else {
+                0.0
+            },
+            rtt_ms: self.connection.rtt().as_secs_f64() * 1000.0,
+        })
+    }
+
+    pub(crate) async fn write_message(
+        stream: &mut SendStream,
+        msg: &MessageType,
+    ) -> Result<(), ProxiError> {
+        crate::transfer::protocol::write_frame(stream, msg).await
+    }
+
+    /// Like `write_message`, but encodes into `frame` (reused across calls)
+    async fn write_framed(
         stream: &mut SendStream,
         msg: &MessageType,
-    ) -> Result<(), crate::GenericError> {
-        let data = bincode::serialize(msg)?;
-        let len = data.len() as u32;
-        stream.write_all(&len.to_be_bytes()).await?;
-        stream.write_all(&data).await?;
+        frame: &mut Vec<u8>,
+    ) -> Result<(), ProxiError> {
+        crate::transfer::protocol::encode_frame(msg, frame)?;
+        stream.write_all(frame).await?;
         Ok(())
     }
 
-    async fn read_message(stream: &mut RecvStream) -> Result<MessageType, crate::GenericError> {
-        let mut len_buf = [0u8; 4];
-        stream.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf) as usize;
+    pub(crate) async fn read_message(stream: &mut RecvStream) -> Result<MessageType, ProxiError> {
+        crate::transfer::protocol::read_frame(stream).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    const CHUNK: usize = 1024;
+
+    /// A reader that hands out at most a few bytes per `read`, like a file
+    /// or socket that returns short
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let mut small = [0u8; 7];
+            let mut small_buf = tokio::io::ReadBuf::new(&mut small[..buf.remaining().min(7)]);
+            let poll = Pin::new(&mut self.0).poll_read(cx, &mut small_buf);
+            buf.put_slice(small_buf.filled());
+            poll
+        }
+    }
+
+    /// Index and length of every chunk the send loop would produce from
+    /// `data`, cut the way `send` cuts them
+    async fn chunks_of(data: Vec<u8>) -> Vec<(u64, usize)> {
+        let hash_pool = HashPool::new(Some(1));
+        let mut source: Cursor<Arc<[u8]>> = Cursor::new(data.into());
+        let mut chunks = Vec::new();
+        let mut chunk_index = 0;
+        while let Some((chunk, _)) =
+            FileSender::prepare_chunk(&mut source, vec![0u8; CHUNK], HashAlgo::Blake3, &hash_pool)
+                .await
+                .unwrap()
+        {
+            chunks.push((chunk_index, chunk.len()));
+            chunk_index = next_chunk_index(chunk_index).unwrap();
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn an_empty_file_has_no_chunks() {
+        assert!(chunks_of(Vec::new()).await.is_empty());
 
-        let mut data = vec![0u8; len];
-        stream.read_exact(&mut data).await?;
+        let (whole, leaves) = merkle::hash_chunks(&mut &[][..], CHUNK, HashAlgo::Blake3)
+            .await
+            .unwrap();
+        assert!(leaves.is_empty());
+        assert_eq!(whole, HashAlgo::Blake3.hash(&[]).unwrap());
+    }
+
+    #[tokio::test]
+    async fn exact_multiples_end_without_an_empty_chunk() {
+        assert_eq!(chunks_of(vec![1; CHUNK]).await, vec![(0, CHUNK)]);
+        assert_eq!(
+            chunks_of(vec![1; 3 * CHUNK]).await,
+            vec![(0, CHUNK), (1, CHUNK), (2, CHUNK)]
+        );
 
-        let msg = bincode::deserialize(&data)?;
-        Ok(msg)
+        let data = vec![1; 3 * CHUNK];
+        let (_, leaves) = merkle::hash_chunks(&mut &data[..], CHUNK, HashAlgo::Blake3)
+            .await
+            .unwrap();
+        assert_eq!(leaves.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_partial_last_chunk_is_sent_short() {
+        assert_eq!(chunks_of(vec![1; 1]).await, vec![(0, 1)]);
+        assert_eq!(
+            chunks_of(vec![1; 2 * CHUNK + 1]).await,
+            vec![(0, CHUNK), (1, CHUNK), (2, 1)]
+        );
+        assert_eq!(
+            chunks_of(vec![1; 2 * CHUNK - 1]).await,
+            vec![(0, CHUNK), (1, CHUNK - 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn short_reads_still_fill_whole_chunks() {
+        let data: Vec<u8> = (0..2 * CHUNK + 5).map(|i| i as u8).collect();
+        let mut reader = Trickle(Cursor::new(data.clone()));
+        let mut buffer = vec![0u8; CHUNK];
+        let mut read = Vec::new();
+        loop {
+            let n = read_chunk(&mut reader, &mut buffer).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            read.push(n);
+        }
+        assert_eq!(read, vec![CHUNK, CHUNK, 5]);
+
+        // Chunk hashes match whether the reader returns short or not
+        let (_, trickled) = merkle::hash_chunks(
+            &mut Trickle(Cursor::new(data.clone())),
+            CHUNK,
+            HashAlgo::Blake3,
+        )
+        .await
+        .unwrap();
+        let (_, whole) = merkle::hash_chunks(&mut &data[..], CHUNK, HashAlgo::Blake3)
+            .await
+            .unwrap();
+        assert_eq!(trickled, whole);
     }
 }
@@ -0,0 +1,67 @@
+//! Loopback QUIC connections for the transfer tests
+
+use crate::crypto::encryption::CertificateManager;
+use crate::transfer::{protocol, TransferManager};
+use quinn::{Connection, Endpoint, RecvStream, SendStream};
+use std::net::SocketAddr;
+
+/// Both ends of a connection over 127.0.0.1. The endpoints are kept so the
+/// connection outlives the call that made it.
+pub struct Loopback {
+    pub client: Connection,
+    pub server: Connection,
+    _endpoints: (Endpoint, Endpoint),
+}
+
+/// Server and client endpoints on ephemeral ports, set up the way
+/// `TransferManager` sets up its own
+pub fn endpoints() -> (Endpoint, Endpoint) {
+    (server("127.0.0.1:0".parse().unwrap()), client())
+}
+
+/// A server endpoint bound to `addr`
+pub fn server(addr: SocketAddr) -> Endpoint {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let cert_manager = CertificateManager::generate_self_signed(1).unwrap();
+    Endpoint::server(TransferManager::server_config(&cert_manager).unwrap(), addr).unwrap()
+}
+
+pub fn client() -> Endpoint {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let cert_manager = CertificateManager::generate_self_signed(1).unwrap();
+    let mut client = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    client.set_default_client_config(TransferManager::client_config(&cert_manager).unwrap());
+    client
+}
+
+pub async fn connect() -> Loopback {
+    let (server_endpoint, client_endpoint) = endpoints();
+    let addr = server_endpoint.local_addr().unwrap();
+    let (client, server) = tokio::join!(
+        async {
+            client_endpoint
+                .connect(addr, "proxishare.local")
+                .unwrap()
+                .await
+                .unwrap()
+        },
+        async { server_endpoint.accept().await.unwrap().await.unwrap() },
+    );
+    Loopback {
+        client,
+        server,
+        _endpoints: (server_endpoint, client_endpoint),
+    }
+}
+
+impl Loopback {
+    /// A stream opened by the client and accepted by the server, preambles
+    /// exchanged: `(client side, server side)`
+    pub async fn stream(&self) -> ((SendStream, RecvStream), (SendStream, RecvStream)) {
+        let (opened, accepted) = tokio::join!(
+            protocol::open_stream(&self.client),
+            protocol::accept_stream(&self.server),
+        );
+        (opened.unwrap(), accepted.unwrap())
+    }
+}
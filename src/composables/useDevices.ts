@@ -8,26 +8,87 @@ export interface Device {
   all_ips: string[];
   port: number;
   last_seen: number;
+  /** Added by hostname rather than discovered */
+  manual: boolean;
   isTrusted?: boolean;
   isReachable?: boolean;
 }
 
+export interface TrustImport {
+  added: string[];
+  overwritten: string[];
+  conflicts: string[];
+}
+
+/** A device recently sent to, for one-tap re-send */
+export interface RecentDestination {
+  device_id: string;
+  device_name: string;
+  ip: string;
+  port: number;
+  last_used_at: number;
+}
+
+/** Totals over our history with one device */
+export interface PeerSummary {
+  device_id: string;
+  device_name: string | null;
+  total_transfers: number;
+  completed_transfers: number;
+  failed_transfers: number;
+  bytes_received: number;
+  bytes_sent: number;
+  last_transfer_at: number;
+  success_rate: number | null;
+}
+
+/** Whether a trusted device answered a reachability sweep */
+export interface DeviceReachability {
+  device_id: string;
+  name: string | null;
+  status: "reachable" | "offline" | "unchecked";
+  address: string | null;
+  port: number;
+}
+
+/** Whether mDNS is advertising us and browsing for others */
+export interface DiscoveryState {
+  broadcasting: boolean;
+  discovering: boolean;
+  registered_service_name: string | null;
+  discovered_count: number;
+}
+
 export function useDevices() {
   const devices = ref<Device[]>([]);
   const isDiscovering = ref(false);
+  const discoveryState = ref<DiscoveryState | null>(null);
   const error = ref<string | null>(null);
+  const recentDestinations = ref<RecentDestination[]>([]);
+  const peerSummaries = ref<PeerSummary[]>([]);
   let pollInterval: number | null = null;
 
   const startDiscovery = async () => {
     try {
       await invoke("start_discovery");
-      isDiscovering.value = true;
       error.value = null;
       fetchDevices();
     } catch (e) {
       console.error("Failed to start discovery:", e);
       error.value = String(e);
     }
+    await fetchDiscoveryState();
+  };
+
+  // What the backend is actually doing, which a failed start or a toggle
+  // elsewhere can make differ from what was last asked for
+  const fetchDiscoveryState = async () => {
+    try {
+      discoveryState.value = await invoke<DiscoveryState>("get_discovery_state");
+      isDiscovering.value = discoveryState.value.discovering;
+    } catch (e) {
+      console.error("Failed to get discovery state:", e);
+    }
   };
 
   const testConnectivity = async (ip: string, port: number): Promise<boolean> => {
@@ -38,6 +99,10 @@ export function useDevices() {
     }
   };
 
+  /** Probe every trusted device; slow ones come back "unchecked" */
+  const sweepKnownDevices = () =>
+    invoke<DeviceReachability[]>("sweep_known_devices");
+
   const findReachableIp = async (deviceId: string): Promise<string | null> => {
     try {
       return await invoke<string | null>("find_reachable_device_ip", { deviceId });
@@ -46,9 +111,88 @@ export function useDevices() {
     }
   };
 
+  /** Drop a device with a stale address; it returns if still announcing */
+  const forgetDevice = async (deviceId: string): Promise<boolean> => {
+    const removed = await invoke<boolean>("forget_device", { deviceId });
+    await fetchDevices();
+    return removed;
+  };
+
+  const addDeviceByHostname = async (hostname: string, port: number): Promise<Device> => {
+    const device = await invoke<Device>("add_device_by_hostname", { hostname, port });
+    await fetchDevices();
+    return device;
+  };
+
+  /**
+   * Trust a device for a limited time, e.g. a guest's. Resolves to when the
+   * trust expires (Unix seconds), or null if it was already trusted for good.
+   */
+  const addTemporaryTrust = async (
+    deviceId: string,
+    durationSecs: number
+  ): Promise<number | null> => {
+    const expiresAt = await invoke<number | null>("add_temporary_trust", {
+      deviceId,
+      durationSecs,
+    });
+    await fetchDevices();
+    return expiresAt;
+  };
+
+  /** Refuse a device outright, trusted or not; it can't even prompt */
+  const blockDevice = async (deviceId: string): Promise<boolean> => {
+    const added = await invoke<boolean>("block_device", { deviceId });
+    await fetchDevices();
+    return added;
+  };
+
+  const unblockDevice = async (deviceId: string): Promise<boolean> => {
+    const removed = await invoke<boolean>("unblock_device", { deviceId });
+    await fetchDevices();
+    return removed;
+  };
+
+  const getBlockedDevices = () => invoke<string[]>("get_blocked_devices");
+
+  const exportTrustedDevices = async (path: string): Promise<number> => {
+    return await invoke<number>("export_trusted_devices", { path });
+  };
+
+  // Merges by default; entries that differ from ours are only replaced after confirmation
+  const importTrustedDevices = async (path: string): Promise<TrustImport> => {
+    const result = await invoke<TrustImport>("import_trusted_devices", { path });
+    if (
+      result.conflicts.length > 0 &&
+      confirm(
+        `${result.conflicts.length} imported device(s) differ from ones you already trust. Overwrite them?`
+      )
+    ) {
+      const overwritten = await invoke<TrustImport>("import_trusted_devices", {
+        path,
+        overwrite: true,
+      });
+      result.overwritten = overwritten.overwritten;
+      result.conflicts = [];
+    }
+    await fetchDevices();
+    return result;
+  };
+
+  // Most recent first, at most 10
+  const fetchRecentDestinations = async () => {
+    recentDestinations.value = await invoke<RecentDestination[]>("get_recent_destinations");
+  };
+
+  const fetchPeerSummaries = async () => {
+    peerSummaries.value = await invoke<PeerSummary[]>("get_peer_summaries");
+  };
+
   const fetchDevices = async () => {
     try {
-      const result = await invoke<Device[]>("get_discovered_devices");
+      const result = await invoke<Device[]>("get_discovered_devices", {
+        hideBlocked: true,
+      });
       // Check trust status and connectivity for each device
       for (const device of result) {
         device.isTrusted = await invoke("is_device_trusted", {
@@ -66,7 +210,10 @@ export function useDevices() {
   onMounted(() => {
     startDiscovery();
     // Poll every 3 seconds for updates
-    pollInterval = window.setInterval(fetchDevices, 3000);
+    pollInterval = window.setInterval(() => {
+      fetchDevices();
+      fetchDiscoveryState();
+    }, 3000);
   });
 
   onUnmounted(() => {
@@ -78,9 +225,24 @@ export function useDevices() {
   return {
     devices,
     isDiscovering,
+    discoveryState,
+    fetchDiscoveryState,
     error,
     refreshDevices: fetchDevices,
     testConnectivity,
     findReachableIp,
+    sweepKnownDevices,
+    addDeviceByHostname,
+    forgetDevice,
+    addTemporaryTrust,
+    blockDevice,
+    unblockDevice,
+    getBlockedDevices,
+    exportTrustedDevices,
+    importTrustedDevices,
+    recentDestinations,
+    fetchRecentDestinations,
+    peerSummaries,
+    fetchPeerSummaries,
   };
 }
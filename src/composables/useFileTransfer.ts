@@ -1,6 +1,6 @@
 import { invoke } from "@tauri-apps/api/core";
 import { listen, UnlistenFn } from "@tauri-apps/api/event";
-import { onUnmounted, ref } from "vue";
+import { computed, onUnmounted, ref } from "vue";
 
 export interface Transfer {
   id: string;
@@ -15,10 +15,14 @@ export interface Transfer {
     | "completed"
     | "failed"
     | "paused"
+    | "interrupted"
     | "cancelled";
   direction: "send" | "receive";
   filePath?: string;
   speed?: number;
+  batchId?: string;
+  /** Chunk size the transfer uses, in bytes */
+  chunkSize?: number;
 }
 
 export interface TransferProgress {
@@ -28,6 +32,31 @@ export interface TransferProgress {
   total_bytes: number;
   direction: string;
   status: string;
+  batch_id: string | null;
+  chunk_size: number;
+}
+
+export interface BatchSummary {
+  batch_id: string;
+  completed: number;
+  failed: number;
+  cancelled: number;
+}
+
+/** Running count of a directory send; the total is known once `done` */
+export interface DirectoryProgress {
+  batch_id: string;
+  files_found: number;
+  bytes_found: number;
+  done: boolean;
+}
+
+/** Files of a directory picked by include/exclude globs */
+export interface SelectedFiles {
+  root: string;
+  files: string[];
+  file_count: number;
+  total_size: number;
 }
 
 export interface TransferRecord {
@@ -43,12 +72,25 @@ export interface TransferRecord {
   file_hash: string;
   created_at: number;
   updated_at: number;
+  remote_ip: string | null;
+  remote_port: number | null;
+  /** JSON-encoded signed receipt from the receiver, for completed sends */
+  receipt: string | null;
+  /** User annotation for organizing history */
+  note: string | null;
+  /** Algorithm of file_hash, e.g. "blake3" or "sha256" */
+  hash_algo: string;
 }
 
 export function useFileTransfer() {
   const activeTransfers = ref<Map<string, Transfer>>(new Map());
   const transfers = ref<Transfer[]>([]);
   const history = ref<TransferRecord[]>([]);
+  const doNotDisturb = ref(false);
+  // Incoming connections that are open, including ones yet to send anything
+  const incomingConnections = ref<Set<number>>(new Set());
+  // Directory sends by batch id, while their walk is still finding files
+  const directoryProgress = ref<Map<string, DirectoryProgress>>(new Map());
   let unlistenProgress: UnlistenFn | null = null;
 
   // Setup progress listener
@@ -62,7 +104,9 @@ export function useFileTransfer() {
         const percent =
           progress.total_bytes > 0
             ? Math.round((progress.bytes_sent / progress.total_bytes) * 100)
-            : 0;
+            : progress.status === "completed"
+              ? 100
+              : 0;
 
         const transfer: Transfer = {
           id: progress.transfer_id,
@@ -73,6 +117,8 @@ export function useFileTransfer() {
           progress: percent,
           status: progress.status as any,
           direction: progress.direction as "send" | "receive",
+          batchId: progress.batch_id ?? undefined,
+          chunkSize: progress.chunk_size,
         };
 
         activeTransfers.value.set(progress.transfer_id, transfer);
@@ -92,6 +138,35 @@ export function useFileTransfer() {
   // Auto-setup listener
   setupProgressListener();
 
+  // After a window reload, show running transfers at their persisted progress
+  // until the next progress event arrives
+  const loadActiveTransfers = async () => {
+    try {
+      const records = await invoke<TransferRecord[]>("get_active_transfers");
+      for (const record of records) {
+        if (activeTransfers.value.has(record.id)) continue;
+        activeTransfers.value.set(record.id, {
+          id: record.id,
+          deviceId: record.device_id,
+          fileName: record.file_name,
+          totalBytes: record.total_size,
+          bytesTransferred: record.bytes_transferred,
+          progress:
+            record.total_size > 0
+              ? Math.round((record.bytes_transferred / record.total_size) * 100)
+              : 0,
+          status: record.status as Transfer["status"],
+          direction: record.direction,
+          filePath: record.file_path,
+        });
+      }
+      transfers.value = Array.from(activeTransfers.value.values());
+    } catch (e) {
+      console.error("Failed to load active transfers:", e);
+    }
+  };
+  loadActiveTransfers();
+
   // Listen for history updates
   const setupHistoryListener = async () => {
     await listen("history-updated", async () => {
@@ -101,6 +176,74 @@ export function useFileTransfer() {
   };
   setupHistoryListener();
 
+  // The sender dropped mid-file; its chunks are kept so it can be resumed
+  const setupInterruptedListener = async () => {
+    await listen<{ transfer_id: string }>("transfer-interrupted", (event) => {
+      const transfer = activeTransfers.value.get(event.payload.transfer_id);
+      if (transfer) {
+        transfer.status = "interrupted";
+        transfers.value = Array.from(activeTransfers.value.values());
+      }
+    });
+  };
+  setupInterruptedListener();
+
+  // Drives the "receiving" indicator; connections that never send an offer
+  // are closed by the backend after a few seconds
+  const setupIncomingListener = async () => {
+    await listen<{ id: number }>("incoming-connection", (event) => {
+      incomingConnections.value = new Set(incomingConnections.value).add(event.payload.id);
+    });
+    await listen<{ id: number }>("incoming-connection-closed", (event) => {
+      const open = new Set(incomingConnections.value);
+      open.delete(event.payload.id);
+      incomingConnections.value = open;
+    });
+  };
+  setupIncomingListener();
+  const receiving = computed(() => incomingConnections.value.size > 0);
+
+  const setupDirectoryListener = async () => {
+    await listen<DirectoryProgress>("directory-progress", (event) => {
+      const walks = new Map(directoryProgress.value);
+      if (event.payload.done) {
+        walks.delete(event.payload.batch_id);
+      } else {
+        walks.set(event.payload.batch_id, event.payload);
+      }
+      directoryProgress.value = walks;
+    });
+  };
+  setupDirectoryListener();
+
+  // Do not disturb pauses and resumes transfers; progress events follow
+  const setupDndListener = async () => {
+    doNotDisturb.value = await invoke<boolean>("get_do_not_disturb");
+    await listen<{ enabled: boolean }>("dnd-changed", (event) => {
+      doNotDisturb.value = event.payload.enabled;
+    });
+  };
+  setupDndListener();
+
+  /** Pause all transfers and refuse new ones until turned off again */
+  const setDoNotDisturb = (enabled: boolean) =>
+    invoke<number>("set_do_not_disturb", { enabled });
+
+  /** Free space receiving must leave; null restores the 500 MiB default */
+  const setDiskSpaceMargin = (marginBytes: number | null) =>
+    invoke<number>("set_disk_space_margin", { marginBytes });
+
+  /** Emit `chunk-progress` for every chunk, for debugging; off on start */
+  const setVerboseProgress = (enabled: boolean) =>
+    invoke<void>("set_verbose_progress", { enabled });
+
+  /** Retry sends that fail on the network; null attempts turns it off */
+  const setAutoRetry = (
+    maxAttempts: number | null,
+    backoffSecs: number | null = null
+  ) =>
+    invoke<number>("set_auto_retry", { maxAttempts, backoffSecs });
+
   // Cleanup on unmount
   onUnmounted(() => {
     if (unlistenProgress) {
@@ -113,13 +256,16 @@ export function useFileTransfer() {
     deviceId: string,
     filePath: string,
     ip: string,
-    port: number
+    port: number,
+    /** Name the receiver saves the file as, instead of the source's name */
+    renameTo?: string
   ) => {
     console.log("[FileTransfer] Invoking send_file:", {
       deviceId,
       ip,
       port,
       path: filePath,
+      renameTo,
     });
     try {
       await invoke("send_file", {
@@ -127,11 +273,13 @@ export function useFileTransfer() {
         ip,
         port,
         path: filePath,
+        renameTo: renameTo ?? null,
       });
 
       // Update local transfer state with file path for retry
+      const sentName = renameTo?.trim() || filePath.split(/[\\/]/).pop();
       const t = Array.from(activeTransfers.value.values()).find(
-        (t) => t.fileName === filePath.split(/[\\/]/).pop()
+        (t) => t.fileName === sentName
       );
       if (t) {
         t.filePath = filePath;
@@ -148,6 +296,91 @@ export function useFileTransfer() {
     }
   };
 
+  /** Send in-memory data (e.g. generated or pasted content) as a file called `name` */
+  const sendBytes = async (
+    deviceId: string,
+    name: string,
+    data: Uint8Array,
+    ip: string,
+    port: number
+  ) => {
+    try {
+      await invoke("send_bytes", {
+        deviceId,
+        ip,
+        port,
+        name,
+        data: Array.from(data),
+      });
+      await loadHistory();
+    } catch (e) {
+      console.error("[FileTransfer] Failed to send data:", e);
+      await loadHistory();
+      throw e;
+    }
+  };
+
+  /**
+   * Send several files as one batch; resolves with the batch id once all
+   * files have been attempted
+   */
+  const sendFiles = async (
+    deviceId: string,
+    filePaths: string[],
+    ip: string,
+    port: number
+  ) => {
+    try {
+      const batchId = await invoke<string>("send_files", {
+        deviceId,
+        ip,
+        port,
+        paths: filePaths,
+      });
+      await loadHistory();
+      return batchId;
+    } catch (e) {
+      console.error("[FileTransfer] Failed to send files:", e);
+      await loadHistory();
+      throw e;
+    }
+  };
+
+  /** Resolve which files `sendDirectory` would send, for confirming first */
+  const selectDirectoryFiles = (
+    path: string,
+    include: string[] = [],
+    exclude: string[] = []
+  ) =>
+    invoke<SelectedFiles>("select_directory_files", { path, include, exclude });
+
+  /** Send the files of a directory matching the globs as one batch */
+  const sendDirectory = async (
+    deviceId: string,
+    path: string,
+    ip: string,
+    port: number,
+    include: string[] = [],
+    exclude: string[] = []
+  ) => {
+    try {
+      const batchId = await invoke<string>("send_directory", {
+        deviceId,
+        ip,
+        port,
+        path,
+        include,
+        exclude,
+      });
+      await loadHistory();
+      return batchId;
+    } catch (e) {
+      console.error("[FileTransfer] Failed to send directory:", e);
+      await loadHistory();
+      throw e;
+    }
+  };
+
   /**
    * Smart send that finds a reachable IP before attempting transfer
    * Falls back to the provided IP if no reachable IP is found
@@ -204,6 +437,33 @@ export function useFileTransfer() {
     }
   };
 
+  const searchHistory = async (query: string, limit?: number) => {
+    try {
+      return await invoke<TransferRecord[]>("search_transfers", {
+        query,
+        limit: limit ?? 100,
+      });
+    } catch (e) {
+      console.error("Failed to search transfer history:", e);
+      return [];
+    }
+  };
+
+  const setTransferNote = async (transferId: string, note: string | null) => {
+    try {
+      await invoke("set_transfer_note", { transferId, note });
+      const record = history.value.find((r) => r.id === transferId);
+      if (record) record.note = note?.trim() || null;
+    } catch (e) {
+      console.error("Failed to set transfer note:", e);
+    }
+  };
+
+  /** Re-hash a transfer's file; false if it no longer matches */
+  const verifyTransfer = async (transferId: string) => {
+    return await invoke<boolean>("verify_transfer", { transferId });
+  };
+
   const clearHistory = async () => {
     try {
       await invoke("clear_transfer_history");
@@ -250,16 +510,46 @@ export function useFileTransfer() {
     }
   };
 
+  /** Cancel a batch's running transfer and drop its queued ones */
+  const cancelBatch = async (batchId: string) => {
+    try {
+      await invoke("cancel_batch", { batchId });
+      for (const [id, t] of activeTransfers.value) {
+        if (t.batchId === batchId) activeTransfers.value.delete(id);
+      }
+      transfers.value = Array.from(activeTransfers.value.values());
+      await loadHistory();
+    } catch (e) {
+      console.error("Failed to cancel batch:", e);
+    }
+  };
+
   return {
     transfers,
     history,
     sendFile,
+    sendFiles,
+    sendBytes,
     sendFileWithFallback,
     loadHistory,
+    loadActiveTransfers,
     loadDeviceHistory,
+    searchHistory,
+    setTransferNote,
+    verifyTransfer,
     clearHistory,
     pauseTransfer,
     resumeTransfer,
     cancelTransfer,
+    cancelBatch,
+    doNotDisturb,
+    setDoNotDisturb,
+    setDiskSpaceMargin,
+    setAutoRetry,
+    setVerboseProgress,
+    receiving,
+    selectDirectoryFiles,
+    sendDirectory,
+    directoryProgress,
   };
 }
@@ -1,15 +1,140 @@
 import { invoke } from "@tauri-apps/api/core";
+import { listen, UnlistenFn } from "@tauri-apps/api/event";
 import { open } from "@tauri-apps/plugin-dialog";
-import { onMounted, ref } from "vue";
+import { onMounted, onUnmounted, ref } from "vue";
+
+export interface FolderValidation {
+  path: string;
+  valid: boolean;
+  failed_check:
+    | "missing"
+    | "not_a_directory"
+    | "not_writable"
+    | "system_location"
+    | null;
+  message: string | null;
+  estimated_file_count: number;
+  large: boolean;
+}
+
+export type SyncDirection = "push" | "pull" | "bidirectional";
+
+/** A local change queued for a sync peer */
+export interface SyncQueueEntry {
+  device_id: string;
+  relative_path: string;
+  status: "pending" | "failed";
+  attempts: number;
+  next_attempt_at: number;
+  last_error: string | null;
+  updated_at: number;
+}
+
+export interface SyncFileState {
+  size: number;
+  modified: number | null;
+  hash: string;
+}
+
+export interface SyncPreviewEntry {
+  path: string;
+  local: SyncFileState | null;
+  remote: SyncFileState | null;
+  action: "send_to_peer" | "fetch_from_peer" | "none";
+}
+
+/** What syncing with a device would change, without changing anything */
+export interface SyncPreview {
+  only_local: SyncPreviewEntry[];
+  only_remote: SyncPreviewEntry[];
+  differing: SyncPreviewEntry[];
+}
+
+/** Hashing progress of a sync folder scan */
+export interface SyncScanProgress {
+  folder: string;
+  files_hashed: number;
+  files_total: number;
+  bytes_hashed: number;
+  bytes_total: number;
+}
+
+/** File count and size of a folder's tree, without hashing */
+export interface FolderAnalysis {
+  root: string;
+  file_count: number;
+  total_size: number;
+  /** Largest first */
+  largest: { relative_path: string; size: number }[];
+}
+
+/** Running count of an `analyzeFolder` walk */
+export interface FolderAnalysisProgress {
+  path: string;
+  files_found: number;
+  bytes_found: number;
+}
+
+/** Result of a history sync with one device */
+export interface HistorySyncSummary {
+  sent: number;
+  received: number;
+  merged: number;
+}
+
+export interface HistorySyncProgress extends HistorySyncSummary {
+  device_id: string;
+  to_send: number;
+  done: boolean;
+}
 
 export function useSync() {
   const sharedFolder = ref<string | null>(null);
+  const direction = ref<SyncDirection>("bidirectional");
   const isSyncing = ref(false);
+  const failedSyncs = ref<SyncQueueEntry[]>([]);
+  const scanProgress = ref<SyncScanProgress | null>(null);
+  const historySyncProgress = ref<HistorySyncProgress | null>(null);
+  let unlistenScan: UnlistenFn | null = null;
+  let unlistenHistory: UnlistenFn | null = null;
 
   const fetchStatus = async () => {
     sharedFolder.value = await invoke("get_sync_status");
+    direction.value = await invoke<SyncDirection>("get_sync_direction");
+  };
+
+  // Changes that ran out of delivery attempts
+  const fetchFailedSyncs = async () => {
+    failedSyncs.value = await invoke<SyncQueueEntry[]>("get_failed_syncs");
+  };
+
+  const setDirection = async (value: SyncDirection) => {
+    await invoke("set_sync_direction", { direction: value });
+    direction.value = value;
   };
 
+  /** Files hashed in parallel by a folder scan; null uses half the cores */
+  const setScanWorkers = (workers: number | null) =>
+    invoke<number>("set_sync_scan_workers", { workers });
+
+  const previewSync = (deviceId: string) =>
+    invoke<SyncPreview>("preview_sync", { deviceId });
+
+  /** Count and size a folder the way a sync scan walks it */
+  const analyzeFolder = (path: string) =>
+    invoke<FolderAnalysis>("analyze_folder", { path });
+
+  /** Stop a running `analyzeFolder`, which then rejects as cancelled */
+  const cancelFolderAnalysis = (path: string) =>
+    invoke<boolean>("cancel_folder_analysis", { path });
+
+  const syncHistory = (deviceId: string, ip: string, port: number) =>
+    invoke<HistorySyncSummary>("sync_history", { deviceId, ip, port });
+
+  /** Most records sent or accepted per history sync; null uses 100 */
+  const setHistorySyncMaxRecords = (maxRecords: number | null) =>
+    invoke<number>("set_history_sync_max_records", { maxRecords });
+
   const selectFolder = async () => {
     const selected = await open({
       directory: true,
@@ -17,16 +142,62 @@ export function useSync() {
     });
 
     if (selected && typeof selected === "string") {
+      const validation = await invoke<FolderValidation>(
+        "validate_sync_folder",
+        { path: selected }
+      );
+      if (!validation.valid) {
+        throw new Error(validation.message ?? "Invalid sync folder");
+      }
+      if (validation.large && !window.confirm(validation.message ?? "")) {
+        return;
+      }
+
       await invoke("set_sync_folder", { path: selected });
       await fetchStatus();
     }
   };
 
-  onMounted(fetchStatus);
+  onMounted(async () => {
+    await fetchStatus();
+    unlistenScan = await listen<SyncScanProgress>(
+      "sync-scan-progress",
+      (event) => {
+        const progress = event.payload;
+        scanProgress.value =
+          progress.files_hashed < progress.files_total ? progress : null;
+      }
+    );
+    unlistenHistory = await listen<HistorySyncProgress>(
+      "history-sync-progress",
+      (event) => {
+        historySyncProgress.value = event.payload.done ? null : event.payload;
+      }
+    );
+  });
+
+  onUnmounted(() => {
+    unlistenScan?.();
+    unlistenScan = null;
+    unlistenHistory?.();
+    unlistenHistory = null;
+  });
 
   return {
     sharedFolder,
+    direction,
     isSyncing,
+    failedSyncs,
+    scanProgress,
+    historySyncProgress,
+    fetchFailedSyncs,
+    setDirection,
+    previewSync,
+    analyzeFolder,
+    cancelFolderAnalysis,
+    setScanWorkers,
+    syncHistory,
+    setHistorySyncMaxRecords,
     selectFolder,
   };
 }
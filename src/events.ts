@@ -0,0 +1,103 @@
+// Payloads of the events the backend emits, mirroring src-tauri/src/events.rs
+import { listen, type EventCallback, type UnlistenFn } from "@tauri-apps/api/event";
+import type { Device } from "./composables/useDevices";
+import type {
+  BatchSummary,
+  DirectoryProgress,
+  TransferProgress,
+} from "./composables/useFileTransfer";
+import type {
+  FolderAnalysisProgress,
+  HistorySyncProgress,
+  SyncScanProgress,
+} from "./composables/useSync";
+
+export interface DuplicateDeviceId {
+  device_id: string;
+  names: string[];
+  is_own_id: boolean;
+}
+
+export interface TransferInterrupted {
+  transfer_id: string;
+  device_id: string | null;
+  bytes_received: number;
+  reason: string;
+}
+
+export interface TransferRetrying {
+  transfer_id: string;
+  attempt: number;
+  max_attempts: number;
+  delay_secs: number;
+  error: string;
+}
+
+export interface ChunkProgress {
+  transfer_id: string;
+  direction: "send" | "receive";
+  chunk_index: number;
+  size: number;
+  hash: string;
+  /** Receiving side only */
+  verified: boolean | null;
+}
+
+export interface PairingRequest {
+  device: { id: string; name: string };
+  code: string;
+  key_verified: boolean;
+  ip: string;
+  port: number;
+}
+
+export interface PeerConnected {
+  device_id: string;
+  device_name: string;
+  ip: string;
+  trusted: boolean;
+  key_verified: boolean;
+}
+
+export interface PeerDisconnected {
+  device_id: string;
+  device_name: string;
+  ip: string;
+}
+
+export interface EventPayloads {
+  "device-discovered": Device;
+  "device-lost": { device_id: string };
+  "duplicate-device-id": DuplicateDeviceId;
+  "transfer-progress": TransferProgress;
+  "transfer-retrying": TransferRetrying;
+  "chunk-progress": ChunkProgress;
+  "transfer-interrupted": TransferInterrupted;
+  "batch-complete": BatchSummary;
+  "batch-cancelled": { batch_id: string; cancelled: number };
+  "directory-progress": DirectoryProgress;
+  "history-updated": null;
+  "pending-send": null;
+  "history-sync-progress": HistorySyncProgress;
+  "sync-scan-progress": SyncScanProgress;
+  "folder-analysis-progress": FolderAnalysisProgress;
+  "sync-failed": { device_id: string; relative_path: string; error: string };
+  "pairing-request": PairingRequest;
+  "peer-connected": PeerConnected;
+  "peer-disconnected": PeerDisconnected;
+  "trust-offered": { device_id: string; device_name: string; transfer_id: string };
+  "trust-expired": { device_ids: string[] };
+  "dnd-changed": { enabled: boolean; transfers: number };
+  "download-dir-unavailable": { transfer_id: string; path: string };
+  "incoming-connection": { id: number; address: string };
+  "incoming-connection-closed": { id: number };
+  "listener-down": { port: number };
+  "listener-recovered": { port: number; attempts: number };
+  "listener-failed": { port: number; error: string };
+}
+
+/** `listen` with the payload type of the named event */
+export const onEvent = <K extends keyof EventPayloads>(
+  name: K,
+  handler: EventCallback<EventPayloads[K]>
+): Promise<UnlistenFn> => listen<EventPayloads[K]>(name, handler);